@@ -0,0 +1,581 @@
+use crate::{
+    Authorizer, Error as SageError, QoS,
+    ReasonCode::{NotAuthorized, TopicFilterInvalid},
+    Result as SageResult, TopicFilter, TopicName, TopicTree,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    future::{ready, Future},
+    io::{Error as IOError, ErrorKind},
+    path::Path,
+    str::FromStr,
+};
+
+const LEVEL_SEPARATOR: char = '/';
+
+/// Whether an `AclRule` allows or denies the access.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum AclPolicy {
+    /// The access is allowed, unless another rule denies it.
+    Allow,
+
+    /// The access is denied, whatever the other rules.
+    Deny,
+}
+
+/// The access an `AclRule` applies to.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum AclAccess {
+    /// Subscribing to the topics.
+    Read,
+
+    /// Publishing to the topics.
+    Write,
+
+    /// Both subscribing and publishing.
+    ReadWrite,
+}
+
+impl AclAccess {
+    fn includes(self, access: AclAccess) -> bool {
+        self == AclAccess::ReadWrite || self == access
+    }
+}
+
+/// The clients an `AclRule` applies to.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
+pub enum AclScope {
+    /// All the clients, including anonymous ones.
+    #[default]
+    All,
+
+    /// The clients authenticated with the given user name.
+    User(String),
+
+    /// The clients whose user name is a member of the given group.
+    Group(String),
+}
+
+/// A rule of an `Acl`, allowing or denying some clients to access the topics
+/// matching `pattern`.
+///
+/// The pattern is a topic filter where `%u` is replaced by the user name of
+/// the client and `%c` by its client id, such as `devices/%c/#`. The
+/// substitutions cannot be made for anonymous clients for `%u`, nor with
+/// values containing `/`, `+` or `#`. An allowing rule then does not apply to
+/// the client, while a denying rule denies all the topics under the levels
+/// before its first substitution, such as `users/#` for `users/%u/secret`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct AclRule {
+    /// The clients the rule applies to.
+    pub scope: AclScope,
+
+    /// Whether the access is allowed or denied.
+    pub policy: AclPolicy,
+
+    /// The access the rule applies to.
+    pub access: AclAccess,
+
+    /// The topic pattern of the rule.
+    pub pattern: String,
+}
+
+impl AclRule {
+    // Returns the pattern with its substitutions replaced for the client, or
+    // `None` if the rule cannot apply to it.
+    fn substitute(&self, client_id: &str, user_name: Option<&str>) -> Option<String> {
+        let safe = |value: &str| !value.contains([LEVEL_SEPARATOR, '+', '#']);
+        let mut pattern = self.pattern.clone();
+        if pattern.contains("%u") {
+            pattern = pattern.replace("%u", user_name.filter(|u| safe(u))?);
+        }
+        if pattern.contains("%c") {
+            if !safe(client_id) {
+                return None;
+            }
+            pattern = pattern.replace("%c", client_id);
+        }
+        Some(pattern)
+    }
+
+    // Returns the pattern with the level of its first substitution and the
+    // following ones replaced by `#`, matching any substitution.
+    fn prefix_pattern(&self) -> String {
+        let mut levels: Vec<&str> = self
+            .pattern
+            .split(LEVEL_SEPARATOR)
+            .take_while(|level| !level.contains('%'))
+            .collect();
+        if levels.len() < self.pattern.split(LEVEL_SEPARATOR).count() {
+            levels.push("#");
+        }
+        levels.join("/")
+    }
+}
+
+// Returns `true` if every topic name matched by `filter` is matched by
+// `pattern`.
+fn covers(pattern: &str, filter: &str) -> bool {
+    if filter.starts_with('$') && pattern.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut levels = filter.split(LEVEL_SEPARATOR);
+    for level in pattern.split(LEVEL_SEPARATOR) {
+        match (level, levels.next()) {
+            ("#", _) => return true,
+            (_, None) | (_, Some("#")) => return false,
+            ("+", Some(_)) => (),
+            (level, Some(filter_level)) if level == filter_level => (),
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+// Returns `true` if some topic name is matched by both `a` and `b`.
+fn intersects(a: &str, b: &str) -> bool {
+    if (a.starts_with('$') && b.starts_with(['+', '#']))
+        || (b.starts_with('$') && a.starts_with(['+', '#']))
+    {
+        return false;
+    }
+    let (mut a, mut b) = (a.split(LEVEL_SEPARATOR), b.split(LEVEL_SEPARATOR));
+    loop {
+        match (a.next(), b.next()) {
+            (Some("#"), _) | (_, Some("#")) | (None, None) => return true,
+            (Some(x), Some(y)) if x == "+" || y == "+" || x == y => (),
+            _ => return false,
+        }
+    }
+}
+
+/// An `Authorizer` driven by access control rules, such as the ones of a
+/// configuration file.
+///
+/// A client is authorized if a rule applying to it allows the access, and no
+/// rule applying to it denies it. Everything is denied by default. A
+/// subscription must be covered by a single rule allowing to read its topic
+/// filter, and is denied if a denying rule matches any of the topics it
+/// matches.
+///
+/// The rules are stored in a `TopicTree`, so that authorizing a message or a
+/// subscription only evaluates the rules whose pattern may match its topic or
+/// one of the topics of its filter.
+///
+/// The configuration file has one statement per line, where empty lines and
+/// lines starting with `#` are ignored:
+/// - `user <name>`, `group <name>` or `all` sets the scope of the rules that
+///   follow. The rules before any scope apply to all the clients.
+/// - `allow <access> <pattern>` or `deny <access> <pattern>` adds a rule,
+///   where the access is `read`, `write` or `readwrite`.
+/// - `members <group> <user>...` adds users to a group.
+///
+/// ```text
+/// members operators thomas guy
+///
+/// allow read $SYS/broker/uptime
+///
+/// user thomas
+/// allow readwrite devices/%c/#
+///
+/// group operators
+/// allow read devices/#
+/// deny read devices/+/secrets/#
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    rules: Vec<AclRule>,
+    tree: TopicTree<usize>,
+    groups: HashMap<String, HashSet<String>>,
+}
+
+impl Acl {
+    /// Creates an empty set of rules, denying everything.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Reads the rules of the configuration file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns any `std::io::Error` reading the file, or one of kind
+    /// `InvalidData` if a line of the file is not valid.
+    pub fn load<P: AsRef<Path>>(path: P) -> SageResult<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Adds `rule`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TopicFilterInvalid` if the pattern of `rule` is not a valid
+    /// topic filter, or is a shared subscription.
+    pub fn insert(&mut self, rule: AclRule) -> SageResult<()> {
+        // The levels with substitutions may match any level.
+        let mut levels = Vec::new();
+        for level in rule.pattern.split(LEVEL_SEPARATOR) {
+            if !level.contains('%') {
+                levels.push(level);
+            } else if level.contains(['+', '#']) {
+                return Err(TopicFilterInvalid.into());
+            } else {
+                levels.push("+");
+            }
+        }
+        let filter = TopicFilter::try_from(levels.join("/"))?;
+        if filter.is_shared() {
+            return Err(TopicFilterInvalid.into());
+        }
+        // The rule is stored under all the topics it may apply to, including
+        // the ones it denies when it cannot be substituted.
+        let filter = TopicFilter::try_from(rule.prefix_pattern())?;
+        self.tree.insert(&filter, self.rules.len());
+        self.rules.push(rule);
+        Ok(())
+    }
+
+    /// Adds `user_name` to the members of `group`.
+    pub fn add_member<S: Into<String>>(&mut self, user_name: S, group: S) {
+        self.groups
+            .entry(user_name.into())
+            .or_default()
+            .insert(group.into());
+    }
+
+    /// Returns the rules, in the order they were added.
+    pub fn rules(&self) -> &[AclRule] {
+        &self.rules
+    }
+
+    fn applies(&self, rule: &AclRule, user_name: Option<&str>) -> bool {
+        match &rule.scope {
+            AclScope::All => true,
+            AclScope::User(user) => user_name == Some(user.as_str()),
+            AclScope::Group(group) => user_name
+                .and_then(|user_name| self.groups.get(user_name))
+                .is_some_and(|groups| groups.contains(group)),
+        }
+    }
+
+    fn check_topic(
+        &self,
+        client_id: &str,
+        user_name: Option<&str>,
+        topic: &TopicName,
+        access: AclAccess,
+    ) -> bool {
+        let mut allowed = false;
+        for &index in self.tree.matches(topic) {
+            let rule = &self.rules[index];
+            if !rule.access.includes(access) || !self.applies(rule, user_name) {
+                continue;
+            }
+            if rule.pattern.contains('%') {
+                match rule.substitute(client_id, user_name) {
+                    Some(pattern) => {
+                        let matches = TopicFilter::try_from(pattern)
+                            .is_ok_and(|filter| filter.matches(topic));
+                        if !matches {
+                            continue;
+                        }
+                    }
+                    None if rule.policy == AclPolicy::Deny => return false,
+                    None => continue,
+                }
+            }
+            match rule.policy {
+                AclPolicy::Allow => allowed = true,
+                AclPolicy::Deny => return false,
+            }
+        }
+        allowed
+    }
+
+    /// Returns `true` if the client may publish to `topic`.
+    pub fn check_publish(
+        &self,
+        client_id: &str,
+        user_name: Option<&str>,
+        topic: &TopicName,
+    ) -> bool {
+        self.check_topic(client_id, user_name, topic, AclAccess::Write)
+    }
+
+    /// Returns `true` if the client may subscribe to `filter`. The share name
+    /// of a shared subscription is ignored.
+    pub fn check_subscribe(
+        &self,
+        client_id: &str,
+        user_name: Option<&str>,
+        filter: &TopicFilter,
+    ) -> bool {
+        if !filter.filter().contains(['+', '#']) {
+            return match TopicName::try_from(filter.filter()) {
+                Ok(topic) => self.check_topic(client_id, user_name, &topic, AclAccess::Read),
+                Err(_) => false,
+            };
+        }
+
+        let rules = self.tree.intersecting(filter);
+        let filter = filter.filter();
+        let mut allowed = false;
+        for &index in rules {
+            let rule = &self.rules[index];
+            if !rule.access.includes(AclAccess::Read) || !self.applies(rule, user_name) {
+                continue;
+            }
+            let pattern = match rule.substitute(client_id, user_name) {
+                Some(pattern) => pattern,
+                None if rule.policy == AclPolicy::Deny => rule.prefix_pattern(),
+                None => continue,
+            };
+            match rule.policy {
+                AclPolicy::Allow => allowed |= covers(&pattern, filter),
+                AclPolicy::Deny if intersects(&pattern, filter) => return false,
+                AclPolicy::Deny => (),
+            }
+        }
+        allowed
+    }
+}
+
+impl FromStr for Acl {
+    type Err = SageError;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        let mut acl = Acl::new();
+        let mut scope = AclScope::All;
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid_data = || {
+                SageError::from(IOError::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: {}", index + 1, line),
+                ))
+            };
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            let single = !rest.is_empty() && !rest.contains(char::is_whitespace);
+            match keyword {
+                "all" if rest.is_empty() => scope = AclScope::All,
+                "user" if single => scope = AclScope::User(rest.into()),
+                "group" if single => scope = AclScope::Group(rest.into()),
+                "members" => {
+                    let mut words = rest.split_whitespace();
+                    let group = words.next().ok_or_else(invalid_data)?;
+                    let mut users = words.peekable();
+                    if users.peek().is_none() {
+                        return Err(invalid_data());
+                    }
+                    for user in users {
+                        acl.add_member(user, group);
+                    }
+                }
+                "allow" | "deny" => {
+                    let (access, pattern) = rest
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(invalid_data)?;
+                    let access = match access {
+                        "read" => AclAccess::Read,
+                        "write" => AclAccess::Write,
+                        "readwrite" => AclAccess::ReadWrite,
+                        _ => return Err(invalid_data()),
+                    };
+                    let policy = if keyword == "allow" {
+                        AclPolicy::Allow
+                    } else {
+                        AclPolicy::Deny
+                    };
+                    let rule = AclRule {
+                        scope: scope.clone(),
+                        policy,
+                        access,
+                        pattern: pattern.trim().into(),
+                    };
+                    acl.insert(rule).map_err(|_| invalid_data())?;
+                }
+                _ => return Err(invalid_data()),
+            }
+        }
+        Ok(acl)
+    }
+}
+
+impl Authorizer for Acl {
+    fn authorize_publish(
+        &self,
+        client_id: &str,
+        user_name: Option<&str>,
+        topic: &TopicName,
+        _: QoS,
+        _: bool,
+    ) -> impl Future<Output = SageResult<()>> + Send {
+        ready(if self.check_publish(client_id, user_name, topic) {
+            Ok(())
+        } else {
+            Err(NotAuthorized.into())
+        })
+    }
+
+    fn authorize_subscribe(
+        &self,
+        client_id: &str,
+        user_name: Option<&str>,
+        filter: &TopicFilter,
+        qos: QoS,
+    ) -> impl Future<Output = SageResult<QoS>> + Send {
+        ready(if self.check_subscribe(client_id, user_name, filter) {
+            Ok(qos)
+        } else {
+            Err(NotAuthorized.into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    const RULES: &str = "
+# Daft Punk
+members operators guy
+
+allow read $SYS/broker/uptime
+
+user thomas
+allow readwrite devices/%c/#
+
+group operators
+allow read devices/#
+deny read devices/+/secrets/#
+
+all
+allow write users/%u
+";
+
+    fn publish(acl: &Acl, client_id: &str, user_name: Option<&str>, topic: &str) -> bool {
+        acl.check_publish(client_id, user_name, &topic.try_into().unwrap())
+    }
+
+    fn subscribe(acl: &Acl, client_id: &str, user_name: Option<&str>, filter: &str) -> bool {
+        acl.check_subscribe(client_id, user_name, &filter.try_into().unwrap())
+    }
+
+    #[test]
+    fn check() {
+        let acl: Acl = RULES.parse().unwrap();
+        assert_eq!(acl.rules().len(), 5);
+
+        assert!(publish(
+            &acl,
+            "helmet",
+            Some("thomas"),
+            "devices/helmet/light"
+        ));
+        assert!(!publish(
+            &acl,
+            "helmet",
+            Some("thomas"),
+            "devices/other/light"
+        ));
+        assert!(!publish(
+            &acl,
+            "helmet",
+            Some("guy"),
+            "devices/helmet/light"
+        ));
+        assert!(!publish(&acl, "a/b", Some("thomas"), "devices/a/b/light"));
+        assert!(publish(&acl, "helmet", Some("guy"), "users/guy"));
+        assert!(!publish(&acl, "helmet", Some("guy"), "users/thomas"));
+        assert!(!publish(&acl, "helmet", None, "users/"));
+        assert!(!publish(&acl, "helmet", None, "$SYS/broker/uptime"));
+
+        assert!(subscribe(&acl, "helmet", None, "$SYS/broker/uptime"));
+        assert!(!subscribe(&acl, "helmet", None, "$SYS/#"));
+        assert!(subscribe(
+            &acl,
+            "helmet",
+            Some("thomas"),
+            "devices/helmet/#"
+        ));
+        assert!(subscribe(&acl, "helmet", Some("guy"), "devices/+/light"));
+        assert!(subscribe(
+            &acl,
+            "helmet",
+            Some("guy"),
+            "$share/g/devices/+/light"
+        ));
+        assert!(!subscribe(&acl, "helmet", Some("guy"), "devices/#"));
+        assert!(!subscribe(&acl, "helmet", Some("guy"), "devices/a/secrets"));
+        assert!(!subscribe(
+            &acl,
+            "helmet",
+            Some("manuel"),
+            "devices/+/light"
+        ));
+        assert!(!subscribe(&acl, "helmet", Some("thomas"), "#"));
+    }
+
+    #[test]
+    fn unsubstituted_deny() {
+        let acl: Acl = "
+deny readwrite users/%u/secret
+allow readwrite users/#
+"
+        .parse()
+        .unwrap();
+        assert!(publish(&acl, "helmet", Some("guy"), "users/guy/public"));
+        assert!(publish(&acl, "helmet", Some("guy"), "users/thomas/secret"));
+        assert!(!publish(&acl, "helmet", Some("guy"), "users/guy/secret"));
+        assert!(!publish(&acl, "helmet", Some("g/y"), "users/g/y/secret"));
+        assert!(!publish(&acl, "helmet", Some("g/y"), "users/guy/public"));
+        assert!(!publish(&acl, "helmet", None, "users/guy/public"));
+
+        assert!(subscribe(&acl, "helmet", Some("guy"), "users/thomas/#"));
+        assert!(!subscribe(&acl, "helmet", Some("guy"), "users/+/secret"));
+        assert!(!subscribe(&acl, "helmet", Some("g/y"), "users/+/+/secret"));
+        assert!(!subscribe(&acl, "helmet", Some("g+"), "users/+/secret"));
+        assert!(!subscribe(&acl, "helmet", Some("g/y"), "users/thomas/#"));
+    }
+
+    #[test]
+    fn invalid() {
+        for rules in [
+            "allow read",
+            "allow execute a/b",
+            "allow read a/#/b",
+            "allow read a/%u+/b",
+            "allow read $share/group/a",
+            "user",
+            "user a b",
+            "members operators",
+            "permit read a",
+        ] {
+            assert!(matches!(
+                rules.parse::<Acl>(),
+                Err(SageError::Io(e)) if e.kind() == ErrorKind::InvalidData
+            ));
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(covers("a/#", "a"));
+        assert!(covers("a/+/c", "a/b/c"));
+        assert!(covers("+/+", "a/+"));
+        assert!(!covers("a/+", "a/#"));
+        assert!(!covers("a/b", "a/+"));
+        assert!(!covers("#", "$SYS/a"));
+        assert!(intersects("a/+", "+/b"));
+        assert!(intersects("a", "a/#"));
+        assert!(!intersects("a/b", "a/c/#"));
+        assert!(!intersects("a/b/c", "a/+"));
+        assert!(!intersects("$SYS/#", "#"));
+    }
+}
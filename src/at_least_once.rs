@@ -0,0 +1,172 @@
+use crate::{
+    PacketIdAllocator, PubAck, Publish, QoS,
+    ReasonCode::{self, PacketIdentifierNotFound, ProtocolError},
+    Result as SageResult,
+};
+use std::collections::VecDeque;
+
+/// The sender side of `AtLeastOnce` deliveries: `Publish` packets are kept
+/// until they are acknowledged with a `PubAck` packet.
+/// The packet identifiers are shared by all the exchanges of a session, hence
+/// they are taken from a `PacketIdAllocator` provided by the caller.
+#[derive(Debug, Default, Clone)]
+pub struct AtLeastOnceSender {
+    pending: VecDeque<Publish>,
+}
+
+impl AtLeastOnceSender {
+    /// Creates a sender with no pending delivery.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Starts the delivery of `publish`, returning the `Publish` packet to
+    /// send with a newly allocated packet identifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the quality of service is not `AtLeastOnce`,
+    /// or any error of `PacketIdAllocator::allocate`.
+    pub fn publish(
+        &mut self,
+        ids: &mut PacketIdAllocator,
+        publish: Publish,
+    ) -> SageResult<Publish> {
+        if publish.qos != QoS::AtLeastOnce {
+            return Err(ProtocolError.into());
+        }
+        let publish = Publish {
+            duplicate: false,
+            packet_identifier: Some(ids.allocate()?),
+            ..publish
+        };
+        self.pending.push_back(publish.clone());
+        Ok(publish)
+    }
+
+    /// Ends the delivery acknowledged by `puback`, releasing its packet
+    /// identifier and returning the delivered `Publish` packet. The delivery
+    /// ends even if the reason code of `puback` is an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PacketIdentifierNotFound` if no delivery is pending for the
+    /// packet identifier.
+    pub fn acknowledge(
+        &mut self,
+        ids: &mut PacketIdAllocator,
+        puback: &PubAck,
+    ) -> SageResult<Publish> {
+        let index = self
+            .pending
+            .iter()
+            .position(|p| p.packet_identifier == Some(puback.packet_identifier))
+            .ok_or(PacketIdentifierNotFound)?;
+        ids.release(puback.packet_identifier);
+        Ok(self.pending.remove(index).unwrap_or_default())
+    }
+
+    /// Returns the `Publish` packets to send again when a session is resumed,
+    /// in their original order and with the `duplicate` flag set.
+    pub fn retransmit(&self) -> Vec<Publish> {
+        self.pending
+            .iter()
+            .map(|publish| Publish {
+                duplicate: true,
+                ..publish.clone()
+            })
+            .collect()
+    }
+
+    /// Returns the number of deliveries awaiting an acknowledgement.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // The `Publish` packets awaiting an acknowledgement, in order.
+    pub(crate) fn unacknowledged(&self) -> impl Iterator<Item = &Publish> {
+        self.pending.iter()
+    }
+
+    // Resumes a delivery restored from a session, whose packet identifier is
+    // already reserved.
+    pub(crate) fn restore(&mut self, publish: Publish) {
+        self.pending.push_back(publish);
+    }
+}
+
+/// The receiver side of `AtLeastOnce` deliveries. Every received `Publish`
+/// packet is delivered to the application and acknowledged, including
+/// duplicates.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AtLeastOnceReceiver;
+
+impl AtLeastOnceReceiver {
+    /// Creates a receiver.
+    pub fn new() -> Self {
+        AtLeastOnceReceiver
+    }
+
+    /// Receives `publish`, returning the message to deliver to the application
+    /// along with the `PubAck` packet to send.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the quality of service is not `AtLeastOnce`
+    /// or the packet identifier is missing.
+    pub fn receive(&self, publish: Publish) -> SageResult<(Publish, PubAck)> {
+        let puback = PubAck::for_publish(&publish, ReasonCode::Success)?;
+        Ok((publish, puback))
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::Error;
+
+    fn publish() -> Publish {
+        Publish {
+            qos: QoS::AtLeastOnce,
+            topic_name: "Around the World".try_into().unwrap(),
+            message: "Harder, Better, Faster, Stronger".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flow() {
+        let mut ids = PacketIdAllocator::new();
+        let mut sender = AtLeastOnceSender::new();
+        let receiver = AtLeastOnceReceiver::new();
+
+        let first = sender.publish(&mut ids, publish()).unwrap();
+        let second = sender.publish(&mut ids, publish()).unwrap();
+        assert_eq!(first.packet_identifier, Some(1));
+        assert_eq!(sender.pending(), 2);
+
+        let (message, puback) = receiver.receive(second.clone()).unwrap();
+        assert_eq!(message, second);
+        assert_eq!(sender.acknowledge(&mut ids, &puback).unwrap(), second);
+        assert!(!ids.is_in_use(2));
+
+        assert_eq!(
+            sender.retransmit(),
+            vec![Publish {
+                duplicate: true,
+                ..first
+            }]
+        );
+
+        assert!(matches!(
+            sender.acknowledge(&mut ids, &puback),
+            Err(Error::Reason(PacketIdentifierNotFound))
+        ));
+        assert!(matches!(
+            sender.publish(&mut ids, Publish::default()),
+            Err(Error::Reason(ProtocolError))
+        ));
+        assert!(receiver.receive(Publish::default()).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::{
+    Auth, Authentication, ConnAck, Connect, Disconnect, Packet,
+    ReasonCode::{self, BadAuthenticationMethod, ProtocolError},
+    Result as SageResult,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Client,
+    Server,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Initial,
+    Pending,
+    Challenged,
+    Authenticated,
+    Failed,
+}
+
+/// The outcome of an incoming packet fed into an `AuthFlow`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AuthStep {
+    /// The peer sent authentication data which must be answered using
+    /// `AuthFlow::respond` or, on the server, `AuthFlow::succeed` and
+    /// `AuthFlow::fail`.
+    Challenge(Vec<u8>),
+
+    /// A packet which must be sent to the peer. The flow has failed and the
+    /// connection is to be closed once it is sent.
+    Send(Packet),
+
+    /// The authentication succeeded.
+    Authenticated,
+
+    /// The authentication failed with the given reason code.
+    Failed(ReasonCode),
+}
+
+/// A state machine driving an enhanced authentication exchange, either upon
+/// connection or for a re-authentication.
+///
+/// - The client starts the exchange with `start` or `reauthenticate`, then
+///   answers each `AuthStep::Challenge` with `respond` until it gets
+///   `AuthStep::Authenticated` or `AuthStep::Failed`.
+/// - The server feeds the `Connect` packet, or the `Auth` packet of a
+///   re-authentication, into `accept` and answers each `AuthStep::Challenge`
+///   with `respond` to continue, `succeed` or `fail`.
+///
+/// Any packet which is not legal at the current step of the exchange, or
+/// whose authentication method differs from the one of the flow, is rejected
+/// with `ProtocolError`.
+#[derive(Debug, Clone)]
+pub struct AuthFlow {
+    role: Role,
+    method: String,
+    state: State,
+    reauthenticating: bool,
+}
+
+impl AuthFlow {
+    /// Creates the client side of an exchange using the given authentication
+    /// method.
+    pub fn client<S: Into<String>>(method: S) -> Self {
+        AuthFlow {
+            role: Role::Client,
+            method: method.into(),
+            state: State::Initial,
+            reauthenticating: false,
+        }
+    }
+
+    /// Creates the server side of an exchange accepting the given
+    /// authentication method.
+    pub fn server<S: Into<String>>(method: S) -> Self {
+        AuthFlow {
+            role: Role::Server,
+            method: method.into(),
+            state: State::Initial,
+            reauthenticating: false,
+        }
+    }
+
+    /// Returns the authentication method of the exchange.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns `true` if the authentication succeeded and no
+    /// re-authentication is in progress.
+    pub fn is_authenticated(&self) -> bool {
+        self.state == State::Authenticated
+    }
+
+    fn authentication(&self, data: Vec<u8>) -> Authentication {
+        Authentication {
+            method: self.method.clone(),
+            data,
+        }
+    }
+
+    fn auth(&self, reason_code: ReasonCode, data: Vec<u8>) -> Packet {
+        Auth {
+            reason_code,
+            authentication: self.authentication(data),
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn check_method(&self, authentication: &Authentication) -> SageResult<()> {
+        if authentication.method == self.method {
+            Ok(())
+        } else {
+            Err(ProtocolError.into())
+        }
+    }
+
+    /// Client only. Starts the exchange, returning `connect` with its
+    /// authentication set to the flow method and the initial `data`.
+    pub fn start(&mut self, connect: Connect, data: Vec<u8>) -> SageResult<Packet> {
+        if self.role != Role::Client || self.state != State::Initial {
+            return Err(ProtocolError.into());
+        }
+        self.state = State::Pending;
+        Ok(Connect {
+            authentication: Some(self.authentication(data)),
+            ..connect
+        }
+        .into())
+    }
+
+    /// Client only. Starts a re-authentication once authenticated, returning
+    /// the `Auth` packet to send.
+    pub fn reauthenticate(&mut self, data: Vec<u8>) -> SageResult<Packet> {
+        if self.role != Role::Client || self.state != State::Authenticated {
+            return Err(ProtocolError.into());
+        }
+        self.state = State::Pending;
+        self.reauthenticating = true;
+        Ok(self.auth(ReasonCode::ReAuthenticate, data))
+    }
+
+    /// Answers the last challenge of the peer, returning the `Auth` packet to
+    /// send.
+    pub fn respond(&mut self, data: Vec<u8>) -> SageResult<Packet> {
+        if self.state != State::Challenged {
+            return Err(ProtocolError.into());
+        }
+        self.state = State::Pending;
+        Ok(self.auth(ReasonCode::ContinueAuthentication, data))
+    }
+
+    /// Server only. Ends the exchange successfully, returning the packet to
+    /// send: a `ConnAck` upon connection, or an `Auth` packet for a
+    /// re-authentication.
+    pub fn succeed(&mut self, data: Vec<u8>) -> SageResult<Packet> {
+        if self.role != Role::Server || self.state != State::Challenged {
+            return Err(ProtocolError.into());
+        }
+        self.state = State::Authenticated;
+        if self.reauthenticating {
+            self.reauthenticating = false;
+            Ok(self.auth(ReasonCode::Success, data))
+        } else {
+            Ok(ConnAck::builder()
+                .authentication(self.authentication(data))
+                .build()
+                .into())
+        }
+    }
+
+    /// Server only. Ends the exchange with an error, returning the packet to
+    /// send: a `ConnAck` upon connection, or a `Disconnect` packet for a
+    /// re-authentication.
+    pub fn fail(&mut self, reason_code: ReasonCode) -> SageResult<Packet> {
+        if self.role != Role::Server || self.state != State::Challenged {
+            return Err(ProtocolError.into());
+        }
+        self.state = State::Failed;
+        if self.reauthenticating {
+            Ok(Disconnect {
+                reason_code,
+                ..Default::default()
+            }
+            .into())
+        } else {
+            Ok(ConnAck::rejection(reason_code).into())
+        }
+    }
+
+    /// Feeds a packet received from the peer into the flow.
+    pub fn accept(&mut self, packet: Packet) -> SageResult<AuthStep> {
+        match (self.role, self.state, packet) {
+            (Role::Server, State::Initial, Packet::Connect(connect)) => {
+                match connect.authentication {
+                    Some(authentication) if authentication.method == self.method => {
+                        self.state = State::Challenged;
+                        Ok(AuthStep::Challenge(authentication.data))
+                    }
+                    Some(_) => {
+                        self.state = State::Failed;
+                        Ok(AuthStep::Send(
+                            ConnAck::rejection(BadAuthenticationMethod).into(),
+                        ))
+                    }
+                    None => Err(ProtocolError.into()),
+                }
+            }
+            (Role::Server, State::Authenticated, Packet::Auth(auth))
+                if auth.reason_code == ReasonCode::ReAuthenticate =>
+            {
+                self.check_method(&auth.authentication)?;
+                self.state = State::Challenged;
+                self.reauthenticating = true;
+                Ok(AuthStep::Challenge(auth.authentication.data))
+            }
+            (_, State::Pending, Packet::Auth(auth))
+                if auth.reason_code == ReasonCode::ContinueAuthentication =>
+            {
+                self.check_method(&auth.authentication)?;
+                self.state = State::Challenged;
+                Ok(AuthStep::Challenge(auth.authentication.data))
+            }
+            (Role::Client, State::Pending, Packet::ConnAck(connack)) if !self.reauthenticating => {
+                if connack.reason_code == ReasonCode::Success {
+                    if let Some(authentication) = &connack.authentication {
+                        self.check_method(authentication)?;
+                    }
+                    self.state = State::Authenticated;
+                    Ok(AuthStep::Authenticated)
+                } else {
+                    self.state = State::Failed;
+                    Ok(AuthStep::Failed(connack.reason_code))
+                }
+            }
+            (Role::Client, State::Pending, Packet::Auth(auth))
+                if self.reauthenticating && auth.reason_code == ReasonCode::Success =>
+            {
+                self.check_method(&auth.authentication)?;
+                self.state = State::Authenticated;
+                self.reauthenticating = false;
+                Ok(AuthStep::Authenticated)
+            }
+            (Role::Client, State::Pending, Packet::Disconnect(disconnect))
+                if self.reauthenticating =>
+            {
+                self.state = State::Failed;
+                Ok(AuthStep::Failed(disconnect.reason_code))
+            }
+            _ => Err(ProtocolError.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn exchange() {
+        let mut client = AuthFlow::client("SCRAM-SHA-1");
+        let mut server = AuthFlow::server("SCRAM-SHA-1");
+
+        let packet = client.start(Default::default(), b"client-first".to_vec());
+        let step = server.accept(packet.unwrap()).unwrap();
+        assert_eq!(step, AuthStep::Challenge(b"client-first".to_vec()));
+
+        let packet = server.respond(b"server-first".to_vec()).unwrap();
+        let step = client.accept(packet).unwrap();
+        assert_eq!(step, AuthStep::Challenge(b"server-first".to_vec()));
+
+        let packet = client.respond(b"client-final".to_vec()).unwrap();
+        let step = server.accept(packet).unwrap();
+        assert_eq!(step, AuthStep::Challenge(b"client-final".to_vec()));
+
+        let packet = server.succeed(b"server-final".to_vec()).unwrap();
+        assert!(matches!(packet, Packet::ConnAck(_)));
+        assert_eq!(client.accept(packet).unwrap(), AuthStep::Authenticated);
+        assert!(client.is_authenticated() && server.is_authenticated());
+
+        let packet = client.reauthenticate(b"client-first".to_vec()).unwrap();
+        let step = server.accept(packet).unwrap();
+        assert_eq!(step, AuthStep::Challenge(b"client-first".to_vec()));
+        let packet = server.fail(ReasonCode::NotAuthorized).unwrap();
+        assert!(matches!(packet, Packet::Disconnect(_)));
+        assert_eq!(
+            client.accept(packet).unwrap(),
+            AuthStep::Failed(ReasonCode::NotAuthorized)
+        );
+    }
+
+    #[test]
+    fn illegal_steps() {
+        let mut client = AuthFlow::client("SCRAM-SHA-1");
+        assert!(client.respond(Vec::new()).is_err());
+        assert!(client.reauthenticate(Vec::new()).is_err());
+        assert!(client.accept(Packet::PingResp).is_err());
+
+        client.start(Default::default(), Vec::new()).unwrap();
+        let packet = AuthFlow::client("GS2-KRB5")
+            .start(Default::default(), Vec::new())
+            .unwrap();
+
+        let mut server = AuthFlow::server("SCRAM-SHA-1");
+        assert!(matches!(
+            server.accept(packet).unwrap(),
+            AuthStep::Send(Packet::ConnAck(connack))
+                if connack.reason_code == BadAuthenticationMethod
+        ));
+        assert!(server.succeed(Vec::new()).is_err());
+    }
+}
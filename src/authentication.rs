@@ -1,5 +1,5 @@
 use crate::{Property, Result as SageResult};
-use std::marker::Unpin;
+use std::{fmt, marker::Unpin};
 use tokio::io::AsyncWrite;
 
 /// By default, `Connect` packets provide optional `user_name` and `password`
@@ -14,7 +14,7 @@ use tokio::io::AsyncWrite;
 /// according to this agreement.
 /// See the section 4.12 (Enhanced Authentication) of the MQTT 5 specifications
 /// for examples.
-#[derive(Debug, PartialEq, Clone, Default)]
+#[derive(PartialEq, Eq, Hash, Clone, Default)]
 pub struct Authentication {
     /// Specifies the authentication method, such as "SCRAM-SHA-1" or "GS2-KRB5".
     /// The actual support for a given authentication method is up to the server.
@@ -28,6 +28,25 @@ pub struct Authentication {
     pub data: Vec<u8>,
 }
 
+/// Placeholder printed instead of sensitive data such as passwords or
+/// authentication data in `Debug` outputs.
+pub(crate) struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<redacted>")
+    }
+}
+
+impl fmt::Debug for Authentication {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Authentication")
+            .field("method", &self.method)
+            .field("data", &Redacted)
+            .finish()
+    }
+}
+
 impl Authentication {
     ///Write authentication data into `writer`, returning the written size
     /// in case of success.
@@ -72,4 +91,16 @@ mod unit {
             vec![21, 0, 6, 87, 105, 108, 108, 111, 119, 22, 0, 4, 13, 21, 234, 94]
         );
     }
+
+    #[test]
+    fn debug_redacts_data() {
+        let test_data = Authentication {
+            method: "Willow".into(),
+            data: vec![0x0D, 0x15, 0xEA, 0x5E],
+        };
+        assert_eq!(
+            format!("{:?}", test_data),
+            r#"Authentication { method: "Willow", data: <redacted> }"#
+        );
+    }
 }
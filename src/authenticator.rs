@@ -0,0 +1,41 @@
+#[cfg(feature = "scram")]
+use crate::ScramCredentials;
+use crate::{Connect, ReasonCode::NotAuthorized, Result as SageResult};
+#[cfg(feature = "scram")]
+use std::collections::HashMap;
+
+/// Authenticates the clients of a broker, such as with their user name and
+/// password, or with the credentials of an enhanced authentication method.
+///
+/// Every method refuses the clients by default, so that an implementation
+/// only provides the methods it supports.
+pub trait Authenticator {
+    /// Checks the credentials of `connect`, for clients which do not use
+    /// enhanced authentication.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason code to refuse the connection with, such as
+    /// `BadUserNameOrPassword`. The default implementation returns
+    /// `NotAuthorized`.
+    fn check(&self, connect: &Connect) -> SageResult<()> {
+        let _ = connect;
+        Err(NotAuthorized.into())
+    }
+
+    /// Returns the salted verifiers of `user_name` to authenticate it with
+    /// `SCRAM-SHA-256`, if any. The default implementation returns `None`.
+    #[cfg(feature = "scram")]
+    fn scram_credentials(&self, user_name: &str) -> Option<ScramCredentials> {
+        let _ = user_name;
+        None
+    }
+}
+
+/// The SCRAM credentials of users, indexed by their user name.
+#[cfg(feature = "scram")]
+impl Authenticator for HashMap<String, ScramCredentials> {
+    fn scram_credentials(&self, user_name: &str) -> Option<ScramCredentials> {
+        self.get(user_name).cloned()
+    }
+}
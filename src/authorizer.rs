@@ -0,0 +1,180 @@
+use crate::{
+    PacketType, QoS,
+    ReasonCode::{self, GrantedQoS1, GrantedQoS2, Success, UnspecifiedError},
+    Result as SageResult, SubAck, Subscribe, TopicFilter, TopicName,
+};
+use std::future::Future;
+
+/// Authorizes the clients of a broker to publish and subscribe, such as with
+/// access control lists or by asking a policy service.
+///
+/// The client is identified by its client id and the user name it was
+/// authenticated as, if any.
+///
+/// The topic of a `Publish` packet is known once its topic alias is resolved
+/// with `ServerConnection::resolve_topic`, so that it can be authorized
+/// before the packet is handed to `ServerConnection::handle_packet`. A
+/// refused message is then answered with `ServerConnection::refuse`, or the
+/// client disconnected with `NotAuthorized`.
+pub trait Authorizer {
+    /// Decides whether the client may publish a message to `topic` with `qos`
+    /// and `retain`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason code to refuse the message with, such as
+    /// `NotAuthorized`.
+    fn authorize_publish(
+        &self,
+        client_id: &str,
+        user_name: Option<&str>,
+        topic: &TopicName,
+        qos: QoS,
+        retain: bool,
+    ) -> impl Future<Output = SageResult<()>> + Send;
+
+    /// Decides whether the client may subscribe to `filter` with `qos`,
+    /// returning the maximum quality of service granted, which may be lower
+    /// than `qos`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason code to refuse the subscription with, such as
+    /// `NotAuthorized`.
+    fn authorize_subscribe(
+        &self,
+        client_id: &str,
+        user_name: Option<&str>,
+        filter: &TopicFilter,
+        qos: QoS,
+    ) -> impl Future<Output = SageResult<QoS>> + Send;
+
+    /// Authorizes each topic filter of `subscribe`, returning the `SubAck`
+    /// packet to answer it with. Each refused filter is given the reason code
+    /// of its refusal, or `UnspecifiedError` if that reason code is not
+    /// allowed in a `SubAck` packet. The others are granted the lowest of the
+    /// requested and authorized qualities of service.
+    fn suback(
+        &self,
+        client_id: &str,
+        user_name: Option<&str>,
+        subscribe: &Subscribe,
+    ) -> impl Future<Output = SubAck> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let mut reason_codes = Vec::with_capacity(subscribe.subscriptions.len());
+            for (filter, options) in &subscribe.subscriptions {
+                let reason_code = match self
+                    .authorize_subscribe(client_id, user_name, filter, options.qos)
+                    .await
+                {
+                    Ok(qos) => match qos.min(options.qos) {
+                        QoS::AtMostOnce => Success,
+                        QoS::AtLeastOnce => GrantedQoS1,
+                        QoS::ExactlyOnce => GrantedQoS2,
+                    },
+                    Err(e) => match ReasonCode::from(e) {
+                        reason_code if reason_code.allowed_in(PacketType::SubAck) => reason_code,
+                        _ => UnspecifiedError,
+                    },
+                };
+                reason_codes.push(reason_code);
+            }
+            SubAck {
+                packet_identifier: subscribe.packet_identifier,
+                reason_codes,
+                ..Default::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{
+        ReasonCode::{BadUserNameOrPassword, NotAuthorized},
+        SubscriptionOptions,
+    };
+
+    // Lets every user subscribe to `sensors/#` up to `AtLeastOnce`, and only
+    // publish to its own topic.
+    struct Sensors;
+
+    impl Authorizer for Sensors {
+        async fn authorize_publish(
+            &self,
+            _: &str,
+            user_name: Option<&str>,
+            topic: &TopicName,
+            _: QoS,
+            retain: bool,
+        ) -> SageResult<()> {
+            match user_name {
+                Some(user_name)
+                    if !retain && topic.as_str() == format!("sensors/{}", user_name) =>
+                {
+                    Ok(())
+                }
+                _ => Err(NotAuthorized.into()),
+            }
+        }
+
+        async fn authorize_subscribe(
+            &self,
+            _: &str,
+            user_name: Option<&str>,
+            filter: &TopicFilter,
+            _: QoS,
+        ) -> SageResult<QoS> {
+            match (user_name, filter.to_string().as_str()) {
+                (None, _) => Err(BadUserNameOrPassword.into()),
+                (_, "sensors/#") => Ok(QoS::AtLeastOnce),
+                _ => Err(NotAuthorized.into()),
+            }
+        }
+    }
+
+    fn subscription(filter: &str, qos: QoS) -> (TopicFilter, SubscriptionOptions) {
+        let options = SubscriptionOptions {
+            qos,
+            ..Default::default()
+        };
+        (filter.try_into().unwrap(), options)
+    }
+
+    #[tokio::test]
+    async fn authorize() {
+        let topic = "sensors/thomas".try_into().unwrap();
+        assert!(Sensors
+            .authorize_publish("daft", Some("thomas"), &topic, QoS::AtLeastOnce, false)
+            .await
+            .is_ok());
+        assert!(Sensors
+            .authorize_publish("punk", Some("guy"), &topic, QoS::AtLeastOnce, false)
+            .await
+            .is_err());
+
+        let subscribe = Subscribe {
+            packet_identifier: 7,
+            subscriptions: vec![
+                subscription("sensors/#", QoS::ExactlyOnce),
+                subscription("sensors/#", QoS::AtMostOnce),
+                subscription("actuators/#", QoS::AtMostOnce),
+            ],
+            ..Default::default()
+        };
+        let suback = Sensors.suback("daft", Some("thomas"), &subscribe).await;
+        assert_eq!(suback.packet_identifier, 7);
+        assert_eq!(
+            suback.reason_codes,
+            vec![GrantedQoS1, Success, NotAuthorized]
+        );
+
+        let suback = Sensors.suback("daft", None, &subscribe).await;
+        assert_eq!(suback.reason_codes, vec![UnspecifiedError; 3]);
+    }
+}
@@ -0,0 +1,549 @@
+use crate::{
+    ClientConnection, ClientEvent, Connect, Disconnect, Packet, Publish, QoS,
+    ReasonCode::ProtocolError, Result as SageResult, Subscribe, SubscriptionOptions, TopicFilter,
+    TopicName,
+};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// The direction messages are forwarded in by a `BridgeRule`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum BridgeDirection {
+    /// The local messages are published to the remote broker.
+    Out,
+
+    /// The remote messages are subscribed to and published locally.
+    In,
+
+    /// Both.
+    Both,
+}
+
+/// Selects the topics a `Bridge` forwards, and how their names are mapped
+/// between the local and the remote broker.
+///
+/// A topic matches the rule if it is made of the prefix of its side followed
+/// by a topic matching `pattern`. The prefix is then replaced by the one of
+/// the other side, such that `site/sensors/temperature` is forwarded out as
+/// `factories/lyon/sensors/temperature` by a rule with the `sensors/#`
+/// pattern, the `site/` local prefix and the `factories/lyon/` remote one.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct BridgeRule {
+    direction: BridgeDirection,
+    pattern: TopicFilter,
+    local_prefix: String,
+    remote_prefix: String,
+    maximum_qos: QoS,
+}
+
+impl BridgeRule {
+    /// Creates a rule forwarding the topics matching `pattern` in
+    /// `direction`, without prefix and up to `ExactlyOnce`.
+    pub fn new(direction: BridgeDirection, pattern: TopicFilter) -> Self {
+        BridgeRule {
+            direction,
+            pattern,
+            local_prefix: String::new(),
+            remote_prefix: String::new(),
+            maximum_qos: QoS::ExactlyOnce,
+        }
+    }
+
+    /// Sets the prefix of the matching topics on the local broker.
+    pub fn with_local_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.local_prefix = prefix.into();
+        self
+    }
+
+    /// Sets the prefix of the matching topics on the remote broker.
+    pub fn with_remote_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.remote_prefix = prefix.into();
+        self
+    }
+
+    /// Caps the quality of service of the forwarded messages to
+    /// `maximum_qos`, in both directions.
+    pub fn with_maximum_qos(mut self, maximum_qos: QoS) -> Self {
+        self.maximum_qos = maximum_qos;
+        self
+    }
+
+    /// The direction of the rule.
+    pub fn direction(&self) -> BridgeDirection {
+        self.direction
+    }
+
+    fn map(&self, topic: &TopicName, from: &str, to: &str) -> Option<TopicName> {
+        let rest = topic.as_str().strip_prefix(from)?;
+        if !self.pattern.matches(&TopicName::try_from(rest).ok()?) {
+            return None;
+        }
+        TopicName::try_from(format!("{}{}", to, rest)).ok()
+    }
+
+    /// Returns the remote name of the local `topic`, if it is forwarded out.
+    pub fn outgoing(&self, topic: &TopicName) -> Option<TopicName> {
+        match self.direction {
+            BridgeDirection::In => None,
+            _ => self.map(topic, &self.local_prefix, &self.remote_prefix),
+        }
+    }
+
+    /// Returns the local name of the remote `topic`, if it is forwarded in.
+    pub fn incoming(&self, topic: &TopicName) -> Option<TopicName> {
+        match self.direction {
+            BridgeDirection::Out => None,
+            _ => self.map(topic, &self.remote_prefix, &self.local_prefix),
+        }
+    }
+
+    /// Returns the topic filter to subscribe to on the remote broker.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TopicFilterInvalid` if the remote prefix makes the filter
+    /// invalid, such as with a multi-level wildcard.
+    pub fn remote_filter(&self) -> SageResult<TopicFilter> {
+        TopicFilter::try_from(format!("{}{}", self.remote_prefix, self.pattern))
+    }
+}
+
+/// An event of a `Bridge` to be handled by the embedding broker.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BridgeEvent {
+    /// A network connection to the remote broker must be opened, and
+    /// `Bridge::transport_opened` or `Bridge::transport_closed` called
+    /// depending on the outcome.
+    Connect,
+
+    /// The remote broker accepted the connection.
+    Connected,
+
+    /// A message of the remote broker to publish locally, with its local
+    /// topic name.
+    Message(Publish),
+
+    /// The connection to the remote broker is closed, and its network
+    /// connection must be closed too. A new one is requested with `Connect`
+    /// once the reconnection delay elapses.
+    Disconnected(Disconnect),
+}
+
+/// A bridge between the local broker and a remote one, to which it connects
+/// as a client, without any I/O.
+///
+/// The local messages given to `forward` are published to the remote broker
+/// if they match a `BridgeRule` going out, and the messages of the remote
+/// broker matching the rules going in are subscribed to and returned as
+/// `BridgeEvent::Message` events, with their topics mapped and their quality
+/// of service capped by the rules. The remote subscriptions are made with
+/// `no_local`, so that the messages forwarded out do not come back, but the
+/// broker must not forward out the messages it received from the bridge
+/// either, to avoid loops.
+///
+/// The bridge reconnects automatically whenever the connection is refused
+/// or lost, after a delay doubling from the minimum to the maximum one upon
+/// each failed attempt. Messages forwarded while disconnected are dropped.
+#[derive(Debug)]
+pub struct Bridge {
+    connect: Connect,
+    rules: Vec<BridgeRule>,
+    subscribe: Subscribe,
+    connection: Option<ClientConnection>,
+    events: VecDeque<BridgeEvent>,
+    minimum_delay: Duration,
+    maximum_delay: Duration,
+    delay: Duration,
+    reconnect_at: Option<Instant>,
+}
+
+impl Bridge {
+    /// Creates a bridge connecting to the remote broker with `connect` and
+    /// forwarding messages according to `rules`. The reconnection delay goes
+    /// from one second to one minute.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TopicFilterInvalid` if the remote filter of a rule is
+    /// invalid.
+    pub fn new(connect: Connect, rules: Vec<BridgeRule>) -> SageResult<Self> {
+        let mut subscriptions = Vec::new();
+        for rule in &rules {
+            if rule.direction != BridgeDirection::Out {
+                let options = SubscriptionOptions {
+                    qos: rule.maximum_qos,
+                    no_local: true,
+                    retain_as_published: true,
+                    ..Default::default()
+                };
+                subscriptions.push((rule.remote_filter()?, options));
+            }
+        }
+        let minimum_delay = Duration::from_secs(1);
+        Ok(Bridge {
+            connect,
+            rules,
+            subscribe: Subscribe {
+                subscriptions,
+                ..Default::default()
+            },
+            connection: None,
+            events: VecDeque::new(),
+            minimum_delay,
+            maximum_delay: Duration::from_secs(60),
+            delay: minimum_delay,
+            reconnect_at: None,
+        })
+    }
+
+    /// Sets the minimum and maximum delays before reconnecting.
+    pub fn with_reconnect_delays(mut self, minimum: Duration, maximum: Duration) -> Self {
+        self.minimum_delay = minimum;
+        self.maximum_delay = maximum.max(minimum);
+        self.delay = minimum;
+        self
+    }
+
+    /// Returns `true` once the remote broker accepted the connection, until
+    /// it is closed.
+    pub fn is_connected(&self) -> bool {
+        self.connection
+            .as_ref()
+            .is_some_and(ClientConnection::is_connected)
+    }
+
+    /// Starts the bridge, requesting a network connection with a
+    /// `BridgeEvent::Connect` event.
+    pub fn start(&mut self) {
+        self.reconnect_at = None;
+        self.events.push_back(BridgeEvent::Connect);
+    }
+
+    /// Starts the connection once the network connection is opened.
+    pub fn transport_opened(&mut self, now: Instant) -> SageResult<()> {
+        let mut connection = ClientConnection::new(self.connect.clone());
+        connection.connect(now)?;
+        self.connection = Some(connection);
+        Ok(())
+    }
+
+    /// Handles the network connection failing to open or being closed,
+    /// scheduling a reconnection unless one already is because the connection
+    /// was closed first.
+    pub fn transport_closed(&mut self, now: Instant) {
+        match self.connection.take() {
+            Some(connection) if connection.is_closed() => (),
+            Some(_) => {
+                self.events
+                    .push_back(BridgeEvent::Disconnected(Default::default()));
+                self.schedule_reconnect(now);
+            }
+            None => self.schedule_reconnect(now),
+        }
+    }
+
+    fn schedule_reconnect(&mut self, now: Instant) {
+        self.reconnect_at = now.checked_add(self.delay);
+        self.delay = self.delay.saturating_mul(2).min(self.maximum_delay);
+    }
+
+    /// Publishes a local message to the remote broker, if it matches a rule
+    /// going out. Returns `true` if it was forwarded.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `ClientConnection::publish`.
+    pub fn forward(&mut self, publish: &Publish, now: Instant) -> SageResult<bool> {
+        let connection = match &mut self.connection {
+            Some(connection) if connection.is_connected() => connection,
+            _ => return Ok(false),
+        };
+        let (topic_name, maximum_qos) = match self
+            .rules
+            .iter()
+            .find_map(|rule| Some((rule.outgoing(&publish.topic_name)?, rule.maximum_qos)))
+        {
+            Some(mapped) => mapped,
+            None => return Ok(false),
+        };
+        let publish = Publish {
+            topic_name,
+            qos: publish.qos.min(maximum_qos),
+            duplicate: false,
+            packet_identifier: None,
+            topic_alias: None,
+            subscription_identifiers: Vec::new(),
+            raw_properties: None,
+            ..publish.clone()
+        };
+        connection.publish(publish, now)?;
+        Ok(true)
+    }
+
+    /// Returns the next packet to send to the remote broker.
+    pub fn poll_transmit(&mut self) -> Option<Packet> {
+        self.connection.as_mut()?.poll_transmit()
+    }
+
+    /// Returns the next event to handle by the broker.
+    pub fn poll_event(&mut self) -> Option<BridgeEvent> {
+        self.events.pop_front()
+    }
+
+    /// Returns the next time `handle_timeout` must be called at, if any.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        match &self.connection {
+            Some(connection) if !connection.is_closed() => connection.poll_timeout(),
+            _ => self.reconnect_at,
+        }
+    }
+
+    /// Handles the passing of time: the keep alive of the connection and the
+    /// reconnection once its delay elapsed.
+    pub fn handle_timeout(&mut self, now: Instant) {
+        match &mut self.connection {
+            Some(connection) if !connection.is_closed() => {
+                connection.handle_timeout(now);
+                self.drain_events(now);
+            }
+            _ if self.reconnect_at.is_some_and(|at| at <= now) => self.start(),
+            _ => (),
+        }
+    }
+
+    /// Handles a packet received from the remote broker.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `ClientConnection::handle_packet`, after which
+    /// the connection is closed.
+    pub fn handle_packet(&mut self, packet: Packet, now: Instant) -> SageResult<()> {
+        let result = self
+            .connection
+            .as_mut()
+            .ok_or(ProtocolError)?
+            .handle_packet(packet, now);
+        self.drain_events(now);
+        result
+    }
+
+    fn drain_events(&mut self, now: Instant) {
+        while let Some(event) = self.connection.as_mut().and_then(|c| c.poll_event()) {
+            match event {
+                ClientEvent::Connected(_) => {
+                    self.delay = self.minimum_delay;
+                    if let Some(connection) = &mut self.connection {
+                        if !self.subscribe.subscriptions.is_empty() {
+                            let _ = connection.subscribe(self.subscribe.clone(), now);
+                        }
+                    }
+                    self.events.push_back(BridgeEvent::Connected);
+                }
+                ClientEvent::Message(publish) => {
+                    let mapped = self.rules.iter().find_map(|rule| {
+                        Some((rule.incoming(&publish.topic_name)?, rule.maximum_qos))
+                    });
+                    if let Some((topic_name, maximum_qos)) = mapped {
+                        let publish = Publish {
+                            topic_name,
+                            qos: publish.qos.min(maximum_qos),
+                            packet_identifier: None,
+                            topic_alias: None,
+                            subscription_identifiers: Vec::new(),
+                            ..publish
+                        };
+                        self.events.push_back(BridgeEvent::Message(publish));
+                    }
+                }
+                ClientEvent::ConnectionRefused(connack) => {
+                    let disconnect = Disconnect {
+                        reason_code: connack.reason_code,
+                        ..Default::default()
+                    };
+                    self.events.push_back(BridgeEvent::Disconnected(disconnect));
+                    self.schedule_reconnect(now);
+                }
+                ClientEvent::Disconnected(disconnect) => {
+                    self.events.push_back(BridgeEvent::Disconnected(disconnect));
+                    self.schedule_reconnect(now);
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{
+        ConnAck,
+        ReasonCode::{self, ServerUnavailable},
+        SubAck,
+    };
+
+    fn rule(direction: BridgeDirection, pattern: &str) -> BridgeRule {
+        BridgeRule::new(direction, pattern.try_into().unwrap())
+    }
+
+    fn topic(name: &str) -> TopicName {
+        name.try_into().unwrap()
+    }
+
+    #[test]
+    fn mapping() {
+        let rule = rule(BridgeDirection::Both, "sensors/#")
+            .with_local_prefix("site/")
+            .with_remote_prefix("factories/lyon/");
+        assert_eq!(
+            rule.outgoing(&topic("site/sensors/temperature")),
+            Some(topic("factories/lyon/sensors/temperature"))
+        );
+        assert_eq!(rule.outgoing(&topic("sensors/temperature")), None);
+        assert_eq!(rule.outgoing(&topic("site/actuators/valve")), None);
+        assert_eq!(
+            rule.incoming(&topic("factories/lyon/sensors/humidity")),
+            Some(topic("site/sensors/humidity"))
+        );
+        assert_eq!(
+            rule.remote_filter().unwrap().as_str(),
+            "factories/lyon/sensors/#"
+        );
+
+        let rule = rule.with_remote_prefix("lyon#/");
+        assert!(rule.remote_filter().is_err());
+        assert!(Bridge::new(Default::default(), vec![rule]).is_err());
+    }
+
+    fn connect(bridge: &mut Bridge, now: Instant) {
+        assert_eq!(bridge.poll_event(), Some(BridgeEvent::Connect));
+        bridge.transport_opened(now).unwrap();
+        assert!(matches!(bridge.poll_transmit(), Some(Packet::Connect(_))));
+        bridge
+            .handle_packet(ConnAck::default().into(), now)
+            .unwrap();
+        assert_eq!(bridge.poll_event(), Some(BridgeEvent::Connected));
+    }
+
+    #[test]
+    fn forward() {
+        let now = Instant::now();
+        let rules = vec![
+            rule(BridgeDirection::Out, "sensors/#")
+                .with_remote_prefix("lyon/")
+                .with_maximum_qos(QoS::AtLeastOnce),
+            rule(BridgeDirection::In, "commands/#").with_local_prefix("remote/"),
+        ];
+        let mut bridge = Bridge::new(Default::default(), rules).unwrap();
+        let publish = Publish {
+            qos: QoS::ExactlyOnce,
+            topic_name: topic("sensors/temperature"),
+            message: "21".into(),
+            ..Default::default()
+        };
+        assert!(!bridge.forward(&publish, now).unwrap());
+
+        bridge.start();
+        connect(&mut bridge, now);
+        let subscribe = match bridge.poll_transmit() {
+            Some(Packet::Subscribe(subscribe)) => subscribe,
+            packet => panic!("unexpected {:?}", packet),
+        };
+        assert_eq!(subscribe.subscriptions.len(), 1);
+        assert_eq!(subscribe.subscriptions[0].0.as_str(), "commands/#");
+        let suback = SubAck {
+            packet_identifier: subscribe.packet_identifier,
+            reason_codes: vec![ReasonCode::Success],
+            ..Default::default()
+        };
+        bridge.handle_packet(suback.into(), now).unwrap();
+
+        assert!(bridge.forward(&publish, now).unwrap());
+        match bridge.poll_transmit() {
+            Some(Packet::Publish(forwarded)) => {
+                assert_eq!(forwarded.topic_name, topic("lyon/sensors/temperature"));
+                assert_eq!(forwarded.qos, QoS::AtLeastOnce);
+            }
+            packet => panic!("unexpected {:?}", packet),
+        }
+        let unmatched = Publish {
+            topic_name: topic("actuators/valve"),
+            ..publish
+        };
+        assert!(!bridge.forward(&unmatched, now).unwrap());
+
+        let command = Publish {
+            topic_name: topic("commands/reboot"),
+            ..Default::default()
+        };
+        bridge.handle_packet(command.into(), now).unwrap();
+        match bridge.poll_event() {
+            Some(BridgeEvent::Message(publish)) => {
+                assert_eq!(publish.topic_name, topic("remote/commands/reboot"))
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+
+    #[test]
+    fn reconnect() {
+        let now = Instant::now();
+        let mut bridge = Bridge::new(Default::default(), Vec::new())
+            .unwrap()
+            .with_reconnect_delays(Duration::from_secs(1), Duration::from_secs(3));
+        bridge.start();
+        assert_eq!(bridge.poll_event(), Some(BridgeEvent::Connect));
+        bridge.transport_closed(now);
+        assert_eq!(bridge.poll_event(), None);
+        assert_eq!(bridge.poll_timeout(), Some(now + Duration::from_secs(1)));
+
+        bridge.handle_timeout(now + Duration::from_secs(1));
+        assert_eq!(bridge.poll_event(), Some(BridgeEvent::Connect));
+        let later = now + Duration::from_secs(1);
+        bridge.transport_opened(later).unwrap();
+        bridge.poll_transmit();
+        let refused = ConnAck {
+            reason_code: ServerUnavailable,
+            ..Default::default()
+        };
+        bridge.handle_packet(refused.into(), later).unwrap();
+        assert!(matches!(
+            bridge.poll_event(),
+            Some(BridgeEvent::Disconnected(disconnect)) if disconnect.reason_code == ServerUnavailable
+        ));
+        assert_eq!(bridge.poll_timeout(), Some(later + Duration::from_secs(2)));
+
+        let later = later + Duration::from_secs(2);
+        bridge.handle_timeout(later);
+        connect(&mut bridge, later);
+        assert!(bridge.is_connected());
+        bridge
+            .handle_packet(Disconnect::default().into(), later)
+            .unwrap();
+        assert!(matches!(
+            bridge.poll_event(),
+            Some(BridgeEvent::Disconnected(_))
+        ));
+        assert!(!bridge.is_connected());
+        assert_eq!(bridge.poll_timeout(), Some(later + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn unbounded_reconnect_delay() {
+        let now = Instant::now();
+        let mut bridge = Bridge::new(Default::default(), Vec::new())
+            .unwrap()
+            .with_reconnect_delays(Duration::from_secs(1), Duration::MAX);
+        bridge.transport_closed(now);
+        assert_eq!(bridge.poll_timeout(), Some(now + Duration::from_secs(1)));
+        bridge.transport_closed(now);
+        assert_eq!(bridge.poll_timeout(), Some(now + Duration::from_secs(2)));
+        for _ in 0..100 {
+            bridge.transport_closed(now);
+        }
+        assert_eq!(bridge.delay, Duration::MAX);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{
+    defaults::{
+        DEFAULT_MAXIMUM_QOS, DEFAULT_RETAIN_AVAILABLE, DEFAULT_SHARED_SUBSCRIPTION_AVAILABLE,
+        DEFAULT_SUBSCRIPTION_IDENTIFIER_AVAILABLE, DEFAULT_WILCARD_SUBSCRIPTION_AVAILABLE,
+    },
+    ConnAck, ConnAckBuilder, QoS,
+};
+
+/// The features of a broker which operators can turn off, advertised to the
+/// clients in the `ConnAck` packet.
+///
+/// `ServerConnection` enforces the capabilities advertised in the `ConnAck`
+/// packet it sends: a client using a feature which is not available is
+/// disconnected with the matching reason code, such as
+/// `WildcardSubscriptionsNotSupported`, and a `Connect` packet whose will
+/// message exceeds them is refused with `QoSNotSupported` or
+/// `RetainNotSupported`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Capabilities {
+    /// The maximum quality of service of the messages published by the
+    /// clients.
+    pub maximum_qos: QoS,
+
+    /// Whether the clients may publish retained messages.
+    pub retain_available: bool,
+
+    /// Whether the clients may subscribe to topic filters with wildcards.
+    pub wildcard_subscription_available: bool,
+
+    /// Whether the clients may set subscription identifiers.
+    pub subscription_identifiers_available: bool,
+
+    /// Whether the clients may make shared subscriptions.
+    pub shared_subscription_available: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            maximum_qos: DEFAULT_MAXIMUM_QOS,
+            retain_available: DEFAULT_RETAIN_AVAILABLE,
+            wildcard_subscription_available: DEFAULT_WILCARD_SUBSCRIPTION_AVAILABLE,
+            subscription_identifiers_available: DEFAULT_SUBSCRIPTION_IDENTIFIER_AVAILABLE,
+            shared_subscription_available: DEFAULT_SHARED_SUBSCRIPTION_AVAILABLE,
+        }
+    }
+}
+
+impl From<&ConnAck> for Capabilities {
+    fn from(connack: &ConnAck) -> Self {
+        Capabilities {
+            maximum_qos: connack.maximum_qos,
+            retain_available: connack.retain_available,
+            wildcard_subscription_available: connack.wildcard_subscription_available,
+            subscription_identifiers_available: connack.subscription_identifiers_available,
+            shared_subscription_available: connack.shared_subscription_available,
+        }
+    }
+}
+
+impl ConnAckBuilder {
+    /// Advertises `capabilities`.
+    pub fn capabilities(self, capabilities: Capabilities) -> Self {
+        self.maximum_qos(capabilities.maximum_qos)
+            .retain_available(capabilities.retain_available)
+            .wildcard_subscription_available(capabilities.wildcard_subscription_available)
+            .subscription_identifiers_available(capabilities.subscription_identifiers_available)
+            .shared_subscription_available(capabilities.shared_subscription_available)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn advertise() {
+        assert_eq!(
+            Capabilities::from(&ConnAck::default()),
+            Capabilities::default()
+        );
+        let capabilities = Capabilities {
+            maximum_qos: QoS::AtLeastOnce,
+            retain_available: false,
+            wildcard_subscription_available: false,
+            subscription_identifiers_available: false,
+            shared_subscription_available: false,
+        };
+        let connack = ConnAck::builder().capabilities(capabilities).build();
+        assert_eq!(Capabilities::from(&connack), capabilities);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::{
+    Connect, Error as SageError, PeerInfo, ReasonCode::NotAuthorized, Result as SageResult,
+};
+use std::{
+    io::{Error as IOError, ErrorKind},
+    str::FromStr,
+};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+/// The identity proven by a client certificate: its subject common name and
+/// its subject alternative names.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct CertificateIdentity {
+    /// The common name (CN) of the subject, if any.
+    pub common_name: Option<String>,
+
+    /// The DNS names of the subject alternative name extension.
+    pub dns_names: Vec<String>,
+
+    /// The email addresses of the subject alternative name extension.
+    pub emails: Vec<String>,
+
+    /// The URIs of the subject alternative name extension, such as SPIFFE
+    /// ids.
+    pub uris: Vec<String>,
+}
+
+impl CertificateIdentity {
+    /// Extracts the identity of the DER encoded certificate `der`, such as
+    /// the first one of `PeerInfo::certificates`.
+    ///
+    /// The certificate is not verified: the TLS listener does it against its
+    /// certificate authorities when it requests the client certificates.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `std::io::Error` of kind `InvalidData` if `der` is not a
+    /// valid certificate.
+    pub fn from_der(der: &[u8]) -> SageResult<Self> {
+        let invalid_data = |e: String| SageError::from(IOError::new(ErrorKind::InvalidData, e));
+        let (_, certificate) =
+            X509Certificate::from_der(der).map_err(|e| invalid_data(e.to_string()))?;
+        let mut identity = CertificateIdentity {
+            common_name: certificate
+                .subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(Into::into),
+            ..Default::default()
+        };
+        if let Some(names) = certificate
+            .subject_alternative_name()
+            .map_err(|e| invalid_data(e.to_string()))?
+        {
+            for name in &names.value.general_names {
+                match name {
+                    GeneralName::DNSName(name) => identity.dns_names.push(name.to_string()),
+                    GeneralName::RFC822Name(email) => identity.emails.push(email.to_string()),
+                    GeneralName::URI(uri) => identity.uris.push(uri.to_string()),
+                    _ => (),
+                }
+            }
+        }
+        Ok(identity)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Part {
+    Literal(String),
+    CommonName,
+    DnsName,
+    Email,
+    Uri,
+}
+
+/// Maps the identity of a client certificate to an MQTT user name, for the
+/// listeners requiring client certificates.
+///
+/// The mapping is a template where `{cn}` is replaced by the common name of
+/// the certificate, and `{dns}`, `{email}` and `{uri}` by its first subject
+/// alternative name of that kind, such as `device-{cn}`. The default template
+/// is `{cn}`.
+///
+/// By default, the mapped user name replaces the one of the `Connect` packet.
+/// With `with_reject_mismatch`, a `Connect` packet with another user name is
+/// refused instead.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CertificateMapping {
+    parts: Vec<Part>,
+    reject_mismatch: bool,
+}
+
+impl Default for CertificateMapping {
+    fn default() -> Self {
+        CertificateMapping {
+            parts: vec![Part::CommonName],
+            reject_mismatch: false,
+        }
+    }
+}
+
+impl FromStr for CertificateMapping {
+    type Err = SageError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let invalid_input =
+            || SageError::from(IOError::new(ErrorKind::InvalidInput, template.to_string()));
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                Some(0) => {
+                    let (placeholder, tail) =
+                        rest[1..].split_once('}').ok_or_else(invalid_input)?;
+                    parts.push(match placeholder {
+                        "cn" => Part::CommonName,
+                        "dns" => Part::DnsName,
+                        "email" => Part::Email,
+                        "uri" => Part::Uri,
+                        _ => return Err(invalid_input()),
+                    });
+                    rest = tail;
+                }
+                Some(index) if rest[index..].starts_with('{') => {
+                    parts.push(Part::Literal(rest[..index].into()));
+                    rest = &rest[index..];
+                }
+                Some(_) => return Err(invalid_input()),
+                None => {
+                    parts.push(Part::Literal(rest.into()));
+                    rest = "";
+                }
+            }
+        }
+        if parts.is_empty() {
+            return Err(invalid_input());
+        }
+        Ok(CertificateMapping {
+            parts,
+            reject_mismatch: false,
+        })
+    }
+}
+
+impl CertificateMapping {
+    /// Refuses the `Connect` packets whose user name differs from the mapped
+    /// one, instead of replacing it.
+    pub fn with_reject_mismatch(self) -> Self {
+        CertificateMapping {
+            reject_mismatch: true,
+            ..self
+        }
+    }
+
+    /// Returns the user name `identity` maps to, or `None` if it lacks any of
+    /// the names of the template.
+    pub fn user_name(&self, identity: &CertificateIdentity) -> Option<String> {
+        let mut user_name = String::new();
+        for part in &self.parts {
+            let value = match part {
+                Part::Literal(literal) => Some(literal),
+                Part::CommonName => identity.common_name.as_ref(),
+                Part::DnsName => identity.dns_names.first(),
+                Part::Email => identity.emails.first(),
+                Part::Uri => identity.uris.first(),
+            };
+            user_name.push_str(value?);
+        }
+        Some(user_name)
+    }
+
+    /// Maps the certificate presented by `peer` to the user name of
+    /// `connect`, returning the user name.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotAuthorized` if `peer` presented no valid certificate, if
+    /// the certificate lacks any of the names of the template, or if the user
+    /// name of `connect` differs from the mapped one when mismatches are
+    /// rejected.
+    pub fn apply(&self, connect: &mut Connect, peer: &PeerInfo) -> SageResult<String> {
+        let certificate = peer.certificates.first().ok_or(NotAuthorized)?;
+        let identity = CertificateIdentity::from_der(certificate).map_err(|_| NotAuthorized)?;
+        let user_name = self.user_name(&identity).ok_or(NotAuthorized)?;
+        if self.reject_mismatch && matches!(&connect.user_name, Some(name) if *name != user_name) {
+            return Err(NotAuthorized.into());
+        }
+        connect.user_name = Some(user_name.clone());
+        Ok(user_name)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use x509_parser::pem::parse_x509_pem;
+
+    // A self-signed certificate for `CN=thomas`, with the alternative names
+    // `thomas.example.com`, `thomas@example.com` and
+    // `spiffe://example.com/thomas`.
+    const CERTIFICATE: &[u8] = b"-----BEGIN CERTIFICATE-----
+MIIB8zCCAZmgAwIBAgIUWqHMZudw1GkiSp1ZCGkpZNfxawIwCgYIKoZIzj0EAwIw
+JTESMBAGA1UECgwJRGFmdCBQdW5rMQ8wDQYDVQQDDAZ0aG9tYXMwIBcNMjYxMDE2
+MDc0MzE4WhgPMjEyNjA5MjIwNzQzMThaMCUxEjAQBgNVBAoMCURhZnQgUHVuazEP
+MA0GA1UEAwwGdGhvbWFzMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEyTRDIO+F
+dk1/b1ezeu8cd4WzYz6nMb2uEiW5zKlGgU7TQykcC95rL/WSiPNVvNkVvOP01GP6
+OE/jOXdW7EIkEqOBpDCBoTAdBgNVHQ4EFgQUGODCuXjYllfEUPGM/Kkr5+E4xEQw
+HwYDVR0jBBgwFoAUGODCuXjYllfEUPGM/Kkr5+E4xEQwDwYDVR0TAQH/BAUwAwEB
+/zBOBgNVHREERzBFghJ0aG9tYXMuZXhhbXBsZS5jb22BEnRob21hc0BleGFtcGxl
+LmNvbYYbc3BpZmZlOi8vZXhhbXBsZS5jb20vdGhvbWFzMAoGCCqGSM49BAMCA0gA
+MEUCIQDiKfHMEZdUUXrzCW58wDpJfNUhkt8prCq8fD9TFeDibgIgRvr8FxDtAwhQ
+253FBLvsppInoBM9L/lfZxsrDL5WK2E=
+-----END CERTIFICATE-----
+";
+
+    fn peer() -> PeerInfo {
+        let (_, pem) = parse_x509_pem(CERTIFICATE).unwrap();
+        PeerInfo {
+            certificates: vec![pem.contents],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn identity() {
+        let identity = CertificateIdentity::from_der(&peer().certificates[0]).unwrap();
+        assert_eq!(identity.common_name.as_deref(), Some("thomas"));
+        assert_eq!(identity.dns_names, vec!["thomas.example.com"]);
+        assert_eq!(identity.emails, vec!["thomas@example.com"]);
+        assert_eq!(identity.uris, vec!["spiffe://example.com/thomas"]);
+        assert!(CertificateIdentity::from_der(&[0x30]).is_err());
+
+        for template in ["", "{cn", "cn}", "{o}", "{}"] {
+            assert!(template.parse::<CertificateMapping>().is_err());
+        }
+        let mapping: CertificateMapping = "device-{cn}@{dns}".parse().unwrap();
+        assert_eq!(
+            mapping.user_name(&identity).as_deref(),
+            Some("device-thomas@thomas.example.com")
+        );
+        assert_eq!(mapping.user_name(&Default::default()), None);
+    }
+
+    #[test]
+    fn apply() {
+        let mapping = CertificateMapping::default();
+        let mut connect = Connect {
+            user_name: Some("guy".into()),
+            ..Default::default()
+        };
+        assert_eq!(mapping.apply(&mut connect, &peer()).unwrap(), "thomas");
+        assert_eq!(connect.user_name.as_deref(), Some("thomas"));
+        assert!(matches!(
+            mapping.apply(&mut connect, &Default::default()),
+            Err(SageError::Reason(NotAuthorized))
+        ));
+
+        let mapping: CertificateMapping = "{email}".parse().unwrap();
+        let mapping = mapping.with_reject_mismatch();
+        assert!(mapping.apply(&mut connect, &peer()).is_err());
+        connect.user_name = None;
+        assert_eq!(
+            mapping.apply(&mut connect, &peer()).unwrap(),
+            "thomas@example.com"
+        );
+        assert!(mapping.apply(&mut connect, &peer()).is_ok());
+    }
+}
@@ -0,0 +1,613 @@
+use crate::{
+    deliveries::{Delivered, Deliveries},
+    AuthFlow, AuthStep, ConnAck, Connect, Disconnect, KeepAlive, Packet, Publish,
+    ReasonCode::{self, KeepAliveTimeout, ProtocolError},
+    Result as SageResult, SubAck, Subscribe, UnSubAck, UnSubscribe,
+};
+use std::{collections::VecDeque, time::Instant};
+
+/// An event of a `ClientConnection` to be handled by the application.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ClientEvent {
+    /// The server accepted the connection.
+    Connected(ConnAck),
+
+    /// The server refused the connection. The connection is closed.
+    ConnectionRefused(ConnAck),
+
+    /// The server sent authentication data, which must be answered using
+    /// `ClientConnection::authenticate`.
+    AuthChallenge(Vec<u8>),
+
+    /// A re-authentication succeeded.
+    Reauthenticated,
+
+    /// A message was received. `ExactlyOnce` messages are only delivered
+    /// once, even if the server sends them again.
+    Message(Publish),
+
+    /// The delivery of an outgoing message ended, with the reason code given
+    /// by the server.
+    Acknowledged {
+        /// The packet identifier of the delivered `Publish` packet.
+        packet_identifier: u16,
+
+        /// The reason code of the acknowledgement.
+        reason_code: ReasonCode,
+    },
+
+    /// The server acknowledged a subscription.
+    SubAck(SubAck),
+
+    /// The server acknowledged an unsubscription.
+    UnSubAck(UnSubAck),
+
+    /// The connection is closed, either by the server, by the client upon a
+    /// protocol violation or a keep alive timeout, or by `disconnect`. The
+    /// `Disconnect` packet is the one sent by either side.
+    Disconnected(Disconnect),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Initial,
+    Connecting,
+    Connected,
+    Closed,
+}
+
+/// The client side of an MQTT connection, without any I/O.
+///
+/// `ClientConnection` consumes the packets decoded from the network with
+/// `handle_packet` and the passing of time with `handle_timeout`. In return
+/// it produces the packets to send with `poll_transmit` and the events of the
+/// application with `poll_event`. The current time is always given by the
+/// caller, so that the connection can be bound to any transport or runtime.
+///
+/// The connection enforces the ordering of the protocol: nothing but the
+/// authentication exchange can happen before the `ConnAck` packet is
+/// received, and any packet which is not legal closes the connection with
+/// `ProtocolError`. It handles acknowledgements, retransmissions upon session
+/// resumption, the Receive Maximum and topic aliases of the server, and keep
+/// alive pings.
+#[derive(Debug)]
+pub struct ClientConnection {
+    state: State,
+    connect: Connect,
+    auth: Option<AuthFlow>,
+    // Created when connecting.
+    keep_alive: Option<KeepAlive>,
+    deliveries: Deliveries,
+    transmit: VecDeque<Packet>,
+    events: VecDeque<ClientEvent>,
+}
+
+impl ClientConnection {
+    /// Creates a connection which will connect using `connect`. If `connect`
+    /// has an authentication, the enhanced authentication is used.
+    pub fn new(connect: Connect) -> Self {
+        let auth = connect
+            .authentication
+            .as_ref()
+            .map(|authentication| AuthFlow::client(authentication.method.clone()));
+        ClientConnection {
+            state: State::Initial,
+            auth,
+            keep_alive: None,
+            deliveries: Default::default(),
+            transmit: VecDeque::new(),
+            events: VecDeque::new(),
+            connect,
+        }
+    }
+
+    /// Returns `true` once the server accepted the connection, until it is
+    /// closed.
+    pub fn is_connected(&self) -> bool {
+        self.state == State::Connected
+    }
+
+    /// Returns `true` if the connection is closed.
+    pub fn is_closed(&self) -> bool {
+        self.state == State::Closed
+    }
+
+    /// Returns the next packet to send to the server.
+    pub fn poll_transmit(&mut self) -> Option<Packet> {
+        self.transmit.pop_front()
+    }
+
+    /// Returns the next event to handle by the application.
+    pub fn poll_event(&mut self) -> Option<ClientEvent> {
+        self.events.pop_front()
+    }
+
+    /// Returns the next time `handle_timeout` must be called at, if any.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        if self.state != State::Connected {
+            return None;
+        }
+        let keep_alive = self.keep_alive.as_ref()?;
+        match (keep_alive.ping_deadline(), keep_alive.timeout_deadline()) {
+            (Some(ping), Some(timeout)) => Some(ping.min(timeout)),
+            (ping, timeout) => ping.or(timeout),
+        }
+    }
+
+    fn send<P: Into<Packet>>(&mut self, packet: P, now: Instant) {
+        if let Some(keep_alive) = &mut self.keep_alive {
+            keep_alive.sent(now);
+        }
+        self.transmit.push_back(packet.into());
+    }
+
+    fn close(&mut self, reason_code: ReasonCode, now: Instant) {
+        let disconnect = Disconnect {
+            reason_code,
+            ..Default::default()
+        };
+        self.send(disconnect.clone(), now);
+        self.state = State::Closed;
+        self.events.push_back(ClientEvent::Disconnected(disconnect));
+    }
+
+    /// Starts the connection, sending the `Connect` packet. The
+    /// authentication data of the packet are the initial data of the enhanced
+    /// authentication.
+    pub fn connect(&mut self, now: Instant) -> SageResult<()> {
+        if self.state != State::Initial {
+            return Err(ProtocolError.into());
+        }
+        let packet = match (&mut self.auth, &self.connect.authentication) {
+            (Some(auth), Some(authentication)) => {
+                auth.start(self.connect.clone(), authentication.data.clone())?
+            }
+            _ => self.connect.clone().into(),
+        };
+        self.keep_alive = Some(KeepAlive::new(self.connect.keep_alive, now));
+        self.state = State::Connecting;
+        self.send(packet, now);
+        Ok(())
+    }
+
+    /// Answers the last `ClientEvent::AuthChallenge` of the server.
+    pub fn authenticate(&mut self, data: Vec<u8>, now: Instant) -> SageResult<()> {
+        let packet = self.auth.as_mut().ok_or(ProtocolError)?.respond(data)?;
+        self.send(packet, now);
+        Ok(())
+    }
+
+    /// Starts a re-authentication once connected.
+    pub fn reauthenticate(&mut self, data: Vec<u8>, now: Instant) -> SageResult<()> {
+        if self.state != State::Connected {
+            return Err(ProtocolError.into());
+        }
+        let packet = self
+            .auth
+            .as_mut()
+            .ok_or(ProtocolError)?
+            .reauthenticate(data)?;
+        self.send(packet, now);
+        Ok(())
+    }
+
+    /// Publishes a message. `AtLeastOnce` and `ExactlyOnce` messages are given
+    /// a packet identifier, returned if any, and may be held until the
+    /// Receive Maximum of the server allows them to be sent.
+    pub fn publish(&mut self, publish: Publish, now: Instant) -> SageResult<Option<u16>> {
+        if self.state != State::Connected {
+            return Err(ProtocolError.into());
+        }
+        let (packet_identifier, packet) = self.deliveries.publish(publish)?;
+        if let Some(packet) = packet {
+            self.send(packet, now);
+        }
+        Ok(packet_identifier)
+    }
+
+    /// Subscribes to topics, returning the packet identifier given to
+    /// `subscribe`.
+    pub fn subscribe(&mut self, subscribe: Subscribe, now: Instant) -> SageResult<u16> {
+        if self.state != State::Connected {
+            return Err(ProtocolError.into());
+        }
+        let packet_identifier = self.deliveries.ids.allocate()?;
+        self.send(
+            Subscribe {
+                packet_identifier,
+                ..subscribe
+            },
+            now,
+        );
+        Ok(packet_identifier)
+    }
+
+    /// Unsubscribes from topics, returning the packet identifier given to
+    /// `unsubscribe`.
+    pub fn unsubscribe(&mut self, unsubscribe: UnSubscribe, now: Instant) -> SageResult<u16> {
+        if self.state != State::Connected {
+            return Err(ProtocolError.into());
+        }
+        let packet_identifier = self.deliveries.ids.allocate()?;
+        self.send(
+            UnSubscribe {
+                packet_identifier,
+                ..unsubscribe
+            },
+            now,
+        );
+        Ok(packet_identifier)
+    }
+
+    /// Closes the connection, sending `disconnect`.
+    pub fn disconnect(&mut self, disconnect: Disconnect, now: Instant) {
+        if self.state == State::Closed {
+            return;
+        }
+        if self.state != State::Initial {
+            self.send(disconnect.clone(), now);
+        }
+        self.state = State::Closed;
+        self.events.push_back(ClientEvent::Disconnected(disconnect));
+    }
+
+    /// Handles the passing of time: sends a `PingReq` packet when the keep
+    /// alive requires it, and closes the connection with `KeepAliveTimeout`
+    /// if nothing was received from the server for one and a half times the
+    /// keep alive.
+    pub fn handle_timeout(&mut self, now: Instant) {
+        if self.state != State::Connected {
+            return;
+        }
+        let keep_alive = match &self.keep_alive {
+            Some(keep_alive) => keep_alive,
+            None => return,
+        };
+        if keep_alive.is_timed_out(now) {
+            self.close(KeepAliveTimeout, now);
+        } else if keep_alive.should_ping(now) {
+            self.send(Packet::PingReq, now);
+        }
+    }
+
+    /// Handles a packet received from the server.
+    ///
+    /// # Errors
+    ///
+    /// If the packet is not legal at this point of the connection, the
+    /// connection is closed with a `Disconnect` packet and the reason code of
+    /// the violation is returned.
+    pub fn handle_packet(&mut self, packet: Packet, now: Instant) -> SageResult<()> {
+        if let Some(keep_alive) = &mut self.keep_alive {
+            keep_alive.received(now);
+        }
+        let result = match self.state {
+            State::Connecting => self.handle_connecting(packet, now),
+            State::Connected => self.handle_connected(packet, now),
+            State::Initial | State::Closed => Err(ProtocolError.into()),
+        };
+        if let Err(error) = result {
+            let reason_code = ReasonCode::from(error);
+            if self.state != State::Closed {
+                self.close(reason_code, now);
+            }
+            return Err(reason_code.into());
+        }
+        Ok(())
+    }
+
+    fn handle_connecting(&mut self, packet: Packet, now: Instant) -> SageResult<()> {
+        let connack = match (&mut self.auth, packet) {
+            (Some(auth), packet @ (Packet::Auth(_) | Packet::ConnAck(_))) => {
+                let connack = match &packet {
+                    Packet::ConnAck(connack) => Some(connack.clone()),
+                    _ => None,
+                };
+                match auth.accept(packet)? {
+                    AuthStep::Challenge(data) => {
+                        self.events.push_back(ClientEvent::AuthChallenge(data));
+                        return Ok(());
+                    }
+                    _ => connack.ok_or(ProtocolError)?,
+                }
+            }
+            (None, Packet::ConnAck(connack)) => connack,
+            _ => return Err(ProtocolError.into()),
+        };
+
+        if connack.reason_code.is_error() {
+            self.state = State::Closed;
+            self.events
+                .push_back(ClientEvent::ConnectionRefused(connack));
+            return Ok(());
+        }
+
+        self.state = State::Connected;
+        if let Some(keep_alive) = connack.keep_alive {
+            self.keep_alive = Some(KeepAlive::new(keep_alive, now));
+        }
+        for packet in self.deliveries.connected(
+            connack.session_present,
+            connack.receive_maximum,
+            connack.topic_alias_maximum,
+            self.connect.topic_alias_maximum,
+        )? {
+            self.send(packet, now);
+        }
+        self.events.push_back(ClientEvent::Connected(connack));
+        Ok(())
+    }
+
+    fn handle_connected(&mut self, packet: Packet, now: Instant) -> SageResult<()> {
+        match packet {
+            Packet::Publish(_)
+            | Packet::PubAck(_)
+            | Packet::PubRec(_)
+            | Packet::PubRel(_)
+            | Packet::PubComp(_) => {
+                let (packets, delivered) = self.deliveries.handle(packet)?;
+                for packet in packets {
+                    self.send(packet, now);
+                }
+                match delivered {
+                    Some(Delivered::Message(publish)) => {
+                        self.events.push_back(ClientEvent::Message(publish))
+                    }
+                    Some(Delivered::Acknowledged {
+                        packet_identifier,
+                        reason_code,
+                    }) => self.events.push_back(ClientEvent::Acknowledged {
+                        packet_identifier,
+                        reason_code,
+                    }),
+                    None => (),
+                }
+            }
+            Packet::SubAck(suback) => {
+                if !self.deliveries.ids.release(suback.packet_identifier) {
+                    return Err(ProtocolError.into());
+                }
+                self.events.push_back(ClientEvent::SubAck(suback));
+            }
+            Packet::UnSubAck(unsuback) => {
+                if !self.deliveries.ids.release(unsuback.packet_identifier) {
+                    return Err(ProtocolError.into());
+                }
+                self.events.push_back(ClientEvent::UnSubAck(unsuback));
+            }
+            Packet::PingResp => (),
+            Packet::Auth(auth) => {
+                match self
+                    .auth
+                    .as_mut()
+                    .ok_or(ProtocolError)?
+                    .accept(auth.into())?
+                {
+                    AuthStep::Challenge(data) => {
+                        self.events.push_back(ClientEvent::AuthChallenge(data))
+                    }
+                    AuthStep::Authenticated => self.events.push_back(ClientEvent::Reauthenticated),
+                    _ => return Err(ProtocolError.into()),
+                }
+            }
+            Packet::Disconnect(disconnect) => {
+                self.state = State::Closed;
+                self.events.push_back(ClientEvent::Disconnected(disconnect));
+            }
+            _ => return Err(ProtocolError.into()),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{Authentication, PubComp, PubRec, PubRel, QoS};
+    use std::time::Duration;
+
+    fn publish(qos: QoS) -> Publish {
+        Publish {
+            qos,
+            topic_name: "Around the World".try_into().unwrap(),
+            message: "Harder, Better, Faster, Stronger".into(),
+            ..Default::default()
+        }
+    }
+
+    fn connected(connack: ConnAck, now: Instant) -> ClientConnection {
+        let mut client = ClientConnection::new(Connect {
+            keep_alive: 10,
+            ..Default::default()
+        });
+        client.connect(now).unwrap();
+        assert!(matches!(client.poll_transmit(), Some(Packet::Connect(_))));
+        client.handle_packet(connack.into(), now).unwrap();
+        assert!(matches!(
+            client.poll_event(),
+            Some(ClientEvent::Connected(_))
+        ));
+        client
+    }
+
+    #[test]
+    fn ordering() {
+        let now = Instant::now();
+        let mut client = ClientConnection::new(Default::default());
+        assert!(client.publish(publish(QoS::AtMostOnce), now).is_err());
+        client.connect(now).unwrap();
+        assert!(client.connect(now).is_err());
+        assert!(client.publish(publish(QoS::AtMostOnce), now).is_err());
+        client.poll_transmit();
+
+        assert!(client
+            .handle_packet(Packet::Publish(publish(QoS::AtMostOnce)), now)
+            .is_err());
+        assert!(client.is_closed());
+        assert!(matches!(
+            client.poll_transmit(),
+            Some(Packet::Disconnect(disconnect)) if disconnect.reason_code == ProtocolError
+        ));
+        assert!(matches!(
+            client.poll_event(),
+            Some(ClientEvent::Disconnected(_))
+        ));
+    }
+
+    #[test]
+    fn disconnect() {
+        let now = Instant::now();
+        let mut client = connected(Default::default(), now);
+        let disconnect = Disconnect {
+            reason_code: ReasonCode::DisconnectWithWillMessage,
+            ..Default::default()
+        };
+        client.disconnect(disconnect.clone(), now);
+        assert!(client.is_closed());
+        assert_eq!(
+            client.poll_transmit(),
+            Some(Packet::Disconnect(disconnect.clone()))
+        );
+        assert_eq!(
+            client.poll_event(),
+            Some(ClientEvent::Disconnected(disconnect))
+        );
+
+        client.disconnect(Default::default(), now);
+        assert_eq!(client.poll_transmit(), None);
+        assert_eq!(client.poll_event(), None);
+    }
+
+    #[test]
+    fn refused() {
+        let now = Instant::now();
+        let mut client = ClientConnection::new(Default::default());
+        client.connect(now).unwrap();
+        let connack = ConnAck::rejection(ReasonCode::NotAuthorized);
+        client.handle_packet(connack.clone().into(), now).unwrap();
+        assert_eq!(
+            client.poll_event(),
+            Some(ClientEvent::ConnectionRefused(connack))
+        );
+        assert!(client.is_closed());
+    }
+
+    #[test]
+    fn deliveries() {
+        let now = Instant::now();
+        let connack = ConnAck::builder().receive_maximum(1).build();
+        let mut client = connected(connack, now);
+
+        assert_eq!(
+            client.publish(publish(QoS::ExactlyOnce), now).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            client.publish(publish(QoS::AtLeastOnce), now).unwrap(),
+            Some(2)
+        );
+        assert!(
+            matches!(client.poll_transmit(), Some(Packet::Publish(p)) if p.packet_identifier == Some(1))
+        );
+        assert_eq!(client.poll_transmit(), None);
+
+        let pubrec = PubRec {
+            packet_identifier: 1,
+            ..Default::default()
+        };
+        client.handle_packet(pubrec.into(), now).unwrap();
+        assert!(matches!(client.poll_transmit(), Some(Packet::PubRel(_))));
+        let pubcomp = PubComp {
+            packet_identifier: 1,
+            ..Default::default()
+        };
+        client.handle_packet(pubcomp.into(), now).unwrap();
+        assert!(
+            matches!(client.poll_transmit(), Some(Packet::Publish(p)) if p.packet_identifier == Some(2))
+        );
+        assert_eq!(
+            client.poll_event(),
+            Some(ClientEvent::Acknowledged {
+                packet_identifier: 1,
+                reason_code: ReasonCode::Success
+            })
+        );
+
+        let incoming = Publish {
+            packet_identifier: Some(7),
+            ..publish(QoS::ExactlyOnce)
+        };
+        client.handle_packet(incoming.clone().into(), now).unwrap();
+        client.handle_packet(incoming.clone().into(), now).unwrap();
+        assert_eq!(client.poll_event(), Some(ClientEvent::Message(incoming)));
+        assert_eq!(client.poll_event(), None);
+        assert!(matches!(client.poll_transmit(), Some(Packet::PubRec(_))));
+        assert!(matches!(client.poll_transmit(), Some(Packet::PubRec(_))));
+        let pubrel = PubRel {
+            packet_identifier: 7,
+            ..Default::default()
+        };
+        client.handle_packet(pubrel.into(), now).unwrap();
+        assert!(matches!(
+            client.poll_transmit(),
+            Some(Packet::PubComp(pubcomp)) if pubcomp.reason_code == ReasonCode::Success
+        ));
+    }
+
+    #[test]
+    fn keep_alive() {
+        let now = Instant::now();
+        let mut client = connected(ConnAck::default(), now);
+        assert_eq!(client.poll_timeout(), Some(now + Duration::from_secs(10)));
+
+        client.handle_timeout(now + Duration::from_secs(10));
+        assert_eq!(client.poll_transmit(), Some(Packet::PingReq));
+        client.handle_timeout(now + Duration::from_secs(15));
+        assert!(client.is_closed());
+        assert!(matches!(
+            client.poll_transmit(),
+            Some(Packet::Disconnect(disconnect)) if disconnect.reason_code == KeepAliveTimeout
+        ));
+    }
+
+    #[test]
+    fn authentication() {
+        let now = Instant::now();
+        let mut client = ClientConnection::new(Connect {
+            authentication: Some(Authentication {
+                method: "SCRAM-SHA-1".into(),
+                data: b"client-first".to_vec(),
+            }),
+            ..Default::default()
+        });
+        client.connect(now).unwrap();
+        client.poll_transmit();
+
+        let mut server = AuthFlow::server("SCRAM-SHA-1");
+        server
+            .accept(
+                Connect {
+                    authentication: Some(Authentication {
+                        method: "SCRAM-SHA-1".into(),
+                        data: b"client-first".to_vec(),
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+            )
+            .unwrap();
+        let packet = server.respond(b"server-first".to_vec()).unwrap();
+        client.handle_packet(packet, now).unwrap();
+        assert_eq!(
+            client.poll_event(),
+            Some(ClientEvent::AuthChallenge(b"server-first".to_vec()))
+        );
+
+        client.authenticate(b"client-final".to_vec(), now).unwrap();
+        server.accept(client.poll_transmit().unwrap()).unwrap();
+        let packet = server.succeed(Vec::new()).unwrap();
+        client.handle_packet(packet, now).unwrap();
+        assert!(client.is_connected());
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{
+    random, ClientID, Connect, ReasonCode::ClientIdentifierNotValid, Result as SageResult,
+};
+use std::{fmt, sync::Arc};
+
+/// The longest client id every server must accept.
+const MAX_LENGTH: usize = 23;
+const ALPHABET: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Generates client ids, such as for a server to assign one to a client
+/// connecting without it.
+///
+/// The generated ids are made of a prefix followed with random alphanumeric
+/// characters, and are at most 23 characters long. They are therefore always
+/// accepted by servers, as required by the specification. The random
+/// characters come from the random number generator of the operating system.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct ClientIdGenerator {
+    prefix: String,
+    random_length: usize,
+}
+
+impl Default for ClientIdGenerator {
+    fn default() -> Self {
+        ClientIdGenerator {
+            prefix: Default::default(),
+            random_length: MAX_LENGTH,
+        }
+    }
+}
+
+impl ClientIdGenerator {
+    /// Creates a generator of ids made of `length` random characters.
+    /// `length` is clamped between 1 and 23.
+    pub fn new(length: usize) -> Self {
+        ClientIdGenerator {
+            random_length: length.clamp(1, MAX_LENGTH),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a generator of ids made of `prefix` followed with random
+    /// characters, `length` characters long in total.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientIdentifierNotValid` if `prefix` contains characters
+    /// other than `0-9`, `a-z` and `A-Z`, if `length` is greater than 23 or if
+    /// it leaves no room for random characters after `prefix`.
+    pub fn with_prefix<S: Into<String>>(prefix: S, length: usize) -> SageResult<Self> {
+        let prefix = prefix.into();
+        if !prefix.chars().all(|c| c.is_ascii_alphanumeric())
+            || length > MAX_LENGTH
+            || length <= prefix.len()
+        {
+            Err(ClientIdentifierNotValid.into())
+        } else {
+            Ok(ClientIdGenerator {
+                random_length: length - prefix.len(),
+                prefix,
+            })
+        }
+    }
+
+    /// Generates a new client id.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the operating system fails to provide random bytes.
+    pub fn generate(&self) -> ClientID {
+        let mut id = self.prefix.clone();
+        let length = self.prefix.len() + self.random_length;
+        while id.len() < length {
+            let mut bytes = [0; MAX_LENGTH];
+            random::fill(&mut bytes);
+            // The six bits values out of the alphabet are discarded, so that
+            // every character is as likely.
+            for bits in bytes.iter().map(|byte| (byte & 0x3F) as usize) {
+                if bits < ALPHABET.len() && id.len() < length {
+                    id.push(ALPHABET[bits] as char);
+                }
+            }
+        }
+        id
+    }
+
+    /// Generates a client id from a random UUID, encoded in base 62 into 22
+    /// characters.
+    #[cfg(feature = "uuid")]
+    pub fn uuid() -> ClientID {
+        let mut value = uuid::Uuid::new_v4().as_u128();
+        let mut id = Vec::with_capacity(22);
+        for _ in 0..22 {
+            id.push(ALPHABET[(value % 62) as usize]);
+            value /= 62;
+        }
+        id.into_iter().rev().map(char::from).collect()
+    }
+}
+
+/// How a server handles the `Connect` packets without a client id, which it
+/// may either accept by assigning an id to the client, or reject.
+#[derive(Clone)]
+pub enum ClientIdAssignment {
+    /// An id is generated with a `ClientIdGenerator`.
+    Generate(ClientIdGenerator),
+
+    /// An id is generated by a custom function, such as one using the ids of
+    /// a database.
+    Custom(Arc<dyn Fn() -> ClientID + Send + Sync>),
+
+    /// The connection is refused with `ClientIdentifierNotValid`.
+    Reject,
+}
+
+impl Default for ClientIdAssignment {
+    fn default() -> Self {
+        ClientIdAssignment::Generate(Default::default())
+    }
+}
+
+impl fmt::Debug for ClientIdAssignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientIdAssignment::Generate(generator) => {
+                f.debug_tuple("Generate").field(generator).finish()
+            }
+            ClientIdAssignment::Custom(_) => f.write_str("Custom"),
+            ClientIdAssignment::Reject => f.write_str("Reject"),
+        }
+    }
+}
+
+impl ClientIdAssignment {
+    /// Assigns a client id to `connect` if it has none, returning it to send
+    /// as the `assigned_client_id` of the `ConnAck` packet. Nothing is done
+    /// and `None` is returned if `connect` has a client id.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientIdentifierNotValid` if `connect` has no client id and
+    /// the assignment is `Reject`, or if Clean Start is not set: the client
+    /// cannot resume a session it has no id for.
+    pub fn assign(&self, connect: &mut Connect) -> SageResult<Option<ClientID>> {
+        if connect.client_id.is_some() {
+            return Ok(None);
+        }
+        if !connect.clean_start {
+            return Err(ClientIdentifierNotValid.into());
+        }
+        let client_id = match self {
+            ClientIdAssignment::Generate(generator) => generator.generate(),
+            ClientIdAssignment::Custom(generate) => generate(),
+            ClientIdAssignment::Reject => return Err(ClientIdentifierNotValid.into()),
+        };
+        connect.client_id = Some(client_id.clone());
+        Ok(Some(client_id))
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    fn is_valid(id: &str) -> bool {
+        !id.is_empty() && id.len() <= MAX_LENGTH && id.chars().all(|c| c.is_ascii_alphanumeric())
+    }
+
+    #[test]
+    fn generate() {
+        for length in [0, 1, 10, 11, 23, 100] {
+            let id = ClientIdGenerator::new(length).generate();
+            assert!(is_valid(&id), "{}", id);
+            assert_eq!(id.len(), length.clamp(1, MAX_LENGTH));
+        }
+        let generator = ClientIdGenerator::default();
+        assert_ne!(generator.generate(), generator.generate());
+
+        let generator = ClientIdGenerator::with_prefix("sage", 12).unwrap();
+        let id = generator.generate();
+        assert!(is_valid(&id) && id.starts_with("sage") && id.len() == 12);
+
+        for (prefix, length) in [("sage/", 12), ("sage", 24), ("sage", 4), ("", 30)] {
+            assert!(matches!(
+                ClientIdGenerator::with_prefix(prefix, length),
+                Err(crate::Error::Reason(ClientIdentifierNotValid))
+            ));
+        }
+    }
+
+    #[test]
+    fn uniform() {
+        let generator = ClientIdGenerator::default();
+        let mut counts = [0; 62];
+        for _ in 0..1000 {
+            for c in generator.generate().bytes() {
+                counts[ALPHABET.iter().position(|&a| a == c).unwrap()] += 1;
+            }
+        }
+        let average = 1000 * MAX_LENGTH / ALPHABET.len();
+        for count in counts {
+            assert!(count > average / 2 && count < average * 3 / 2, "{}", count);
+        }
+    }
+
+    #[test]
+    fn assign() {
+        let clean_start = || Connect {
+            clean_start: true,
+            ..Default::default()
+        };
+        let assignment = ClientIdAssignment::default();
+        let mut connect = Connect {
+            client_id: Some("Jaden".into()),
+            ..clean_start()
+        };
+        assert_eq!(assignment.assign(&mut connect).unwrap(), None);
+        assert_eq!(connect.client_id, Some("Jaden".into()));
+
+        let mut connect = clean_start();
+        let client_id = assignment.assign(&mut connect).unwrap();
+        assert!(client_id.as_deref().is_some_and(is_valid));
+        assert_eq!(connect.client_id, client_id);
+
+        let assignment = ClientIdAssignment::Custom(Arc::new(|| "Jaden".to_string()));
+        assert_eq!(
+            assignment.assign(&mut clean_start()).unwrap(),
+            Some("Jaden".into())
+        );
+        assert!(matches!(
+            assignment.assign(&mut Connect::default()),
+            Err(crate::Error::Reason(ClientIdentifierNotValid))
+        ));
+        assert!(matches!(
+            ClientIdAssignment::Reject.assign(&mut clean_start()),
+            Err(crate::Error::Reason(ClientIdentifierNotValid))
+        ));
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn uuid() {
+        let id = ClientIdGenerator::uuid();
+        assert!(is_valid(&id) && id.len() == 22, "{}", id);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::{
+    BalancePolicy, Message, ReasonCode::ProtocolError, Result as SageResult, ShareBalancer,
+    SharedSubscription, TopicFilter, TopicTree,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// A message exchanged between the nodes of a `Cluster`, over a transport
+/// chosen by the broker.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClusterMessage {
+    /// All the topic filters the sender has local subscribers for, replacing
+    /// the ones previously known. Sent to a peer upon connecting to it.
+    Sync(Vec<TopicFilter>),
+
+    /// The sender has its first local subscriber of the topic filter, which
+    /// can be a shared subscription.
+    Subscribed(TopicFilter),
+
+    /// The sender has no local subscriber of the topic filter anymore.
+    Unsubscribed(TopicFilter),
+
+    /// A message published on the sender, to deliver to the local
+    /// subscribers of the receiver.
+    Forward {
+        /// The message.
+        message: Message,
+
+        /// The shared subscription the message is to be delivered for, if it
+        /// was dispatched to the receiver for a share group.
+        shared: Option<TopicFilter>,
+    },
+}
+
+/// An event of a `Cluster` to be handled by the broker.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ClusterEvent {
+    /// A message forwarded by a peer, to deliver to the local subscribers
+    /// which are not in a share group if `shared` is `None`, and else to one
+    /// local member of the share group of `shared` only.
+    Message {
+        /// The node the message was published on.
+        origin: String,
+
+        /// The message.
+        message: Message,
+
+        /// The shared subscription the message was dispatched for.
+        shared: Option<SharedSubscription>,
+    },
+}
+
+/// The state a node of a broker cluster shares with its peers, without any
+/// I/O.
+///
+/// Each node tells its peers the topic filters it has local subscribers for,
+/// with the `ClusterMessage` packets returned by `poll_transmit`, so that a
+/// message published on any node is forwarded only to the nodes with matching
+/// subscribers. The peers form a full mesh given by a static list, each node
+/// being identified by a unique name.
+///
+/// The shared subscriptions are coordinated across the cluster: the node a
+/// message is published on picks one node among the ones having members of a
+/// share group, itself included, using its `BalancePolicy` with the nodes as
+/// members, and that node delivers it to one of its local members. A message
+/// is thus delivered once per share group of the whole cluster.
+///
+/// The messages received from peers must never be given to `publish`, as
+/// their origin already forwarded them to every node concerned.
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    node: String,
+    peers: Vec<String>,
+    local: HashMap<TopicFilter, usize>,
+    remote: HashMap<String, HashSet<TopicFilter>>,
+    // The peers by the filters of their subscribers which are not in a share
+    // group.
+    routes: TopicTree<String>,
+    shares: ShareBalancer<String>,
+    transmit: VecDeque<(String, ClusterMessage)>,
+    events: VecDeque<ClusterEvent>,
+}
+
+impl Cluster {
+    /// Creates the state of the node named `node`, whose peers are named
+    /// `peers`. The share groups are balanced across nodes in turn.
+    pub fn new<S: Into<String>>(node: S, peers: Vec<String>) -> Self {
+        let node = node.into();
+        Cluster {
+            peers: peers.into_iter().filter(|peer| *peer != node).collect(),
+            node,
+            local: HashMap::new(),
+            remote: HashMap::new(),
+            routes: TopicTree::new(),
+            shares: ShareBalancer::new(BalancePolicy::RoundRobin),
+            transmit: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Sets the policy picking the node a message of a share group is
+    /// dispatched to. The deliveries are not tracked across nodes, so
+    /// `LeastInflight` behaves as `RoundRobin`.
+    pub fn with_balance_policy(mut self, policy: BalancePolicy) -> Self {
+        self.shares = ShareBalancer::new(policy);
+        self
+    }
+
+    /// The name of the node.
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// The names of the peers.
+    pub fn peers(&self) -> &[String] {
+        &self.peers
+    }
+
+    /// Returns `true` if `node` has subscribers of `filter`.
+    pub fn has_subscribers(&self, node: &str, filter: &TopicFilter) -> bool {
+        if node == self.node {
+            self.local.contains_key(filter)
+        } else {
+            self.remote
+                .get(node)
+                .is_some_and(|filters| filters.contains(filter))
+        }
+    }
+
+    fn broadcast(&mut self, message: ClusterMessage) {
+        for peer in &self.peers {
+            self.transmit.push_back((peer.clone(), message.clone()));
+        }
+    }
+
+    /// Records a local subscription to `filter`, which can be a shared
+    /// subscription, telling the peers if it is the first one.
+    pub fn subscribe(&mut self, filter: TopicFilter) {
+        let count = self.local.entry(filter.clone()).or_default();
+        *count += 1;
+        if *count == 1 {
+            if let Some(shared) = filter.shared_subscription() {
+                self.shares.join(shared, self.node.clone());
+            }
+            self.broadcast(ClusterMessage::Subscribed(filter));
+        }
+    }
+
+    /// Removes a local subscription to `filter`, telling the peers if it was
+    /// the last one.
+    pub fn unsubscribe(&mut self, filter: &TopicFilter) {
+        let count = match self.local.get_mut(filter) {
+            Some(count) => count,
+            None => return,
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.local.remove(filter);
+            if let Some(shared) = filter.shared_subscription() {
+                self.shares.leave(&shared, &self.node);
+            }
+            self.broadcast(ClusterMessage::Unsubscribed(filter.clone()));
+        }
+    }
+
+    fn forget(&mut self, peer: &str) {
+        if let Some(filters) = self.remote.remove(peer) {
+            for filter in &filters {
+                self.remove_remote(peer, filter);
+            }
+        }
+    }
+
+    fn remove_remote(&mut self, peer: &str, filter: &TopicFilter) {
+        match filter.shared_subscription() {
+            Some(shared) => self.shares.leave(&shared, &peer.to_string()),
+            None => {
+                self.routes.remove(filter, |p| p == peer);
+            }
+        }
+    }
+
+    fn add_remote(&mut self, peer: &str, filter: TopicFilter) {
+        let filters = self.remote.entry(peer.to_string()).or_default();
+        if !filters.insert(filter.clone()) {
+            return;
+        }
+        match filter.shared_subscription() {
+            Some(shared) => self.shares.join(shared, peer.to_string()),
+            None => self.routes.insert(&filter, peer.to_string()),
+        }
+    }
+
+    /// Handles the connection to `peer` being established, sending it the
+    /// local subscriptions.
+    pub fn peer_connected(&mut self, peer: &str) {
+        let filters = self.local.keys().cloned().collect();
+        self.transmit
+            .push_back((peer.to_string(), ClusterMessage::Sync(filters)));
+    }
+
+    /// Handles the connection to `peer` being lost, forgetting its
+    /// subscriptions until it connects again.
+    pub fn peer_disconnected(&mut self, peer: &str) {
+        self.forget(peer);
+    }
+
+    /// Handles a message received from `peer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if `peer` is not a peer of the node.
+    pub fn handle_message(&mut self, peer: &str, message: ClusterMessage) -> SageResult<()> {
+        if !self.peers.iter().any(|p| p == peer) {
+            return Err(ProtocolError.into());
+        }
+        match message {
+            ClusterMessage::Sync(filters) => {
+                self.forget(peer);
+                self.remote.insert(peer.to_string(), HashSet::new());
+                for filter in filters {
+                    self.add_remote(peer, filter);
+                }
+            }
+            ClusterMessage::Subscribed(filter) => self.add_remote(peer, filter),
+            ClusterMessage::Unsubscribed(filter) => {
+                let removed = self
+                    .remote
+                    .get_mut(peer)
+                    .is_some_and(|filters| filters.remove(&filter));
+                if removed {
+                    self.remove_remote(peer, &filter);
+                }
+            }
+            ClusterMessage::Forward { message, shared } => {
+                self.events.push_back(ClusterEvent::Message {
+                    origin: peer.to_string(),
+                    message,
+                    shared: shared.as_ref().and_then(TopicFilter::shared_subscription),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Forwards a message published by a local client to the peers with
+    /// matching subscribers, and dispatches it to a node for each matching
+    /// share group.
+    ///
+    /// Returns the share groups the message was dispatched to this node for,
+    /// to be delivered to one local member each. The local subscribers which
+    /// are not in a share group are not concerned by the cluster and always
+    /// receive the message.
+    pub fn publish(&mut self, message: &Message) -> Vec<SharedSubscription> {
+        let matched: HashSet<&String> = self.routes.matches(&message.topic).into_iter().collect();
+        for peer in &self.peers {
+            if matched.contains(peer) {
+                let forward = ClusterMessage::Forward {
+                    message: message.clone(),
+                    shared: None,
+                };
+                self.transmit.push_back((peer.clone(), forward));
+            }
+        }
+        let mut local = Vec::new();
+        for (shared, node) in self.shares.dispatch(&message.topic) {
+            self.shares.completed(&node);
+            if node == self.node {
+                local.push(shared);
+            } else {
+                let forward = ClusterMessage::Forward {
+                    message: message.clone(),
+                    shared: Some(shared.into()),
+                };
+                self.transmit.push_back((node, forward));
+            }
+        }
+        local
+    }
+
+    /// Returns the next message to send, with the name of the peer to send it
+    /// to.
+    pub fn poll_transmit(&mut self) -> Option<(String, ClusterMessage)> {
+        self.transmit.pop_front()
+    }
+
+    /// Returns the next event to handle by the broker.
+    pub fn poll_event(&mut self) -> Option<ClusterEvent> {
+        self.events.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    fn filter(filter: &str) -> TopicFilter {
+        filter.try_into().unwrap()
+    }
+
+    fn message(topic: &str) -> Message {
+        Message {
+            topic: topic.try_into().unwrap(),
+            payload: "Harder".into(),
+            ..Default::default()
+        }
+    }
+
+    // Delivers the pending messages of every node until none is left.
+    fn exchange(nodes: &mut [Cluster]) {
+        loop {
+            let mut sent = Vec::new();
+            for node in nodes.iter_mut() {
+                while let Some((peer, message)) = node.poll_transmit() {
+                    sent.push((node.node().to_string(), peer, message));
+                }
+            }
+            if sent.is_empty() {
+                return;
+            }
+            for (from, to, message) in sent {
+                let node = nodes.iter_mut().find(|n| n.node() == to).unwrap();
+                node.handle_message(&from, message).unwrap();
+            }
+        }
+    }
+
+    fn cluster() -> Vec<Cluster> {
+        let names: Vec<String> = vec!["a".into(), "b".into(), "c".into()];
+        names
+            .iter()
+            .map(|name| Cluster::new(name.as_str(), names.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn routing() {
+        let mut nodes = cluster();
+        assert_eq!(nodes[0].peers(), ["b".to_string(), "c".to_string()]);
+        nodes[1].subscribe(filter("sensors/+"));
+        nodes[1].subscribe(filter("sensors/+"));
+        exchange(&mut nodes);
+        assert!(nodes[0].has_subscribers("b", &filter("sensors/+")));
+
+        assert!(nodes[0].publish(&message("sensors/temperature")).is_empty());
+        assert!(nodes[0].publish(&message("actuators/valve")).is_empty());
+        exchange(&mut nodes);
+        assert_eq!(
+            nodes[1].poll_event(),
+            Some(ClusterEvent::Message {
+                origin: "a".into(),
+                message: message("sensors/temperature"),
+                shared: None,
+            })
+        );
+        assert_eq!(nodes[1].poll_event(), None);
+        assert_eq!(nodes[2].poll_event(), None);
+
+        nodes[1].unsubscribe(&filter("sensors/+"));
+        exchange(&mut nodes);
+        assert!(nodes[0].has_subscribers("b", &filter("sensors/+")));
+        nodes[1].unsubscribe(&filter("sensors/+"));
+        exchange(&mut nodes);
+        assert!(!nodes[0].has_subscribers("b", &filter("sensors/+")));
+        nodes[0].publish(&message("sensors/temperature"));
+        assert_eq!(nodes[0].poll_transmit(), None);
+
+        // A peer with several matching subscriptions gets the message once.
+        nodes[2].subscribe(filter("sensors/#"));
+        nodes[2].subscribe(filter("+/humidity"));
+        exchange(&mut nodes);
+        nodes[0].publish(&message("sensors/humidity"));
+        assert!(matches!(nodes[0].poll_transmit(), Some((peer, _)) if peer == "c"));
+        assert_eq!(nodes[0].poll_transmit(), None);
+
+        assert!(nodes[0]
+            .handle_message("d", ClusterMessage::Sync(Vec::new()))
+            .is_err());
+    }
+
+    #[test]
+    fn reconnect() {
+        let mut nodes = cluster();
+        nodes[1].subscribe(filter("sensors/#"));
+        while nodes[1].poll_transmit().is_some() {}
+        assert!(!nodes[0].has_subscribers("b", &filter("sensors/#")));
+
+        nodes[1].peer_connected("a");
+        exchange(&mut nodes);
+        assert!(nodes[0].has_subscribers("b", &filter("sensors/#")));
+
+        nodes[0].peer_disconnected("b");
+        assert!(!nodes[0].has_subscribers("b", &filter("sensors/#")));
+        nodes[0].publish(&message("sensors/temperature"));
+        assert_eq!(nodes[0].poll_transmit(), None);
+    }
+
+    #[test]
+    fn shared() {
+        let mut nodes = cluster();
+        let group = filter("$share/workers/jobs/#");
+        nodes[0].subscribe(group.clone());
+        nodes[2].subscribe(group.clone());
+        exchange(&mut nodes);
+
+        let shared = group.shared_subscription().unwrap();
+        let mut deliveries = HashMap::<String, usize>::new();
+        for _ in 0..4 {
+            for local in nodes[1].publish(&message("jobs/build")) {
+                assert_eq!(local, shared);
+            }
+            exchange(&mut nodes);
+            for node in nodes.iter_mut() {
+                while let Some(ClusterEvent::Message { shared: group, .. }) = node.poll_event() {
+                    assert_eq!(group.as_ref(), Some(&shared));
+                    *deliveries.entry(node.node().to_string()).or_default() += 1;
+                }
+            }
+        }
+        assert_eq!(
+            deliveries,
+            HashMap::from([("a".to_string(), 2), ("c".to_string(), 2)])
+        );
+
+        let local: usize = (0..4)
+            .map(|_| nodes[0].publish(&message("jobs/test")).len())
+            .sum();
+        assert_eq!(local, 2);
+    }
+}
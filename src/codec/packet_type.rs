@@ -1,4 +1,6 @@
-use crate::{codec, PacketType, ReasonCode::MalformedPacket, Result as SageResult};
+use crate::{
+    codec, DecodeOptions, Deviation, PacketType, ReasonCode::MalformedPacket, Result as SageResult,
+};
 use std::{convert::TryInto, marker::Unpin};
 use tokio::io::{AsyncRead, AsyncWrite};
 
@@ -42,7 +44,40 @@ pub async fn write_control_packet_type<W: AsyncWrite + Unpin>(
 pub async fn read_control_packet_type<R: AsyncRead + Unpin>(
     reader: &mut R,
 ) -> SageResult<PacketType> {
-    let packet_type = codec::read_byte(reader).await?;
+    read_control_packet_type_with(reader, &DecodeOptions::default()).await
+}
+
+/// Read the given `reader` for a `PacketType`, according to the given
+/// decoding `options`.
+/// In case of success, returns a `PacketType` instance.
+pub async fn read_control_packet_type_with<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    options: &DecodeOptions,
+) -> SageResult<PacketType> {
+    let byte = codec::read_byte(reader).await?;
+    match parse_control_packet_type(byte) {
+        // Publish flags are not reserved and the reserved packet type cannot
+        // be normalized.
+        Err(_) if options.accept_reserved_flags && !matches!(byte >> 4, 0b0000 | 0b0011) => {
+            let packet_type = parse_control_packet_type(normalize_flags(byte >> 4))?;
+            options.notify(Deviation::ReservedFlags {
+                packet_type,
+                flags: byte & 0b0000_1111,
+            });
+            Ok(packet_type)
+        }
+        result => result,
+    }
+}
+
+fn normalize_flags(packet_type: u8) -> u8 {
+    match packet_type {
+        0b0110 | 0b1000 | 0b1010 => packet_type << 4 | 0b0010,
+        _ => packet_type << 4,
+    }
+}
+
+fn parse_control_packet_type(packet_type: u8) -> SageResult<PacketType> {
     let packet_type = match (packet_type >> 4, packet_type & 0b0000_1111) {
         (0b0000, 0b0000) => PacketType::Reserved,
         (0b0001, 0b0000) => PacketType::Connect,
@@ -73,7 +108,10 @@ pub async fn read_control_packet_type<R: AsyncRead + Unpin>(
 mod unit {
 
     use crate::{Error, ReasonCode};
-    use std::io::Cursor;
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
 
     use super::*;
 
@@ -110,4 +148,37 @@ mod unit {
             }
         }
     }
+
+    #[tokio::test]
+    async fn lenient_reserved_flags() {
+        let deviations = Arc::new(Mutex::new(Vec::new()));
+        let options = DecodeOptions {
+            accept_reserved_flags: true,
+            ..Default::default()
+        }
+        .on_deviation({
+            let deviations = deviations.clone();
+            move |deviation| deviations.lock().unwrap().push(deviation)
+        });
+
+        let mut test_stream = Cursor::new([0b1000_0000]);
+        assert_eq!(
+            read_control_packet_type_with(&mut test_stream, &options)
+                .await
+                .unwrap(),
+            PacketType::Subscribe
+        );
+        assert_eq!(
+            *deviations.lock().unwrap(),
+            vec![Deviation::ReservedFlags {
+                packet_type: PacketType::Subscribe,
+                flags: 0b0000
+            }]
+        );
+
+        let mut test_stream = Cursor::new([0b0000_0001]);
+        assert!(read_control_packet_type_with(&mut test_stream, &options)
+            .await
+            .is_err());
+    }
 }
@@ -1,67 +1,37 @@
-use crate::{codec, ReasonCode, Result as SageResult};
-use std::marker::Unpin;
-use tokio::io::AsyncWrite;
+use crate::{codec, DecodeOptions, Deviation, ReasonCode, Result as SageResult};
+use std::{convert::TryFrom, marker::Unpin};
+use tokio::io::{AsyncRead, AsyncWrite};
 
 ///Write the given `ReasonCode`in one byte, returning `1` in case of success.
 pub async fn write_reason_code<W: AsyncWrite + Unpin>(
     code: ReasonCode,
     writer: &mut W,
 ) -> SageResult<usize> {
-    codec::write_byte(
-        match code {
-            ReasonCode::Success => 0x00,
-            ReasonCode::GrantedQoS1 => 0x01,
-            ReasonCode::GrantedQoS2 => 0x02,
-            ReasonCode::DisconnectWithWillMessage => 0x04,
-            ReasonCode::NoMatchingSubscribers => 0x10,
-            ReasonCode::NoSubscriptionExisted => 0x11,
-            ReasonCode::ContinueAuthentication => 0x18,
-            ReasonCode::ReAuthenticate => 0x19,
-            ReasonCode::UnspecifiedError => 0x80,
-            ReasonCode::MalformedPacket => 0x81,
-            ReasonCode::ProtocolError => 0x82,
-            ReasonCode::ImplementationSpecificError => 0x83,
-            ReasonCode::UnsupportedProtocolVersion => 0x84,
-            ReasonCode::ClientIdentifierNotValid => 0x85,
-            ReasonCode::BadUserNameOrPassword => 0x86,
-            ReasonCode::NotAuthorized => 0x87,
-            ReasonCode::ServerUnavailable => 0x88,
-            ReasonCode::ServerBusy => 0x89,
-            ReasonCode::Banned => 0x8A,
-            ReasonCode::ServerShuttingDown => 0x8B,
-            ReasonCode::BadAuthenticationMethod => 0x8C,
-            ReasonCode::KeepAliveTimeout => 0x8D,
-            ReasonCode::SessionTakenOver => 0x8E,
-            ReasonCode::TopicFilterInvalid => 0x8F,
-            ReasonCode::TopicNameInvalid => 0x90,
-            ReasonCode::PacketIdentifierInUse => 0x91,
-            ReasonCode::PacketIdentifierNotFound => 0x92,
-            ReasonCode::ReceiveMaximumExceeded => 0x93,
-            ReasonCode::TopicAliasInvalid => 0x94,
-            ReasonCode::PacketTooLarge => 0x95,
-            ReasonCode::MessageRateTooHigh => 0x96,
-            ReasonCode::QuotaExceeded => 0x97,
-            ReasonCode::AdministrativeAction => 0x98,
-            ReasonCode::PayloadFormatInvalid => 0x99,
-            ReasonCode::RetainNotSupported => 0x9A,
-            ReasonCode::QoSNotSupported => 0x9B,
-            ReasonCode::UseAnotherServer => 0x9C,
-            ReasonCode::ServerMoved => 0x9D,
-            ReasonCode::SharedSubscriptionsNotSupported => 0x9E,
-            ReasonCode::ConnectionRateExceeded => 0x9F,
-            ReasonCode::MaximumConnectTime => 0xA0,
-            ReasonCode::SubscriptionIdentifiersNotSupported => 0xA1,
-            ReasonCode::WildcardSubscriptionsNotSupported => 0xA2,
-        },
-        writer,
-    )
-    .await
+    codec::write_byte(code.into(), writer).await
+}
+
+/// Read the given `reader` for a `ReasonCode`, according to the given
+/// decoding `options`.
+/// In case of success, returns a `ReasonCode` instance.
+pub async fn read_reason_code<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    options: &DecodeOptions,
+) -> SageResult<ReasonCode> {
+    let value = codec::read_byte(reader).await?;
+    match ReasonCode::try_from(value) {
+        Err(_) if options.accept_unknown_reason_codes => {
+            options.notify(Deviation::UnknownReasonCode(value));
+            Ok(ReasonCode::Unknown(value))
+        }
+        result => result,
+    }
 }
 
 #[cfg(test)]
 mod unit {
 
     use super::*;
+    use std::io::Cursor;
 
     #[tokio::test]
     async fn encode() {
@@ -118,4 +88,24 @@ mod unit {
             assert_eq!(result[0], byte);
         }
     }
+
+    #[tokio::test]
+    async fn decode_unknown() {
+        let mut test_stream = Cursor::new([0xF0]);
+        assert!(read_reason_code(&mut test_stream, &Default::default())
+            .await
+            .is_err());
+
+        let options = DecodeOptions {
+            accept_unknown_reason_codes: true,
+            ..Default::default()
+        };
+        let mut test_stream = Cursor::new([0xF0]);
+        let reason_code = read_reason_code(&mut test_stream, &options).await.unwrap();
+        assert_eq!(reason_code, ReasonCode::Unknown(0xF0));
+
+        let mut result = Vec::new();
+        write_reason_code(reason_code, &mut result).await.unwrap();
+        assert_eq!(result, vec![0xF0]);
+    }
 }
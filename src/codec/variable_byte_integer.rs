@@ -35,10 +35,10 @@ pub async fn read_variable_byte_integer<R: AsyncRead + Unpin>(reader: &mut R) ->
         let mut buffer = vec![0u8; 1];
         reader.read_exact(&mut buffer).await?;
         let encoded_byte = buffer[0];
-        value += ((encoded_byte & 127u8) as u32) * multiplier;
         if multiplier > 2_097_152 {
             return Err(MalformedPacket.into());
         }
+        value += ((encoded_byte & 127u8) as u32) * multiplier;
         multiplier *= 128;
         if encoded_byte & 128u8 == 0 {
             break;
@@ -266,4 +266,11 @@ mod unit {
             panic!("Should be IO Error");
         }
     }
+
+    #[tokio::test]
+    async fn decode_five_bytes() {
+        let mut test_stream = Cursor::new([0xFF, 0xFF, 0xFF, 0xFF, 0x7F]);
+        let result = read_variable_byte_integer(&mut test_stream).await;
+        assert!(matches!(result, Err(Error::Reason(MalformedPacket))));
+    }
 }
@@ -0,0 +1,156 @@
+use crate::{Connect, Disconnect, Expiry, ReasonCode::SessionTakenOver};
+
+/// The state of the session a broker holds for the client id of a `Connect`
+/// packet.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ExistingSession {
+    /// There is no session, or it has expired.
+    None,
+
+    /// There is a session with no network connection.
+    Disconnected,
+
+    /// There is a session, still used by another network connection.
+    Connected,
+}
+
+/// What a broker must do upon receiving a `Connect` packet, depending on the
+/// Clean Start flag, the existing session of the client and the Session Expiry
+/// Interval it requests.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct ConnectDecision {
+    /// The Session Present flag to send in the `ConnAck` packet: `true` if the
+    /// existing session is resumed.
+    pub session_present: bool,
+
+    /// If `true`, the state of the existing session must be discarded and a
+    /// new session started.
+    pub discard_session: bool,
+
+    /// If `true`, the network connection currently using the session must be
+    /// closed with a `Disconnect` packet, as given by `takeover_disconnect`.
+    pub take_over: bool,
+
+    /// If `true`, the session must be kept after the network connection is
+    /// closed, until the Session Expiry Interval elapses. Otherwise it ends
+    /// with the network connection.
+    pub persist_session: bool,
+}
+
+impl ConnectDecision {
+    /// Decides how to handle `connect`, given the `existing` session of its
+    /// client:
+    /// - With Clean Start, any existing session is discarded and a new one is
+    ///   started.
+    /// - Without Clean Start, the existing session is resumed if any, and a
+    ///   new one is started otherwise.
+    ///
+    /// In both cases, the connection still using the existing session is
+    /// taken over.
+    pub fn new(connect: &Connect, existing: ExistingSession) -> Self {
+        let has_session = existing != ExistingSession::None;
+        ConnectDecision {
+            session_present: has_session && !connect.clean_start,
+            discard_session: has_session && connect.clean_start,
+            take_over: existing == ExistingSession::Connected,
+            persist_session: !matches!(
+                connect.session_expiry_interval,
+                Expiry::Default | Expiry::Seconds(0)
+            ),
+        }
+    }
+
+    /// The `Disconnect` packet to send to the connection being taken over, if
+    /// any.
+    pub fn takeover_disconnect(&self) -> Option<Disconnect> {
+        if self.take_over {
+            Some(Disconnect {
+                reason_code: SessionTakenOver,
+                ..Default::default()
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn decision_table() {
+        let connect = |clean_start, session_expiry_interval| Connect {
+            clean_start,
+            session_expiry_interval,
+            ..Default::default()
+        };
+        let decision =
+            |session_present, discard_session, take_over, persist_session| ConnectDecision {
+                session_present,
+                discard_session,
+                take_over,
+                persist_session,
+            };
+
+        for (clean_start, expiry, existing, expected) in [
+            (
+                true,
+                Expiry::Default,
+                ExistingSession::None,
+                decision(false, false, false, false),
+            ),
+            (
+                true,
+                Expiry::Never,
+                ExistingSession::Disconnected,
+                decision(false, true, false, true),
+            ),
+            (
+                true,
+                Expiry::Seconds(0),
+                ExistingSession::Connected,
+                decision(false, true, true, false),
+            ),
+            (
+                false,
+                Expiry::Seconds(60),
+                ExistingSession::None,
+                decision(false, false, false, true),
+            ),
+            (
+                false,
+                Expiry::Default,
+                ExistingSession::Disconnected,
+                decision(true, false, false, false),
+            ),
+            (
+                false,
+                Expiry::Never,
+                ExistingSession::Connected,
+                decision(true, false, true, true),
+            ),
+        ] {
+            assert_eq!(
+                ConnectDecision::new(&connect(clean_start, expiry), existing),
+                expected,
+                "{} {:?} {:?}",
+                clean_start,
+                expiry,
+                existing
+            );
+        }
+
+        assert!(decision(false, false, false, false)
+            .takeover_disconnect()
+            .is_none());
+        assert_eq!(
+            decision(true, false, true, true)
+                .takeover_disconnect()
+                .unwrap()
+                .reason_code,
+            SessionTakenOver
+        );
+    }
+}
@@ -1,9 +1,9 @@
 use crate::{
-    codec, Authentication, PropertiesDecoder, Property,
+    codec, Authentication, DecodeOptions, Properties, PropertiesDecoder, Property,
     ReasonCode::{self, ProtocolError},
     Result as SageResult,
 };
-use std::{convert::TryFrom, marker::Unpin};
+use std::marker::Unpin;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 /// The `Auth` packet is used for enhanced authentication upon connection.
@@ -11,7 +11,7 @@ use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 /// the `Authentication` structure. Then the client and server exchange `Auth`
 /// packets until either the the client sends a `Disconnect` packet or the
 /// server respond with a `Connack` packet.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Auth {
     /// The packet reason code. Can be any of:
     /// - Success: The authentication is successful
@@ -28,6 +28,10 @@ pub struct Auth {
 
     /// General purpose user properties.
     pub user_properties: Vec<(String, String)>,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for Auth {
@@ -37,6 +41,7 @@ impl Default for Auth {
             authentication: Default::default(),
             reason_string: None,
             user_properties: Default::default(),
+            raw_properties: None,
         }
     }
 }
@@ -60,11 +65,21 @@ impl Auth {
         Ok(n_bytes)
     }
 
-    pub(crate) async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> SageResult<Self> {
-        let reason_code = ReasonCode::try_from(codec::read_byte(reader).await?)?;
+    pub(crate) async fn read<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        remaining_size: usize,
+        options: &DecodeOptions,
+    ) -> SageResult<Self> {
+        // The reason code and properties are omitted on `Success` without
+        // properties.
+        if remaining_size == 0 {
+            return Ok(Default::default());
+        }
+
+        let reason_code = codec::read_reason_code(reader, options).await?;
 
         let mut user_properties = Vec::new();
-        let mut properties = PropertiesDecoder::take(reader).await?;
+        let mut properties = PropertiesDecoder::take_with(reader, options).await?;
         let mut reason_string = None;
         let mut authentication_method = None;
         let mut authentication_data = Default::default();
@@ -78,6 +93,7 @@ impl Auth {
                 _ => return Err(ProtocolError.into()),
             }
         }
+        let raw_properties = properties.raw_properties();
 
         if let Some(method) = authentication_method {
             let authentication = Authentication {
@@ -90,6 +106,7 @@ impl Auth {
                 reason_string,
                 authentication,
                 user_properties,
+                raw_properties,
             })
         } else {
             Err(ProtocolError.into())
@@ -119,6 +136,7 @@ mod unit {
             },
             reason_string: Some("Biwi".into()),
             user_properties: vec![("Mogwaï".into(), "Cat".into())],
+            raw_properties: None,
         }
     }
 
@@ -134,7 +152,34 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = Auth::read(&mut test_data).await.unwrap();
+        let tested_result = Auth::read(&mut test_data, encoded().len(), &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
+
+    #[tokio::test]
+    async fn decode_shortened() {
+        let mut test_data = Cursor::new(Vec::new());
+        let auth = Auth::read(&mut test_data, 0, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(auth, Auth::default());
+    }
+
+    #[tokio::test]
+    async fn redacted() {
+        let options = DecodeOptions {
+            retain_raw_properties: true,
+            ..Default::default()
+        };
+        let mut test_data = Cursor::new(encoded());
+        let auth = Auth::read(&mut test_data, encoded().len(), &options)
+            .await
+            .unwrap();
+        assert!(auth.raw_properties.is_some());
+        let debug = format!("{:?}", auth);
+        assert!(debug.contains("AuthenticationData(<redacted>)"));
+        assert!(!debug.contains("[13, 21, 234, 94]"));
+    }
 }
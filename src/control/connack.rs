@@ -5,20 +5,22 @@ use crate::{
         DEFAULT_SHARED_SUBSCRIPTION_AVAILABLE, DEFAULT_SUBSCRIPTION_IDENTIFIER_AVAILABLE,
         DEFAULT_TOPIC_ALIAS_MAXIMUM, DEFAULT_WILCARD_SUBSCRIPTION_AVAILABLE,
     },
-    Authentication, ClientID, PropertiesDecoder, Property, QoS,
+    duration, Authentication, ClientID, Connect, DecodeOptions, Expiry, Properties,
+    PropertiesDecoder, Property, QoS,
     ReasonCode::{self, ProtocolError},
-    Result as SageResult,
+    Result as SageResult, Saturation, ServerReference,
 };
-use std::{convert::TryInto, marker::Unpin};
+use std::{marker::Unpin, time::Duration};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 /// The `Connack` message is sent from the server to the client to acknowledge
 /// the connection request. This can be the direct response to a `Connect`
 /// message or the closing exchange of `Connack` packets.
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct ConnAck {
     /// If the `session_present` is true, the connection is accepted using a
-    /// previously and unexpired session.
+    /// previously and unexpired session. It must be false if the connection
+    /// is refused, i.e. if the `reason_code` is not `Success`.
     pub session_present: bool,
 
     /// The reason code for the connect acknowledgement.
@@ -35,9 +37,10 @@ pub struct ConnAck {
     /// - `ServerBusy`
     pub reason_code: ReasonCode,
 
-    /// The session expiry interval the server will use. If absent the server
-    /// simply accepted the value sent by the server in the `Connect` packet.
-    pub session_expiry_interval: Option<u32>,
+    /// The session expiry interval the server will use. If `Default` the
+    /// server simply accepted the value sent by the client in the `Connect`
+    /// packet.
+    pub session_expiry_interval: Expiry,
 
     /// The maximum number of `AtLeastOnce` and `ExactlyOnce` qualities of
     /// service the server will concurrently treat for the client.
@@ -99,6 +102,10 @@ pub struct ConnAck {
     /// Upon using enhanced connexion, ending the `Connack` exchange will result in
     /// a `ConnAck` packet which may contain `Authentication` data.
     pub authentication: Option<Authentication>,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for ConnAck {
@@ -106,7 +113,7 @@ impl Default for ConnAck {
         ConnAck {
             session_present: false,
             reason_code: ReasonCode::Success,
-            session_expiry_interval: None,
+            session_expiry_interval: Expiry::Default,
             receive_maximum: DEFAULT_RECEIVE_MAXIMUM,
             maximum_qos: DEFAULT_MAXIMUM_QOS,
             retain_available: DEFAULT_RETAIN_AVAILABLE,
@@ -122,18 +129,260 @@ impl Default for ConnAck {
             response_information: Default::default(),
             reference: None,
             authentication: None,
+            raw_properties: None,
+        }
+    }
+}
+
+/// A builder for `ConnAck` packets, obtained with `ConnAck::builder()`.
+/// Each property that is not explicitly set keeps its default value.
+///
+/// Servers can use `assign_client_id_with` to generate a client identifier
+/// only when the `Connect` packet they respond to did not provide one:
+///
+/// ```
+/// use sage_mqtt::{ConnAck, Connect};
+///
+/// let connect = Connect::default();
+/// let connack = ConnAck::builder()
+///     .assign_client_id_with(&connect, || "Jaden".into())
+///     .server_keep_alive(30)
+///     .build();
+/// assert_eq!(connack.assigned_client_id, Some("Jaden".into()));
+/// assert_eq!(connack.keep_alive, Some(30));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct ConnAckBuilder {
+    connack: ConnAck,
+}
+
+impl ConnAckBuilder {
+    /// Sets whether the connection is accepted using an existing session.
+    pub fn session_present(mut self, session_present: bool) -> Self {
+        self.connack.session_present = session_present;
+        self
+    }
+
+    /// Sets the reason code of the acknowledgement.
+    pub fn reason_code(mut self, reason_code: ReasonCode) -> Self {
+        self.connack.reason_code = reason_code;
+        self
+    }
+
+    /// Sets the session expiry interval the server will use.
+    pub fn session_expiry_interval(mut self, session_expiry_interval: Expiry) -> Self {
+        self.connack.session_expiry_interval = session_expiry_interval;
+        self
+    }
+
+    /// Sets the maximum number of concurrent `AtLeastOnce` and `ExactlyOnce`
+    /// messages the server will process.
+    pub fn receive_maximum(mut self, receive_maximum: u16) -> Self {
+        self.connack.receive_maximum = receive_maximum;
+        self
+    }
+
+    /// Sets the maximum quality of service the server accepts.
+    pub fn maximum_qos(mut self, maximum_qos: QoS) -> Self {
+        self.connack.maximum_qos = maximum_qos;
+        self
+    }
+
+    /// Sets whether the server supports retained messages.
+    pub fn retain_available(mut self, retain_available: bool) -> Self {
+        self.connack.retain_available = retain_available;
+        self
+    }
+
+    /// Sets the maximum packet size the server accepts.
+    pub fn maximum_packet_size(mut self, maximum_packet_size: u32) -> Self {
+        self.connack.maximum_packet_size = Some(maximum_packet_size);
+        self
+    }
+
+    /// Sets the client identifier assigned by the server.
+    pub fn assigned_client_id(mut self, client_id: ClientID) -> Self {
+        self.connack.assigned_client_id = Some(client_id);
+        self
+    }
+
+    /// Calls `generator` to assign a client identifier if `connect` does not
+    /// provide any (or provides an empty one). Otherwise, the assigned client
+    /// identifier is left untouched.
+    pub fn assign_client_id_with<F>(mut self, connect: &Connect, generator: F) -> Self
+    where
+        F: FnOnce() -> ClientID,
+    {
+        let has_client_id = matches!(&connect.client_id, Some(id) if !id.is_empty());
+        if !has_client_id {
+            self.connack.assigned_client_id = Some(generator());
         }
+        self
+    }
+
+    /// Sets the maximum value the server accepts as topic alias.
+    pub fn topic_alias_maximum(mut self, topic_alias_maximum: u16) -> Self {
+        self.connack.topic_alias_maximum = topic_alias_maximum;
+        self
+    }
+
+    /// Sets the human readable reason string.
+    pub fn reason_string<S: Into<String>>(mut self, reason_string: S) -> Self {
+        self.connack.reason_string = Some(reason_string.into());
+        self
+    }
+
+    /// Appends a user property.
+    pub fn user_property<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.connack
+            .user_properties
+            .push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets whether the server accepts wildcard subscriptions.
+    pub fn wildcard_subscription_available(mut self, available: bool) -> Self {
+        self.connack.wildcard_subscription_available = available;
+        self
+    }
+
+    /// Sets whether the server accepts subscription identifiers.
+    pub fn subscription_identifiers_available(mut self, available: bool) -> Self {
+        self.connack.subscription_identifiers_available = available;
+        self
+    }
+
+    /// Sets whether the server accepts shared subscriptions.
+    pub fn shared_subscription_available(mut self, available: bool) -> Self {
+        self.connack.shared_subscription_available = available;
+        self
+    }
+
+    /// Overrides the keep alive value requested by the client.
+    pub fn server_keep_alive(mut self, keep_alive: u16) -> Self {
+        self.connack.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Sets the response information sent to the client.
+    pub fn response_information<S: Into<String>>(mut self, response_information: S) -> Self {
+        self.connack.response_information = Some(response_information.into());
+        self
+    }
+
+    /// Sets the server reference the client should connect to instead.
+    pub fn reference<S: Into<String>>(mut self, reference: S) -> Self {
+        self.connack.reference = Some(reference.into());
+        self
+    }
+
+    /// Sets the enhanced authentication data.
+    pub fn authentication(mut self, authentication: Authentication) -> Self {
+        self.connack.authentication = Some(authentication);
+        self
+    }
+
+    /// Builds the `ConnAck` packet.
+    pub fn build(self) -> ConnAck {
+        self.connack
     }
 }
 
 impl ConnAck {
+    /// Returns a `ConnAckBuilder` initialized with default values.
+    pub fn builder() -> ConnAckBuilder {
+        ConnAckBuilder::default()
+    }
+
+    /// Creates a `ConnAck` packet refusing the connection with the given
+    /// reason code. No session is ever present in a refused connection.
+    pub fn rejection(reason_code: ReasonCode) -> Self {
+        ConnAck {
+            session_present: false,
+            reason_code,
+            ..Default::default()
+        }
+    }
+
+    /// Checks the consistency of the packet fields.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if `session_present` is set although the
+    /// `reason_code` is not `Success`, or if `receive_maximum` is zero.
+    pub fn validate(&self) -> SageResult<()> {
+        if (self.session_present && self.reason_code != ReasonCode::Success)
+            || self.receive_maximum == 0
+        {
+            Err(ProtocolError.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Creates a `ConnAck` packet temporarily redirecting the client to
+    /// another server, using the `UseAnotherServer` reason code.
+    pub fn redirect_to<S: Into<String>>(reference: S) -> Self {
+        ConnAck {
+            reason_code: ReasonCode::UseAnotherServer,
+            reference: Some(reference.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a `ConnAck` packet permanently redirecting the client to
+    /// another server, using the `ServerMoved` reason code.
+    pub fn moved_to<S: Into<String>>(reference: S) -> Self {
+        ConnAck {
+            reason_code: ReasonCode::ServerMoved,
+            reference: Some(reference.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Parses the server reference, if any, into the list of servers the
+    /// client is redirected to.
+    pub fn server_references(&self) -> SageResult<Vec<ServerReference>> {
+        match &self.reference {
+            Some(reference) => ServerReference::parse_list(reference),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the session expiry interval as a `Duration` if it is expressed
+    /// in seconds.
+    pub fn session_expiry_interval_duration(&self) -> Option<Duration> {
+        self.session_expiry_interval.as_duration()
+    }
+
+    /// Sets the session expiry interval from a `Duration`. A saturated
+    /// interval means the session never expires.
+    pub fn set_session_expiry_interval_duration(&mut self, interval: Duration) -> Saturation {
+        let (secs, saturation) = duration::to_u32_seconds(interval);
+        self.session_expiry_interval = secs.into();
+        saturation
+    }
+
+    /// Returns the keep alive imposed by the server as a `Duration`, if any.
+    pub fn keep_alive_duration(&self) -> Option<Duration> {
+        self.keep_alive.map(|secs| Duration::from_secs(secs as u64))
+    }
+
+    /// Sets the keep alive imposed by the server from a `Duration`.
+    pub fn set_keep_alive_duration(&mut self, keep_alive: Duration) -> Saturation {
+        let (secs, saturation) = duration::to_u16_seconds(keep_alive);
+        self.keep_alive = Some(secs);
+        saturation
+    }
+
     pub(crate) async fn write<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
+        self.validate()?;
         let mut n_bytes = codec::write_bool(self.session_present, writer).await?;
         n_bytes += codec::write_reason_code(self.reason_code, writer).await?;
 
         let mut properties = Vec::new();
 
-        if let Some(v) = self.session_expiry_interval {
+        if let Some(v) = self.session_expiry_interval.to_seconds() {
             n_bytes += Property::SessionExpiryInterval(v)
                 .encode(&mut properties)
                 .await?;
@@ -206,12 +455,15 @@ impl ConnAck {
         Ok(n_bytes)
     }
 
-    pub(crate) async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> SageResult<Self> {
+    pub(crate) async fn read<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        options: &DecodeOptions,
+    ) -> SageResult<Self> {
         let session_present = codec::read_bool(reader).await?;
 
-        let reason_code = codec::read_byte(reader).await?.try_into()?;
+        let reason_code = codec::read_reason_code(reader, options).await?;
 
-        let mut session_expiry_interval = None;
+        let mut session_expiry_interval = Expiry::Default;
         let mut receive_maximum = DEFAULT_RECEIVE_MAXIMUM;
         let mut maximum_qos = DEFAULT_MAXIMUM_QOS;
         let mut retain_available = DEFAULT_RETAIN_AVAILABLE;
@@ -229,10 +481,10 @@ impl ConnAck {
         let mut authentication_method = None;
         let mut authentication_data = Default::default();
 
-        let mut decoder = PropertiesDecoder::take(reader).await?;
+        let mut decoder = PropertiesDecoder::take_with(reader, options).await?;
         while decoder.has_properties() {
             match decoder.read().await? {
-                Property::SessionExpiryInterval(v) => session_expiry_interval = Some(v),
+                Property::SessionExpiryInterval(v) => session_expiry_interval = v.into(),
                 Property::ReceiveMaximum(v) => receive_maximum = v,
                 Property::MaximumQoS(v) => maximum_qos = v,
                 Property::RetainAvailable(v) => retain_available = v,
@@ -256,6 +508,7 @@ impl ConnAck {
                 _ => return Err(ProtocolError.into()),
             }
         }
+        let raw_properties = decoder.raw_properties();
 
         let authentication = if let Some(method) = authentication_method {
             Some(Authentication {
@@ -269,7 +522,7 @@ impl ConnAck {
             None
         };
 
-        Ok(ConnAck {
+        let connack = ConnAck {
             session_present,
             reason_code,
             session_expiry_interval,
@@ -288,7 +541,10 @@ impl ConnAck {
             response_information,
             reference,
             authentication,
-        })
+            raw_properties,
+        };
+        connack.validate()?;
+        Ok(connack)
     }
 }
 
@@ -300,7 +556,7 @@ mod unit {
 
     fn encoded() -> Vec<u8> {
         vec![
-            1, 138, 111, 17, 0, 0, 5, 57, 33, 0, 30, 36, 1, 37, 0, 39, 0, 0, 1, 0, 18, 0, 11, 87,
+            0, 138, 111, 17, 0, 0, 5, 57, 33, 0, 30, 36, 1, 37, 0, 39, 0, 0, 1, 0, 18, 0, 11, 87,
             97, 108, 107, 84, 104, 105, 115, 87, 97, 121, 34, 0, 10, 31, 0, 7, 82, 85, 78, 45, 68,
             77, 67, 38, 0, 7, 77, 111, 103, 119, 97, 195, 175, 0, 3, 67, 97, 116, 40, 0, 42, 0, 19,
             0, 17, 26, 0, 9, 65, 101, 114, 111, 115, 109, 105, 116, 104, 28, 0, 14, 80, 97, 105,
@@ -311,9 +567,9 @@ mod unit {
 
     fn decoded() -> ConnAck {
         ConnAck {
-            session_present: true,
+            session_present: false,
             reason_code: ReasonCode::Banned,
-            session_expiry_interval: Some(1337),
+            session_expiry_interval: Expiry::Seconds(1337),
             receive_maximum: 30,
             maximum_qos: QoS::AtLeastOnce,
             retain_available: false,
@@ -332,6 +588,7 @@ mod unit {
                 method: "Willow".into(),
                 data: vec![0x0D, 0x15, 0xEA, 0x5E],
             }),
+            raw_properties: None,
         }
     }
 
@@ -347,7 +604,62 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = ConnAck::read(&mut test_data).await.unwrap();
+        let tested_result = ConnAck::read(&mut test_data, &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
+
+    #[tokio::test]
+    async fn session_present_on_rejection() {
+        let test_data = ConnAck {
+            session_present: true,
+            ..ConnAck::rejection(ReasonCode::NotAuthorized)
+        };
+        assert!(test_data.validate().is_err());
+        assert!(test_data.write(&mut Vec::new()).await.is_err());
+
+        let mut encoded = encoded();
+        encoded[0] = 1;
+        let mut test_data = Cursor::new(encoded);
+        assert!(ConnAck::read(&mut test_data, &Default::default())
+            .await
+            .is_err());
+
+        assert!(ConnAck::rejection(ReasonCode::NotAuthorized)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn zero_receive_maximum() {
+        let test_data = ConnAck {
+            receive_maximum: 0,
+            ..Default::default()
+        };
+        assert!(test_data.validate().is_err());
+    }
+
+    #[test]
+    fn builder_default() {
+        assert_eq!(ConnAck::builder().build(), ConnAck::default());
+    }
+
+    #[test]
+    fn builder_assigned_client_id() {
+        for (client_id, assigned) in [
+            (None, Some("Jaden".into())),
+            (Some("".into()), Some("Jaden".into())),
+            (Some("Willow".into()), None),
+        ] {
+            let connect = Connect {
+                client_id,
+                ..Default::default()
+            };
+            let connack = ConnAck::builder()
+                .assign_client_id_with(&connect, || "Jaden".into())
+                .build();
+            assert_eq!(connack.assigned_client_id, assigned);
+        }
+    }
 }
@@ -5,11 +5,12 @@ use crate::{
         DEFAULT_REQUEST_PROBLEM_INFORMATION, DEFAULT_REQUEST_RESPONSE_INFORMATION,
         DEFAULT_TOPIC_ALIAS_MAXIMUM, DEFAULT_WILL_DELAY_INTERVAL,
     },
-    Authentication, ClientID, PropertiesDecoder, Property, QoS,
-    ReasonCode::{ClientIdentifierNotValid, MalformedPacket, ProtocolError},
-    Result as SageResult, Topic, Will,
+    duration, Authentication, ClientID, DecodeOptions, Expiry, Properties, PropertiesDecoder,
+    Property, QoS,
+    ReasonCode::{BadUserNameOrPassword, ClientIdentifierNotValid, MalformedPacket, ProtocolError},
+    Redacted, Result as SageResult, Saturation, Will,
 };
-use std::{convert::TryInto, marker::Unpin};
+use std::{convert::TryInto, fmt, marker::Unpin, time::Duration};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 /// The `Connect` control packet is used to open a session. It is the first
@@ -32,7 +33,12 @@ use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 /// to the server by setting `client_id` to either `None` or an empty string.
 /// In that case the server will decide itself for an identifier and return
 /// it into the _CONNACK_ packet.
-#[derive(PartialEq, Debug, Clone)]
+///
+/// # Debug output
+///
+/// The `password` and the authentication data are never printed using
+/// `Debug`, so that `Connect` packets can safely be logged.
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub struct Connect {
     /// If set, the server will start a new session and drop any existing one
     /// if any.
@@ -41,7 +47,9 @@ pub struct Connect {
     /// An optional user name to send to the server.
     pub user_name: Option<String>,
 
-    /// An option password to send to the server.
+    /// An option password to send to the server. A password can be sent
+    /// without any `user_name`, unless the server decodes packets with
+    /// `DecodeOptions::reject_password_without_user_name`.
     pub password: Option<Vec<u8>>,
 
     /// Specifies the maximum amount of time the client and the server may not
@@ -55,11 +63,13 @@ pub struct Connect {
 
     /// Once the connection is closed, the client and server still keep the
     /// session active during a certain amount of time expressed in seconds.
-    /// - If the value is `0` (default) the session ends when the connection is closed.
-    /// - If the value is `0xFFFFFFFF` the session never expires.
+    /// - If `Default` or `Seconds(0)` the session ends when the connection is
+    ///   closed.
+    /// - If `Never` the session never expires.
+    ///
     /// The client can override the session expiry interval within the
     /// DISCONNECT packet.
-    pub session_expiry_interval: Option<u32>,
+    pub session_expiry_interval: Expiry,
 
     /// This value sets the maximum number of _AtLeastOnce_ and _ExactlyOnce_
     /// packets that should be processed concurrently.
@@ -121,6 +131,10 @@ pub struct Connect {
     /// The client's Last Will to send in case of ungraceful disconnection.
     /// This is optional and default is `None`.
     pub will: Option<Will>,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for Connect {
@@ -130,7 +144,7 @@ impl Default for Connect {
             user_name: None,
             password: Default::default(),
             keep_alive: 600,
-            session_expiry_interval: None,
+            session_expiry_interval: Expiry::Default,
             receive_maximum: DEFAULT_RECEIVE_MAXIMUM,
             maximum_packet_size: None,
             topic_alias_maximum: DEFAULT_TOPIC_ALIAS_MAXIMUM,
@@ -140,10 +154,39 @@ impl Default for Connect {
             authentication: None,
             client_id: None,
             will: None,
+            raw_properties: None,
         }
     }
 }
 
+impl fmt::Debug for Connect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connect")
+            .field("clean_start", &self.clean_start)
+            .field("user_name", &self.user_name)
+            .field("password", &self.password.as_ref().map(|_| Redacted))
+            .field("keep_alive", &self.keep_alive)
+            .field("session_expiry_interval", &self.session_expiry_interval)
+            .field("receive_maximum", &self.receive_maximum)
+            .field("maximum_packet_size", &self.maximum_packet_size)
+            .field("topic_alias_maximum", &self.topic_alias_maximum)
+            .field(
+                "request_response_information",
+                &self.request_response_information,
+            )
+            .field(
+                "request_problem_information",
+                &self.request_problem_information,
+            )
+            .field("user_properties", &self.user_properties)
+            .field("authentication", &self.authentication)
+            .field("client_id", &self.client_id)
+            .field("will", &self.will)
+            .field("raw_properties", &self.raw_properties)
+            .finish()
+    }
+}
+
 #[derive(Debug)]
 struct ConnectFlags {
     pub clean_start: bool,
@@ -155,6 +198,37 @@ struct ConnectFlags {
 }
 
 impl Connect {
+    /// Returns the keep alive as a `Duration`, or `None` if the keep alive
+    /// mechanism is deactivated.
+    pub fn keep_alive_duration(&self) -> Option<Duration> {
+        match self.keep_alive {
+            0 => None,
+            secs => Some(Duration::from_secs(secs as u64)),
+        }
+    }
+
+    /// Sets the keep alive from a `Duration`. A zero duration deactivates the
+    /// keep alive mechanism.
+    pub fn set_keep_alive_duration(&mut self, keep_alive: Duration) -> Saturation {
+        let (secs, saturation) = duration::to_u16_seconds(keep_alive);
+        self.keep_alive = secs;
+        saturation
+    }
+
+    /// Returns the session expiry interval as a `Duration` if it is expressed
+    /// in seconds.
+    pub fn session_expiry_interval_duration(&self) -> Option<Duration> {
+        self.session_expiry_interval.as_duration()
+    }
+
+    /// Sets the session expiry interval from a `Duration`. A saturated
+    /// interval means the session never expires.
+    pub fn set_session_expiry_interval_duration(&mut self, interval: Duration) -> Saturation {
+        let (secs, saturation) = duration::to_u32_seconds(interval);
+        self.session_expiry_interval = secs.into();
+        saturation
+    }
+
     pub(crate) async fn write<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
         // Variable Header (into content)
         let mut n_bytes = codec::write_utf8_string("MQTT", writer).await?;
@@ -183,7 +257,7 @@ impl Connect {
 
         // Properties
         let mut properties = Vec::new();
-        if let Some(session_expiry_interval) = self.session_expiry_interval {
+        if let Some(session_expiry_interval) = self.session_expiry_interval.to_seconds() {
             n_bytes += Property::SessionExpiryInterval(session_expiry_interval)
                 .encode(&mut properties)
                 .await?;
@@ -259,7 +333,7 @@ impl Connect {
             n_bytes += codec::write_variable_byte_integer(properties.len() as u32, writer).await?;
             writer.write_all(&properties).await?;
 
-            n_bytes += codec::write_utf8_string(&w.topic.to_string(), writer).await?;
+            n_bytes += codec::write_utf8_string(w.topic.as_str(), writer).await?;
             n_bytes += codec::write_binary_data(&w.message, writer).await?;
         }
 
@@ -274,7 +348,10 @@ impl Connect {
         Ok(n_bytes)
     }
 
-    pub(crate) async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> SageResult<Self> {
+    pub(crate) async fn read<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        options: &DecodeOptions,
+    ) -> SageResult<Self> {
         let protocol_name = codec::read_utf8_string(reader).await?;
         if protocol_name != "MQTT" {
             return Err(MalformedPacket.into());
@@ -286,12 +363,15 @@ impl Connect {
         }
 
         let flags = ConnectFlags::read(reader).await?;
+        if flags.password && !flags.user_name && options.reject_password_without_user_name {
+            return Err(BadUserNameOrPassword.into());
+        }
 
         let clean_start = flags.clean_start;
 
         let keep_alive = codec::read_two_byte_integer(reader).await?;
 
-        let mut session_expiry_interval = None;
+        let mut session_expiry_interval = Expiry::Default;
         let mut receive_maximum = DEFAULT_RECEIVE_MAXIMUM;
         let mut maximum_packet_size = None;
         let mut topic_alias_maximum = DEFAULT_TOPIC_ALIAS_MAXIMUM;
@@ -301,11 +381,11 @@ impl Connect {
         let mut authentication_method = None;
         let mut authentication_data = Default::default();
 
-        let mut decoder = PropertiesDecoder::take(reader).await?;
+        let mut decoder = PropertiesDecoder::take_with(reader, options).await?;
 
         while decoder.has_properties() {
             match decoder.read().await? {
-                Property::SessionExpiryInterval(v) => session_expiry_interval = Some(v),
+                Property::SessionExpiryInterval(v) => session_expiry_interval = v.into(),
                 Property::ReceiveMaximum(v) => receive_maximum = v,
                 Property::MaximumPacketSize(v) => maximum_packet_size = Some(v),
                 Property::TopicAliasMaximum(v) => topic_alias_maximum = v,
@@ -317,6 +397,7 @@ impl Connect {
                 _ => return Err(ProtocolError.into()),
             };
         }
+        let raw_properties = decoder.raw_properties();
         let reader = decoder.into_inner();
 
         let authentication = if let Some(method) = authentication_method {
@@ -367,7 +448,7 @@ impl Connect {
                 }
             }
             let reader = decoder.into_inner();
-            let topic = Topic::from(codec::read_utf8_string(reader).await?);
+            let topic = codec::read_utf8_string(reader).await?.try_into()?;
             let message = codec::read_binary_data(reader).await?;
             (
                 reader,
@@ -416,6 +497,7 @@ impl Connect {
             user_properties,
             client_id,
             will,
+            raw_properties,
         })
     }
 }
@@ -434,7 +516,8 @@ impl ConnectFlags {
     pub(crate) async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> SageResult<Self> {
         let bits = codec::read_byte(reader).await?;
 
-        if bits & 0x01 != 0 {
+        let will = bits & 0b0000_0100 > 0;
+        if bits & 0x01 != 0 || (!will && bits & 0b0011_1000 != 0) {
             Err(MalformedPacket.into())
         } else {
             Ok(ConnectFlags {
@@ -453,6 +536,7 @@ impl ConnectFlags {
 mod unit {
 
     use super::*;
+    use crate::Error;
     use std::io::Cursor;
 
     fn encoded() -> Vec<u8> {
@@ -465,7 +549,7 @@ mod unit {
 
     fn decoded() -> Connect {
         let keep_alive = 10;
-        let session_expiry_interval = Some(10);
+        let session_expiry_interval = Expiry::Seconds(10);
 
         Connect {
             keep_alive,
@@ -475,7 +559,7 @@ mod unit {
             password: Some("Jaden".into()),
             will: Some(Will {
                 qos: QoS::AtLeastOnce,
-                ..Will::with_message(Topic::from("CloZee"), "Oregon")
+                ..Will::with_message("CloZee".try_into().unwrap(), "Oregon")
             }),
             ..Default::default()
         }
@@ -500,7 +584,9 @@ mod unit {
     #[tokio::test]
     async fn decode_default_auth() {
         let mut test_data = Cursor::new(vec![0, 4, 77, 81, 84, 84, 5, 0, 2, 88, 3, 21, 0, 0, 0, 0]);
-        let tested_result = Connect::read(&mut test_data).await.unwrap();
+        let tested_result = Connect::read(&mut test_data, &Default::default())
+            .await
+            .unwrap();
         assert_eq!(
             tested_result,
             Connect {
@@ -523,7 +609,83 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = Connect::read(&mut test_data).await.unwrap();
+        let tested_result = Connect::read(&mut test_data, &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
+
+    #[tokio::test]
+    async fn decode_password_without_user_name() {
+        let test_data = Connect {
+            password: Some("Jaden".into()),
+            ..Default::default()
+        };
+        let mut encoded = Vec::new();
+        test_data.clone().write(&mut encoded).await.unwrap();
+
+        let tested_result = Connect::read(&mut Cursor::new(&encoded), &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(tested_result, test_data);
+
+        let options = DecodeOptions {
+            reject_password_without_user_name: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            Connect::read(&mut Cursor::new(&encoded), &options).await,
+            Err(Error::Reason(BadUserNameOrPassword))
+        ));
+    }
+
+    #[tokio::test]
+    async fn decode_will_flags_without_will() {
+        let mut test_data = Cursor::new(vec![0, 4, 77, 81, 84, 84, 5, 0b0010_0000, 0, 0, 0, 0, 0]);
+        assert!(matches!(
+            Connect::read(&mut test_data, &Default::default()).await,
+            Err(Error::Reason(MalformedPacket))
+        ));
+    }
+
+    #[test]
+    fn keep_alive_duration() {
+        let mut test_data = Connect::default();
+        assert_eq!(
+            test_data.set_keep_alive_duration(Duration::from_secs(90)),
+            Saturation::Exact
+        );
+        assert_eq!(test_data.keep_alive, 90);
+        assert_eq!(
+            test_data.keep_alive_duration(),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            test_data.set_keep_alive_duration(Duration::from_secs(100_000)),
+            Saturation::Saturated
+        );
+        assert_eq!(test_data.keep_alive, u16::MAX);
+        assert_eq!(
+            test_data.set_keep_alive_duration(Duration::ZERO),
+            Saturation::Exact
+        );
+        assert_eq!(test_data.keep_alive_duration(), None);
+    }
+
+    #[test]
+    fn debug_redacts_credentials() {
+        let test_data = Connect {
+            authentication: Some(Authentication {
+                method: "Willow".into(),
+                data: vec![0x0D, 0x15, 0xEA, 0x5E],
+            }),
+            ..decoded()
+        };
+        let output = format!("{:?}", test_data);
+        assert!(output.contains(r#"user_name: Some("Willow")"#));
+        assert!(output.contains("password: Some(<redacted>)"));
+        assert!(output.contains("data: <redacted>"));
+        assert!(!output.contains("74, 97, 100, 101, 110"));
+        assert!(!output.contains("13, 21, 234, 94"));
+    }
 }
@@ -1,14 +1,14 @@
 use crate::{
-    codec, PropertiesDecoder, Property,
+    codec, duration, DecodeOptions, Expiry, Properties, PropertiesDecoder, Property,
     ReasonCode::{self, ProtocolError},
-    Result as SageResult,
+    Result as SageResult, Saturation, ServerReference,
 };
-use std::{convert::TryInto, marker::Unpin};
+use std::{marker::Unpin, time::Duration};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 /// A `Disconnect` packet can be sent by the client or the server to gracefully
 /// disconnect.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Disconnect {
     /// The reason code code the `Disconnect` notice.can be any of:
     /// - Client or Server
@@ -46,9 +46,9 @@ pub struct Disconnect {
     pub reason_code: ReasonCode,
 
     /// `session_expiry_interval` can be used to override the session expiry
-    /// period formerly set upon connection. If not present, the session expiry
+    /// period formerly set upon connection. If `Default`, the session expiry
     /// interval value set using `Connect` or `Connack` is still in use.
-    pub session_expiry_interval: Option<u32>,
+    pub session_expiry_interval: Expiry,
 
     /// An optional descriptin of the reason for deconnecting.
     pub reason_string: Option<String>,
@@ -60,6 +60,10 @@ pub struct Disconnect {
     /// `reference` field is used to inform the client about why new server to
     /// connect to instead.
     pub reference: Option<String>,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for Disconnect {
@@ -67,20 +71,64 @@ impl Default for Disconnect {
         Disconnect {
             reason_code: ReasonCode::Success,
             reason_string: None,
-            session_expiry_interval: None,
+            session_expiry_interval: Expiry::Default,
             user_properties: Default::default(),
             reference: None,
+            raw_properties: None,
         }
     }
 }
 
 impl Disconnect {
+    /// Creates a `Disconnect` packet temporarily redirecting the client to
+    /// another server, using the `UseAnotherServer` reason code.
+    pub fn redirect_to<S: Into<String>>(reference: S) -> Self {
+        Disconnect {
+            reason_code: ReasonCode::UseAnotherServer,
+            reference: Some(reference.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a `Disconnect` packet permanently redirecting the client to
+    /// another server, using the `ServerMoved` reason code.
+    pub fn moved_to<S: Into<String>>(reference: S) -> Self {
+        Disconnect {
+            reason_code: ReasonCode::ServerMoved,
+            reference: Some(reference.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Parses the server reference, if any, into the list of servers the
+    /// client is redirected to.
+    pub fn server_references(&self) -> SageResult<Vec<ServerReference>> {
+        match &self.reference {
+            Some(reference) => ServerReference::parse_list(reference),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the session expiry interval as a `Duration` if it is expressed
+    /// in seconds.
+    pub fn session_expiry_interval_duration(&self) -> Option<Duration> {
+        self.session_expiry_interval.as_duration()
+    }
+
+    /// Sets the session expiry interval from a `Duration`. A saturated
+    /// interval means the session never expires.
+    pub fn set_session_expiry_interval_duration(&mut self, interval: Duration) -> Saturation {
+        let (secs, saturation) = duration::to_u32_seconds(interval);
+        self.session_expiry_interval = secs.into();
+        saturation
+    }
+
     pub(crate) async fn write<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
         let mut n_bytes = codec::write_reason_code(self.reason_code, writer).await?;
 
         let mut properties = Vec::new();
 
-        if let Some(v) = self.session_expiry_interval {
+        if let Some(v) = self.session_expiry_interval.to_seconds() {
             n_bytes += Property::SessionExpiryInterval(v)
                 .encode(&mut properties)
                 .await?;
@@ -101,32 +149,33 @@ impl Disconnect {
         Ok(n_bytes)
     }
 
-    pub(crate) async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> SageResult<Self> {
-        let reason_code = codec::read_byte(reader).await?.try_into()?;
-
-        let mut user_properties = Vec::new();
-        let mut properties = PropertiesDecoder::take(reader).await?;
-        let mut session_expiry_interval = None;
-        let mut reason_string = None;
-        let mut reference = None;
-
-        while properties.has_properties() {
-            match properties.read().await? {
-                Property::SessionExpiryInterval(v) => session_expiry_interval = Some(v),
-                Property::ReasonString(v) => reason_string = Some(v),
-                Property::UserProperty(k, v) => user_properties.push((k, v)),
-                Property::ServerReference(v) => reference = Some(v),
-                _ => return Err(ProtocolError.into()),
+    pub(crate) async fn read<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        remaining_size: usize,
+        options: &DecodeOptions,
+    ) -> SageResult<Self> {
+        let mut disconnect = Disconnect::default();
+        if remaining_size > 0 {
+            disconnect.reason_code = codec::read_reason_code(reader, options).await?;
+        }
+
+        if remaining_size > 1 {
+            let mut properties = PropertiesDecoder::take_with(reader, options).await?;
+            while properties.has_properties() {
+                match properties.read().await? {
+                    Property::SessionExpiryInterval(v) => {
+                        disconnect.session_expiry_interval = v.into()
+                    }
+                    Property::ReasonString(v) => disconnect.reason_string = Some(v),
+                    Property::UserProperty(k, v) => disconnect.user_properties.push((k, v)),
+                    Property::ServerReference(v) => disconnect.reference = Some(v),
+                    _ => return Err(ProtocolError.into()),
+                }
             }
+            disconnect.raw_properties = properties.raw_properties();
         }
 
-        Ok(Disconnect {
-            reason_code,
-            session_expiry_interval,
-            reason_string,
-            user_properties,
-            reference,
-        })
+        Ok(disconnect)
     }
 }
 
@@ -148,13 +197,14 @@ mod unit {
     fn decoded() -> Disconnect {
         Disconnect {
             reason_code: ReasonCode::MessageRateTooHigh,
-            session_expiry_interval: Some(1337),
+            session_expiry_interval: Expiry::Seconds(1337),
             reason_string: Some("Lose Yourself to Dance".into()),
             user_properties: vec![
                 ("Daft".into(), "Punk".into()),
                 ("Pharrell".into(), "Williams".into()),
             ],
             reference: Some("Come on".into()),
+            raw_properties: None,
         }
     }
 
@@ -170,7 +220,38 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = Disconnect::read(&mut test_data).await.unwrap();
+        let tested_result = Disconnect::read(&mut test_data, encoded().len(), &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
+
+    #[tokio::test]
+    async fn decode_shortened() {
+        let mut test_data = Cursor::new(Vec::new());
+        let disconnect = Disconnect::read(&mut test_data, 0, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(disconnect, Disconnect::default());
+
+        let mut test_data = Cursor::new(encoded()[..1].to_vec());
+        let disconnect = Disconnect::read(&mut test_data, 1, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(disconnect.reason_code, decoded().reason_code);
+        assert_eq!(disconnect.reason_string, None);
+    }
+
+    #[test]
+    fn redirect_to() {
+        let disconnect = Disconnect::redirect_to("myserver.xyz.org:8883");
+        assert_eq!(disconnect.reason_code, ReasonCode::UseAnotherServer);
+        assert_eq!(
+            disconnect.server_references().unwrap(),
+            vec![ServerReference {
+                host: "myserver.xyz.org".into(),
+                port: Some(8883)
+            }]
+        );
+    }
 }
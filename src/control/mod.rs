@@ -24,7 +24,7 @@ mod unsubscribe;
 pub type ClientID = String;
 
 pub use auth::Auth;
-pub use connack::ConnAck;
+pub use connack::{ConnAck, ConnAckBuilder};
 pub use connect::Connect;
 pub use disconnect::Disconnect;
 pub use puback::PubAck;
@@ -37,8 +37,24 @@ pub use subscribe::{RetainHandling, Subscribe, SubscriptionOptions};
 pub use unsuback::UnSubAck;
 pub use unsubscribe::UnSubscribe;
 
+/// The wire form used to encode `PubAck`, `PubRec`, `PubRel` and `PubComp`
+/// packets.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum AckEncoding {
+    /// The packet is shortened to its packet identifier when the reason code
+    /// is `Success` and there are no properties.
+    #[default]
+    Short,
+
+    /// The reason code and properties are always encoded, even if they could
+    /// be omitted.
+    Full,
+}
+
 /// A ping request message
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct PingReq;
 
 /// A ping response message
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct PingResp;
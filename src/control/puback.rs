@@ -1,14 +1,15 @@
 use crate::{
-    codec, PropertiesDecoder, Property,
+    codec, AckEncoding, DecodeOptions, PacketType, Properties, PropertiesDecoder, Property,
+    Publish, QoS,
     ReasonCode::{self, ProtocolError},
     Result as SageResult,
 };
-use std::{convert::TryInto, marker::Unpin};
+use std::marker::Unpin;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 /// A `PubAck` is the response for a `Publish` message with `AtLeastOnce` as
 /// quality of service.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct PubAck {
     /// The packet identifier is used to identify the message throughout the
     /// communication.
@@ -31,6 +32,14 @@ pub struct PubAck {
 
     /// General purpose user properties
     pub user_properties: Vec<(String, String)>,
+
+    /// Whether the packet is shortened when possible. Set upon decoding
+    /// according to the form that was read.
+    pub encoding: AckEncoding,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for PubAck {
@@ -40,11 +49,35 @@ impl Default for PubAck {
             reason_code: ReasonCode::Success,
             reason_string: None,
             user_properties: Default::default(),
+            encoding: Default::default(),
+            raw_properties: None,
         }
     }
 }
 
 impl PubAck {
+    /// Creates the `PubAck` acknowledging the given `Publish`, copying its
+    /// packet identifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the `Publish` is not sent with `AtLeastOnce`
+    /// quality of service or if the reason code is not valid in a `PubAck`.
+    pub fn for_publish(publish: &Publish, reason_code: ReasonCode) -> SageResult<Self> {
+        let packet_identifier = match (publish.qos, publish.packet_identifier) {
+            (QoS::AtLeastOnce, Some(packet_identifier)) => packet_identifier,
+            _ => return Err(ProtocolError.into()),
+        };
+        if !reason_code.allowed_in(PacketType::PubAck) {
+            return Err(ProtocolError.into());
+        }
+        Ok(PubAck {
+            packet_identifier,
+            reason_code,
+            ..Default::default()
+        })
+    }
+
     pub(crate) async fn write<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
         let mut n_bytes = codec::write_two_byte_integer(self.packet_identifier, writer).await?;
 
@@ -57,7 +90,10 @@ impl PubAck {
             n_bytes += Property::UserProperty(k, v).encode(&mut properties).await?;
         }
 
-        if n_bytes == 2 && self.reason_code != ReasonCode::Success {
+        if n_bytes == 2
+            && self.reason_code == ReasonCode::Success
+            && self.encoding == AckEncoding::Short
+        {
             Ok(2)
         } else {
             n_bytes += codec::write_reason_code(self.reason_code, writer).await?;
@@ -69,7 +105,8 @@ impl PubAck {
 
     pub(crate) async fn read<R: AsyncRead + Unpin>(
         reader: &mut R,
-        shortened: bool,
+        remaining_size: usize,
+        options: &DecodeOptions,
     ) -> SageResult<Self> {
         let packet_identifier = codec::read_two_byte_integer(reader).await?;
 
@@ -78,12 +115,12 @@ impl PubAck {
             ..Default::default()
         };
 
-        if shortened {
-            puback.reason_code = ReasonCode::Success;
-        } else {
-            puback.reason_code = codec::read_byte(reader).await?.try_into()?;
-
-            let mut properties = PropertiesDecoder::take(reader).await?;
+        if remaining_size > 2 {
+            puback.encoding = AckEncoding::Full;
+            puback.reason_code = codec::read_reason_code(reader, options).await?;
+        }
+        if remaining_size > 3 {
+            let mut properties = PropertiesDecoder::take_with(reader, options).await?;
             while properties.has_properties() {
                 match properties.read().await? {
                     Property::ReasonString(v) => puback.reason_string = Some(v),
@@ -91,6 +128,7 @@ impl PubAck {
                     _ => return Err(ProtocolError.into()),
                 }
             }
+            puback.raw_properties = properties.raw_properties();
         }
 
         Ok(puback)
@@ -116,6 +154,8 @@ mod unit {
             reason_code: ReasonCode::QuotaExceeded,
             reason_string: Some("Black Betty".into()),
             user_properties: vec![("Mogwaï".into(), "Cat".into())],
+            encoding: AckEncoding::Full,
+            raw_properties: None,
         }
     }
 
@@ -131,7 +171,66 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = PubAck::read(&mut test_data, false).await.unwrap();
+        let tested_result = PubAck::read(&mut test_data, encoded().len(), &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
+
+    #[tokio::test]
+    async fn decode_shortened() {
+        let mut test_data = Cursor::new(encoded()[..2].to_vec());
+        let puback = PubAck::read(&mut test_data, 2, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(puback.packet_identifier, 1337);
+        assert_eq!(puback.reason_code, ReasonCode::Success);
+        assert_eq!(puback.encoding, AckEncoding::Short);
+
+        let mut test_data = Cursor::new(encoded()[..3].to_vec());
+        let puback = PubAck::read(&mut test_data, 3, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(puback.reason_code, decoded().reason_code);
+        assert_eq!(puback.encoding, AckEncoding::Full);
+        assert!(puback.user_properties.is_empty());
+    }
+
+    #[tokio::test]
+    async fn encode_shortened() {
+        let mut puback = PubAck {
+            packet_identifier: 1337,
+            ..Default::default()
+        };
+        let mut tested_result = Vec::new();
+        let n_bytes = puback.clone().write(&mut tested_result).await.unwrap();
+        assert_eq!(tested_result, vec![5, 57]);
+        assert_eq!(n_bytes, 2);
+
+        puback.encoding = AckEncoding::Full;
+        let mut tested_result = Vec::new();
+        let n_bytes = puback.write(&mut tested_result).await.unwrap();
+        assert_eq!(tested_result, vec![5, 57, 0, 0]);
+        assert_eq!(n_bytes, 4);
+    }
+
+    #[test]
+    fn for_publish() {
+        let publish = Publish {
+            qos: QoS::AtLeastOnce,
+            packet_identifier: Some(1337),
+            ..Default::default()
+        };
+        let puback = PubAck::for_publish(&publish, ReasonCode::Success).unwrap();
+        assert_eq!(puback.packet_identifier, 1337);
+
+        assert!(PubAck::for_publish(&publish, ReasonCode::GrantedQoS2).is_err());
+
+        let publish = Publish {
+            qos: QoS::ExactlyOnce,
+            packet_identifier: Some(1337),
+            ..Default::default()
+        };
+        assert!(PubAck::for_publish(&publish, ReasonCode::Success).is_err());
+    }
 }
@@ -1,14 +1,14 @@
 use crate::{
-    codec, PropertiesDecoder, Property,
+    codec, AckEncoding, DecodeOptions, PacketType, Properties, PropertiesDecoder, Property, PubRel,
     ReasonCode::{self, ProtocolError},
     Result as SageResult,
 };
-use std::{convert::TryInto, marker::Unpin};
+use std::marker::Unpin;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 /// The `PubComp` packet is sent during an `ExactlyOnce` quality of service
 /// publish.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct PubComp {
     /// The packet identifier is used to identify the message throughout the
     /// communication.
@@ -31,6 +31,14 @@ pub struct PubComp {
 
     /// General purpose user properties
     pub user_properties: Vec<(String, String)>,
+
+    /// Whether the packet is shortened when possible. Set upon decoding
+    /// according to the form that was read.
+    pub encoding: AckEncoding,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for PubComp {
@@ -40,11 +48,31 @@ impl Default for PubComp {
             reason_code: ReasonCode::Success,
             reason_string: None,
             user_properties: Default::default(),
+            encoding: Default::default(),
+            raw_properties: None,
         }
     }
 }
 
 impl PubComp {
+    /// Creates the `PubComp` responding to the given `PubRel`, copying its
+    /// packet identifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the reason code is not valid in a `PubComp`.
+    pub fn responding_to(pubrel: &PubRel, reason_code: ReasonCode) -> SageResult<Self> {
+        let packet_identifier = pubrel.packet_identifier;
+        if !reason_code.allowed_in(PacketType::PubComp) {
+            return Err(ProtocolError.into());
+        }
+        Ok(PubComp {
+            packet_identifier,
+            reason_code,
+            ..Default::default()
+        })
+    }
+
     pub(crate) async fn write<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
         let mut n_bytes = codec::write_two_byte_integer(self.packet_identifier, writer).await?;
 
@@ -57,7 +85,10 @@ impl PubComp {
             n_bytes += Property::UserProperty(k, v).encode(&mut properties).await?;
         }
 
-        if n_bytes == 2 && self.reason_code != ReasonCode::Success {
+        if n_bytes == 2
+            && self.reason_code == ReasonCode::Success
+            && self.encoding == AckEncoding::Short
+        {
             Ok(2)
         } else {
             n_bytes += codec::write_reason_code(self.reason_code, writer).await?;
@@ -69,7 +100,8 @@ impl PubComp {
 
     pub(crate) async fn read<R: AsyncRead + Unpin>(
         reader: &mut R,
-        shortened: bool,
+        remaining_size: usize,
+        options: &DecodeOptions,
     ) -> SageResult<Self> {
         let packet_identifier = codec::read_two_byte_integer(reader).await?;
 
@@ -78,12 +110,12 @@ impl PubComp {
             ..Default::default()
         };
 
-        if shortened {
-            pubcomp.reason_code = ReasonCode::Success;
-        } else {
-            pubcomp.reason_code = codec::read_byte(reader).await?.try_into()?;
-
-            let mut properties = PropertiesDecoder::take(reader).await?;
+        if remaining_size > 2 {
+            pubcomp.encoding = AckEncoding::Full;
+            pubcomp.reason_code = codec::read_reason_code(reader, options).await?;
+        }
+        if remaining_size > 3 {
+            let mut properties = PropertiesDecoder::take_with(reader, options).await?;
             while properties.has_properties() {
                 match properties.read().await? {
                     Property::ReasonString(v) => pubcomp.reason_string = Some(v),
@@ -91,6 +123,7 @@ impl PubComp {
                     _ => return Err(ProtocolError.into()),
                 }
             }
+            pubcomp.raw_properties = properties.raw_properties();
         }
 
         Ok(pubcomp)
@@ -116,6 +149,8 @@ mod unit {
             reason_code: ReasonCode::PacketIdentifierNotFound,
             reason_string: Some("Black Betty".into()),
             user_properties: vec![("Hærya".into(), "Cat".into())],
+            encoding: AckEncoding::Full,
+            raw_properties: None,
         }
     }
 
@@ -131,7 +166,28 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = PubComp::read(&mut test_data, false).await.unwrap();
+        let tested_result = PubComp::read(&mut test_data, encoded().len(), &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
+
+    #[tokio::test]
+    async fn decode_shortened() {
+        let mut test_data = Cursor::new(encoded()[..2].to_vec());
+        let pubcomp = PubComp::read(&mut test_data, 2, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(pubcomp.packet_identifier, 1337);
+        assert_eq!(pubcomp.reason_code, ReasonCode::Success);
+        assert_eq!(pubcomp.encoding, AckEncoding::Short);
+
+        let mut test_data = Cursor::new(encoded()[..3].to_vec());
+        let pubcomp = PubComp::read(&mut test_data, 3, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(pubcomp.reason_code, decoded().reason_code);
+        assert_eq!(pubcomp.encoding, AckEncoding::Full);
+        assert!(pubcomp.user_properties.is_empty());
+    }
 }
@@ -1,14 +1,20 @@
 use crate::{
-    codec, defaults::DEFAULT_PAYLOAD_FORMAT_INDICATOR, PropertiesDecoder, Property, QoS,
-    ReasonCode::ProtocolError, Result as SageResult, Topic,
+    codec,
+    defaults::DEFAULT_PAYLOAD_FORMAT_INDICATOR,
+    duration, DecodeOptions, Properties, PropertiesDecoder, Property, QoS,
+    ReasonCode::{MalformedPacket, ProtocolError},
+    Result as SageResult, Saturation, SubscriptionId, TopicName,
 };
 
-use std::marker::Unpin;
+use std::{
+    marker::Unpin,
+    time::{Duration, Instant},
+};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// The `Publish` packet is used to send an application message to a given
 /// topic.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Publish {
     /// In case of `AtLeastOnce` and `ExactlyOnce` qualities of service,
     /// `duplicate` is set to `true` when the message is a new attempt to send
@@ -23,7 +29,7 @@ pub struct Publish {
     pub retain: bool,
 
     /// The name of the topic to publish the message to.
-    pub topic_name: Topic,
+    pub topic_name: TopicName,
 
     /// The packet identifier is used in `AtLeastOnce` and `ExactlyOnce`
     /// qualities of service to keep track of the packet.
@@ -43,7 +49,7 @@ pub struct Publish {
     /// If the message is part of a Request/Response communication, the response
     /// topic is use to assign the topic which must be used as response. The
     /// presence of a response topic identifies the message as a requestion.
-    pub response_topic: Option<Topic>,
+    pub response_topic: Option<TopicName>,
 
     /// If the message is part of a Request/Response communication, it can be
     /// optionnaly accompagnied with correlation data which are exchanged
@@ -55,7 +61,7 @@ pub struct Publish {
 
     /// References the different subscriptions identifiers that are used for
     /// the message delivery.
-    pub subscription_identifiers: Vec<u32>,
+    pub subscription_identifiers: Vec<SubscriptionId>,
 
     /// Describes the type of content of the payload. Is generally a MIME
     /// descriptor.
@@ -63,6 +69,10 @@ pub struct Publish {
 
     /// The content of the message
     pub message: Vec<u8>,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for Publish {
@@ -83,13 +93,74 @@ impl Default for Publish {
             subscription_identifiers: Default::default(),
             content_type: Default::default(),
             message: Default::default(),
+            raw_properties: None,
         }
     }
 }
 
 impl Publish {
+    /// Returns the message expiry interval as a `Duration`, if any.
+    pub fn message_expiry_interval_duration(&self) -> Option<Duration> {
+        self.message_expiry_interval
+            .map(|secs| Duration::from_secs(secs as u64))
+    }
+
+    /// Sets the message expiry interval from a `Duration`.
+    pub fn set_message_expiry_interval_duration(&mut self, interval: Duration) -> Saturation {
+        let (secs, saturation) = duration::to_u32_seconds(interval);
+        self.message_expiry_interval = Some(secs);
+        saturation
+    }
+
+    /// Returns the instant the message expires, given the instant it was
+    /// received at. Returns `None` if the message never expires.
+    pub fn message_expiry_deadline(&self, received_at: Instant) -> Option<Instant> {
+        self.message_expiry_interval
+            .and_then(|secs| duration::deadline(received_at, secs))
+    }
+
+    /// Updates the message expiry interval to the time remaining until the
+    /// message expires, given the instant it was received at. As required by
+    /// the specification, this must be done before forwarding a message that
+    /// has been waiting to be delivered. Since the interval is overwritten,
+    /// this must be called on a copy of the message as received.
+    /// Returns `false` if the message has expired, in which case it must not
+    /// be forwarded.
+    pub fn update_message_expiry_interval(&mut self, received_at: Instant, now: Instant) -> bool {
+        if let Some(deadline) = self.message_expiry_deadline(received_at) {
+            match duration::remaining_seconds(deadline, now) {
+                Some(secs) => self.message_expiry_interval = Some(secs),
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Sets the subscription identifiers of a message forwarded by a server,
+    /// given the identifiers of the subscriptions it matches. Subscriptions
+    /// without identifier are ignored.
+    pub fn set_subscription_identifiers<I>(&mut self, subscription_identifiers: I)
+    where
+        I: IntoIterator<Item = Option<SubscriptionId>>,
+    {
+        self.subscription_identifiers = subscription_identifiers.into_iter().flatten().collect();
+    }
+
+    /// Builds the response to the message if it is a request, that is if it
+    /// has a response topic. The response is sent to the response topic,
+    /// with the same quality of service and correlation data as the request.
+    pub fn response(&self, message: Vec<u8>) -> Option<Publish> {
+        Some(Publish {
+            qos: self.qos,
+            topic_name: self.response_topic.clone()?,
+            correlation_data: self.correlation_data.clone(),
+            message,
+            ..Default::default()
+        })
+    }
+
     pub(crate) async fn write<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
-        let mut n_bytes = codec::write_utf8_string(&self.topic_name.to_string(), writer).await?;
+        let mut n_bytes = codec::write_utf8_string(self.topic_name.as_str(), writer).await?;
 
         if self.qos != QoS::AtMostOnce {
             if let Some(packet_identifier) = self.packet_identifier {
@@ -149,10 +220,11 @@ impl Publish {
         qos: QoS,
         retain: bool,
         remaining_size: u64,
+        options: &DecodeOptions,
     ) -> SageResult<Self> {
         let mut reader = reader.take(remaining_size);
 
-        let topic_name = Topic::from(codec::read_utf8_string(&mut reader).await?);
+        let topic_name = codec::read_utf8_string(&mut reader).await?.try_into()?;
 
         let packet_identifier = if qos != QoS::AtMostOnce {
             Some(codec::read_two_byte_integer(&mut reader).await?)
@@ -168,7 +240,7 @@ impl Publish {
         let mut subscription_identifiers = Vec::new();
         let mut content_type = Default::default();
 
-        let mut properties = PropertiesDecoder::take(&mut reader).await?;
+        let mut properties = PropertiesDecoder::take_with(&mut reader, options).await?;
         while properties.has_properties() {
             match properties.read().await? {
                 Property::PayloadFormatIndicator(v) => payload_format_indicator = v,
@@ -182,9 +254,13 @@ impl Publish {
                 _ => return Err(ProtocolError.into()),
             }
         }
+        let raw_properties = properties.raw_properties();
 
         let mut message = Vec::new();
         reader.read_to_end(&mut message).await?;
+        if reader.limit() > 0 {
+            return Err(MalformedPacket.into());
+        }
 
         Ok(Publish {
             duplicate,
@@ -201,6 +277,7 @@ impl Publish {
             subscription_identifiers,
             content_type,
             message,
+            raw_properties,
         })
     }
 }
@@ -209,6 +286,7 @@ impl Publish {
 mod unit {
 
     use super::*;
+    use crate::Subscribe;
     use std::io::Cursor;
 
     fn encoded() -> Vec<u8> {
@@ -228,17 +306,21 @@ mod unit {
             duplicate: false,
             qos: QoS::AtLeastOnce,
             retain: true,
-            topic_name: Topic::from("One More Time"),
+            topic_name: "One More Time".try_into().unwrap(),
             packet_identifier: Some(1337),
             payload_format_indicator: true,
             message_expiry_interval: Some(17),
             topic_alias: Some(451),
-            response_topic: Some(Topic::from("Smells Like Teen Spirit")),
+            response_topic: Some("Smells Like Teen Spirit".try_into().unwrap()),
             correlation_data: Some(vec![0x0D, 0x15, 0xEA, 0x5E]),
             user_properties: vec![("Mogwaï".into(), "Cat".into())],
-            subscription_identifiers: vec![34, 32, 10, 11],
+            subscription_identifiers: [34, 32, 10, 11]
+                .iter()
+                .filter_map(|&id| SubscriptionId::new(id))
+                .collect(),
             content_type: "Nirvana".into(),
             message: "all the bases are belong to us".into(),
+            raw_properties: None,
         }
     }
 
@@ -254,9 +336,54 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = Publish::read(&mut test_data, false, QoS::AtLeastOnce, true, 124)
-            .await
-            .unwrap();
+        let tested_result = Publish::read(
+            &mut test_data,
+            false,
+            QoS::AtLeastOnce,
+            true,
+            124,
+            &Default::default(),
+        )
+        .await
+        .unwrap();
         assert_eq!(tested_result, decoded());
     }
+
+    #[test]
+    fn update_message_expiry_interval() {
+        let received_at = Instant::now();
+        let mut publish = Publish {
+            message_expiry_interval: Some(10),
+            ..Default::default()
+        };
+        assert!(publish
+            .update_message_expiry_interval(received_at, received_at + Duration::from_secs(4)));
+        assert_eq!(publish.message_expiry_interval, Some(6));
+        assert!(!publish
+            .update_message_expiry_interval(received_at, received_at + Duration::from_secs(10)));
+    }
+    #[test]
+    fn set_subscription_identifiers() {
+        let subscriptions = [
+            Subscribe {
+                subscription_identifier: SubscriptionId::new(1337),
+                ..Default::default()
+            },
+            Subscribe::default(),
+            Subscribe {
+                subscription_identifier: SubscriptionId::new(42),
+                ..Default::default()
+            },
+        ];
+        let mut publish = Publish::default();
+        publish
+            .set_subscription_identifiers(subscriptions.iter().map(|s| s.subscription_identifier));
+        assert_eq!(
+            publish.subscription_identifiers,
+            vec![
+                SubscriptionId::new(1337).unwrap(),
+                SubscriptionId::new(42).unwrap()
+            ]
+        );
+    }
 }
@@ -1,14 +1,15 @@
 use crate::{
-    codec, PropertiesDecoder, Property,
+    codec, AckEncoding, DecodeOptions, PacketType, Properties, PropertiesDecoder, Property,
+    Publish, QoS,
     ReasonCode::{self, ProtocolError},
     Result as SageResult,
 };
-use std::{convert::TryInto, marker::Unpin};
+use std::marker::Unpin;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 /// The `PubRec` packet is sent during an `ExactlyOnce` quality of service
 /// publish.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct PubRec {
     /// The packet identifier is used to identify the message throughout the
     /// communication.
@@ -31,6 +32,14 @@ pub struct PubRec {
 
     /// General purpose user properties
     pub user_properties: Vec<(String, String)>,
+
+    /// Whether the packet is shortened when possible. Set upon decoding
+    /// according to the form that was read.
+    pub encoding: AckEncoding,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for PubRec {
@@ -40,11 +49,35 @@ impl Default for PubRec {
             reason_code: ReasonCode::Success,
             reason_string: None,
             user_properties: Default::default(),
+            encoding: Default::default(),
+            raw_properties: None,
         }
     }
 }
 
 impl PubRec {
+    /// Creates the `PubRec` acknowledging the given `Publish`, copying its
+    /// packet identifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the `Publish` is not sent with `ExactlyOnce`
+    /// quality of service or if the reason code is not valid in a `PubRec`.
+    pub fn for_publish(publish: &Publish, reason_code: ReasonCode) -> SageResult<Self> {
+        let packet_identifier = match (publish.qos, publish.packet_identifier) {
+            (QoS::ExactlyOnce, Some(packet_identifier)) => packet_identifier,
+            _ => return Err(ProtocolError.into()),
+        };
+        if !reason_code.allowed_in(PacketType::PubRec) {
+            return Err(ProtocolError.into());
+        }
+        Ok(PubRec {
+            packet_identifier,
+            reason_code,
+            ..Default::default()
+        })
+    }
+
     pub(crate) async fn write<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
         let mut n_bytes = codec::write_two_byte_integer(self.packet_identifier, writer).await?;
 
@@ -57,7 +90,10 @@ impl PubRec {
             n_bytes += Property::UserProperty(k, v).encode(&mut properties).await?;
         }
 
-        if n_bytes == 2 && self.reason_code != ReasonCode::Success {
+        if n_bytes == 2
+            && self.reason_code == ReasonCode::Success
+            && self.encoding == AckEncoding::Short
+        {
             Ok(2)
         } else {
             n_bytes += codec::write_reason_code(self.reason_code, writer).await?;
@@ -69,7 +105,8 @@ impl PubRec {
 
     pub(crate) async fn read<R: AsyncRead + Unpin>(
         reader: &mut R,
-        shortened: bool,
+        remaining_size: usize,
+        options: &DecodeOptions,
     ) -> SageResult<Self> {
         let packet_identifier = codec::read_two_byte_integer(reader).await?;
 
@@ -78,12 +115,12 @@ impl PubRec {
             ..Default::default()
         };
 
-        if shortened {
-            pubrec.reason_code = ReasonCode::Success;
-        } else {
-            pubrec.reason_code = codec::read_byte(reader).await?.try_into()?;
-
-            let mut properties = PropertiesDecoder::take(reader).await?;
+        if remaining_size > 2 {
+            pubrec.encoding = AckEncoding::Full;
+            pubrec.reason_code = codec::read_reason_code(reader, options).await?;
+        }
+        if remaining_size > 3 {
+            let mut properties = PropertiesDecoder::take_with(reader, options).await?;
             while properties.has_properties() {
                 match properties.read().await? {
                     Property::ReasonString(v) => pubrec.reason_string = Some(v),
@@ -91,6 +128,7 @@ impl PubRec {
                     _ => return Err(ProtocolError.into()),
                 }
             }
+            pubrec.raw_properties = properties.raw_properties();
         }
 
         Ok(pubrec)
@@ -115,6 +153,8 @@ mod unit {
             reason_code: ReasonCode::ImplementationSpecificError,
             reason_string: Some("Black Betty".into()),
             user_properties: vec![("Mogwaï".into(), "Cat".into())],
+            encoding: AckEncoding::Full,
+            raw_properties: None,
         }
     }
 
@@ -130,7 +170,28 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = PubRec::read(&mut test_data, false).await.unwrap();
+        let tested_result = PubRec::read(&mut test_data, encoded().len(), &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
+
+    #[tokio::test]
+    async fn decode_shortened() {
+        let mut test_data = Cursor::new(encoded()[..2].to_vec());
+        let pubrec = PubRec::read(&mut test_data, 2, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(pubrec.packet_identifier, 1337);
+        assert_eq!(pubrec.reason_code, ReasonCode::Success);
+        assert_eq!(pubrec.encoding, AckEncoding::Short);
+
+        let mut test_data = Cursor::new(encoded()[..3].to_vec());
+        let pubrec = PubRec::read(&mut test_data, 3, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(pubrec.reason_code, decoded().reason_code);
+        assert_eq!(pubrec.encoding, AckEncoding::Full);
+        assert!(pubrec.user_properties.is_empty());
+    }
 }
@@ -1,14 +1,14 @@
 use crate::{
-    codec, PropertiesDecoder, Property,
+    codec, AckEncoding, DecodeOptions, PacketType, Properties, PropertiesDecoder, Property, PubRec,
     ReasonCode::{self, ProtocolError},
     Result as SageResult,
 };
-use std::{convert::TryInto, marker::Unpin};
+use std::marker::Unpin;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 /// The `PubRel` packet is sent during an `ExactlyOnce` quality of service
 /// publish.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct PubRel {
     /// The packet identifier is used to identify the message throughout the
     /// communication.
@@ -31,6 +31,14 @@ pub struct PubRel {
 
     /// General purpose user properties
     pub user_properties: Vec<(String, String)>,
+
+    /// Whether the packet is shortened when possible. Set upon decoding
+    /// according to the form that was read.
+    pub encoding: AckEncoding,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for PubRel {
@@ -40,11 +48,36 @@ impl Default for PubRel {
             reason_code: ReasonCode::Success,
             reason_string: None,
             user_properties: Default::default(),
+            encoding: Default::default(),
+            raw_properties: None,
         }
     }
 }
 
 impl PubRel {
+    /// Creates the `PubRel` responding to the given `PubRec`, copying its
+    /// packet identifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the `PubRec` carries an error reason code,
+    /// which ends the exchange, or if the reason code is not valid in a
+    /// `PubRel`.
+    pub fn responding_to(pubrec: &PubRec, reason_code: ReasonCode) -> SageResult<Self> {
+        if pubrec.reason_code.is_error() {
+            return Err(ProtocolError.into());
+        }
+        let packet_identifier = pubrec.packet_identifier;
+        if !reason_code.allowed_in(PacketType::PubRel) {
+            return Err(ProtocolError.into());
+        }
+        Ok(PubRel {
+            packet_identifier,
+            reason_code,
+            ..Default::default()
+        })
+    }
+
     pub(crate) async fn write<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
         let mut n_bytes = codec::write_two_byte_integer(self.packet_identifier, writer).await?;
 
@@ -57,7 +90,10 @@ impl PubRel {
             n_bytes += Property::UserProperty(k, v).encode(&mut properties).await?;
         }
 
-        if n_bytes == 2 && self.reason_code != ReasonCode::Success {
+        if n_bytes == 2
+            && self.reason_code == ReasonCode::Success
+            && self.encoding == AckEncoding::Short
+        {
             Ok(2)
         } else {
             n_bytes += codec::write_reason_code(self.reason_code, writer).await?;
@@ -69,7 +105,8 @@ impl PubRel {
 
     pub(crate) async fn read<R: AsyncRead + Unpin>(
         reader: &mut R,
-        shortened: bool,
+        remaining_size: usize,
+        options: &DecodeOptions,
     ) -> SageResult<Self> {
         let packet_identifier = codec::read_two_byte_integer(reader).await?;
 
@@ -78,12 +115,12 @@ impl PubRel {
             ..Default::default()
         };
 
-        if shortened {
-            pubrel.reason_code = ReasonCode::Success;
-        } else {
-            pubrel.reason_code = codec::read_byte(reader).await?.try_into()?;
-
-            let mut properties = PropertiesDecoder::take(reader).await?;
+        if remaining_size > 2 {
+            pubrel.encoding = AckEncoding::Full;
+            pubrel.reason_code = codec::read_reason_code(reader, options).await?;
+        }
+        if remaining_size > 3 {
+            let mut properties = PropertiesDecoder::take_with(reader, options).await?;
             while properties.has_properties() {
                 match properties.read().await? {
                     Property::ReasonString(v) => pubrel.reason_string = Some(v),
@@ -91,6 +128,7 @@ impl PubRel {
                     _ => return Err(ProtocolError.into()),
                 }
             }
+            pubrel.raw_properties = properties.raw_properties();
         }
 
         Ok(pubrel)
@@ -115,6 +153,8 @@ mod unit {
             reason_code: ReasonCode::PacketIdentifierNotFound,
             reason_string: Some("Black Betty".into()),
             user_properties: vec![("Mogwaï".into(), "Cat".into())],
+            encoding: AckEncoding::Full,
+            raw_properties: None,
         }
     }
 
@@ -130,7 +170,45 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = PubRel::read(&mut test_data, false).await.unwrap();
+        let tested_result = PubRel::read(&mut test_data, encoded().len(), &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
+
+    #[tokio::test]
+    async fn decode_shortened() {
+        let mut test_data = Cursor::new(encoded()[..2].to_vec());
+        let pubrel = PubRel::read(&mut test_data, 2, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(pubrel.packet_identifier, 1337);
+        assert_eq!(pubrel.reason_code, ReasonCode::Success);
+        assert_eq!(pubrel.encoding, AckEncoding::Short);
+
+        let mut test_data = Cursor::new(encoded()[..3].to_vec());
+        let pubrel = PubRel::read(&mut test_data, 3, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(pubrel.reason_code, decoded().reason_code);
+        assert_eq!(pubrel.encoding, AckEncoding::Full);
+        assert!(pubrel.user_properties.is_empty());
+    }
+
+    #[test]
+    fn responding_to() {
+        let pubrec = PubRec {
+            packet_identifier: 1337,
+            ..Default::default()
+        };
+        let pubrel = PubRel::responding_to(&pubrec, ReasonCode::Success).unwrap();
+        assert_eq!(pubrel.packet_identifier, 1337);
+
+        let pubrec = PubRec {
+            packet_identifier: 1337,
+            reason_code: ReasonCode::QuotaExceeded,
+            ..Default::default()
+        };
+        assert!(PubRel::responding_to(&pubrec, ReasonCode::Success).is_err());
+    }
 }
@@ -1,14 +1,14 @@
 use crate::{
-    codec, PropertiesDecoder, Property,
+    codec, DecodeOptions, Properties, PropertiesDecoder, Property,
     ReasonCode::{self, ProtocolError},
     Result as SageResult,
 };
-use std::{convert::TryInto, marker::Unpin};
+use std::marker::Unpin;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// The `SubAck` packet is sent by a server to confirm a `Subscribe` has been
 /// received and processed.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct SubAck {
     /// The packet identifier is used to identify the message throughout the
     /// communication.
@@ -21,6 +21,10 @@ pub struct SubAck {
     /// The indices in this array match the incides in the `Subscribe`'s
     /// subscriptions array.
     pub reason_codes: Vec<ReasonCode>,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for SubAck {
@@ -29,6 +33,7 @@ impl Default for SubAck {
             packet_identifier: 0,
             user_properties: Default::default(),
             reason_codes: Default::default(),
+            raw_properties: None,
         }
     }
 }
@@ -56,29 +61,32 @@ impl SubAck {
     pub(crate) async fn read<R: AsyncRead + Unpin>(
         reader: &mut R,
         remaining_size: usize,
+        options: &DecodeOptions,
     ) -> SageResult<Self> {
         let mut reader = reader.take(remaining_size as u64);
 
         let packet_identifier = codec::read_two_byte_integer(&mut reader).await?;
         let mut user_properties = Vec::new();
-        let mut properties = PropertiesDecoder::take(&mut reader).await?;
+        let mut properties = PropertiesDecoder::take_with(&mut reader, options).await?;
         while properties.has_properties() {
             match properties.read().await? {
                 Property::UserProperty(k, v) => user_properties.push((k, v)),
                 _ => return Err(ProtocolError.into()),
             }
         }
+        let raw_properties = properties.raw_properties();
 
         let mut reason_codes = Vec::new();
 
         while reader.limit() > 0 {
-            reason_codes.push(codec::read_byte(&mut reader).await?.try_into()?);
+            reason_codes.push(codec::read_reason_code(&mut reader, options).await?);
         }
 
         Ok(SubAck {
             packet_identifier,
             user_properties,
             reason_codes,
+            raw_properties,
         })
     }
 }
@@ -102,6 +110,7 @@ mod unit {
                 ReasonCode::PacketIdentifierInUse,
                 ReasonCode::TopicFilterInvalid,
             ],
+            raw_properties: None,
         }
     }
 
@@ -117,7 +126,9 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = SubAck::read(&mut test_data, 20).await.unwrap();
+        let tested_result = SubAck::read(&mut test_data, 20, &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
 }
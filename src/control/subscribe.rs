@@ -1,17 +1,19 @@
 use crate::{
-    codec, Error, PropertiesDecoder, Property, QoS,
+    codec, DecodeOptions, Error, Properties, PropertiesDecoder, Property, QoS,
     ReasonCode::{MalformedPacket, ProtocolError},
-    Result as SageResult, Topic,
+    Result as SageResult, SubscriptionId, TopicFilter,
 };
 use std::{
     convert::{TryFrom, TryInto},
+    fmt::{Display, Formatter, Result as FmtResult},
     marker::Unpin,
 };
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// This option specifies whether retained messages are sent when the
 /// subscription is established;
-#[derive(Eq, Debug, PartialEq, Clone, Copy)]
+#[derive(Eq, Hash, Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RetainHandling {
     /// Send retained messages at the time of the subscribe
     OnSubscribe = 0x00,
@@ -35,8 +37,33 @@ impl TryFrom<u8> for RetainHandling {
     }
 }
 
+impl From<RetainHandling> for u8 {
+    fn from(retain_handling: RetainHandling) -> Self {
+        retain_handling as u8
+    }
+}
+
+impl Display for RetainHandling {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let description = match self {
+            RetainHandling::OnSubscribe => "Send retained messages at subscribe",
+            RetainHandling::OnFirstSubscribe => {
+                "Send retained messages at subscribe if the subscription does not exist"
+            }
+            RetainHandling::DontSend => "Do not send retained messages at subscribe",
+        };
+        write!(f, "{}", description)
+    }
+}
+
 /// Options used to describe a specific subscription.
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// Options can be converted from and to their `u8` encoding, as found in the
+/// `Subscribe` packet.
+/// The default options are the ones of the specification:
+/// `ExactlyOnce` quality of service, `no_local` and `retain_as_published`
+/// set to `false` and retained messages sent upon subscription.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubscriptionOptions {
     /// The maximum quality of service the client is expected to receive
     /// messages.
@@ -57,7 +84,7 @@ pub struct SubscriptionOptions {
 impl Default for SubscriptionOptions {
     fn default() -> Self {
         SubscriptionOptions {
-            qos: QoS::AtMostOnce,
+            qos: QoS::ExactlyOnce,
             no_local: false,
             retain_as_published: false,
             retain_handling: RetainHandling::OnSubscribe,
@@ -65,21 +92,20 @@ impl Default for SubscriptionOptions {
     }
 }
 
-impl SubscriptionOptions {
-    async fn encode<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
-        let byte: u8 = self.qos as u8
-            | (self.no_local as u8) << 2
-            | (self.retain_as_published as u8) << 3
-            | (self.retain_handling as u8) << 4;
-        codec::write_byte(byte, writer).await
+impl From<SubscriptionOptions> for u8 {
+    fn from(options: SubscriptionOptions) -> Self {
+        options.qos as u8
+            | (options.no_local as u8) << 2
+            | (options.retain_as_published as u8) << 3
+            | (options.retain_handling as u8) << 4
     }
+}
 
-    async fn decode<R: AsyncRead + Unpin>(reader: &mut R) -> SageResult<Self> {
-        let flags = codec::read_byte(reader).await?;
+impl TryFrom<u8> for SubscriptionOptions {
+    type Error = Error;
+    fn try_from(flags: u8) -> Result<Self, Self::Error> {
         if flags & 0b1100_0000 > 0 {
             Err(MalformedPacket.into())
-        } else if flags & 0b1100_0000 > 0 {
-            Err(ProtocolError.into())
         } else {
             Ok(SubscriptionOptions {
                 qos: (flags & 0b0000_0011).try_into()?,
@@ -91,24 +117,39 @@ impl SubscriptionOptions {
     }
 }
 
+impl SubscriptionOptions {
+    async fn encode<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
+        codec::write_byte(self.into(), writer).await
+    }
+
+    async fn decode<R: AsyncRead + Unpin>(reader: &mut R) -> SageResult<Self> {
+        codec::read_byte(reader).await?.try_into()
+    }
+}
+
 /// The subscribe packet is a request from the client to listen to one or more
 /// topics.
-#[derive(Default, Debug, PartialEq, Clone)]
+#[derive(Default, Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Subscribe {
     /// The packet identifier is used to identify the message throughout the
     /// communication.
     pub packet_identifier: u16,
 
     /// Optional identifier used to represent the subscription in nextcoming
-    /// mmessages.
-    pub subscription_identifier: Option<u32>,
+    /// mmessages. A `Subscribe` packet can carry at most one identifier, which
+    /// is never zero.
+    pub subscription_identifier: Option<SubscriptionId>,
 
     /// General purpose user properies
     pub user_properties: Vec<(String, String)>,
 
     /// The list of topics to subscribe to with options.
     /// Each topics can use wildcards.
-    pub subscriptions: Vec<(Topic, SubscriptionOptions)>,
+    pub subscriptions: Vec<(TopicFilter, SubscriptionOptions)>,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Subscribe {
@@ -130,7 +171,7 @@ impl Subscribe {
         writer.write_all(&properties).await?;
 
         for option in self.subscriptions {
-            n_bytes += codec::write_utf8_string(&option.0.to_string(), writer).await?;
+            n_bytes += codec::write_utf8_string(option.0.as_str(), writer).await?;
             n_bytes += option.1.encode(writer).await?;
         }
 
@@ -140,6 +181,7 @@ impl Subscribe {
     pub(crate) async fn read<R: AsyncRead + Unpin>(
         reader: &mut R,
         remaining_size: usize,
+        options: &DecodeOptions,
     ) -> SageResult<Self> {
         let mut reader = reader.take(remaining_size as u64);
         let packet_identifier = codec::read_two_byte_integer(&mut reader).await?;
@@ -147,22 +189,25 @@ impl Subscribe {
         let mut user_properties = Vec::new();
         let mut subscription_identifier = None;
 
-        let mut properties = PropertiesDecoder::take(&mut reader).await?;
+        let mut properties = PropertiesDecoder::take_with(&mut reader, options).await?;
         while properties.has_properties() {
             match properties.read().await? {
-                Property::SubscriptionIdentifier(v) => subscription_identifier = Some(v),
+                Property::SubscriptionIdentifier(v) => {
+                    if subscription_identifier.replace(v).is_some() {
+                        return Err(ProtocolError.into());
+                    }
+                }
                 Property::UserProperty(k, v) => user_properties.push((k, v)),
                 _ => return Err(ProtocolError.into()),
             }
         }
+        let raw_properties = properties.raw_properties();
 
         let mut subscriptions = Vec::new();
 
         while reader.limit() > 0 {
-            subscriptions.push((
-                Topic::from(codec::read_utf8_string(&mut reader).await?),
-                SubscriptionOptions::decode(&mut reader).await?,
-            ));
+            let topic = codec::read_utf8_string(&mut reader).await?.try_into()?;
+            subscriptions.push((topic, SubscriptionOptions::decode(&mut reader).await?));
         }
 
         if subscriptions.is_empty() {
@@ -173,6 +218,7 @@ impl Subscribe {
                 subscription_identifier,
                 user_properties,
                 subscriptions,
+                raw_properties,
             })
         }
     }
@@ -210,11 +256,11 @@ mod unit {
     fn decoded() -> Subscribe {
         Subscribe {
             packet_identifier: 1337,
-            subscription_identifier: Some(451),
+            subscription_identifier: SubscriptionId::new(451),
             user_properties: vec![("Mogwaï".into(), "Cat".into())],
             subscriptions: vec![
                 (
-                    Topic::from("harder"),
+                    "harder".try_into().unwrap(),
                     SubscriptionOptions {
                         qos: QoS::AtLeastOnce,
                         no_local: false,
@@ -223,7 +269,7 @@ mod unit {
                     },
                 ),
                 (
-                    Topic::from("better"),
+                    "better".try_into().unwrap(),
                     SubscriptionOptions {
                         qos: QoS::AtMostOnce,
                         no_local: true,
@@ -232,7 +278,7 @@ mod unit {
                     },
                 ),
                 (
-                    Topic::from("faster"),
+                    "faster".try_into().unwrap(),
                     SubscriptionOptions {
                         qos: QoS::ExactlyOnce,
                         no_local: true,
@@ -241,7 +287,7 @@ mod unit {
                     },
                 ),
                 (
-                    Topic::from("stronger"),
+                    "stronger".try_into().unwrap(),
                     SubscriptionOptions {
                         qos: QoS::AtLeastOnce,
                         no_local: false,
@@ -250,6 +296,7 @@ mod unit {
                     },
                 ),
             ],
+            raw_properties: None,
         }
     }
 
@@ -265,7 +312,47 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = Subscribe::read(&mut test_data, 59).await.unwrap();
+        let tested_result = Subscribe::read(&mut test_data, 59, &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
+
+    #[tokio::test]
+    async fn decode_several_subscription_identifiers() {
+        let mut test_data = Cursor::new(vec![
+            5, 57, 4, 11, 1, 11, 2, 0, 6, 104, 97, 114, 100, 101, 114, 0,
+        ]);
+        assert!(matches!(
+            Subscribe::read(&mut test_data, 16, &Default::default()).await,
+            Err(Error::Reason(ProtocolError))
+        ));
+    }
+
+    #[tokio::test]
+    async fn decode_invalid_filter() {
+        use crate::ReasonCode::TopicFilterInvalid;
+        let mut test_data = Cursor::new(vec![
+            5, 57, 0, 0, 14, 115, 112, 111, 114, 116, 47, 35, 47, 116, 101, 110, 110, 105, 115, 0,
+        ]);
+        assert!(matches!(
+            Subscribe::read(&mut test_data, 20, &Default::default()).await,
+            Err(Error::Reason(TopicFilterInvalid))
+        ));
+    }
+
+    #[test]
+    fn options_round_trip() {
+        let options = SubscriptionOptions {
+            qos: QoS::AtLeastOnce,
+            no_local: true,
+            retain_as_published: false,
+            retain_handling: RetainHandling::OnFirstSubscribe,
+        };
+        let flags: u8 = options.into();
+        assert_eq!(flags, 0b0001_0101);
+        assert_eq!(SubscriptionOptions::try_from(flags).unwrap(), options);
+        assert!(SubscriptionOptions::try_from(0b0011_0000).is_err());
+        assert!(SubscriptionOptions::try_from(0b0100_0000).is_err());
+    }
 }
@@ -1,13 +1,13 @@
 use crate::{
-    codec, PropertiesDecoder, Property,
+    codec, DecodeOptions, Properties, PropertiesDecoder, Property,
     ReasonCode::{self, ProtocolError},
     Result as SageResult,
 };
-use std::{convert::TryInto, marker::Unpin};
+use std::marker::Unpin;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// An `UnSubAck` is sent by the server to acknowledge an unsubscribe request.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct UnSubAck {
     /// The packet identifier is used to identify the message throughout the
     /// communication
@@ -23,6 +23,10 @@ pub struct UnSubAck {
     /// Each `ReasonCode` at a given index correspond to a unsubscribe request
     /// from the `Unsubscribe` packet at the same index.
     pub reason_codes: Vec<ReasonCode>,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for UnSubAck {
@@ -32,6 +36,7 @@ impl Default for UnSubAck {
             reason_string: None,
             user_properties: Default::default(),
             reason_codes: Default::default(),
+            raw_properties: None,
         }
     }
 }
@@ -64,12 +69,13 @@ impl UnSubAck {
     pub(crate) async fn read<R: AsyncRead + Unpin>(
         reader: &mut R,
         remaining_size: usize,
+        options: &DecodeOptions,
     ) -> SageResult<Self> {
         let mut reader = reader.take(remaining_size as u64);
 
         let packet_identifier = codec::read_two_byte_integer(&mut reader).await?;
         let mut user_properties = Vec::new();
-        let mut properties = PropertiesDecoder::take(&mut reader).await?;
+        let mut properties = PropertiesDecoder::take_with(&mut reader, options).await?;
         let mut reason_string = None;
         while properties.has_properties() {
             match properties.read().await? {
@@ -78,11 +84,12 @@ impl UnSubAck {
                 _ => return Err(ProtocolError.into()),
             }
         }
+        let raw_properties = properties.raw_properties();
 
         let mut reason_codes = Vec::new();
 
         while reader.limit() > 0 {
-            reason_codes.push(codec::read_byte(&mut reader).await?.try_into()?);
+            reason_codes.push(codec::read_reason_code(&mut reader, options).await?);
         }
 
         Ok(UnSubAck {
@@ -90,6 +97,7 @@ impl UnSubAck {
             user_properties,
             reason_string,
             reason_codes,
+            raw_properties,
         })
     }
 }
@@ -116,6 +124,7 @@ mod unit {
                 ReasonCode::PacketIdentifierInUse,
                 ReasonCode::TopicFilterInvalid,
             ],
+            raw_properties: None,
         }
     }
 
@@ -131,7 +140,9 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = UnSubAck::read(&mut test_data, 41).await.unwrap();
+        let tested_result = UnSubAck::read(&mut test_data, 41, &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
 }
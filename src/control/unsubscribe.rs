@@ -1,9 +1,12 @@
-use crate::{codec, PropertiesDecoder, Property, ReasonCode::ProtocolError, Result as SageResult};
+use crate::{
+    codec, DecodeOptions, Properties, PropertiesDecoder, Property, ReasonCode::ProtocolError,
+    Result as SageResult, TopicFilter,
+};
 use std::marker::Unpin;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// An `Unsubscribe` packet is sent from the client to unsubsribe to a topic.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct UnSubscribe {
     /// The packet identifier is used to identify the message throughout the
     /// communication.
@@ -13,7 +16,11 @@ pub struct UnSubscribe {
     pub user_properties: Vec<(String, String)>,
 
     /// The list of topics to unsubsribe to. They can contains wildcards.
-    pub subscriptions: Vec<String>,
+    pub subscriptions: Vec<TopicFilter>,
+
+    /// If the packet was decoded with `DecodeOptions::retain_raw_properties`,
+    /// the properties exactly as they were read. Ignored when encoding.
+    pub raw_properties: Option<Properties>,
 }
 
 impl Default for UnSubscribe {
@@ -22,6 +29,7 @@ impl Default for UnSubscribe {
             packet_identifier: 0,
             user_properties: Default::default(),
             subscriptions: Default::default(),
+            raw_properties: None,
         }
     }
 }
@@ -38,7 +46,7 @@ impl UnSubscribe {
         writer.write_all(&properties).await?;
 
         for option in self.subscriptions {
-            n_bytes += codec::write_utf8_string(&option, writer).await?;
+            n_bytes += codec::write_utf8_string(option.as_str(), writer).await?;
         }
 
         Ok(n_bytes)
@@ -47,6 +55,7 @@ impl UnSubscribe {
     pub(crate) async fn read<R: AsyncRead + Unpin>(
         reader: &mut R,
         remaining_size: usize,
+        options: &DecodeOptions,
     ) -> SageResult<Self> {
         let mut reader = reader.take(remaining_size as u64);
 
@@ -54,18 +63,19 @@ impl UnSubscribe {
 
         let mut user_properties = Vec::new();
 
-        let mut properties = PropertiesDecoder::take(&mut reader).await?;
+        let mut properties = PropertiesDecoder::take_with(&mut reader, options).await?;
         while properties.has_properties() {
             match properties.read().await? {
                 Property::UserProperty(k, v) => user_properties.push((k, v)),
                 _ => return Err(ProtocolError.into()),
             }
         }
+        let raw_properties = properties.raw_properties();
 
         let mut subscriptions = Vec::new();
 
         while reader.limit() > 0 {
-            subscriptions.push(codec::read_utf8_string(&mut reader).await?);
+            subscriptions.push(codec::read_utf8_string(&mut reader).await?.try_into()?);
         }
 
         if subscriptions.is_empty() {
@@ -75,6 +85,7 @@ impl UnSubscribe {
                 packet_identifier,
                 user_properties,
                 subscriptions,
+                raw_properties,
             })
         }
     }
@@ -98,11 +109,12 @@ mod unit {
             packet_identifier: 1337,
             user_properties: vec![("Mogwaï".into(), "Cat".into())],
             subscriptions: vec![
-                "harder".into(),
-                "better".into(),
-                "faster".into(),
-                "stronger".into(),
+                "harder".try_into().unwrap(),
+                "better".try_into().unwrap(),
+                "faster".try_into().unwrap(),
+                "stronger".try_into().unwrap(),
             ],
+            raw_properties: None,
         }
     }
 
@@ -118,7 +130,9 @@ mod unit {
     #[tokio::test]
     async fn decode() {
         let mut test_data = Cursor::new(encoded());
-        let tested_result = UnSubscribe::read(&mut test_data, 52).await.unwrap();
+        let tested_result = UnSubscribe::read(&mut test_data, 52, &Default::default())
+            .await
+            .unwrap();
         assert_eq!(tested_result, decoded());
     }
 }
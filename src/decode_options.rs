@@ -0,0 +1,89 @@
+use crate::PacketType;
+use std::{fmt, sync::Arc};
+
+/// A deviation from the specification which was accepted while decoding a
+/// packet with lenient `DecodeOptions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Deviation {
+    /// The reserved flags of the fixed header did not have the value the
+    /// specification requires for the packet type, and were ignored.
+    ReservedFlags {
+        /// The type of the decoded packet.
+        packet_type: PacketType,
+
+        /// The value of the flags that was read.
+        flags: u8,
+    },
+
+    /// A reason code which is not part of the specification was read and
+    /// decoded as `ReasonCode::Unknown`.
+    UnknownReasonCode(u8),
+}
+
+/// Options controlling how strictly packets are decoded.
+/// The default options reject any packet that does not conform to the
+/// specification. Lenient options can be used to interoperate with peers
+/// known to deviate from it, accepting and normalizing such packets instead of
+/// dropping the connection.
+#[derive(Default, Clone)]
+pub struct DecodeOptions {
+    /// If `true`, fixed headers with invalid reserved flags are accepted and
+    /// decoded as if the flags had their expected value.
+    pub accept_reserved_flags: bool,
+
+    /// If `true`, reason codes which are not part of the specification are
+    /// accepted and decoded as `ReasonCode::Unknown`.
+    pub accept_unknown_reason_codes: bool,
+
+    /// If `true`, `Connect` packets with a password but no user name, which
+    /// the specification permits, are rejected with `BadUserNameOrPassword`.
+    pub reject_password_without_user_name: bool,
+
+    /// If `true`, decoded packets retain the sequence of properties as it was
+    /// read in their `raw_properties` field.
+    pub retain_raw_properties: bool,
+
+    /// If any, the maximum size of a packet, including its fixed header.
+    /// Larger packets are rejected with `PacketTooLarge` as soon as their
+    /// fixed header is read.
+    pub maximum_packet_size: Option<u32>,
+
+    /// If any, a callback notified of every deviation accepted while decoding.
+    pub on_deviation: Option<Arc<dyn Fn(Deviation) + Send + Sync>>,
+}
+
+impl DecodeOptions {
+    /// Sets the callback notified of every deviation accepted while decoding.
+    pub fn on_deviation<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(Deviation) + Send + Sync + 'static,
+    {
+        self.on_deviation = Some(Arc::new(callback));
+        self
+    }
+
+    pub(crate) fn notify(&self, deviation: Deviation) {
+        if let Some(callback) = &self.on_deviation {
+            callback(deviation);
+        }
+    }
+}
+
+impl fmt::Debug for DecodeOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DecodeOptions")
+            .field("accept_reserved_flags", &self.accept_reserved_flags)
+            .field(
+                "accept_unknown_reason_codes",
+                &self.accept_unknown_reason_codes,
+            )
+            .field(
+                "reject_password_without_user_name",
+                &self.reject_password_without_user_name,
+            )
+            .field("retain_raw_properties", &self.retain_raw_properties)
+            .field("maximum_packet_size", &self.maximum_packet_size)
+            .field("on_deviation", &self.on_deviation.is_some())
+            .finish()
+    }
+}
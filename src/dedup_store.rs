@@ -0,0 +1,176 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+/// A storage of the packet identifiers of the incoming `ExactlyOnce` messages
+/// received but not released yet with a `PubRel` packet, per session.
+///
+/// Remembering them across restarts is what guarantees an exactly once
+/// delivery: a `Publish` packet whose identifier is stored is a duplicate and
+/// must not be delivered again. Sessions are identified by a key such as the
+/// client id.
+pub trait DedupStore<K> {
+    /// Records the packet identifier of a received `Publish` packet. Returns
+    /// `false` if it was already recorded, in which case the packet is a
+    /// duplicate.
+    fn insert(&mut self, session: &K, packet_identifier: u16) -> bool;
+
+    /// Removes the packet identifier released by a `PubRel` packet. Returns
+    /// `false` if it was not recorded.
+    fn remove(&mut self, session: &K, packet_identifier: u16) -> bool;
+
+    /// Returns `true` if the packet identifier is recorded.
+    fn contains(&self, session: &K, packet_identifier: u16) -> bool;
+
+    /// Returns the recorded packet identifiers of `session`, in ascending
+    /// order, such as to fill `SessionState::incoming`.
+    fn packet_identifiers(&self, session: &K) -> Vec<u16>;
+
+    /// Removes all the packet identifiers of `session`, such as when it ends.
+    fn clear(&mut self, session: &K);
+}
+
+/// The content of an `InMemoryDedupStore`, which can be saved and restored
+/// using any storage backend, with the `Serialize` and `Deserialize` traits of
+/// `serde` if the `serde` feature is enabled.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DedupSnapshot<K> {
+    /// The recorded packet identifiers of each session, in ascending order.
+    pub sessions: Vec<(K, Vec<u16>)>,
+}
+
+impl<K> Default for DedupSnapshot<K> {
+    fn default() -> Self {
+        DedupSnapshot {
+            sessions: Default::default(),
+        }
+    }
+}
+
+/// A `DedupStore` keeping the packet identifiers in memory.
+#[derive(Debug, Clone)]
+pub struct InMemoryDedupStore<K> {
+    sessions: HashMap<K, HashSet<u16>>,
+}
+
+impl<K> Default for InMemoryDedupStore<K> {
+    fn default() -> Self {
+        InMemoryDedupStore {
+            sessions: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> InMemoryDedupStore<K> {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns a snapshot of the content of the store.
+    pub fn snapshot(&self) -> DedupSnapshot<K> {
+        DedupSnapshot {
+            sessions: self
+                .sessions
+                .keys()
+                .map(|session| (session.clone(), self.packet_identifiers(session)))
+                .collect(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> From<DedupSnapshot<K>> for InMemoryDedupStore<K> {
+    /// Restores a store from a snapshot.
+    fn from(snapshot: DedupSnapshot<K>) -> Self {
+        InMemoryDedupStore {
+            sessions: snapshot
+                .sessions
+                .into_iter()
+                .filter(|(_, packet_identifiers)| !packet_identifiers.is_empty())
+                .map(|(session, packet_identifiers)| {
+                    (session, packet_identifiers.into_iter().collect())
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> DedupStore<K> for InMemoryDedupStore<K> {
+    fn insert(&mut self, session: &K, packet_identifier: u16) -> bool {
+        self.sessions
+            .entry(session.clone())
+            .or_default()
+            .insert(packet_identifier)
+    }
+
+    fn remove(&mut self, session: &K, packet_identifier: u16) -> bool {
+        match self.sessions.get_mut(session) {
+            Some(packet_identifiers) => {
+                let removed = packet_identifiers.remove(&packet_identifier);
+                if packet_identifiers.is_empty() {
+                    self.sessions.remove(session);
+                }
+                removed
+            }
+            None => false,
+        }
+    }
+
+    fn contains(&self, session: &K, packet_identifier: u16) -> bool {
+        matches!(self.sessions.get(session), Some(ids) if ids.contains(&packet_identifier))
+    }
+
+    fn packet_identifiers(&self, session: &K) -> Vec<u16> {
+        let mut packet_identifiers: Vec<u16> = self
+            .sessions
+            .get(session)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default();
+        packet_identifiers.sort_unstable();
+        packet_identifiers
+    }
+
+    fn clear(&mut self, session: &K) {
+        self.sessions.remove(session);
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn dedup() {
+        let mut store = InMemoryDedupStore::new();
+        assert!(store.insert(&"client1", 3));
+        assert!(store.insert(&"client1", 1));
+        assert!(!store.insert(&"client1", 3));
+        assert!(store.insert(&"client2", 3));
+        assert!(store.contains(&"client1", 1));
+        assert_eq!(store.packet_identifiers(&"client1"), vec![1, 3]);
+
+        assert!(store.remove(&"client1", 1));
+        assert!(!store.remove(&"client1", 1));
+        assert!(!store.contains(&"client1", 1));
+        store.clear(&"client2");
+        assert!(store.packet_identifiers(&"client2").is_empty());
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut store = InMemoryDedupStore::new();
+        store.insert(&"client1", 42);
+        store.insert(&"client1", 7);
+        store.insert(&"client2", 1);
+        store.remove(&"client2", 1);
+
+        let snapshot = store.snapshot();
+        assert_eq!(snapshot.sessions, vec![("client1", vec![7, 42])]);
+        let restored = InMemoryDedupStore::from(snapshot.clone());
+        assert!(restored.contains(&"client1", 42));
+        assert_eq!(restored.snapshot(), snapshot);
+    }
+}
@@ -1,6 +1,6 @@
 //! A set of default values for MQTT packets
 
-use crate::QoS;
+use crate::{Expiry, QoS};
 
 /// Default maximum qos
 pub const DEFAULT_MAXIMUM_QOS: QoS = QoS::ExactlyOnce;
@@ -21,7 +21,7 @@ pub const DEFAULT_REQUEST_RESPONSE_INFORMATION: bool = false;
 pub const DEFAULT_RETAIN_AVAILABLE: bool = true;
 
 /// Default session expiry interval
-pub const DEFAULT_SESSION_EXPIRY_INTERVAL: Option<u32> = None;
+pub const DEFAULT_SESSION_EXPIRY_INTERVAL: Expiry = Expiry::Default;
 
 /// Default shared subscription available
 pub const DEFAULT_SHARED_SUBSCRIPTION_AVAILABLE: bool = true;
@@ -0,0 +1,272 @@
+use crate::{
+    AtLeastOnceReceiver, AtLeastOnceSender, ExactlyOnceReceiver, ExactlyOnceSender, InflightWindow,
+    Message, OutgoingDelivery, Packet, PacketIdAllocator, Publish, QoS,
+    ReasonCode::{self, ProtocolError},
+    Result as SageResult, SessionState, TopicAliasAllocator, TopicAliasRegistry,
+};
+
+// The outcome of a `Publish` or acknowledgement packet handled by `Deliveries`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum Delivered {
+    // A message to deliver to the application.
+    Message(Publish),
+
+    // The delivery of an outgoing message ended.
+    Acknowledged {
+        packet_identifier: u16,
+        reason_code: ReasonCode,
+    },
+}
+
+// The message deliveries of a connection, in both directions, shared by the
+// client and server connections.
+#[derive(Debug)]
+pub(crate) struct Deliveries {
+    pub(crate) ids: PacketIdAllocator,
+    at_least_once: AtLeastOnceSender,
+    exactly_once: ExactlyOnceSender,
+    exactly_once_receiver: ExactlyOnceReceiver,
+    window: InflightWindow,
+    outgoing_aliases: TopicAliasAllocator,
+    incoming_aliases: TopicAliasRegistry,
+}
+
+impl Default for Deliveries {
+    fn default() -> Self {
+        Deliveries {
+            ids: PacketIdAllocator::new(),
+            at_least_once: AtLeastOnceSender::new(),
+            exactly_once: ExactlyOnceSender::new(),
+            exactly_once_receiver: ExactlyOnceReceiver::new(),
+            window: InflightWindow::new(u16::MAX),
+            outgoing_aliases: TopicAliasAllocator::new(0),
+            incoming_aliases: TopicAliasRegistry::new(0),
+        }
+    }
+}
+
+impl Deliveries {
+    // Starts a new network connection, with the Receive Maximum and topic
+    // alias maximum of the peer, and the topic alias maximum advertised to it.
+    // The deliveries of the session are resumed if `session_present` is set,
+    // returning the packets to send again, or discarded otherwise.
+    pub(crate) fn connected(
+        &mut self,
+        session_present: bool,
+        receive_maximum: u16,
+        outgoing_alias_maximum: u16,
+        incoming_alias_maximum: u16,
+    ) -> SageResult<Vec<Packet>> {
+        self.window = InflightWindow::new(receive_maximum);
+        self.outgoing_aliases = TopicAliasAllocator::new(outgoing_alias_maximum);
+        self.incoming_aliases = TopicAliasRegistry::new(incoming_alias_maximum);
+
+        let mut packets = Vec::new();
+        if session_present {
+            let publishes = self
+                .at_least_once
+                .retransmit()
+                .into_iter()
+                .map(Packet::from);
+            for packet in publishes.chain(self.exactly_once.retransmit()) {
+                match packet {
+                    Packet::Publish(publish) => packets.extend(self.send(publish)?),
+                    packet => packets.push(packet),
+                }
+            }
+        } else {
+            self.ids = PacketIdAllocator::new();
+            self.at_least_once = AtLeastOnceSender::new();
+            self.exactly_once = ExactlyOnceSender::new();
+            self.exactly_once_receiver = ExactlyOnceReceiver::new();
+        }
+        Ok(packets)
+    }
+
+    // Restores the deliveries of a session, to resume upon the next
+    // connection.
+    pub(crate) fn restore(&mut self, state: &SessionState) {
+        *self = Default::default();
+        self.ids = state.packet_id_allocator();
+        for delivery in &state.outgoing {
+            match delivery {
+                OutgoingDelivery::Unacknowledged {
+                    packet_identifier,
+                    message,
+                } => {
+                    let publish = message.clone().into_publish(Some(*packet_identifier), None);
+                    match publish.qos {
+                        QoS::AtMostOnce => (),
+                        QoS::AtLeastOnce => self.at_least_once.restore(publish),
+                        QoS::ExactlyOnce => self.exactly_once.restore(publish, false),
+                    }
+                }
+                OutgoingDelivery::Released { packet_identifier } => {
+                    let publish = Publish {
+                        qos: QoS::ExactlyOnce,
+                        packet_identifier: Some(*packet_identifier),
+                        ..Default::default()
+                    };
+                    self.exactly_once.restore(publish, true);
+                }
+            }
+        }
+        for packet_identifier in &state.incoming {
+            self.exactly_once_receiver.restore(*packet_identifier);
+        }
+    }
+
+    // Returns the state of the deliveries, to save into the session. The
+    // `AtLeastOnce` deliveries come first, then the `ExactlyOnce` ones.
+    pub(crate) fn session_state(&self) -> SessionState {
+        let unacknowledged = |publish: &Publish| OutgoingDelivery::Unacknowledged {
+            packet_identifier: publish.packet_identifier.unwrap_or_default(),
+            message: Message::from(publish.clone()),
+        };
+        let outgoing = self
+            .at_least_once
+            .unacknowledged()
+            .map(unacknowledged)
+            .chain(self.exactly_once.deliveries().map(|(publish, released)| {
+                if released {
+                    OutgoingDelivery::Released {
+                        packet_identifier: publish.packet_identifier.unwrap_or_default(),
+                    }
+                } else {
+                    unacknowledged(publish)
+                }
+            }))
+            .collect();
+        SessionState {
+            subscriptions: Vec::new(),
+            outgoing,
+            incoming: self.exactly_once_receiver.received(),
+            next_packet_identifier: self.ids.next(),
+        }
+    }
+
+    fn send(&mut self, publish: Publish) -> SageResult<Option<Packet>> {
+        Ok(self
+            .window
+            .send(publish)?
+            .map(|publish| self.alias(publish)))
+    }
+
+    fn alias(&mut self, mut publish: Publish) -> Packet {
+        self.outgoing_aliases.assign(&mut publish);
+        publish.into()
+    }
+
+    // Starts the delivery of an outgoing message, returning its packet
+    // identifier and the packet to send, unless it waits for the Receive
+    // Maximum of the peer.
+    pub(crate) fn publish(
+        &mut self,
+        publish: Publish,
+    ) -> SageResult<(Option<u16>, Option<Packet>)> {
+        let publish = match publish.qos {
+            QoS::AtMostOnce => Publish {
+                packet_identifier: None,
+                ..publish
+            },
+            QoS::AtLeastOnce => self.at_least_once.publish(&mut self.ids, publish)?,
+            QoS::ExactlyOnce => self.exactly_once.publish(&mut self.ids, publish)?,
+        };
+        let packet_identifier = publish.packet_identifier;
+        Ok((packet_identifier, self.send(publish)?))
+    }
+
+    // Returns the number of outgoing deliveries in flight, counting against
+    // the Receive Maximum of the peer.
+    pub(crate) fn in_flight(&self) -> usize {
+        self.window.in_flight()
+    }
+
+    // Returns the number of outgoing messages waiting for the Receive Maximum
+    // of the peer.
+    pub(crate) fn queued(&self) -> usize {
+        self.window.queued()
+    }
+
+    // Resolves the topic alias of an incoming `Publish` packet.
+    pub(crate) fn resolve(&mut self, publish: &mut Publish) -> SageResult<()> {
+        self.incoming_aliases.resolve(publish)
+    }
+
+    // Returns the number of incoming `ExactlyOnce` messages not released yet,
+    // which count against the Receive Maximum advertised to the peer.
+    pub(crate) fn incoming(&self) -> usize {
+        self.exactly_once_receiver.pending()
+    }
+
+    fn acknowledged(
+        &mut self,
+        packet: Packet,
+        packet_identifier: u16,
+        reason_code: ReasonCode,
+    ) -> (Vec<Packet>, Option<Delivered>) {
+        let packets = self
+            .window
+            .receive(&packet)
+            .into_iter()
+            .map(|publish| self.alias(publish))
+            .collect();
+        let delivered = Delivered::Acknowledged {
+            packet_identifier,
+            reason_code,
+        };
+        (packets, Some(delivered))
+    }
+
+    // Handles a `Publish`, `PubAck`, `PubRec`, `PubRel` or `PubComp` packet,
+    // returning the packets to send in response and the outcome for the
+    // application, if any. Any other packet is a `ProtocolError`.
+    pub(crate) fn handle(
+        &mut self,
+        packet: Packet,
+    ) -> SageResult<(Vec<Packet>, Option<Delivered>)> {
+        match packet {
+            Packet::Publish(mut publish) => {
+                self.incoming_aliases.resolve(&mut publish)?;
+                match publish.qos {
+                    QoS::AtMostOnce => Ok((Vec::new(), Some(Delivered::Message(publish)))),
+                    QoS::AtLeastOnce => {
+                        let (publish, puback) = AtLeastOnceReceiver::new().receive(publish)?;
+                        Ok((vec![puback.into()], Some(Delivered::Message(publish))))
+                    }
+                    QoS::ExactlyOnce => {
+                        let (publish, pubrec) = self.exactly_once_receiver.receive(publish)?;
+                        Ok((vec![pubrec.into()], publish.map(Delivered::Message)))
+                    }
+                }
+            }
+            Packet::PubAck(puback) => {
+                self.at_least_once.acknowledge(&mut self.ids, &puback)?;
+                let (packet_identifier, reason_code) =
+                    (puback.packet_identifier, puback.reason_code);
+                Ok(self.acknowledged(puback.into(), packet_identifier, reason_code))
+            }
+            Packet::PubRec(pubrec) => {
+                match self.exactly_once.receive_pubrec(&mut self.ids, &pubrec) {
+                    Some(pubrel) => Ok((vec![pubrel.into()], None)),
+                    None => {
+                        let (packet_identifier, reason_code) =
+                            (pubrec.packet_identifier, pubrec.reason_code);
+                        Ok(self.acknowledged(pubrec.into(), packet_identifier, reason_code))
+                    }
+                }
+            }
+            Packet::PubComp(pubcomp) => {
+                self.exactly_once.receive_pubcomp(&mut self.ids, &pubcomp)?;
+                let (packet_identifier, reason_code) =
+                    (pubcomp.packet_identifier, pubcomp.reason_code);
+                Ok(self.acknowledged(pubcomp.into(), packet_identifier, reason_code))
+            }
+            Packet::PubRel(pubrel) => {
+                let pubcomp = self.exactly_once_receiver.receive_pubrel(&pubrel);
+                Ok((vec![pubcomp.into()], None))
+            }
+            _ => Err(ProtocolError.into()),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+/// The outcome of storing a `Duration` into an MQTT field expressed in whole
+/// seconds. Sub-second precision is always truncated.
+#[must_use]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Saturation {
+    /// The number of seconds fits in the field and was stored as is.
+    Exact,
+
+    /// The number of seconds exceeds the capacity of the field, which was set
+    /// to its maximum value instead.
+    Saturated,
+}
+
+pub(crate) fn to_u16_seconds(duration: Duration) -> (u16, Saturation) {
+    let secs = duration.as_secs();
+    if secs > u16::MAX as u64 {
+        (u16::MAX, Saturation::Saturated)
+    } else {
+        (secs as u16, Saturation::Exact)
+    }
+}
+
+pub(crate) fn to_u32_seconds(duration: Duration) -> (u32, Saturation) {
+    let secs = duration.as_secs();
+    if secs > u32::MAX as u64 {
+        (u32::MAX, Saturation::Saturated)
+    } else {
+        (secs as u32, Saturation::Exact)
+    }
+}
+
+/// Returns the instant `secs` seconds after `start`, or `None` if it cannot be
+/// represented, in which case the deadline is never reached.
+pub(crate) fn deadline(start: Instant, secs: u32) -> Option<Instant> {
+    start.checked_add(Duration::from_secs(secs as u64))
+}
+
+/// Returns the number of seconds remaining from `now` to `deadline`, rounded
+/// up, or `None` if the deadline is reached.
+pub(crate) fn remaining_seconds(deadline: Instant, now: Instant) -> Option<u32> {
+    let remaining = deadline.checked_duration_since(now)?;
+    if remaining.is_zero() {
+        None
+    } else {
+        let secs = remaining.as_secs() + (remaining.subsec_nanos() > 0) as u64;
+        Some(secs.min(u32::MAX as u64) as u32)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn remaining() {
+        let start = Instant::now();
+        let end = deadline(start, 10).unwrap();
+        assert_eq!(remaining_seconds(end, start), Some(10));
+        assert_eq!(
+            remaining_seconds(end, start + Duration::from_millis(2500)),
+            Some(8)
+        );
+        assert_eq!(remaining_seconds(end, end), None);
+        assert_eq!(remaining_seconds(end, end + Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn u16_seconds() {
+        assert_eq!(
+            to_u16_seconds(Duration::from_millis(1500)),
+            (1, Saturation::Exact)
+        );
+        assert_eq!(
+            to_u16_seconds(Duration::from_secs(65_535)),
+            (65_535, Saturation::Exact)
+        );
+        assert_eq!(
+            to_u16_seconds(Duration::from_secs(65_536)),
+            (65_535, Saturation::Saturated)
+        );
+    }
+
+    #[test]
+    fn u32_seconds() {
+        assert_eq!(to_u32_seconds(Duration::ZERO), (0, Saturation::Exact));
+        assert_eq!(
+            to_u32_seconds(Duration::from_secs(u32::MAX as u64 + 1)),
+            (u32::MAX, Saturation::Saturated)
+        );
+    }
+}
@@ -17,13 +17,21 @@ pub enum Error {
 
     /// Error described using a MQTT Reason code
     Reason(ReasonCode),
+
+    /// All the packet identifiers are in use
+    PacketIdentifiersExhausted,
+
+    /// All the topic aliases are in use
+    TopicAliasesExhausted,
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         match self {
-            Error::Reason(rc) => write!(f, "{:?}", rc),
+            Error::Reason(rc) => rc.fmt(f),
             Error::Io(ref e) => e.fmt(f),
+            Error::PacketIdentifiersExhausted => write!(f, "Packet identifiers exhausted"),
+            Error::TopicAliasesExhausted => write!(f, "Topic aliases exhausted"),
         }
     }
 }
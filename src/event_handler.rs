@@ -0,0 +1,98 @@
+use crate::{Publish, ReasonCode, SubAck, Subscribe};
+use std::future::{ready, Future};
+
+/// Observes the lifecycle of the clients of a broker, such as for auditing,
+/// billing or presence, without changing how the broker handles them.
+///
+/// The broker calls `on_connect` once it accepted a connection with
+/// `ServerConnection::connack`, `on_disconnect` upon a
+/// `ServerEvent::Disconnected` event of an accepted connection, `on_publish`
+/// upon a `ServerEvent::Message` event and `on_subscribe` once it answered a
+/// `ServerEvent::Subscribe` event with `ServerConnection::suback`. The client
+/// is identified by its client id, the assigned one if the server assigned
+/// it.
+///
+/// Every callback does nothing by default, so that a handler only implements
+/// the ones it needs.
+pub trait EventHandler {
+    /// A client connected, authenticated as `user_name` if any, resuming its
+    /// session if `session_present` is set.
+    fn on_connect(
+        &self,
+        client_id: &str,
+        user_name: Option<&str>,
+        session_present: bool,
+    ) -> impl Future<Output = ()> + Send {
+        let _ = (client_id, user_name, session_present);
+        ready(())
+    }
+
+    /// The connection of a client closed with `reason_code`, which is
+    /// `Success` upon a normal disconnection.
+    fn on_disconnect(
+        &self,
+        client_id: &str,
+        reason_code: ReasonCode,
+    ) -> impl Future<Output = ()> + Send {
+        let _ = (client_id, reason_code);
+        ready(())
+    }
+
+    /// A client published a message, with the topic alias resolved.
+    fn on_publish(&self, client_id: &str, publish: &Publish) -> impl Future<Output = ()> + Send {
+        let _ = (client_id, publish);
+        ready(())
+    }
+
+    /// A client subscribed to the topic filters of `subscribe`, which were
+    /// granted or refused with the reason codes of `suback`, in the same
+    /// order.
+    fn on_subscribe(
+        &self,
+        client_id: &str,
+        subscribe: &Subscribe,
+        suback: &SubAck,
+    ) -> impl Future<Output = ()> + Send {
+        let _ = (client_id, subscribe, suback);
+        ready(())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use std::{collections::HashSet, sync::Mutex};
+
+    // Tracks the clients online, ignoring publications and subscriptions.
+    #[derive(Default)]
+    struct Presence {
+        online: Mutex<HashSet<String>>,
+    }
+
+    impl EventHandler for Presence {
+        async fn on_connect(&self, client_id: &str, _: Option<&str>, _: bool) {
+            self.online.lock().unwrap().insert(client_id.into());
+        }
+
+        async fn on_disconnect(&self, client_id: &str, _: ReasonCode) {
+            self.online.lock().unwrap().remove(client_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn presence() {
+        let presence = Presence::default();
+        presence.on_connect("daft", Some("thomas"), false).await;
+        presence.on_connect("punk", None, true).await;
+        presence.on_publish("daft", &Publish::default()).await;
+        presence
+            .on_subscribe("punk", &Subscribe::default(), &SubAck::default())
+            .await;
+        presence.on_disconnect("daft", ReasonCode::Success).await;
+        assert_eq!(
+            *presence.online.lock().unwrap(),
+            HashSet::from(["punk".to_string()])
+        );
+    }
+}
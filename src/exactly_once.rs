@@ -0,0 +1,322 @@
+use crate::{
+    Packet, PacketIdAllocator, PubComp, PubRec, PubRel, Publish, QoS,
+    ReasonCode::{self, PacketIdentifierNotFound, ProtocolError},
+    Result as SageResult,
+};
+use std::collections::{HashSet, VecDeque};
+
+#[derive(Debug, Clone)]
+enum Delivery {
+    // The `Publish` packet was sent, awaiting a `PubRec` packet.
+    Published(Publish),
+    // The `PubRel` packet was sent, awaiting a `PubComp` packet.
+    Released(Publish),
+}
+
+impl Delivery {
+    fn packet_identifier(&self) -> Option<u16> {
+        match self {
+            Delivery::Published(publish) | Delivery::Released(publish) => publish.packet_identifier,
+        }
+    }
+}
+
+/// The sender side of `ExactlyOnce` deliveries: a `Publish` packet is kept
+/// until it is received by the peer with a `PubRec` packet, which is answered
+/// with a `PubRel` packet. The delivery ends upon the `PubComp` packet.
+/// The packet identifiers are shared by all the exchanges of a session, hence
+/// they are taken from a `PacketIdAllocator` provided by the caller.
+#[derive(Debug, Default, Clone)]
+pub struct ExactlyOnceSender {
+    pending: VecDeque<Delivery>,
+}
+
+impl ExactlyOnceSender {
+    /// Creates a sender with no pending delivery.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn position(&self, packet_identifier: u16) -> Option<usize> {
+        self.pending
+            .iter()
+            .position(|d| d.packet_identifier() == Some(packet_identifier))
+    }
+
+    /// Starts the delivery of `publish`, returning the `Publish` packet to
+    /// send with a newly allocated packet identifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the quality of service is not `ExactlyOnce`,
+    /// or any error of `PacketIdAllocator::allocate`.
+    pub fn publish(
+        &mut self,
+        ids: &mut PacketIdAllocator,
+        publish: Publish,
+    ) -> SageResult<Publish> {
+        if publish.qos != QoS::ExactlyOnce {
+            return Err(ProtocolError.into());
+        }
+        let publish = Publish {
+            duplicate: false,
+            packet_identifier: Some(ids.allocate()?),
+            ..publish
+        };
+        self.pending.push_back(Delivery::Published(publish.clone()));
+        Ok(publish)
+    }
+
+    /// Handles a received `PubRec` packet, returning the `PubRel` packet to
+    /// send if any:
+    /// - If the `PubRec` reason code is an error, the delivery ends and its
+    ///   packet identifier is released. No `PubRel` is sent.
+    /// - If no delivery is pending for the packet identifier, the `PubRel`
+    ///   reason code is `PacketIdentifierNotFound`.
+    pub fn receive_pubrec(
+        &mut self,
+        ids: &mut PacketIdAllocator,
+        pubrec: &PubRec,
+    ) -> Option<PubRel> {
+        let packet_identifier = pubrec.packet_identifier;
+        let index = match self.position(packet_identifier) {
+            Some(index) => index,
+            None => {
+                return Some(PubRel {
+                    packet_identifier,
+                    reason_code: PacketIdentifierNotFound,
+                    ..Default::default()
+                })
+            }
+        };
+
+        if pubrec.reason_code.is_error() {
+            self.pending.remove(index);
+            ids.release(packet_identifier);
+            return None;
+        }
+
+        if let Some(delivery) = self.pending.get_mut(index) {
+            if let Delivery::Published(publish) = delivery {
+                *delivery = Delivery::Released(publish.clone());
+            }
+        }
+        Some(PubRel {
+            packet_identifier,
+            ..Default::default()
+        })
+    }
+
+    /// Ends the delivery completed by `pubcomp`, releasing its packet
+    /// identifier and returning the delivered `Publish` packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PacketIdentifierNotFound` if no delivery awaits a `PubComp`
+    /// packet for the packet identifier.
+    pub fn receive_pubcomp(
+        &mut self,
+        ids: &mut PacketIdAllocator,
+        pubcomp: &PubComp,
+    ) -> SageResult<Publish> {
+        match self.position(pubcomp.packet_identifier) {
+            Some(index) if matches!(self.pending[index], Delivery::Released(_)) => {
+                ids.release(pubcomp.packet_identifier);
+                match self.pending.remove(index) {
+                    Some(Delivery::Released(publish)) => Ok(publish),
+                    _ => Err(PacketIdentifierNotFound.into()),
+                }
+            }
+            _ => Err(PacketIdentifierNotFound.into()),
+        }
+    }
+
+    /// Returns the packets to send again when a session is resumed, in their
+    /// original order: `Publish` packets not received yet with the
+    /// `duplicate` flag set, and `PubRel` packets not completed yet.
+    pub fn retransmit(&self) -> Vec<Packet> {
+        self.pending
+            .iter()
+            .map(|delivery| match delivery {
+                Delivery::Published(publish) => Publish {
+                    duplicate: true,
+                    ..publish.clone()
+                }
+                .into(),
+                Delivery::Released(publish) => PubRel {
+                    packet_identifier: publish.packet_identifier.unwrap_or_default(),
+                    ..Default::default()
+                }
+                .into(),
+            })
+            .collect()
+    }
+
+    /// Returns the number of deliveries not completed yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // The deliveries not completed yet, in order, as the `Publish` packet
+    // sent and whether it was released.
+    pub(crate) fn deliveries(&self) -> impl Iterator<Item = (&Publish, bool)> {
+        self.pending.iter().map(|delivery| match delivery {
+            Delivery::Published(publish) => (publish, false),
+            Delivery::Released(publish) => (publish, true),
+        })
+    }
+
+    // Resumes a delivery restored from a session, whose packet identifier is
+    // already reserved.
+    pub(crate) fn restore(&mut self, publish: Publish, released: bool) {
+        self.pending.push_back(if released {
+            Delivery::Released(publish)
+        } else {
+            Delivery::Published(publish)
+        });
+    }
+}
+
+/// The receiver side of `ExactlyOnce` deliveries. A received `Publish` packet
+/// is delivered to the application only once, until its packet identifier is
+/// released by a `PubRel` packet. Duplicates received in the meantime are
+/// acknowledged but not delivered again.
+#[derive(Debug, Default, Clone)]
+pub struct ExactlyOnceReceiver {
+    received: HashSet<u16>,
+}
+
+impl ExactlyOnceReceiver {
+    /// Creates a receiver with no pending delivery.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Receives `publish`, returning the message to deliver to the application
+    /// if it was not delivered yet, along with the `PubRec` packet to send.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the quality of service is not `ExactlyOnce`
+    /// or the packet identifier is missing.
+    pub fn receive(&mut self, publish: Publish) -> SageResult<(Option<Publish>, PubRec)> {
+        let pubrec = PubRec::for_publish(&publish, ReasonCode::Success)?;
+        if self.received.insert(pubrec.packet_identifier) {
+            Ok((Some(publish), pubrec))
+        } else {
+            Ok((None, pubrec))
+        }
+    }
+
+    /// Handles a received `PubRel` packet, returning the `PubComp` packet to
+    /// send. Its reason code is `PacketIdentifierNotFound` if no delivery is
+    /// pending for the packet identifier.
+    pub fn receive_pubrel(&mut self, pubrel: &PubRel) -> PubComp {
+        let reason_code = if self.received.remove(&pubrel.packet_identifier) {
+            ReasonCode::Success
+        } else {
+            PacketIdentifierNotFound
+        };
+        PubComp {
+            packet_identifier: pubrel.packet_identifier,
+            reason_code,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the number of deliveries awaiting a `PubRel` packet.
+    pub fn pending(&self) -> usize {
+        self.received.len()
+    }
+
+    // The packet identifiers awaiting a `PubRel` packet, in ascending order.
+    pub(crate) fn received(&self) -> Vec<u16> {
+        let mut received: Vec<u16> = self.received.iter().copied().collect();
+        received.sort_unstable();
+        received
+    }
+
+    // Resumes a delivery restored from a session.
+    pub(crate) fn restore(&mut self, packet_identifier: u16) {
+        self.received.insert(packet_identifier);
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::Error;
+
+    fn publish() -> Publish {
+        Publish {
+            qos: QoS::ExactlyOnce,
+            topic_name: "Around the World".try_into().unwrap(),
+            message: "Harder, Better, Faster, Stronger".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flow() {
+        let mut ids = PacketIdAllocator::new();
+        let mut sender = ExactlyOnceSender::new();
+        let mut receiver = ExactlyOnceReceiver::new();
+
+        let publish = sender.publish(&mut ids, publish()).unwrap();
+        let (message, pubrec) = receiver.receive(publish.clone()).unwrap();
+        assert_eq!(message, Some(publish.clone()));
+
+        let retransmitted = match sender.retransmit().pop() {
+            Some(Packet::Publish(publish)) => publish,
+            _ => panic!("unexpected retransmission"),
+        };
+        assert!(retransmitted.duplicate);
+        let (message, _) = receiver.receive(retransmitted).unwrap();
+        assert_eq!(message, None);
+
+        let pubrel = sender.receive_pubrec(&mut ids, &pubrec).unwrap();
+        assert_eq!(pubrel.reason_code, ReasonCode::Success);
+        assert!(matches!(sender.retransmit()[..], [Packet::PubRel(_)]));
+
+        let pubcomp = receiver.receive_pubrel(&pubrel);
+        assert_eq!(pubcomp.reason_code, ReasonCode::Success);
+        assert_eq!(sender.receive_pubcomp(&mut ids, &pubcomp).unwrap(), publish);
+        assert_eq!(sender.pending(), 0);
+        assert_eq!(receiver.pending(), 0);
+        assert_eq!(ids.in_use(), 0);
+    }
+
+    #[test]
+    fn packet_identifier_not_found() {
+        let mut ids = PacketIdAllocator::new();
+        let mut sender = ExactlyOnceSender::new();
+        let mut receiver = ExactlyOnceReceiver::new();
+
+        let pubrec = PubRec {
+            packet_identifier: 1337,
+            ..Default::default()
+        };
+        let pubrel = sender.receive_pubrec(&mut ids, &pubrec).unwrap();
+        assert_eq!(pubrel.reason_code, PacketIdentifierNotFound);
+
+        let pubcomp = receiver.receive_pubrel(&pubrel);
+        assert_eq!(pubcomp.reason_code, PacketIdentifierNotFound);
+        assert!(matches!(
+            sender.receive_pubcomp(&mut ids, &pubcomp),
+            Err(Error::Reason(PacketIdentifierNotFound))
+        ));
+    }
+
+    #[test]
+    fn rejected() {
+        let mut ids = PacketIdAllocator::new();
+        let mut sender = ExactlyOnceSender::new();
+
+        let publish = sender.publish(&mut ids, publish()).unwrap();
+        let pubrec = PubRec::for_publish(&publish, ReasonCode::QuotaExceeded).unwrap();
+        assert_eq!(sender.receive_pubrec(&mut ids, &pubrec), None);
+        assert_eq!(sender.pending(), 0);
+        assert_eq!(ids.in_use(), 0);
+    }
+}
@@ -0,0 +1,76 @@
+use std::{convert::From, time::Duration};
+
+/// Describes the Session Expiry Interval of a session, which carries special
+/// meanings beyond a raw number of seconds:
+/// - The property may be absent from the packet (`Default`), whose meaning
+///   depends on the packet it is used in.
+/// - The value `0xFFFFFFFF` means the session never expires (`Never`).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum Expiry {
+    /// The property is absent from the packet:
+    /// - In `Connect`, the session ends when the network connection is closed.
+    /// - In `ConnAck`, the server uses the value requested by the client.
+    /// - In `Disconnect`, the value set upon connection is kept.
+    #[default]
+    Default,
+
+    /// The session expires the given amount of seconds after the network
+    /// connection is closed. `Seconds(0)` explicitly ends the session when
+    /// the network connection is closed.
+    Seconds(u32),
+
+    /// The session never expires.
+    Never,
+}
+
+impl Expiry {
+    /// Returns the value of the Session Expiry Interval as sent on the wire,
+    /// or `None` if the property is absent.
+    pub fn to_seconds(&self) -> Option<u32> {
+        match self {
+            Expiry::Default => None,
+            Expiry::Seconds(secs) => Some(*secs),
+            Expiry::Never => Some(u32::MAX),
+        }
+    }
+
+    /// Returns the interval as a `Duration` if it is expressed in seconds.
+    pub fn as_duration(&self) -> Option<Duration> {
+        match self {
+            Expiry::Seconds(secs) => Some(Duration::from_secs(*secs as u64)),
+            _ => None,
+        }
+    }
+}
+
+impl From<u32> for Expiry {
+    /// Converts a raw interval into an `Expiry`, `0xFFFFFFFF` being `Never`.
+    fn from(secs: u32) -> Self {
+        if secs == u32::MAX {
+            Expiry::Never
+        } else {
+            Expiry::Seconds(secs)
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn from_seconds() {
+        assert_eq!(Expiry::from(0), Expiry::Seconds(0));
+        assert_eq!(Expiry::from(1337), Expiry::Seconds(1337));
+        assert_eq!(Expiry::from(0xFFFF_FFFF), Expiry::Never);
+    }
+
+    #[test]
+    fn to_seconds() {
+        assert_eq!(Expiry::Default.to_seconds(), None);
+        assert_eq!(Expiry::Seconds(0).to_seconds(), Some(0));
+        assert_eq!(Expiry::Never.to_seconds(), Some(0xFFFF_FFFF));
+    }
+}
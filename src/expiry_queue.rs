@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    mem,
+    time::{Duration, Instant},
+};
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 11;
+
+/// A queue of handles, such as the ones of stored messages, each expiring at
+/// a given deadline.
+///
+/// The queue is a hierarchical timing wheel with a resolution of one
+/// millisecond: inserting and removing a handle are constant time operations,
+/// and draining the expired handles only visits the non-empty slots of the
+/// wheel. The current time is always given by the caller.
+#[derive(Debug, Clone)]
+pub struct ExpiryQueue<K> {
+    start: Instant,
+    current: u64,
+    levels: Vec<Vec<Vec<(K, u64)>>>,
+    deadlines: HashMap<K, u64>,
+    overdue: Vec<(K, u64)>,
+}
+
+impl<K: Hash + Eq + Clone> ExpiryQueue<K> {
+    /// Creates an empty queue, where `now` is the current time.
+    pub fn new(now: Instant) -> Self {
+        ExpiryQueue {
+            start: now,
+            current: 0,
+            levels: (0..LEVELS)
+                .map(|_| (0..SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            deadlines: HashMap::new(),
+            overdue: Vec::new(),
+        }
+    }
+
+    /// Returns the number of handles in the queue.
+    pub fn len(&self) -> usize {
+        self.deadlines.len()
+    }
+
+    /// Returns `true` if the queue contains no handle.
+    pub fn is_empty(&self) -> bool {
+        self.deadlines.is_empty()
+    }
+
+    /// Returns `true` if `key` is in the queue.
+    pub fn contains(&self, key: &K) -> bool {
+        self.deadlines.contains_key(key)
+    }
+
+    /// Inserts `key`, expiring at `deadline`. If `key` is already in the
+    /// queue, its deadline is replaced.
+    pub fn insert(&mut self, key: K, deadline: Instant) {
+        // Deadlines are rounded up so that no handle expires early.
+        let elapsed = deadline.saturating_duration_since(self.start);
+        let millis = elapsed.as_millis() as u64;
+        let tick = if elapsed > Duration::from_millis(millis) {
+            millis + 1
+        } else {
+            millis
+        };
+        self.deadlines.insert(key.clone(), tick);
+        self.place(key, tick);
+    }
+
+    /// Removes `key` from the queue. Returns `false` if it was not in the
+    /// queue.
+    pub fn remove(&mut self, key: &K) -> bool {
+        // The entry is left in the wheel and skipped once it is reached.
+        self.deadlines.remove(key).is_some()
+    }
+
+    /// Removes and returns the handles whose deadline is at or before `now`,
+    /// in the order of their deadlines.
+    pub fn expired_before(&mut self, now: Instant) -> Vec<K> {
+        let now = now.saturating_duration_since(self.start).as_millis() as u64;
+        let mut expired = Vec::new();
+        for (key, tick) in mem::take(&mut self.overdue) {
+            self.expire(key, tick, &mut expired);
+        }
+
+        while let Some((tick, level, slot)) = self.next_slot() {
+            if tick > now {
+                break;
+            }
+            self.current = tick;
+            for (key, tick) in mem::take(&mut self.levels[level][slot]) {
+                if tick <= self.current {
+                    self.expire(key, tick, &mut expired);
+                } else {
+                    self.place(key, tick);
+                }
+            }
+        }
+        self.current = self.current.max(now);
+        expired
+    }
+
+    /// Returns the time until the next slot of the wheel must be visited,
+    /// which is no later than the next deadline. Returns `None` if the wheel
+    /// is empty. Removed handles may cause early wakeups.
+    pub fn next_wakeup(&self, now: Instant) -> Option<Duration> {
+        if !self.overdue.is_empty() {
+            return Some(Duration::ZERO);
+        }
+        self.next_slot().map(|(tick, _, _)| {
+            (self.start + Duration::from_millis(tick)).saturating_duration_since(now)
+        })
+    }
+
+    fn expire(&mut self, key: K, tick: u64, expired: &mut Vec<K>) {
+        // Skips the handles which were removed or inserted again since.
+        if self.deadlines.get(&key) == Some(&tick) {
+            self.deadlines.remove(&key);
+            expired.push(key);
+        }
+    }
+
+    fn place(&mut self, key: K, tick: u64) {
+        if tick <= self.current {
+            self.overdue.push((key, tick));
+        } else {
+            // The level is the one of the highest group of bits which differs
+            // between the current tick and the deadline.
+            let level = ((63 - (self.current ^ tick).leading_zeros()) / SLOT_BITS) as usize;
+            let slot = (tick >> (level as u32 * SLOT_BITS)) as usize & (SLOTS - 1);
+            self.levels[level][slot].push((key, tick));
+        }
+    }
+
+    // Returns the first tick of the earliest non-empty slot, along with its
+    // position in the wheel.
+    fn next_slot(&self) -> Option<(u64, usize, usize)> {
+        let mut next = None;
+        for (level, slots) in self.levels.iter().enumerate() {
+            let shift = level as u32 * SLOT_BITS;
+            let current = (self.current >> shift) as usize & (SLOTS - 1);
+            let base = self
+                .current
+                .checked_shr(shift + SLOT_BITS)
+                .and_then(|b| b.checked_shl(shift + SLOT_BITS))
+                .unwrap_or(0);
+            if let Some(slot) = (current + 1..SLOTS).find(|&slot| !slots[slot].is_empty()) {
+                let tick = base + ((slot as u64) << shift);
+                if !matches!(next, Some((next, _, _)) if next <= tick) {
+                    next = Some((tick, level, slot));
+                }
+            }
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::test_rng::TestRng;
+
+    #[test]
+    fn expire() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut queue = ExpiryQueue::new(start);
+        queue.insert("a", ms(5));
+        queue.insert("b", ms(70));
+        queue.insert("c", ms(5000));
+        queue.insert("d", ms(3_600_000));
+        queue.insert("e", ms(64));
+        assert_eq!(queue.len(), 5);
+        assert_eq!(queue.next_wakeup(start), Some(Duration::from_millis(5)));
+
+        assert!(queue.expired_before(ms(4)).is_empty());
+        assert_eq!(queue.expired_before(ms(70)), vec!["a", "e", "b"]);
+
+        queue.insert("c", ms(100));
+        assert!(queue.remove(&"d"));
+        assert!(!queue.remove(&"d"));
+        queue.insert("f", ms(10));
+        assert_eq!(queue.expired_before(ms(100)), vec!["f", "c"]);
+        assert!(queue.expired_before(ms(10_000_000)).is_empty());
+        assert!(queue.is_empty());
+        assert_eq!(queue.next_wakeup(ms(10_000_000)), None);
+    }
+
+    #[test]
+    fn matches_sorted_deadlines() {
+        let start = Instant::now();
+        let mut queue = ExpiryQueue::new(start);
+        let mut rng = TestRng::new(1337);
+        let mut deadlines = Vec::new();
+        for key in 0..2000 {
+            let deadline = rng.draw() % 10_000_000;
+            queue.insert(key, start + Duration::from_millis(deadline));
+            deadlines.push((deadline, key));
+        }
+        deadlines.sort_unstable();
+
+        let mut expired = Vec::new();
+        for now in (0..10_000_000).step_by(777_777).chain([10_000_000]) {
+            let drained = queue.expired_before(start + Duration::from_millis(now));
+            assert!(drained
+                .iter()
+                .all(|key| deadlines.iter().any(|&(d, k)| k == *key && d <= now)));
+            expired.extend(drained);
+        }
+        assert!(queue.is_empty());
+        let order: Vec<u64> = expired
+            .iter()
+            .map(|key| deadlines.iter().find(|&&(_, k)| k == *key).unwrap().0)
+            .collect();
+        assert!(order.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(expired.len(), 2000);
+    }
+}
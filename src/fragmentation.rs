@@ -0,0 +1,277 @@
+//! Opt-in splitting of application messages too large for the peer's maximum
+//! packet size into several `Publish` packets, and their reassembly on the
+//! receiving side.
+//! Fragments are identified using user properties: each fragment carries the
+//! identifier of the message it belongs to, its index and the total number of
+//! fragments. Both peers must agree on using this convention.
+
+use crate::{
+    Publish,
+    ReasonCode::{ProtocolError, QuotaExceeded},
+    Result as SageResult,
+};
+use std::collections::HashMap;
+
+/// The user property key holding the identifier of the fragmented message.
+pub const FRAGMENT_ID: &str = "sage-fragment-id";
+
+/// The user property key holding the index of the fragment, starting at `0`.
+pub const FRAGMENT_INDEX: &str = "sage-fragment-index";
+
+/// The user property key holding the total number of fragments.
+pub const FRAGMENT_TOTAL: &str = "sage-fragment-total";
+
+/// Splits `publish` into fragments carrying at most `max_message_size` bytes
+/// of message each. `id` identifies the message among the ones being
+/// reassembled by the receiver, and must be unique for a given topic.
+/// Every fragment is a copy of `publish` with its properties, hence
+/// `max_message_size` must leave room for them along with the fragmentation
+/// user properties within the peer's maximum packet size.
+/// A message fitting in `max_message_size` is still sent as a single fragment.
+pub fn fragment(publish: &Publish, id: &str, max_message_size: usize) -> Vec<Publish> {
+    let max_message_size = max_message_size.max(1);
+    let chunks: Vec<&[u8]> = if publish.message.is_empty() {
+        vec![&[]]
+    } else {
+        publish.message.chunks(max_message_size).collect()
+    };
+    let total = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut fragment = Publish {
+                message: chunk.to_vec(),
+                ..publish.clone()
+            };
+            fragment.user_properties.extend(vec![
+                (FRAGMENT_ID.into(), id.into()),
+                (FRAGMENT_INDEX.into(), index.to_string()),
+                (FRAGMENT_TOTAL.into(), total.to_string()),
+            ]);
+            fragment
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Partial {
+    fragments: Vec<Option<Publish>>,
+    received: usize,
+}
+
+/// Collects fragments produced by `fragment` until the complete message can
+/// be rebuilt.
+///
+/// The fragmentation user properties come from the peer, so the number of
+/// fragments of a message and the number of messages being reassembled at
+/// once are bounded, by 1024 and 64 respectively unless configured
+/// otherwise.
+#[derive(Debug)]
+pub struct Reassembler {
+    partials: HashMap<(String, String), Partial>,
+    maximum_fragments: usize,
+    maximum_partials: usize,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Reassembler {
+            partials: HashMap::new(),
+            maximum_fragments: 1024,
+            maximum_partials: 64,
+        }
+    }
+}
+
+impl Reassembler {
+    /// Creates an empty reassembler.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the maximum number of fragments of a message.
+    pub fn with_maximum_fragments(mut self, maximum_fragments: usize) -> Self {
+        self.maximum_fragments = maximum_fragments;
+        self
+    }
+
+    /// Sets the maximum number of messages being reassembled at once.
+    pub fn with_maximum_partials(mut self, maximum_partials: usize) -> Self {
+        self.maximum_partials = maximum_partials;
+        self
+    }
+
+    /// Returns the number of messages being reassembled.
+    pub fn len(&self) -> usize {
+        self.partials.len()
+    }
+
+    /// Returns `true` if no message is being reassembled.
+    pub fn is_empty(&self) -> bool {
+        self.partials.is_empty()
+    }
+
+    /// Feeds a received `Publish` into the reassembler.
+    /// Returns the complete message once all its fragments have been received,
+    /// or `None` if some are still missing. A `Publish` which is not a fragment
+    /// is returned as is.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the fragmentation user properties are
+    /// malformed or inconsistent with the previous fragments, and
+    /// `QuotaExceeded` if the message has more fragments than the maximum or
+    /// if it starts a new message while the maximum number of messages are
+    /// being reassembled. Nothing is allocated for a refused fragment.
+    pub fn push(&mut self, mut publish: Publish) -> SageResult<Option<Publish>> {
+        let mut id = None;
+        let mut index = None;
+        let mut total = None;
+        publish.user_properties.retain(|(k, v)| match k.as_str() {
+            FRAGMENT_ID => {
+                id = Some(v.clone());
+                false
+            }
+            FRAGMENT_INDEX => {
+                index = Some(v.parse::<usize>());
+                false
+            }
+            FRAGMENT_TOTAL => {
+                total = Some(v.parse::<usize>());
+                false
+            }
+            _ => true,
+        });
+
+        let (id, index, total) = match (id, index, total) {
+            (None, None, None) => return Ok(Some(publish)),
+            (Some(id), Some(Ok(index)), Some(Ok(total))) if index < total => (id, index, total),
+            _ => return Err(ProtocolError.into()),
+        };
+
+        if total > self.maximum_fragments {
+            return Err(QuotaExceeded.into());
+        }
+        let key = (publish.topic_name.to_string(), id);
+        if !self.partials.contains_key(&key) && self.partials.len() >= self.maximum_partials {
+            return Err(QuotaExceeded.into());
+        }
+        let partial = self.partials.entry(key.clone()).or_insert_with(|| Partial {
+            fragments: vec![None; total],
+            received: 0,
+        });
+        if partial.fragments.len() != total {
+            return Err(ProtocolError.into());
+        }
+        if partial.fragments[index].is_none() {
+            partial.received += 1;
+        }
+        partial.fragments[index] = Some(publish);
+
+        if partial.received < total {
+            return Ok(None);
+        }
+
+        let mut fragments = self
+            .partials
+            .remove(&key)
+            .map(|partial| partial.fragments)
+            .unwrap_or_default()
+            .into_iter()
+            .flatten();
+        let mut publish = match fragments.next() {
+            Some(publish) => publish,
+            None => return Err(ProtocolError.into()),
+        };
+        for fragment in fragments {
+            publish.message.extend(fragment.message);
+        }
+        Ok(Some(publish))
+    }
+
+    /// Discards the fragments received so far for the message `id` published
+    /// to `topic_name`, such as when it expired.
+    pub fn discard(&mut self, topic_name: &str, id: &str) {
+        self.partials.remove(&(topic_name.into(), id.into()));
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let publish = Publish {
+            topic_name: "Around the World".try_into().unwrap(),
+            user_properties: vec![("Mogwaï".into(), "Cat".into())],
+            message: (0..=255).collect(),
+            ..Default::default()
+        };
+
+        let mut fragments = fragment(&publish, "1337", 100);
+        assert_eq!(fragments.len(), 3);
+        assert!(fragments.iter().all(|f| f.message.len() <= 100));
+
+        let mut reassembler = Reassembler::new();
+        let last = fragments.remove(1);
+        for f in fragments {
+            assert_eq!(reassembler.push(f).unwrap(), None);
+        }
+        assert_eq!(reassembler.push(last).unwrap(), Some(publish.clone()));
+
+        assert_eq!(
+            reassembler.push(publish.clone()).unwrap(),
+            Some(publish.clone())
+        );
+    }
+
+    fn fragment_of(id: &str, index: usize, total: &str) -> Publish {
+        Publish {
+            topic_name: "Around the World".try_into().unwrap(),
+            user_properties: vec![
+                (FRAGMENT_ID.into(), id.into()),
+                (FRAGMENT_INDEX.into(), index.to_string()),
+                (FRAGMENT_TOTAL.into(), total.into()),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn oversized_total() {
+        let mut reassembler = Reassembler::new().with_maximum_fragments(4);
+        assert!(matches!(
+            reassembler.push(fragment_of("1", 0, "18446744073709551615")),
+            Err(crate::Error::Reason(QuotaExceeded))
+        ));
+        assert!(matches!(
+            reassembler.push(fragment_of("1", 0, "5")),
+            Err(crate::Error::Reason(QuotaExceeded))
+        ));
+        assert!(reassembler.is_empty());
+        assert_eq!(reassembler.push(fragment_of("1", 0, "4")).unwrap(), None);
+    }
+
+    #[test]
+    fn partials_exhaustion() {
+        let mut reassembler = Reassembler::new().with_maximum_partials(2);
+        assert_eq!(reassembler.push(fragment_of("1", 0, "2")).unwrap(), None);
+        assert_eq!(reassembler.push(fragment_of("2", 0, "2")).unwrap(), None);
+        assert!(matches!(
+            reassembler.push(fragment_of("3", 0, "2")),
+            Err(crate::Error::Reason(QuotaExceeded))
+        ));
+        assert_eq!(reassembler.len(), 2);
+
+        assert!(reassembler
+            .push(fragment_of("1", 1, "2"))
+            .unwrap()
+            .is_some());
+        assert_eq!(reassembler.push(fragment_of("3", 0, "2")).unwrap(), None);
+        reassembler.discard("Around the World", "2");
+        assert_eq!(reassembler.push(fragment_of("4", 0, "2")).unwrap(), None);
+    }
+}
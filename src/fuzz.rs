@@ -0,0 +1,113 @@
+//! Entry points for fuzzing the decoders.
+//! Decoding never panics: any input, including truncated packets, packets
+//! claiming lengths larger than the available data or packets whose body does
+//! not match their remaining length, results in an error.
+
+use crate::{Packet, Result as SageResult};
+
+/// Decodes a single control packet from `data`, without requiring any async
+/// runtime.
+/// Returns the decoded packet, or the error it is rejected with.
+///
+/// ```
+/// let data = [0xC0, 0x00]; // PINGREQ
+/// assert!(sage_mqtt::fuzz::decode_any(&data).is_ok());
+/// assert!(sage_mqtt::fuzz::decode_any(&data[..1]).is_err());
+/// ```
+pub fn decode_any(data: &[u8]) -> SageResult<Packet> {
+    Packet::decode_slice(data).map(|(packet, _)| packet)
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{Auth, Authentication, ConnAck, Connect, Disconnect, PubAck, ReasonCode};
+
+    // Packets whose body is fully determined by their fields, with a remaining
+    // length encoded on a single byte.
+    fn packets() -> Vec<Vec<u8>> {
+        let packets: Vec<Packet> = vec![
+            Connect {
+                client_id: Some("Ice".into()),
+                ..Default::default()
+            }
+            .into(),
+            ConnAck {
+                reason_string: Some("Baby".into()),
+                ..Default::default()
+            }
+            .into(),
+            PubAck {
+                packet_identifier: 1337,
+                reason_string: Some("Vanilla".into()),
+                ..Default::default()
+            }
+            .into(),
+            Disconnect {
+                reason_code: ReasonCode::ServerShuttingDown,
+                reason_string: Some("Under Pressure".into()),
+                ..Default::default()
+            }
+            .into(),
+            Auth {
+                reason_code: ReasonCode::ContinueAuthentication,
+                authentication: Authentication {
+                    method: "Willow".into(),
+                    data: vec![0x0D, 0x15, 0xEA, 0x5E],
+                },
+                ..Default::default()
+            }
+            .into(),
+        ];
+        packets
+            .into_iter()
+            .map(|packet| packet.encode_vec().unwrap())
+            .inspect(|data| assert!(data[1] < 0x80))
+            .collect()
+    }
+
+    #[test]
+    fn valid() {
+        for data in packets() {
+            assert!(decode_any(&data).is_ok());
+        }
+    }
+
+    #[test]
+    fn truncated_any() {
+        for data in packets() {
+            for n in 0..data.len() {
+                assert!(decode_any(&data[..n]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn shorter_remaining_length() {
+        for mut data in packets() {
+            data[1] -= 1;
+            assert!(decode_any(&data).is_err());
+        }
+    }
+
+    #[test]
+    fn longer_remaining_length() {
+        for mut data in packets() {
+            data[1] += 1;
+            data.push(0x00);
+            assert!(decode_any(&data).is_err());
+        }
+        // PINGREQ with a one byte body.
+        assert!(decode_any(&[0xC0, 0x01, 0x00]).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        // PUBLISH claiming a 200 bytes remaining length.
+        let data = [0x30, 0xC8, 0x01, 0x00, 0x03, 0x61, 0x2F, 0x62];
+        for n in 0..=data.len() {
+            assert!(decode_any(&data[..n]).is_err());
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{Listener, PeerInfo};
+use std::{
+    future::Future,
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+type Handshake<S> = Pin<Box<dyn Future<Output = IoResult<(S, PeerInfo)>> + Send>>;
+
+/// The time given to a handshake by default before dropping its connection.
+pub(crate) const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of handshakes in progress above which no connection is accepted
+/// by default.
+pub(crate) const DEFAULT_MAXIMUM_HANDSHAKES: usize = 256;
+
+/// The handshakes in progress of a listener wrapping another one, such as a
+/// TLS or a WebSocket listener.
+///
+/// A handshake which does not complete within the timeout fails, and no
+/// connection is accepted while the maximum number of handshakes are in
+/// progress, so that idle peers cannot exhaust the resources of the server.
+pub(crate) struct Handshakes<S> {
+    pending: Vec<Handshake<S>>,
+    timeout: Option<Duration>,
+    maximum: usize,
+}
+
+impl<S> Default for Handshakes<S> {
+    fn default() -> Self {
+        Handshakes {
+            pending: Vec::new(),
+            timeout: Some(DEFAULT_HANDSHAKE_TIMEOUT),
+            maximum: DEFAULT_MAXIMUM_HANDSHAKES,
+        }
+    }
+}
+
+impl<S: 'static> Handshakes<S> {
+    /// Sets the time given to the handshakes started from now on, `None`
+    /// waiting for them indefinitely.
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Sets the number of handshakes in progress above which no connection is
+    /// accepted.
+    pub(crate) fn set_maximum(&mut self, maximum: usize) {
+        self.maximum = maximum;
+    }
+
+    /// Returns `true` if no connection must be accepted until a handshake
+    /// ends.
+    pub(crate) fn is_full(&self) -> bool {
+        self.pending.len() >= self.maximum
+    }
+
+    /// Starts tracking a handshake. It fails with a `TimedOut` IO error if it
+    /// does not complete in time.
+    pub(crate) fn push<F>(&mut self, handshake: F)
+    where
+        F: Future<Output = IoResult<(S, PeerInfo)>> + Send + 'static,
+    {
+        match self.timeout {
+            Some(timeout) => self.pending.push(Box::pin(async move {
+                tokio::time::timeout(timeout, handshake)
+                    .await
+                    .unwrap_or_else(|_| Err(IoError::new(ErrorKind::TimedOut, "handshake timeout")))
+            })),
+            None => self.pending.push(Box::pin(handshake)),
+        }
+    }
+
+    /// Polls the handshakes in progress, and returns the first connection
+    /// whose handshake completes. Failed handshakes are dropped.
+    pub(crate) fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<(S, PeerInfo)> {
+        let mut index = 0;
+        while index < self.pending.len() {
+            match self.pending[index].as_mut().poll(cx) {
+                Poll::Ready(Ok(connection)) => {
+                    drop(self.pending.swap_remove(index));
+                    return Poll::Ready(connection);
+                }
+                Poll::Ready(Err(_)) => drop(self.pending.swap_remove(index)),
+                Poll::Pending => index += 1,
+            }
+        }
+        Poll::Pending
+    }
+
+    /// Accepts the connections of `listener` while there is room for their
+    /// handshake, starting a handshake for each of them with `start`, and
+    /// returns the first connection whose handshake completes. Only the errors
+    /// of `listener` are returned.
+    pub(crate) fn poll_accept<L, F>(
+        &mut self,
+        listener: &mut L,
+        cx: &mut Context<'_>,
+        mut start: impl FnMut(L::Stream, PeerInfo) -> F,
+    ) -> Poll<IoResult<(S, PeerInfo)>>
+    where
+        L: Listener,
+        F: Future<Output = IoResult<(S, PeerInfo)>> + Send + 'static,
+    {
+        loop {
+            let full = self.is_full();
+            if !full {
+                match listener.poll_accept(cx) {
+                    Poll::Ready(Ok((stream, peer))) => {
+                        self.push(start(stream, peer));
+                        continue;
+                    }
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => (),
+                }
+            }
+            match self.poll_next(cx) {
+                Poll::Ready(connection) => return Poll::Ready(Ok(connection)),
+                // Failed handshakes made room while `listener` was not polled.
+                Poll::Pending if full && !self.is_full() => (),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use std::{
+        collections::VecDeque,
+        future::{pending, poll_fn},
+        io::Cursor,
+    };
+
+    struct Queued(VecDeque<(Cursor<Vec<u8>>, PeerInfo)>);
+
+    impl Listener for Queued {
+        type Stream = Cursor<Vec<u8>>;
+
+        fn poll_accept(&mut self, _: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+            match self.0.pop_front() {
+                Some(connection) => Poll::Ready(Ok(connection)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handshake_timeout() {
+        let mut handshakes = Handshakes::<Cursor<Vec<u8>>>::default();
+        handshakes.set_timeout(Some(Duration::from_secs(5)));
+        handshakes.push(pending());
+        let start = tokio::time::Instant::now();
+        poll_fn(|cx| {
+            assert!(handshakes.poll_next(cx).is_pending());
+            if handshakes.pending.is_empty() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        assert!(start.elapsed() >= Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn maximum_handshakes() {
+        let mut listener = Queued((0..3).map(|_| Default::default()).collect());
+        let mut handshakes = Handshakes::default();
+        handshakes.set_maximum(2);
+        let mut start =
+            |_: Cursor<Vec<u8>>, _: PeerInfo| pending::<IoResult<(Cursor<Vec<u8>>, PeerInfo)>>();
+        poll_fn(|cx| {
+            assert!(handshakes
+                .poll_accept(&mut listener, cx, &mut start)
+                .is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        assert!(handshakes.is_full());
+        assert_eq!(listener.0.len(), 1);
+
+        // The timed out handshakes make room for the last connection.
+        tokio::time::sleep(DEFAULT_HANDSHAKE_TIMEOUT).await;
+        poll_fn(|cx| {
+            assert!(handshakes
+                .poll_accept(&mut listener, cx, &mut start)
+                .is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(handshakes.pending.len(), 1);
+        assert!(listener.0.is_empty());
+    }
+}
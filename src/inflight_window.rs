@@ -0,0 +1,155 @@
+use crate::{Packet, Publish, QoS, Quota, ReasonCode::ProtocolError, Result as SageResult};
+use std::collections::{HashSet, VecDeque};
+
+/// Limits the number of `AtLeastOnce` and `ExactlyOnce` deliveries in flight
+/// to the Receive Maximum advertised by the peer, following the rules of
+/// `Quota`.
+/// Once the limit is reached, `Publish` packets are queued until a delivery
+/// completes. A successful `PubRec` packet does not free any slot since the
+/// `PubRel` exchange is still pending.
+///
+/// `AtMostOnce` messages are never limited.
+#[derive(Debug, Clone)]
+pub struct InflightWindow {
+    quota: Quota,
+    in_flight: HashSet<u16>,
+    queued: VecDeque<Publish>,
+}
+
+impl InflightWindow {
+    /// Creates a window allowing up to `receive_maximum` deliveries in
+    /// flight. A value of zero is not valid in MQTT and is treated as one.
+    pub fn new(receive_maximum: u16) -> Self {
+        InflightWindow {
+            quota: Quota::new(receive_maximum),
+            in_flight: HashSet::new(),
+            queued: VecDeque::new(),
+        }
+    }
+
+    /// Submits a `Publish` packet, returning it if it can be sent right away
+    /// or `None` if it is queued.
+    /// Retransmissions of a delivery already in flight are always sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the quality of service is greater than
+    /// `AtMostOnce` and the packet identifier is missing.
+    pub fn send(&mut self, publish: Publish) -> SageResult<Option<Publish>> {
+        if publish.qos == QoS::AtMostOnce {
+            return Ok(Some(publish));
+        }
+        let packet_identifier = publish.packet_identifier.ok_or(ProtocolError)?;
+        if self.in_flight.contains(&packet_identifier) {
+            Ok(Some(publish))
+        } else if self.queued.is_empty() && self.quota.acquire(publish.qos) {
+            self.in_flight.insert(packet_identifier);
+            Ok(Some(publish))
+        } else {
+            self.queued.push_back(publish);
+            Ok(None)
+        }
+    }
+
+    /// Feeds a packet received from the peer into the window. If it completes
+    /// a delivery in flight, returns the queued `Publish` packets which can
+    /// now be sent, in order. Any other packet is ignored.
+    pub fn receive(&mut self, packet: &Packet) -> Vec<Publish> {
+        match Quota::completed(packet) {
+            Some(packet_identifier) if self.in_flight.remove(&packet_identifier) => {
+                self.quota.release();
+            }
+            _ => return Vec::new(),
+        }
+
+        let mut released = Vec::new();
+        while !self.queued.is_empty() && self.quota.acquire(QoS::AtLeastOnce) {
+            if let Some(publish) = self.queued.pop_front() {
+                if let Some(packet_identifier) = publish.packet_identifier {
+                    self.in_flight.insert(packet_identifier);
+                }
+                released.push(publish);
+            }
+        }
+        released
+    }
+
+    /// Returns the number of deliveries in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Returns the number of `Publish` packets waiting for a free slot.
+    pub fn queued(&self) -> usize {
+        self.queued.len()
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{PubAck, PubComp, PubRec, ReasonCode};
+
+    fn publish(qos: QoS, packet_identifier: u16) -> Publish {
+        Publish {
+            qos,
+            packet_identifier: Some(packet_identifier),
+            topic_name: "Around the World".try_into().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn receive_maximum() {
+        let mut window = InflightWindow::new(2);
+        assert!(window.send(publish(QoS::AtLeastOnce, 1)).unwrap().is_some());
+        assert!(window.send(publish(QoS::ExactlyOnce, 2)).unwrap().is_some());
+        assert!(window.send(publish(QoS::AtLeastOnce, 3)).unwrap().is_none());
+        assert!(window.send(publish(QoS::ExactlyOnce, 4)).unwrap().is_none());
+        assert!(window.send(publish(QoS::AtMostOnce, 0)).unwrap().is_some());
+        assert!(window.send(publish(QoS::AtLeastOnce, 1)).unwrap().is_some());
+        assert_eq!((window.in_flight(), window.queued()), (2, 2));
+
+        let pubrec = PubRec {
+            packet_identifier: 2,
+            ..Default::default()
+        };
+        assert!(window.receive(&pubrec.into()).is_empty());
+
+        let puback = PubAck {
+            packet_identifier: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            window.receive(&puback.clone().into()),
+            vec![publish(QoS::AtLeastOnce, 3)]
+        );
+        assert!(window.receive(&puback.into()).is_empty());
+
+        let pubcomp = PubComp {
+            packet_identifier: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            window.receive(&pubcomp.into()),
+            vec![publish(QoS::ExactlyOnce, 4)]
+        );
+
+        let pubrec = PubRec {
+            packet_identifier: 4,
+            reason_code: ReasonCode::QuotaExceeded,
+            ..Default::default()
+        };
+        assert!(window.receive(&pubrec.into()).is_empty());
+        assert_eq!((window.in_flight(), window.queued()), (1, 0));
+
+        assert!(window.send(Publish::default()).unwrap().is_some());
+        assert!(window
+            .send(Publish {
+                qos: QoS::AtLeastOnce,
+                ..Default::default()
+            })
+            .is_err());
+    }
+}
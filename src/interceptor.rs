@@ -0,0 +1,193 @@
+use crate::{Packet, Publish, Result as SageResult};
+use std::fmt;
+
+/// Inspects, rewrites or refuses the packets of a `ServerConnection`, such as
+/// to add user properties, rewrite topics or block messages according to a
+/// policy.
+///
+/// Unlike an `EventHandler`, an interceptor runs synchronously as part of the
+/// connection, and its changes are seen by the broker as if the client, or
+/// the broker itself, had sent the changed packet.
+pub trait Interceptor {
+    /// Intercepts a packet received from the client, before the connection
+    /// handles it. The topic alias of a `Publish` packet is already resolved.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason code to refuse the packet with. A refused `Publish`
+    /// packet is answered as with `ServerConnection::refuse`, if the reason
+    /// code is allowed in its acknowledgement, and any other refused packet
+    /// closes the connection.
+    fn incoming(&mut self, packet: Packet) -> SageResult<Packet> {
+        Ok(packet)
+    }
+
+    /// Intercepts a message published to the client with
+    /// `ServerConnection::publish`, before it is given a packet identifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason code to refuse the message with, which is then
+    /// not sent and returned by `ServerConnection::publish`.
+    fn outgoing(&mut self, publish: Publish) -> SageResult<Publish> {
+        Ok(publish)
+    }
+}
+
+/// The ordered interceptors of a `ServerConnection`.
+///
+/// The incoming packets go through the interceptors in the order they were
+/// added, and the outgoing messages in the reverse order, so that the first
+/// interceptor is the closest to the client. The chain stops at the first
+/// interceptor refusing a packet.
+#[derive(Default)]
+pub struct InterceptorChain {
+    interceptors: Vec<Box<dyn Interceptor + Send>>,
+}
+
+impl fmt::Debug for InterceptorChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InterceptorChain")
+            .field("len", &self.interceptors.len())
+            .finish()
+    }
+}
+
+impl InterceptorChain {
+    /// Creates an empty chain, which lets every packet through unchanged.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds `interceptor` at the end of the chain, the farthest from the
+    /// client.
+    pub fn push<I: Interceptor + Send + 'static>(&mut self, interceptor: I) {
+        self.interceptors.push(Box::new(interceptor));
+    }
+
+    /// Returns the number of interceptors in the chain.
+    pub fn len(&self) -> usize {
+        self.interceptors.len()
+    }
+
+    /// Returns `true` if the chain has no interceptor.
+    pub fn is_empty(&self) -> bool {
+        self.interceptors.is_empty()
+    }
+
+    /// Runs the interceptors on a packet received from the client.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first interceptor refusing the packet.
+    pub fn incoming(&mut self, packet: Packet) -> SageResult<Packet> {
+        self.interceptors
+            .iter_mut()
+            .try_fold(packet, |packet, interceptor| interceptor.incoming(packet))
+    }
+
+    /// Runs the interceptors on a message published to the client.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first interceptor refusing the message.
+    pub fn outgoing(&mut self, publish: Publish) -> SageResult<Publish> {
+        self.interceptors
+            .iter_mut()
+            .rev()
+            .try_fold(publish, |publish, interceptor| {
+                interceptor.outgoing(publish)
+            })
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::ReasonCode::{NotAuthorized, PayloadFormatInvalid};
+
+    // Tags the messages with the interceptors they went through.
+    struct Tag(&'static str);
+
+    impl Interceptor for Tag {
+        fn incoming(&mut self, packet: Packet) -> SageResult<Packet> {
+            match packet {
+                Packet::Publish(mut publish) => {
+                    publish.user_properties.push(("via".into(), self.0.into()));
+                    Ok(publish.into())
+                }
+                packet => Ok(packet),
+            }
+        }
+
+        fn outgoing(&mut self, mut publish: Publish) -> SageResult<Publish> {
+            publish.user_properties.push(("via".into(), self.0.into()));
+            Ok(publish)
+        }
+    }
+
+    // Refuses the messages which payload is not valid UTF-8, in both
+    // directions, and the subscriptions.
+    struct Utf8Only;
+
+    impl Interceptor for Utf8Only {
+        fn incoming(&mut self, packet: Packet) -> SageResult<Packet> {
+            match packet {
+                Packet::Publish(publish) => Ok(self.outgoing(publish)?.into()),
+                Packet::Subscribe(_) => Err(NotAuthorized.into()),
+                packet => Ok(packet),
+            }
+        }
+
+        fn outgoing(&mut self, publish: Publish) -> SageResult<Publish> {
+            match std::str::from_utf8(&publish.message) {
+                Ok(_) => Ok(publish),
+                Err(_) => Err(PayloadFormatInvalid.into()),
+            }
+        }
+    }
+
+    fn via(publish: &Publish) -> Vec<&str> {
+        publish
+            .user_properties
+            .iter()
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn chain() {
+        let mut chain = InterceptorChain::new();
+        assert!(chain.is_empty());
+        chain.push(Tag("first"));
+        chain.push(Utf8Only);
+        chain.push(Tag("second"));
+        assert_eq!(chain.len(), 3);
+
+        let publish = Publish {
+            message: "Harder".into(),
+            ..Default::default()
+        };
+        match chain.incoming(publish.clone().into()).unwrap() {
+            Packet::Publish(publish) => assert_eq!(via(&publish), vec!["first", "second"]),
+            packet => panic!("unexpected {}", packet),
+        }
+        let outgoing = chain.outgoing(publish).unwrap();
+        assert_eq!(via(&outgoing), vec!["second", "first"]);
+
+        let invalid = Publish {
+            message: vec![0xc3, 0x28],
+            ..Default::default()
+        };
+        assert!(matches!(
+            chain.incoming(invalid.clone().into()),
+            Err(crate::Error::Reason(PayloadFormatInvalid))
+        ));
+        assert!(matches!(
+            chain.outgoing(invalid),
+            Err(crate::Error::Reason(PayloadFormatInvalid))
+        ));
+        assert!(chain.incoming(Packet::PingReq).is_ok());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{Authenticator, Connect, ReasonCode::NotAuthorized, Result as SageResult};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde_json::{Map, Value};
+use std::io::{Error as IOError, ErrorKind};
+
+/// The identity of a client authenticated by a `JwtAuthenticator`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct JwtIdentity {
+    /// The user name, taken from the user name claim of the token.
+    pub user_name: String,
+
+    /// All the claims of the token, such as the groups or permissions of the
+    /// user to base access control on.
+    pub claims: Map<String, Value>,
+}
+
+/// Authenticates clients with a JSON Web Token presented as the password of
+/// the `Connect` packet. Tokens received otherwise, such as within an
+/// enhanced authentication exchange run by the broker, are checked with
+/// `authenticate`.
+///
+/// The signature of the token is checked with the key of the JSON Web Key
+/// Set whose id is the `kid` of the token, or with the only key of the set if
+/// the token has no `kid`. The algorithm of the token must be the `alg` of
+/// the key, or one of the algorithms given with `with_algorithm` if the key
+/// has none, and never only the one the token claims. The token must not be
+/// expired, and its audience and issuer must match the expected ones if any.
+///
+/// The authenticator does no I/O: the key set is fetched by the broker, such
+/// as from the `jwks_uri` of its identity provider, and refreshed with
+/// `update_jwks` when the provider rotates its keys.
+#[derive(Debug, Clone)]
+pub struct JwtAuthenticator {
+    keys: JwkSet,
+    algorithms: Vec<Algorithm>,
+    audience: Vec<String>,
+    issuer: Vec<String>,
+    user_name_claim: String,
+}
+
+fn invalid_data<E: std::fmt::Display>(e: E) -> crate::Error {
+    IOError::new(ErrorKind::InvalidData, e.to_string()).into()
+}
+
+impl JwtAuthenticator {
+    /// Creates an authenticator checking the tokens with the keys of the JSON
+    /// Web Key Set `jwks`. The user name is taken from the `sub` claim.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `std::io::Error` of kind `InvalidData` if `jwks` is not a
+    /// valid key set.
+    pub fn new(jwks: &str) -> SageResult<Self> {
+        Ok(JwtAuthenticator {
+            keys: serde_json::from_str(jwks).map_err(invalid_data)?,
+            algorithms: Vec::new(),
+            audience: Vec::new(),
+            issuer: Vec::new(),
+            user_name_claim: "sub".into(),
+        })
+    }
+
+    /// Accepts the tokens signed with `algorithm`. It can be called several
+    /// times to accept several algorithms. The tokens checked with a key
+    /// without `alg` are refused unless an algorithm is given, and those
+    /// checked with a key with an `alg` must also use one of the given
+    /// algorithms if any.
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithms.push(algorithm);
+        self
+    }
+
+    /// Only accepts the tokens issued for `audience`. It can be called
+    /// several times to accept several audiences.
+    pub fn with_audience<S: Into<String>>(mut self, audience: S) -> Self {
+        self.audience.push(audience.into());
+        self
+    }
+
+    /// Only accepts the tokens issued by `issuer`. It can be called several
+    /// times to accept several issuers.
+    pub fn with_issuer<S: Into<String>>(mut self, issuer: S) -> Self {
+        self.issuer.push(issuer.into());
+        self
+    }
+
+    /// Takes the user name from the claim named `claim`, such as
+    /// `preferred_username`, instead of `sub`.
+    pub fn with_user_name_claim<S: Into<String>>(mut self, claim: S) -> Self {
+        self.user_name_claim = claim.into();
+        self
+    }
+
+    /// Replaces the key set by `jwks`. The previous keys are kept if `jwks`
+    /// is not valid.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `std::io::Error` of kind `InvalidData` if `jwks` is not a
+    /// valid key set.
+    pub fn update_jwks(&mut self, jwks: &str) -> SageResult<()> {
+        self.keys = serde_json::from_str(jwks).map_err(invalid_data)?;
+        Ok(())
+    }
+
+    /// Checks `token`, returning the identity it proves.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotAuthorized` if the token is not valid, is signed by an
+    /// unknown key or with an algorithm which is not accepted, is expired,
+    /// does not match the expected audience or issuer, or has no user name
+    /// claim.
+    pub fn authenticate(&self, token: &str) -> SageResult<JwtIdentity> {
+        let header = decode_header(token).map_err(|_| NotAuthorized)?;
+        let jwk = match &header.kid {
+            Some(kid) => self.keys.find(kid),
+            None if self.keys.keys.len() == 1 => self.keys.keys.first(),
+            None => None,
+        }
+        .ok_or(NotAuthorized)?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|_| NotAuthorized)?;
+
+        // The algorithm claimed by the token is only trusted if it is pinned
+        // by the key or by the configuration.
+        let accepted = match jwk.common.key_algorithm {
+            Some(algorithm) => {
+                let algorithm = algorithm
+                    .to_string()
+                    .parse::<Algorithm>()
+                    .map_err(|_| NotAuthorized)?;
+                algorithm == header.alg
+                    && (self.algorithms.is_empty() || self.algorithms.contains(&algorithm))
+            }
+            None => self.algorithms.contains(&header.alg),
+        };
+        if !accepted {
+            return Err(NotAuthorized.into());
+        }
+
+        let mut validation = Validation::new(header.alg);
+        validation.validate_aud = !self.audience.is_empty();
+        if !self.audience.is_empty() {
+            validation.set_audience(&self.audience);
+        }
+        if !self.issuer.is_empty() {
+            validation.set_issuer(&self.issuer);
+        }
+        let claims = decode::<Map<String, Value>>(token, &key, &validation)
+            .map_err(|_| NotAuthorized)?
+            .claims;
+        let user_name = claims
+            .get(&self.user_name_claim)
+            .and_then(Value::as_str)
+            .ok_or(NotAuthorized)?
+            .into();
+        Ok(JwtIdentity { user_name, claims })
+    }
+}
+
+impl Authenticator for JwtAuthenticator {
+    /// Checks the token given as the password of `connect`, refusing it with
+    /// `NotAuthorized` if it is not valid, or if the user name of `connect`
+    /// differs from the one of the token.
+    fn check(&self, connect: &Connect) -> SageResult<()> {
+        let token = connect
+            .password
+            .as_deref()
+            .and_then(|password| std::str::from_utf8(password).ok())
+            .ok_or(NotAuthorized)?;
+        let identity = self.authenticate(token)?;
+        match &connect.user_name {
+            Some(user_name) if *user_name != identity.user_name => Err(NotAuthorized.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use serde_json::json;
+
+    const JWKS: &str =
+        r#"{"keys":[{"kty":"oct","kid":"daft","alg":"HS256","k":"QXJvdW5kIHRoZSBXb3JsZA"}]}"#;
+
+    fn token(kid: &str, claims: Value) -> String {
+        token_with(Algorithm::HS256, kid, claims)
+    }
+
+    fn token_with(algorithm: Algorithm, kid: &str, claims: Value) -> String {
+        let header = Header {
+            kid: Some(kid.into()),
+            ..Header::new(algorithm)
+        };
+        encode(
+            &header,
+            &claims,
+            &EncodingKey::from_secret(b"Around the World"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn authenticate() {
+        let authenticator = JwtAuthenticator::new(JWKS)
+            .unwrap()
+            .with_audience("broker")
+            .with_user_name_claim("preferred_username");
+        let exp = get_current_timestamp() + 600;
+        let valid = token(
+            "daft",
+            json!({"preferred_username": "thomas", "aud": "broker", "exp": exp, "acl": ["sensors/#"]}),
+        );
+        let identity = authenticator.authenticate(&valid).unwrap();
+        assert_eq!(identity.user_name, "thomas");
+        assert_eq!(identity.claims["acl"], json!(["sensors/#"]));
+
+        for invalid in [
+            token(
+                "punk",
+                json!({"preferred_username": "thomas", "aud": "broker", "exp": exp}),
+            ),
+            token(
+                "daft",
+                json!({"preferred_username": "thomas", "aud": "other", "exp": exp}),
+            ),
+            token(
+                "daft",
+                json!({"preferred_username": "thomas", "aud": "broker", "exp": 1}),
+            ),
+            token("daft", json!({"aud": "broker", "exp": exp})),
+            token_with(
+                Algorithm::HS512,
+                "daft",
+                json!({"preferred_username": "thomas", "aud": "broker", "exp": exp}),
+            ),
+            format!("{}x", valid),
+        ] {
+            assert!(matches!(
+                authenticator.authenticate(&invalid),
+                Err(crate::Error::Reason(NotAuthorized))
+            ));
+        }
+
+        let connect = |user_name: Option<&str>| Connect {
+            user_name: user_name.map(Into::into),
+            password: Some(valid.clone().into_bytes()),
+            ..Default::default()
+        };
+        assert!(authenticator.check(&connect(None)).is_ok());
+        assert!(authenticator.check(&connect(Some("thomas"))).is_ok());
+        assert!(authenticator.check(&connect(Some("guy"))).is_err());
+    }
+
+    #[test]
+    fn algorithm() {
+        let jwks = r#"{"keys":[{"kty":"oct","kid":"daft","k":"QXJvdW5kIHRoZSBXb3JsZA"}]}"#;
+        let exp = get_current_timestamp() + 600;
+        let claims = json!({"sub": "thomas", "exp": exp});
+        let hs256 = token("daft", claims.clone());
+        let hs384 = token_with(Algorithm::HS384, "daft", claims);
+
+        let authenticator = JwtAuthenticator::new(jwks).unwrap();
+        assert!(authenticator.authenticate(&hs256).is_err());
+
+        let authenticator = authenticator.with_algorithm(Algorithm::HS384);
+        assert!(authenticator.authenticate(&hs256).is_err());
+        assert_eq!(
+            authenticator.authenticate(&hs384).unwrap().user_name,
+            "thomas"
+        );
+
+        let authenticator = JwtAuthenticator::new(JWKS)
+            .unwrap()
+            .with_algorithm(Algorithm::HS384);
+        assert!(authenticator.authenticate(&hs256).is_err());
+        assert!(authenticator.authenticate(&hs384).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use std::time::{Duration, Instant};
+
+/// Tracks the keep alive of a connection, without doing any I/O: the current
+/// time is always given by the caller.
+///
+/// - A client must send a packet within each keep alive period, which is a
+///   `PingReq` packet if it has nothing else to send. `ping_deadline` tells
+///   when it must be sent.
+/// - A server must close the connection if it receives no packet within one
+///   and a half times the keep alive period. `timeout_deadline` tells when it
+///   happens.
+///
+/// A keep alive of zero disables the mechanism, in which case no deadline is
+/// ever reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    keep_alive: Option<Duration>,
+    last_sent: Instant,
+    last_received: Instant,
+}
+
+impl KeepAlive {
+    /// Creates a tracker for a keep alive of `keep_alive` seconds, as
+    /// negotiated upon connection, where `now` is the time of the connection.
+    pub fn new(keep_alive: u16, now: Instant) -> Self {
+        KeepAlive {
+            keep_alive: match keep_alive {
+                0 => None,
+                secs => Some(Duration::from_secs(secs.into())),
+            },
+            last_sent: now,
+            last_received: now,
+        }
+    }
+
+    /// Returns the Server Keep Alive a server limiting the keep alive of its
+    /// clients to `maximum` seconds must send in the `ConnAck` packet, when a
+    /// client requests a keep alive of `requested` seconds. It is `None` if
+    /// the request is accepted as is. A disabled keep alive is never accepted
+    /// by a server with a maximum of more than zero.
+    pub fn server_keep_alive(requested: u16, maximum: u16) -> Option<u16> {
+        if (requested == 0 && maximum == 0) || (1..=maximum).contains(&requested) {
+            None
+        } else {
+            Some(maximum)
+        }
+    }
+
+    /// Returns the keep alive period, or `None` if it is disabled.
+    pub fn keep_alive(&self) -> Option<Duration> {
+        self.keep_alive
+    }
+
+    /// Records that a packet was sent at `now`.
+    pub fn sent(&mut self, now: Instant) {
+        self.last_sent = now;
+    }
+
+    /// Records that a packet was received at `now`.
+    pub fn received(&mut self, now: Instant) {
+        self.last_received = now;
+    }
+
+    /// Returns the time at which a client must send a `PingReq` packet if no
+    /// other packet was sent in the meantime.
+    pub fn ping_deadline(&self) -> Option<Instant> {
+        self.keep_alive
+            .map(|keep_alive| self.last_sent + keep_alive)
+    }
+
+    /// Returns `true` if a client must send a `PingReq` packet at `now`.
+    pub fn should_ping(&self, now: Instant) -> bool {
+        matches!(self.ping_deadline(), Some(deadline) if now >= deadline)
+    }
+
+    /// Returns the time at which a server must close the connection if no
+    /// packet was received in the meantime: one and a half times the keep
+    /// alive period after the last received packet.
+    pub fn timeout_deadline(&self) -> Option<Instant> {
+        self.keep_alive
+            .map(|keep_alive| self.last_received + keep_alive + keep_alive / 2)
+    }
+
+    /// Returns `true` if a server must close the connection at `now`.
+    pub fn is_timed_out(&self, now: Instant) -> bool {
+        matches!(self.timeout_deadline(), Some(deadline) if now >= deadline)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn deadlines() {
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let mut keep_alive = KeepAlive::new(10, start);
+        assert_eq!(keep_alive.ping_deadline(), Some(secs(10)));
+        assert_eq!(keep_alive.timeout_deadline(), Some(secs(15)));
+        assert!(!keep_alive.should_ping(secs(9)));
+        assert!(keep_alive.should_ping(secs(10)));
+
+        keep_alive.sent(secs(8));
+        assert!(!keep_alive.should_ping(secs(10)));
+        assert!(keep_alive.should_ping(secs(18)));
+
+        assert!(!keep_alive.is_timed_out(secs(14)));
+        assert!(keep_alive.is_timed_out(secs(15)));
+        keep_alive.received(secs(14));
+        assert!(!keep_alive.is_timed_out(secs(28)));
+        assert!(keep_alive.is_timed_out(secs(29)));
+    }
+
+    #[test]
+    fn server_keep_alive() {
+        assert_eq!(KeepAlive::server_keep_alive(30, 60), None);
+        assert_eq!(KeepAlive::server_keep_alive(60, 60), None);
+        assert_eq!(KeepAlive::server_keep_alive(120, 60), Some(60));
+        assert_eq!(KeepAlive::server_keep_alive(0, 60), Some(60));
+        assert_eq!(KeepAlive::server_keep_alive(0, 0), None);
+        assert_eq!(KeepAlive::server_keep_alive(30, 0), Some(0));
+    }
+
+    #[test]
+    fn disabled() {
+        let start = Instant::now();
+        let keep_alive = KeepAlive::new(0, start);
+        let later = start + Duration::from_secs(100_000);
+        assert_eq!(keep_alive.keep_alive(), None);
+        assert!(!keep_alive.should_ping(later));
+        assert!(!keep_alive.is_timed_out(later));
+    }
+}
@@ -6,29 +6,176 @@
 #![warn(rustdoc::missing_doc_code_examples)]
 #![allow(clippy::large_enum_variant)]
 
+mod acl;
+mod at_least_once;
+mod auth_flow;
 mod authentication;
+mod authenticator;
+mod authorizer;
+mod bridge;
+mod capabilities;
+#[cfg(feature = "x509")]
+mod certificate_identity;
+mod client_connection;
+mod client_id;
+mod cluster;
 /// encode/decode MQTT fundamental types
 pub mod codec;
+mod connect_decision;
 mod control;
+mod decode_options;
+mod dedup_store;
 pub mod defaults;
+mod deliveries;
+mod duration;
 mod error;
+mod event_handler;
+mod exactly_once;
+mod expiry;
+mod expiry_queue;
+pub mod fragmentation;
+pub mod fuzz;
+#[cfg(any(feature = "native-tls", feature = "tls", feature = "websocket"))]
+mod handshakes;
+mod immediate;
+mod inflight_window;
+mod interceptor;
+#[cfg(feature = "jwt")]
+mod jwt;
+mod keep_alive;
+mod listener;
+mod listener_set;
+mod message;
+mod metrics;
+mod offline_queue;
+mod overlap_policy;
 mod packet;
+mod packet_id;
 mod packet_type;
+#[cfg(feature = "password-file")]
+mod password_file;
 mod property;
 mod quality_of_service;
+#[cfg(feature = "quic")]
+mod quic;
+mod quota;
+mod random;
 mod reason_code;
+mod request_response;
+mod retain_store;
+mod retransmit_queue;
+#[cfg(feature = "scram")]
+mod scram;
+mod server_connection;
+mod server_reference;
+mod session_state;
+mod session_store;
+mod share_balancer;
+mod shutdown;
+#[cfg(feature = "sled")]
+mod sled_storage;
+mod storage;
+mod subscription_id;
+mod subscription_store;
+#[cfg(test)]
+mod test_rng;
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+mod tls;
 mod topic;
+mod topic_alias;
+mod topic_template;
+mod topic_tree;
+#[cfg(all(feature = "net", unix))]
+mod unix_socket;
+pub mod user_properties;
+#[cfg(feature = "websocket")]
+mod websocket;
 mod will;
+mod will_scheduler;
+pub use acl::{Acl, AclAccess, AclPolicy, AclRule, AclScope};
+pub use at_least_once::{AtLeastOnceReceiver, AtLeastOnceSender};
+pub use auth_flow::{AuthFlow, AuthStep};
 pub use authentication::Authentication;
+use authentication::Redacted;
+pub use authenticator::Authenticator;
+pub use authorizer::Authorizer;
+pub use bridge::{Bridge, BridgeDirection, BridgeEvent, BridgeRule};
+pub use capabilities::Capabilities;
+#[cfg(feature = "x509")]
+pub use certificate_identity::{CertificateIdentity, CertificateMapping};
+pub use client_connection::{ClientConnection, ClientEvent};
+pub use client_id::{ClientIdAssignment, ClientIdGenerator};
+pub use cluster::{Cluster, ClusterEvent, ClusterMessage};
+pub use connect_decision::{ConnectDecision, ExistingSession};
 pub use control::{
-    Auth, ClientID, ConnAck, Connect, Disconnect, PingReq, PingResp, PubAck, PubComp, PubRec,
-    PubRel, Publish, RetainHandling, SubAck, Subscribe, SubscriptionOptions, UnSubAck, UnSubscribe,
+    AckEncoding, Auth, ClientID, ConnAck, ConnAckBuilder, Connect, Disconnect, PingReq, PingResp,
+    PubAck, PubComp, PubRec, PubRel, Publish, RetainHandling, SubAck, Subscribe,
+    SubscriptionOptions, UnSubAck, UnSubscribe,
 };
+pub use decode_options::{DecodeOptions, Deviation};
+pub use dedup_store::{DedupSnapshot, DedupStore, InMemoryDedupStore};
+pub use duration::Saturation;
 pub use error::{Error, Result};
-pub use packet::Packet;
-use packet_type::PacketType;
-use property::{PropertiesDecoder, Property};
+pub use event_handler::EventHandler;
+pub use exactly_once::{ExactlyOnceReceiver, ExactlyOnceSender};
+pub use expiry::Expiry;
+pub use expiry_queue::ExpiryQueue;
+pub use inflight_window::InflightWindow;
+pub use interceptor::{Interceptor, InterceptorChain};
+#[cfg(feature = "jwt")]
+pub use jwt::{JwtAuthenticator, JwtIdentity};
+pub use keep_alive::KeepAlive;
+pub use listener::{Accept, Listener, PeerCredentials, PeerInfo};
+pub use listener_set::{
+    LimitAction, ListenerAuthentication, ListenerConfig, ListenerSet, ListenerStream,
+};
+pub use message::{Message, MessageProperties};
+pub use metrics::{Histogram, Metrics};
+pub use offline_queue::{InMemoryOfflineQueue, OfflineQueue, OverflowPolicy};
+pub use overlap_policy::{OverlapPolicy, SubscriptionDelivery};
+pub use packet::{EncodeStats, FixedHeader, Packet};
+pub use packet_id::{PacketIdAllocator, PacketIdExhaustion};
+pub use packet_type::PacketType;
+#[cfg(feature = "password-file")]
+pub use password_file::PasswordFile;
+use property::PropertiesDecoder;
+pub use property::{Properties, Property};
 pub use quality_of_service::QoS;
+#[cfg(feature = "quic")]
+pub use quic::{QuicConfig, QuicListener, QuicStream, ZeroRtt};
+pub use quota::Quota;
 pub use reason_code::ReasonCode;
-pub use topic::Topic;
+pub use request_response::{Requester, ResponseInformation};
+pub use retain_store::{InMemoryRetainStore, RetainStore};
+pub use retransmit_queue::RetransmitQueue;
+#[cfg(feature = "scram")]
+pub use scram::{ScramCredentials, ScramServer, ScramStep, SCRAM_SHA_256};
+pub use server_connection::{ServerConnection, ServerEvent};
+pub use server_reference::ServerReference;
+pub use session_state::{OutgoingDelivery, SessionState};
+pub use session_store::{ConnectedSession, SessionStore};
+pub use share_balancer::{BalancePolicy, ShareBalancer};
+pub use shutdown::{Shutdown, ShutdownGuard};
+#[cfg(feature = "sled")]
+pub use sled_storage::SledStorage;
+pub use storage::{InMemoryStorage, Storage, StoredMessage, StoredSession};
+pub use subscription_id::SubscriptionId;
+pub use subscription_store::{Subscription, SubscriptionStore};
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+pub use tls::ClientCertificates;
+#[cfg(feature = "native-tls")]
+pub use tls::NativeTlsListener;
+#[cfg(feature = "tls")]
+pub use tls::TlsListener;
+pub use topic::{SharedSubscription, Topic, TopicFilter, TopicName};
+pub use topic_alias::{TopicAliasAllocator, TopicAliasExhaustion, TopicAliasRegistry};
+pub use topic_template::{Captures, TopicTemplate};
+pub use topic_tree::TopicTree;
+#[cfg(all(feature = "net", unix))]
+pub use unix_socket::UnixSocketListener;
+#[cfg(all(feature = "websocket", feature = "tls"))]
+pub use websocket::SecureWebSocketListener;
+#[cfg(feature = "websocket")]
+pub use websocket::{WebSocketListener, WebSocketStream};
 pub use will::Will;
+pub use will_scheduler::WillScheduler;
@@ -0,0 +1,155 @@
+use std::{
+    future::Future,
+    io::Result as IoResult,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// What is known about the peer of an accepted connection, for the
+/// authentication layer.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct PeerInfo {
+    /// The address of the peer, if the transport has one.
+    pub address: Option<SocketAddr>,
+
+    /// The DER encoded certificate chain presented by the peer over TLS, leaf
+    /// first. Empty if the peer presented no certificate.
+    pub certificates: Vec<Vec<u8>>,
+
+    /// The path of the URI requested by the peer over WebSocket, such as
+    /// `/mqtt`, to route connections upon.
+    pub path: Option<String>,
+
+    /// The credentials of the process of the peer over a Unix domain socket.
+    pub credentials: Option<PeerCredentials>,
+}
+
+/// The credentials of the process at the other end of a Unix domain socket.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct PeerCredentials {
+    /// The user id of the process.
+    pub uid: u32,
+
+    /// The group id of the process.
+    pub gid: u32,
+
+    /// The process id, if the platform provides it.
+    pub pid: Option<i32>,
+}
+
+/// A source of incoming connections, such as a TCP, TLS, WebSocket or Unix
+/// socket front-end, for servers.
+///
+/// Every listener yields streams implementing `AsyncRead` and `AsyncWrite`,
+/// so that the same connection handling code can serve all the transports.
+pub trait Listener {
+    /// The stream of an accepted connection.
+    type Stream: AsyncRead + AsyncWrite + Unpin;
+
+    /// Polls for a new connection. Returns the stream of the connection along
+    /// with the information about its peer once one is accepted.
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>>;
+
+    /// Accepts a new connection.
+    fn accept(&mut self) -> Accept<'_, Self>
+    where
+        Self: Sized,
+    {
+        Accept { listener: self }
+    }
+}
+
+/// The future returned by `Listener::accept`.
+#[derive(Debug)]
+pub struct Accept<'a, L> {
+    listener: &'a mut L,
+}
+
+impl<L: Listener> Future for Accept<'_, L> {
+    type Output = IoResult<(L::Stream, PeerInfo)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.listener.poll_accept(cx)
+    }
+}
+
+#[cfg(feature = "net")]
+impl Listener for tokio::net::TcpListener {
+    type Stream = tokio::net::TcpStream;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+        tokio::net::TcpListener::poll_accept(self, cx).map_ok(|(stream, address)| {
+            (
+                stream,
+                PeerInfo {
+                    address: Some(address),
+                    ..Default::default()
+                },
+            )
+        })
+    }
+}
+
+#[cfg(all(feature = "net", unix))]
+impl Listener for tokio::net::UnixListener {
+    type Stream = tokio::net::UnixStream;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+        tokio::net::UnixListener::poll_accept(self, cx).map_ok(|(stream, _)| {
+            let credentials = stream.peer_cred().ok().map(|credentials| PeerCredentials {
+                uid: credentials.uid(),
+                gid: credentials.gid(),
+                pid: credentials.pid(),
+            });
+            (
+                stream,
+                PeerInfo {
+                    credentials,
+                    ..Default::default()
+                },
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use std::{collections::VecDeque, io::Cursor};
+
+    struct Queued(VecDeque<(Cursor<Vec<u8>>, PeerInfo)>);
+
+    impl Listener for Queued {
+        type Stream = Cursor<Vec<u8>>;
+
+        fn poll_accept(&mut self, _: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+            match self.0.pop_front() {
+                Some(connection) => Poll::Ready(Ok(connection)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn accept() {
+        let peer = PeerInfo {
+            address: Some("127.0.0.1:1883".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut listener = Queued(
+            vec![
+                (Cursor::new(vec![0x0C]), peer.clone()),
+                (Cursor::new(vec![0xC0, 0x00]), Default::default()),
+            ]
+            .into(),
+        );
+        let (stream, info) = listener.accept().await.unwrap();
+        assert_eq!(stream.into_inner(), vec![0x0C]);
+        assert_eq!(info, peer);
+        let (_, info) = listener.accept().await.unwrap();
+        assert_eq!(info.address, None);
+    }
+}
@@ -0,0 +1,592 @@
+use crate::{Connect, Error, Listener, Metrics, PeerInfo, ReasonCode, Result as SageResult};
+use std::{
+    collections::HashMap,
+    io::Result as IoResult,
+    net::IpAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    task::{Context, Poll, Waker},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// What a listener of a `ListenerSet` requires from its clients to let them
+/// connect.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum ListenerAuthentication {
+    /// Any client may connect, including anonymous ones.
+    #[default]
+    Anonymous,
+
+    /// The `Connect` packet must carry a user name or an authentication
+    /// method.
+    Credentials,
+
+    /// The client must have presented a TLS certificate. Its identity can be
+    /// mapped to the user name of the client with `CertificateMapping`.
+    ClientCertificate,
+}
+
+/// The configuration of one listener of a `ListenerSet`, such as a plaintext
+/// internal endpoint or a TLS external one.
+///
+/// Only MQTT 5 is supported by this crate, so the listeners do not filter
+/// protocol versions: `Connect` packets of other versions are refused when
+/// they are decoded.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ListenerConfig {
+    /// The name of the listener, such as `"internal"` or `"wss"`.
+    pub name: String,
+
+    /// What the clients must provide to connect.
+    pub authentication: ListenerAuthentication,
+
+    /// The maximum number of simultaneous connections accepted by the
+    /// listener. The connections beyond it are handled according to the
+    /// `LimitAction` of the `ListenerSet`.
+    pub max_connections: Option<usize>,
+}
+
+/// What a `ListenerSet` does with the connections exceeding one of its
+/// connection limits.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum LimitAction {
+    /// The connection is closed as soon as it is accepted.
+    #[default]
+    Close,
+
+    /// The connection is yielded with `ListenerStream::refusal` returning the
+    /// reason code to refuse its `Connect` packet with: `ServerBusy` beyond
+    /// the limit of the set or of the listener, and `QuotaExceeded` beyond
+    /// the per-IP limit.
+    Refuse,
+
+    /// The listeners at their limit, or all of them at the global limit, are
+    /// not polled until a connection ends, leaving the pending connections in
+    /// the backlog of the system. The address of a peer is only known once
+    /// it is accepted, so the connections exceeding the per-IP limit are
+    /// closed.
+    Pause,
+}
+
+impl ListenerConfig {
+    /// Creates the configuration of an anonymous listener named `name`, with
+    /// no connection limit.
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        ListenerConfig {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the authentication requirement of the listener.
+    pub fn with_authentication(self, authentication: ListenerAuthentication) -> Self {
+        ListenerConfig {
+            authentication,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of simultaneous connections of the listener.
+    pub fn with_max_connections(self, max_connections: usize) -> Self {
+        ListenerConfig {
+            max_connections: Some(max_connections),
+            ..self
+        }
+    }
+
+    /// Checks that `connect`, received from `peer`, meets the authentication
+    /// requirement of the listener.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotAuthorized` if the requirement is not met.
+    pub fn check(&self, connect: &Connect, peer: &PeerInfo) -> SageResult<()> {
+        let authorized = match self.authentication {
+            ListenerAuthentication::Anonymous => true,
+            ListenerAuthentication::Credentials => {
+                connect.user_name.is_some() || connect.authentication.is_some()
+            }
+            ListenerAuthentication::ClientCertificate => !peer.certificates.is_empty(),
+        };
+        if authorized {
+            Ok(())
+        } else {
+            Err(Error::Reason(ReasonCode::NotAuthorized))
+        }
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+trait AnyListener: Send {
+    fn poll_accept_any(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<IoResult<(Box<dyn Stream>, PeerInfo)>>;
+}
+
+impl<L> AnyListener for L
+where
+    L: Listener + Send,
+    L::Stream: Send + 'static,
+{
+    fn poll_accept_any(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<IoResult<(Box<dyn Stream>, PeerInfo)>> {
+        self.poll_accept(cx)
+            .map_ok(|(stream, peer)| (Box::new(stream) as Box<dyn Stream>, peer))
+    }
+}
+
+struct Entry {
+    listener: Box<dyn AnyListener>,
+    config: Arc<ListenerConfig>,
+    connections: Arc<AtomicUsize>,
+}
+
+// The connection counts shared by a `ListenerSet` and its streams.
+#[derive(Default)]
+struct Counts {
+    connections: AtomicUsize,
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Counts {
+    fn per_ip(&self) -> MutexGuard<'_, HashMap<IpAddr, usize>> {
+        self.per_ip.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn waker(&self) -> MutexGuard<'_, Option<Waker>> {
+        self.waker.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A `Listener` accepting the connections of several listeners at once, such
+/// as a plaintext TCP listener on port 1883, a TLS one on port 8883 and a
+/// secure WebSocket one on port 443, each with its own `ListenerConfig`.
+///
+/// The streams it yields tell the configuration of the listener they were
+/// accepted by, to check the `Connect` packet against.
+///
+/// Besides the limit of each listener, the set can limit the connections of
+/// all its listeners and the ones from a single IP address. The connections
+/// exceeding a limit are handled according to its `LimitAction`, and count
+/// against the limits until they are dropped.
+#[derive(Default)]
+pub struct ListenerSet {
+    entries: Vec<Entry>,
+    next: usize,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    action: LimitAction,
+    counts: Arc<Counts>,
+    metrics: Option<Metrics>,
+}
+
+impl ListenerSet {
+    /// Creates an empty set of listeners.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds `listener` to the set, with its `config`.
+    pub fn with_listener<L>(mut self, listener: L, config: ListenerConfig) -> Self
+    where
+        L: Listener + Send + 'static,
+        L::Stream: Send + 'static,
+    {
+        self.entries.push(Entry {
+            listener: Box::new(listener),
+            config: Arc::new(config),
+            connections: Default::default(),
+        });
+        self
+    }
+
+    /// Sets the maximum number of simultaneous connections of all the
+    /// listeners.
+    pub fn with_max_connections(self, max_connections: usize) -> Self {
+        ListenerSet {
+            max_connections: Some(max_connections),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of simultaneous connections from the same IP
+    /// address, over all the listeners.
+    pub fn with_max_connections_per_ip(self, max_connections: usize) -> Self {
+        ListenerSet {
+            max_connections_per_ip: Some(max_connections),
+            ..self
+        }
+    }
+
+    /// Sets what is done with the connections exceeding a limit.
+    pub fn with_limit_action(self, action: LimitAction) -> Self {
+        ListenerSet { action, ..self }
+    }
+
+    /// Records the connections of each listener and the rejected ones into
+    /// `metrics`.
+    pub fn with_metrics(self, metrics: Metrics) -> Self {
+        ListenerSet {
+            metrics: Some(metrics),
+            ..self
+        }
+    }
+
+    /// Returns the configurations of the listeners, in the order they were
+    /// added.
+    pub fn configs(&self) -> impl Iterator<Item = &ListenerConfig> {
+        self.entries.iter().map(|entry| entry.config.as_ref())
+    }
+
+    /// Returns the number of open connections accepted by the listener named
+    /// `name`, if any.
+    pub fn connections(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .find(|entry| entry.config.name == name)
+            .map(|entry| entry.connections.load(Ordering::Acquire))
+    }
+
+    /// Returns the number of open connections accepted by all the listeners.
+    pub fn total_connections(&self) -> usize {
+        self.counts.connections.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of open connections from `ip`.
+    pub fn connections_from(&self, ip: IpAddr) -> usize {
+        self.counts.per_ip().get(&ip).copied().unwrap_or(0)
+    }
+
+    // Returns `true` if the listener at `index` must not be polled because
+    // of a limit.
+    fn paused(&self, index: usize) -> bool {
+        let at_limit = |max: Option<usize>, connections: &AtomicUsize| {
+            max.is_some_and(|max| connections.load(Ordering::Acquire) >= max)
+        };
+        let entry = &self.entries[index];
+        self.action == LimitAction::Pause
+            && (at_limit(self.max_connections, &self.counts.connections)
+                || at_limit(entry.config.max_connections, &entry.connections))
+    }
+
+    // Counts a connection accepted by the listener at `index`, returning its
+    // stream and the reason code to refuse it with if it exceeds a limit.
+    fn track(
+        &self,
+        index: usize,
+        stream: Box<dyn Stream>,
+        peer: &PeerInfo,
+    ) -> (ListenerStream, Option<ReasonCode>) {
+        let entry = &self.entries[index];
+        let ip = peer.address.map(|address| address.ip());
+        let connections = entry.connections.fetch_add(1, Ordering::AcqRel);
+        let mut busy = entry
+            .config
+            .max_connections
+            .is_some_and(|max| connections >= max);
+        let connections = self.counts.connections.fetch_add(1, Ordering::AcqRel);
+        busy |= self.max_connections.is_some_and(|max| connections >= max);
+        let mut over_quota = false;
+        if let Some(ip) = ip {
+            let mut per_ip = self.counts.per_ip();
+            let from_ip = per_ip.entry(ip).or_default();
+            over_quota = self
+                .max_connections_per_ip
+                .is_some_and(|max| *from_ip >= max);
+            *from_ip += 1;
+        }
+        let stream = ListenerStream {
+            stream,
+            config: entry.config.clone(),
+            connections: entry.connections.clone(),
+            counts: self.counts.clone(),
+            ip,
+            refusal: None,
+            metrics: self.metrics.clone(),
+        };
+        stream.record();
+        let refusal = if busy {
+            Some(ReasonCode::ServerBusy)
+        } else if over_quota {
+            Some(ReasonCode::QuotaExceeded)
+        } else {
+            None
+        };
+        (stream, refusal)
+    }
+}
+
+impl Listener for ListenerSet {
+    type Stream = ListenerStream;
+
+    /// Polls the listeners in turn, starting after the one which accepted the
+    /// previous connection so that none of them is starved.
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+        // The waker is registered before checking the limits, so that a
+        // connection ending in between wakes the paused listeners up.
+        if self.action == LimitAction::Pause {
+            *self.counts.waker() = Some(cx.waker().clone());
+        }
+        let count = self.entries.len();
+        for offset in 0..count {
+            let index = (self.next + offset) % count;
+            while !self.paused(index) {
+                match self.entries[index].listener.poll_accept_any(cx) {
+                    Poll::Ready(Ok((stream, peer))) => {
+                        let (mut stream, refusal) = self.track(index, stream, &peer);
+                        if let Some(reason_code) = refusal {
+                            if let Some(metrics) = &self.metrics {
+                                metrics.connection_rejected();
+                            }
+                            match self.action {
+                                LimitAction::Refuse => stream.refusal = Some(reason_code),
+                                LimitAction::Close | LimitAction::Pause => continue,
+                            }
+                        }
+                        self.next = (index + 1) % count;
+                        return Poll::Ready(Ok((stream, peer)));
+                    }
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => break,
+                }
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// The stream of a connection accepted by a `ListenerSet`. The connection
+/// counts against the connection limits until it is dropped.
+pub struct ListenerStream {
+    stream: Box<dyn Stream>,
+    config: Arc<ListenerConfig>,
+    connections: Arc<AtomicUsize>,
+    counts: Arc<Counts>,
+    ip: Option<IpAddr>,
+    refusal: Option<ReasonCode>,
+    metrics: Option<Metrics>,
+}
+
+impl ListenerStream {
+    /// Returns the configuration of the listener which accepted the
+    /// connection.
+    pub fn config(&self) -> &ListenerConfig {
+        &self.config
+    }
+
+    /// Returns the reason code to refuse the `Connect` packet of the
+    /// connection with, if it exceeds a connection limit of a `ListenerSet`
+    /// refusing such connections.
+    pub fn refusal(&self) -> Option<ReasonCode> {
+        self.refusal
+    }
+
+    fn record(&self) {
+        if let Some(metrics) = &self.metrics {
+            let connections = self.connections.load(Ordering::Acquire);
+            metrics.set_listener_connections(&self.config.name, connections);
+        }
+    }
+}
+
+impl std::fmt::Debug for ListenerStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ListenerStream")
+            .field("listener", &self.config.name)
+            .finish()
+    }
+}
+
+impl Drop for ListenerStream {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::AcqRel);
+        self.counts.connections.fetch_sub(1, Ordering::AcqRel);
+        if let Some(ip) = self.ip {
+            let mut per_ip = self.counts.per_ip();
+            if let Some(connections) = per_ip.get_mut(&ip) {
+                *connections -= 1;
+                if *connections == 0 {
+                    per_ip.remove(&ip);
+                }
+            }
+        }
+        self.record();
+        if let Some(waker) = self.counts.waker().take() {
+            waker.wake();
+        }
+    }
+}
+
+impl AsyncRead for ListenerStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ListenerStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use std::{collections::VecDeque, future::poll_fn, io::Cursor};
+
+    struct Queued(VecDeque<PeerInfo>);
+
+    impl Listener for Queued {
+        type Stream = Cursor<Vec<u8>>;
+
+        fn poll_accept(&mut self, _: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+            match self.0.pop_front() {
+                Some(peer) => Poll::Ready(Ok((Cursor::new(Vec::new()), peer))),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    fn queued(count: usize) -> Queued {
+        Queued((0..count).map(|_| Default::default()).collect())
+    }
+
+    fn from(addresses: &[&str]) -> Queued {
+        Queued(
+            addresses
+                .iter()
+                .map(|address| PeerInfo {
+                    address: Some(address.parse().unwrap()),
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn accept() {
+        let mut set = ListenerSet::new()
+            .with_listener(queued(3), ListenerConfig::new("internal"))
+            .with_listener(
+                queued(2),
+                ListenerConfig::new("external").with_max_connections(1),
+            );
+        let (first, _) = set.accept().await.unwrap();
+        let (second, _) = set.accept().await.unwrap();
+        assert_eq!(first.config().name, "internal");
+        assert_eq!(second.config().name, "external");
+        assert_eq!(set.connections("external"), Some(1));
+
+        let (third, _) = set.accept().await.unwrap();
+        assert_eq!(third.config().name, "internal");
+
+        // The second connection of "external" exceeds its limit.
+        let (fourth, _) = set.accept().await.unwrap();
+        assert_eq!(fourth.config().name, "internal");
+        assert_eq!(set.connections("internal"), Some(3));
+        assert_eq!(set.connections("external"), Some(1));
+
+        drop(second);
+        assert_eq!(set.connections("external"), Some(0));
+        assert_eq!(set.connections("wss"), None);
+    }
+
+    #[tokio::test]
+    async fn limits() {
+        let metrics = Metrics::new();
+        let peers = from(&[
+            "10.0.0.1:1000",
+            "10.0.0.1:1001",
+            "10.0.0.2:1000",
+            "10.0.0.3:1000",
+        ]);
+        let mut set = ListenerSet::new()
+            .with_listener(peers, ListenerConfig::new("tcp"))
+            .with_max_connections(2)
+            .with_max_connections_per_ip(1)
+            .with_limit_action(LimitAction::Refuse)
+            .with_metrics(metrics.clone());
+        let (first, _) = set.accept().await.unwrap();
+        let (second, _) = set.accept().await.unwrap();
+        let (third, _) = set.accept().await.unwrap();
+        assert_eq!(first.refusal(), None);
+        assert_eq!(second.refusal(), Some(ReasonCode::QuotaExceeded));
+        assert_eq!(third.refusal(), Some(ReasonCode::ServerBusy));
+        let ip = "10.0.0.1".parse().unwrap();
+        assert_eq!(set.connections_from(ip), 2);
+        assert_eq!(metrics.listener_connections("tcp"), 3);
+
+        drop((second, third));
+        let (fourth, _) = set.accept().await.unwrap();
+        assert_eq!(fourth.refusal(), None);
+        assert_eq!(set.total_connections(), 2);
+        assert_eq!(set.connections_from(ip), 1);
+        assert_eq!(metrics.rejected_connections(), 2);
+        assert_eq!(metrics.listener_connections("tcp"), 2);
+    }
+
+    #[tokio::test]
+    async fn pause() {
+        let mut set = ListenerSet::new()
+            .with_listener(queued(2), ListenerConfig::new("tcp"))
+            .with_max_connections(1)
+            .with_limit_action(LimitAction::Pause);
+        let (first, _) = set.accept().await.unwrap();
+        let pending = poll_fn(|cx| Poll::Ready(set.poll_accept(cx).is_pending())).await;
+        assert!(pending);
+        assert_eq!(set.total_connections(), 1);
+
+        drop(first);
+        let (second, _) = set.accept().await.unwrap();
+        assert_eq!(second.refusal(), None);
+        assert_eq!(set.total_connections(), 1);
+    }
+
+    #[test]
+    fn check() {
+        let connect = Connect::default();
+        let peer = PeerInfo::default();
+        assert!(ListenerConfig::new("a").check(&connect, &peer).is_ok());
+        let config =
+            ListenerConfig::new("b").with_authentication(ListenerAuthentication::Credentials);
+        assert!(matches!(
+            config.check(&connect, &peer),
+            Err(Error::Reason(ReasonCode::NotAuthorized))
+        ));
+        let connect = Connect {
+            user_name: Some("user".into()),
+            ..Default::default()
+        };
+        assert!(config.check(&connect, &peer).is_ok());
+        let config = config.with_authentication(ListenerAuthentication::ClientCertificate);
+        assert!(config.check(&connect, &peer).is_err());
+        let peer = PeerInfo {
+            certificates: vec![vec![0x30]],
+            ..Default::default()
+        };
+        assert!(config.check(&connect, &peer).is_ok());
+    }
+}
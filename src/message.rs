@@ -0,0 +1,158 @@
+use crate::{defaults::DEFAULT_PAYLOAD_FORMAT_INDICATOR, Publish, QoS, TopicName};
+
+/// The properties of an application message, which are forwarded unaltered
+/// each time the message is published.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MessageProperties {
+    /// If true, the payload is a valid UTF-8 encoded string.
+    pub payload_format_indicator: bool,
+
+    /// Optional delay after which the message must not be delivered anymore.
+    pub message_expiry_interval: Option<u32>,
+
+    /// If the message is a request, the topic the response must be published
+    /// to.
+    pub response_topic: Option<TopicName>,
+
+    /// Optional data used to correlate a response with its request.
+    pub correlation_data: Option<Vec<u8>>,
+
+    /// General purpose user properties.
+    pub user_properties: Vec<(String, String)>,
+
+    /// Describes the type of content of the payload. Is generally a MIME
+    /// descriptor.
+    pub content_type: String,
+}
+
+impl Default for MessageProperties {
+    fn default() -> Self {
+        MessageProperties {
+            payload_format_indicator: DEFAULT_PAYLOAD_FORMAT_INDICATOR,
+            message_expiry_interval: None,
+            response_topic: None,
+            correlation_data: None,
+            user_properties: Default::default(),
+            content_type: Default::default(),
+        }
+    }
+}
+
+/// An application message, independent of the `Publish` packet used to
+/// transport it.
+/// Unlike `Publish`, a `Message` does not hold any information specific to a
+/// single delivery, such as the packet identifier, the topic alias, the
+/// duplicate flag or the subscription identifiers. It can hence be stored and
+/// re-published as many times as needed.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Message {
+    /// The name of the topic the message is published to.
+    pub topic: TopicName,
+
+    /// The content of the message.
+    pub payload: Vec<u8>,
+
+    /// The quality of service of the message.
+    pub qos: QoS,
+
+    /// If true, the message is to be retained by the server.
+    pub retain: bool,
+
+    /// The message properties.
+    pub properties: MessageProperties,
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Message {
+            topic: Default::default(),
+            payload: Default::default(),
+            qos: QoS::AtMostOnce,
+            retain: false,
+            properties: Default::default(),
+        }
+    }
+}
+
+impl Message {
+    /// Builds a `Publish` packet delivering the message, using the given
+    /// packet identifier and topic alias. The duplicate flag is not set.
+    pub fn into_publish(self, packet_identifier: Option<u16>, topic_alias: Option<u16>) -> Publish {
+        Publish {
+            duplicate: false,
+            qos: self.qos,
+            retain: self.retain,
+            topic_name: self.topic,
+            packet_identifier,
+            payload_format_indicator: self.properties.payload_format_indicator,
+            message_expiry_interval: self.properties.message_expiry_interval,
+            topic_alias,
+            response_topic: self.properties.response_topic,
+            correlation_data: self.properties.correlation_data,
+            user_properties: self.properties.user_properties,
+            subscription_identifiers: Default::default(),
+            content_type: self.properties.content_type,
+            message: self.payload,
+            raw_properties: None,
+        }
+    }
+}
+
+impl From<Publish> for Message {
+    fn from(publish: Publish) -> Self {
+        Message {
+            topic: publish.topic_name,
+            payload: publish.message,
+            qos: publish.qos,
+            retain: publish.retain,
+            properties: MessageProperties {
+                payload_format_indicator: publish.payload_format_indicator,
+                message_expiry_interval: publish.message_expiry_interval,
+                response_topic: publish.response_topic,
+                correlation_data: publish.correlation_data,
+                user_properties: publish.user_properties,
+                content_type: publish.content_type,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn republish() {
+        let publish = Publish {
+            duplicate: true,
+            qos: QoS::AtLeastOnce,
+            topic_name: "Around the World".try_into().unwrap(),
+            packet_identifier: Some(1337),
+            topic_alias: Some(42),
+            user_properties: vec![("Mogwaï".into(), "Cat".into())],
+            message: "Harder, Better, Faster, Stronger".into(),
+            ..Default::default()
+        };
+
+        let message = Message::from(publish.clone());
+        assert_eq!(
+            message.clone().into_publish(Some(1337), Some(42)),
+            Publish {
+                duplicate: false,
+                ..publish.clone()
+            }
+        );
+        assert_eq!(
+            message.into_publish(Some(7), None),
+            Publish {
+                duplicate: false,
+                packet_identifier: Some(7),
+                topic_alias: None,
+                ..publish
+            }
+        );
+    }
+}
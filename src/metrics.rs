@@ -0,0 +1,582 @@
+use crate::{Packet, PacketType, QoS};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+// The names of the packet types, indexed by `type_index`.
+const PACKET_TYPES: [&str; 16] = [
+    "reserved",
+    "connect",
+    "connack",
+    "publish",
+    "puback",
+    "pubrec",
+    "pubrel",
+    "pubcomp",
+    "subscribe",
+    "suback",
+    "unsubscribe",
+    "unsuback",
+    "pingreq",
+    "pingresp",
+    "disconnect",
+    "auth",
+];
+
+// The upper bounds of the buckets of the delivery latency histograms, in
+// microseconds.
+const LATENCY_BUCKETS: [u64; 10] = [
+    1_000, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000, 5_000_000,
+];
+
+fn type_index(packet_type: PacketType) -> usize {
+    match packet_type {
+        PacketType::Reserved => 0,
+        PacketType::Connect => 1,
+        PacketType::ConnAck => 2,
+        PacketType::Publish { .. } => 3,
+        PacketType::PubAck => 4,
+        PacketType::PubRec => 5,
+        PacketType::PubRel => 6,
+        PacketType::PubComp => 7,
+        PacketType::Subscribe => 8,
+        PacketType::SubAck => 9,
+        PacketType::UnSubscribe => 10,
+        PacketType::UnSubAck => 11,
+        PacketType::PingReq => 12,
+        PacketType::PingResp => 13,
+        PacketType::Disconnect => 14,
+        PacketType::Auth => 15,
+    }
+}
+
+/// A snapshot of a histogram of `Metrics`.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Histogram {
+    /// The number of observations lower or equal to each upper bound, in
+    /// increasing order of the bounds. Observations greater than the last
+    /// bound are only counted in `count`.
+    pub buckets: Vec<(Duration, u64)>,
+
+    /// The number of observations.
+    pub count: u64,
+
+    /// The sum of the observations.
+    pub sum: Duration,
+}
+
+#[derive(Debug, Default)]
+struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl LatencyHistogram {
+    fn observe(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|&bound| micros <= bound) {
+            self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        let mut cumulated = 0;
+        let buckets = LATENCY_BUCKETS
+            .iter()
+            .zip(self.buckets.iter())
+            .map(|(&bound, count)| {
+                cumulated += count.load(Ordering::Relaxed);
+                (Duration::from_micros(bound), cumulated)
+            })
+            .collect();
+        Histogram {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    connections: AtomicU64,
+    connections_total: AtomicU64,
+    rejected_connections: AtomicU64,
+    listener_connections: Mutex<BTreeMap<String, u64>>,
+    authentication_failures: AtomicU64,
+    packets_received: [AtomicU64; PACKET_TYPES.len()],
+    packets_sent: [AtomicU64; PACKET_TYPES.len()],
+    payload_bytes_received: AtomicU64,
+    payload_bytes_sent: AtomicU64,
+    queued_messages: AtomicU64,
+    dropped_messages: AtomicU64,
+    in_flight_messages: AtomicU64,
+    at_least_once_latency: LatencyHistogram,
+    exactly_once_latency: LatencyHistogram,
+}
+
+/// The counters, gauges and histograms of a broker, such as the number of
+/// connections or of packets by type.
+///
+/// A `Metrics` is a cheap handle to shared atomic values: the clones given to
+/// each `ServerConnection` with `ServerConnection::set_metrics` update the
+/// same values, which the broker reads at any time, or renders for
+/// Prometheus with the `prometheus` feature.
+///
+/// `ServerConnection` updates everything but the numbers of queued, dropped
+/// and in flight messages. The dropped messages are counted by the
+/// `SessionStore` given the metrics with `SessionStore::set_metrics`, and the
+/// broker sets the others from its session store. The connections of each
+/// listener and the ones rejected for exceeding a connection limit are
+/// counted by the `ListenerSet` given the metrics with
+/// `ListenerSet::with_metrics`.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+impl Metrics {
+    /// Creates a set of metrics, all zero.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Counts a new accepted connection.
+    pub fn connection_opened(&self) {
+        self.inner.connections.fetch_add(1, Ordering::Relaxed);
+        self.inner.connections_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the closing of an accepted connection.
+    pub fn connection_closed(&self) {
+        let _ = self
+            .inner
+            .connections
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+
+    /// Counts a network connection rejected for exceeding a connection
+    /// limit.
+    pub fn connection_rejected(&self) {
+        self.inner
+            .rejected_connections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sets the number of network connections open on the listener named
+    /// `listener`.
+    pub fn set_listener_connections(&self, listener: &str, connections: usize) {
+        self.inner
+            .listener_connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(listener.to_string(), connections as u64);
+    }
+
+    /// Counts a connection refused because of its credentials.
+    pub fn authentication_failed(&self) {
+        self.inner
+            .authentication_failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a packet received from a client, along with the size of its
+    /// payload if it is a `Publish` packet.
+    pub fn packet_received(&self, packet: &Packet) {
+        self.inner.packets_received[type_index(packet.packet_type())]
+            .fetch_add(1, Ordering::Relaxed);
+        if let Packet::Publish(publish) = packet {
+            self.inner
+                .payload_bytes_received
+                .fetch_add(publish.message.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a packet sent to a client, along with the size of its payload
+    /// if it is a `Publish` packet.
+    pub fn packet_sent(&self, packet: &Packet) {
+        self.inner.packets_sent[type_index(packet.packet_type())].fetch_add(1, Ordering::Relaxed);
+        if let Packet::Publish(publish) = packet {
+            self.inner
+                .payload_bytes_sent
+                .fetch_add(publish.message.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Sets the number of messages waiting to be sent to the clients, such as
+    /// in their offline queues.
+    pub fn set_queued_messages(&self, queued: usize) {
+        self.inner
+            .queued_messages
+            .store(queued as u64, Ordering::Relaxed);
+    }
+
+    /// Counts `count` messages dropped or rejected by the offline queues.
+    pub fn messages_dropped(&self, count: usize) {
+        self.inner
+            .dropped_messages
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Sets the number of outgoing messages waiting for their
+    /// acknowledgement.
+    pub fn set_in_flight_messages(&self, in_flight: usize) {
+        self.inner
+            .in_flight_messages
+            .store(in_flight as u64, Ordering::Relaxed);
+    }
+
+    /// Records the time between the publication of an outgoing message with
+    /// `qos` and the end of its delivery. `AtMostOnce` deliveries are
+    /// ignored.
+    pub fn delivery_completed(&self, qos: QoS, latency: Duration) {
+        match qos {
+            QoS::AtMostOnce => (),
+            QoS::AtLeastOnce => self.inner.at_least_once_latency.observe(latency),
+            QoS::ExactlyOnce => self.inner.exactly_once_latency.observe(latency),
+        }
+    }
+
+    /// Returns the number of open connections.
+    pub fn connections(&self) -> u64 {
+        self.inner.connections.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of connections accepted so far.
+    pub fn connections_total(&self) -> u64 {
+        self.inner.connections_total.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of network connections rejected for exceeding a
+    /// connection limit.
+    pub fn rejected_connections(&self) -> u64 {
+        self.inner.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of network connections open on the listener named
+    /// `listener`.
+    pub fn listener_connections(&self, listener: &str) -> u64 {
+        self.inner
+            .listener_connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(listener)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Returns the number of connections refused because of their
+    /// credentials.
+    pub fn authentication_failures(&self) -> u64 {
+        self.inner.authentication_failures.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of packets of `packet_type` received. The flags of
+    /// `Publish` packets are ignored.
+    pub fn packets_received(&self, packet_type: PacketType) -> u64 {
+        self.inner.packets_received[type_index(packet_type)].load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of packets of `packet_type` sent. The flags of
+    /// `Publish` packets are ignored.
+    pub fn packets_sent(&self, packet_type: PacketType) -> u64 {
+        self.inner.packets_sent[type_index(packet_type)].load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of payload bytes received.
+    pub fn payload_bytes_received(&self) -> u64 {
+        self.inner.payload_bytes_received.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of payload bytes sent.
+    pub fn payload_bytes_sent(&self) -> u64 {
+        self.inner.payload_bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of messages waiting to be sent.
+    pub fn queued_messages(&self) -> u64 {
+        self.inner.queued_messages.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of messages dropped or rejected by the offline
+    /// queues.
+    pub fn dropped_messages(&self) -> u64 {
+        self.inner.dropped_messages.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of outgoing messages waiting for their
+    /// acknowledgement.
+    pub fn in_flight_messages(&self) -> u64 {
+        self.inner.in_flight_messages.load(Ordering::Relaxed)
+    }
+
+    /// Returns the histogram of the delivery latencies of the messages sent
+    /// with `qos`, which is empty for `AtMostOnce`.
+    pub fn delivery_latencies(&self, qos: QoS) -> Histogram {
+        match qos {
+            QoS::AtMostOnce => Default::default(),
+            QoS::AtLeastOnce => self.inner.at_least_once_latency.snapshot(),
+            QoS::ExactlyOnce => self.inner.exactly_once_latency.snapshot(),
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format, for
+    /// scraping.
+    #[cfg(feature = "prometheus")]
+    pub fn to_prometheus(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        let single = |value: u64| [(String::new(), value)];
+        let by_type = |counters: &[AtomicU64]| -> Vec<(String, u64)> {
+            PACKET_TYPES
+                .iter()
+                .zip(counters)
+                .skip(1)
+                .map(|(name, n)| {
+                    let labels = format!("{{type=\"{}\"}}", escape_label_value(name));
+                    (labels, n.load(Ordering::Relaxed))
+                })
+                .collect()
+        };
+
+        metric(
+            "mqtt_connections",
+            "gauge",
+            "Number of open connections.",
+            &single(self.connections()),
+        );
+        metric(
+            "mqtt_connections_total",
+            "counter",
+            "Number of accepted connections.",
+            &single(self.connections_total()),
+        );
+        metric(
+            "mqtt_rejected_connections_total",
+            "counter",
+            "Number of network connections rejected for exceeding a connection limit.",
+            &single(self.rejected_connections()),
+        );
+        let by_listener: Vec<(String, u64)> = self
+            .inner
+            .listener_connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(name, n)| {
+                let labels = format!("{{listener=\"{}\"}}", escape_label_value(name));
+                (labels, *n)
+            })
+            .collect();
+        metric(
+            "mqtt_listener_connections",
+            "gauge",
+            "Number of open network connections, by listener.",
+            &by_listener,
+        );
+        metric(
+            "mqtt_authentication_failures_total",
+            "counter",
+            "Number of connections refused because of their credentials.",
+            &single(self.authentication_failures()),
+        );
+        metric(
+            "mqtt_packets_received_total",
+            "counter",
+            "Number of packets received, by type.",
+            &by_type(&self.inner.packets_received),
+        );
+        metric(
+            "mqtt_packets_sent_total",
+            "counter",
+            "Number of packets sent, by type.",
+            &by_type(&self.inner.packets_sent),
+        );
+        metric(
+            "mqtt_payload_bytes_received_total",
+            "counter",
+            "Number of payload bytes received.",
+            &single(self.payload_bytes_received()),
+        );
+        metric(
+            "mqtt_payload_bytes_sent_total",
+            "counter",
+            "Number of payload bytes sent.",
+            &single(self.payload_bytes_sent()),
+        );
+        metric(
+            "mqtt_queued_messages",
+            "gauge",
+            "Number of messages waiting to be sent.",
+            &single(self.queued_messages()),
+        );
+        metric(
+            "mqtt_dropped_messages_total",
+            "counter",
+            "Number of messages dropped or rejected by the offline queues.",
+            &single(self.dropped_messages()),
+        );
+        metric(
+            "mqtt_in_flight_messages",
+            "gauge",
+            "Number of outgoing messages waiting for their acknowledgement.",
+            &single(self.in_flight_messages()),
+        );
+
+        let name = "mqtt_delivery_latency_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} Time to deliver the outgoing messages, by quality of service.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (qos, label) in [(QoS::AtLeastOnce, "1"), (QoS::ExactlyOnce, "2")] {
+            let histogram = self.delivery_latencies(qos);
+            for (bound, count) in &histogram.buckets {
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{qos=\"{}\",le=\"{}\"}} {}",
+                    name,
+                    label,
+                    bound.as_secs_f64(),
+                    count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{qos=\"{}\",le=\"+Inf\"}} {}",
+                name, label, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "{}_sum{{qos=\"{}\"}} {}",
+                name,
+                label,
+                histogram.sum.as_secs_f64()
+            );
+            let _ = writeln!(
+                out,
+                "{}_count{{qos=\"{}\"}} {}",
+                name, label, histogram.count
+            );
+        }
+        out
+    }
+}
+
+/// Escapes a label value of the Prometheus text exposition format, in which
+/// backslashes, double quotes and line feeds must be escaped.
+#[cfg(feature = "prometheus")]
+fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::Publish;
+
+    #[test]
+    fn record() {
+        let metrics = Metrics::new();
+        let clone = metrics.clone();
+        clone.connection_opened();
+        clone.connection_opened();
+        clone.connection_closed();
+        metrics.connection_closed();
+        metrics.connection_closed();
+        assert_eq!((metrics.connections(), metrics.connections_total()), (0, 2));
+        clone.connection_rejected();
+        clone.set_listener_connections("internal", 3);
+        assert_eq!(metrics.rejected_connections(), 1);
+        assert_eq!(metrics.listener_connections("internal"), 3);
+        assert_eq!(metrics.listener_connections("wss"), 0);
+
+        let publish = Publish {
+            message: "Harder".into(),
+            ..Default::default()
+        };
+        clone.packet_received(&publish.into());
+        clone.packet_sent(&Packet::PingResp);
+        assert_eq!(metrics.packets_received(PacketType::Connect), 0);
+        assert_eq!(
+            metrics.packets_received(PacketType::Publish {
+                duplicate: true,
+                qos: QoS::ExactlyOnce,
+                retain: false,
+            }),
+            1
+        );
+        assert_eq!(metrics.packets_sent(PacketType::PingResp), 1);
+        assert_eq!(metrics.payload_bytes_received(), 6);
+        assert_eq!(metrics.payload_bytes_sent(), 0);
+
+        clone.delivery_completed(QoS::AtLeastOnce, Duration::from_millis(3));
+        clone.delivery_completed(QoS::AtLeastOnce, Duration::from_secs(60));
+        clone.delivery_completed(QoS::AtMostOnce, Duration::from_millis(3));
+        let histogram = metrics.delivery_latencies(QoS::AtLeastOnce);
+        assert_eq!(histogram.count, 2);
+        assert_eq!(histogram.sum, Duration::from_millis(60_003));
+        assert_eq!(histogram.buckets[0], (Duration::from_millis(1), 0));
+        assert_eq!(histogram.buckets[1], (Duration::from_millis(5), 1));
+        assert_eq!(histogram.buckets.last(), Some(&(Duration::from_secs(5), 1)));
+        assert_eq!(metrics.delivery_latencies(QoS::ExactlyOnce).count, 0);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn prometheus() {
+        let metrics = Metrics::new();
+        metrics.connection_opened();
+        metrics.set_listener_connections("wss", 2);
+        metrics.packet_sent(&Packet::PingResp);
+        metrics.delivery_completed(QoS::ExactlyOnce, Duration::from_millis(20));
+        let text = metrics.to_prometheus();
+        assert!(text.contains("# TYPE mqtt_connections gauge\nmqtt_connections 1\n"));
+        assert!(text.contains("mqtt_listener_connections{listener=\"wss\"} 2\n"));
+        metrics.set_listener_connections("\"tcp\"\n", 1);
+        let text = metrics.to_prometheus();
+        assert!(text.contains("mqtt_listener_connections{listener=\"\\\"tcp\\\"\\n\"} 1\n"));
+        assert!(text.contains("mqtt_packets_sent_total{type=\"pingresp\"} 1\n"));
+        assert!(text.contains("mqtt_packets_sent_total{type=\"publish\"} 0\n"));
+        assert!(text.contains("mqtt_delivery_latency_seconds_bucket{qos=\"2\",le=\"0.01\"} 0\n"));
+        assert!(text.contains("mqtt_delivery_latency_seconds_bucket{qos=\"2\",le=\"0.025\"} 1\n"));
+        assert!(text.contains("mqtt_delivery_latency_seconds_count{qos=\"2\"} 1\n"));
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn escape_label_values() {
+        assert_eq!(escape_label_value("wss"), "wss");
+        assert_eq!(
+            escape_label_value("say \"hello\"\\\n"),
+            "say \\\"hello\\\"\\\\\\n"
+        );
+    }
+}
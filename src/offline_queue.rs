@@ -0,0 +1,241 @@
+use crate::{Publish, ReasonCode::QuotaExceeded, Result as SageResult};
+use std::collections::VecDeque;
+
+/// What an `OfflineQueue` does with a new message once it is full.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum OverflowPolicy {
+    /// The oldest messages of the queue are dropped to make room for the new
+    /// one.
+    #[default]
+    DropOldest,
+
+    /// The new message is dropped.
+    DropNewest,
+
+    /// The new message is rejected with `QuotaExceeded`.
+    Reject,
+
+    /// The new message is dropped, and the client is told once it reconnects
+    /// by closing its connection with `QuotaExceeded`, as reported by
+    /// `OfflineQueue::overflowed`, so that it knows messages were lost.
+    DisconnectOnReconnect,
+}
+
+/// A queue of the messages pending delivery to the client of a session while
+/// it is disconnected, for brokers.
+///
+/// The messages are queued as the `Publish` packets to send once the client
+/// reconnects, such as built by `SubscriptionDelivery::apply`, without packet
+/// identifier.
+pub trait OfflineQueue {
+    /// Queues `publish`. Returns the messages dropped to keep the queue within
+    /// its limits, in the order they were queued, which may include
+    /// `publish` itself.
+    ///
+    /// # Errors
+    ///
+    /// Returns `QuotaExceeded` if the queue is full and rejects new messages.
+    fn enqueue(&mut self, publish: Publish) -> SageResult<Vec<Publish>>;
+
+    /// Removes and returns all the queued messages, in the order they were
+    /// queued.
+    fn drain(&mut self) -> Vec<Publish>;
+
+    /// Returns the number of queued messages.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if no message is queued.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if messages were dropped since the last `drain` with
+    /// `OverflowPolicy::DisconnectOnReconnect`, in which case the client must
+    /// be disconnected with `QuotaExceeded` once it reconnects.
+    fn overflowed(&self) -> bool {
+        false
+    }
+}
+
+/// An `OfflineQueue` keeping the messages in memory, up to a given number of
+/// messages and optionally of payload bytes.
+#[derive(Debug, Clone)]
+pub struct InMemoryOfflineQueue {
+    messages: VecDeque<Publish>,
+    capacity: usize,
+    maximum_bytes: Option<usize>,
+    bytes: usize,
+    policy: OverflowPolicy,
+    overflowed: bool,
+}
+
+impl InMemoryOfflineQueue {
+    /// Creates an empty queue holding up to `capacity` messages, applying
+    /// `policy` once full.
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        InMemoryOfflineQueue {
+            messages: VecDeque::new(),
+            capacity,
+            maximum_bytes: None,
+            bytes: 0,
+            policy,
+            overflowed: false,
+        }
+    }
+
+    /// Also limits the total size of the payloads of the queued messages to
+    /// `maximum_bytes`. A message larger than the limit is never queued.
+    pub fn with_maximum_bytes(mut self, maximum_bytes: usize) -> Self {
+        self.maximum_bytes = Some(maximum_bytes);
+        self
+    }
+
+    /// The maximum number of queued messages.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The maximum total size of the payloads of the queued messages, if any.
+    pub fn maximum_bytes(&self) -> Option<usize> {
+        self.maximum_bytes
+    }
+
+    /// Returns the total size of the payloads of the queued messages.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// What the queue does with a new message once it is full.
+    pub fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    // Returns `true` if `size` more bytes fit in the queue, once it holds
+    // `len` messages of `bytes` bytes.
+    fn fits(&self, len: usize, bytes: usize, size: usize) -> bool {
+        len < self.capacity
+            && self
+                .maximum_bytes
+                .is_none_or(|maximum| bytes + size <= maximum)
+    }
+}
+
+impl OfflineQueue for InMemoryOfflineQueue {
+    fn enqueue(&mut self, publish: Publish) -> SageResult<Vec<Publish>> {
+        let size = publish.message.len();
+        let mut dropped = Vec::new();
+        if !self.fits(self.messages.len(), self.bytes, size) {
+            match self.policy {
+                OverflowPolicy::DropOldest if self.fits(0, 0, size) => {
+                    while !self.fits(self.messages.len(), self.bytes, size) {
+                        if let Some(oldest) = self.messages.pop_front() {
+                            self.bytes -= oldest.message.len();
+                            dropped.push(oldest);
+                        }
+                    }
+                }
+                OverflowPolicy::DropOldest | OverflowPolicy::DropNewest => {
+                    return Ok(vec![publish])
+                }
+                OverflowPolicy::Reject => return Err(QuotaExceeded.into()),
+                OverflowPolicy::DisconnectOnReconnect => {
+                    self.overflowed = true;
+                    return Ok(vec![publish]);
+                }
+            }
+        }
+        self.bytes += size;
+        self.messages.push_back(publish);
+        Ok(dropped)
+    }
+
+    fn drain(&mut self) -> Vec<Publish> {
+        self.bytes = 0;
+        self.overflowed = false;
+        self.messages.drain(..).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    fn publish(payload: &str) -> Publish {
+        Publish {
+            topic_name: "Around the World".try_into().unwrap(),
+            message: payload.into(),
+            ..Default::default()
+        }
+    }
+
+    fn fill(policy: OverflowPolicy) -> InMemoryOfflineQueue {
+        let mut queue = InMemoryOfflineQueue::new(2, policy);
+        assert!(queue.enqueue(publish("a")).unwrap().is_empty());
+        assert!(queue.enqueue(publish("b")).unwrap().is_empty());
+        queue
+    }
+
+    fn payloads(queue: &mut InMemoryOfflineQueue) -> Vec<Vec<u8>> {
+        queue.drain().into_iter().map(|p| p.message).collect()
+    }
+
+    #[test]
+    fn overflow() {
+        let mut queue = fill(OverflowPolicy::DropOldest);
+        assert_eq!(queue.enqueue(publish("c")).unwrap(), vec![publish("a")]);
+        assert_eq!(payloads(&mut queue), [b"b", b"c"]);
+        assert!(queue.is_empty());
+
+        let mut queue = fill(OverflowPolicy::DropNewest);
+        assert_eq!(queue.enqueue(publish("c")).unwrap(), vec![publish("c")]);
+        assert_eq!(payloads(&mut queue), [b"a", b"b"]);
+
+        let mut queue = fill(OverflowPolicy::Reject);
+        assert!(matches!(
+            queue.enqueue(publish("c")),
+            Err(crate::Error::Reason(QuotaExceeded))
+        ));
+        assert_eq!(queue.len(), 2);
+
+        let mut queue = fill(OverflowPolicy::DisconnectOnReconnect);
+        assert!(!queue.overflowed());
+        assert_eq!(queue.enqueue(publish("c")).unwrap(), vec![publish("c")]);
+        assert!(queue.overflowed());
+        assert_eq!(payloads(&mut queue), [b"a", b"b"]);
+        assert!(!queue.overflowed());
+
+        let mut queue = InMemoryOfflineQueue::new(0, OverflowPolicy::DropOldest);
+        assert_eq!(queue.enqueue(publish("a")).unwrap(), vec![publish("a")]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn maximum_bytes() {
+        let mut queue =
+            InMemoryOfflineQueue::new(8, OverflowPolicy::DropOldest).with_maximum_bytes(12);
+        for payload in ["Bet", "ter", "Harder"] {
+            assert!(queue.enqueue(publish(payload)).unwrap().is_empty());
+        }
+        assert_eq!(queue.bytes(), 12);
+        assert_eq!(
+            queue.enqueue(publish("Faster")).unwrap(),
+            vec![publish("Bet"), publish("ter")]
+        );
+        assert_eq!(queue.bytes(), 12);
+        assert_eq!(
+            queue.enqueue(publish("Stronger, more")).unwrap(),
+            vec![publish("Stronger, more")]
+        );
+        assert_eq!(payloads(&mut queue), [b"Harder", b"Faster"]);
+        assert_eq!(queue.bytes(), 0);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{Publish, QoS, SubscriptionId, SubscriptionStore, TopicName};
+use std::hash::Hash;
+
+/// How a broker delivers a message matching several subscriptions of the same
+/// client. The specification allows both policies, but a broker should stick
+/// to one of them.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum OverlapPolicy {
+    /// The message is delivered once, with the maximum quality of service of
+    /// the matching subscriptions and all their subscription identifiers.
+    #[default]
+    Merge,
+
+    /// The message is delivered once per matching subscription, each time
+    /// with the options and the subscription identifier of the subscription.
+    PerSubscription,
+}
+
+/// A delivery of a message to a client, resolved from its matching
+/// subscriptions by an `OverlapPolicy`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct SubscriptionDelivery {
+    /// The maximum quality of service to deliver the message with.
+    pub qos: QoS,
+
+    /// If `true`, the retain flag of the message is kept.
+    pub retain_as_published: bool,
+
+    /// The subscription identifiers to attach to the message.
+    pub subscription_identifiers: Vec<SubscriptionId>,
+}
+
+impl SubscriptionDelivery {
+    /// Builds the `Publish` packet forwarding `publish` according to the
+    /// delivery. The packet identifier and the topic alias must be set by
+    /// the sender.
+    pub fn apply(&self, publish: &Publish) -> Publish {
+        Publish {
+            qos: publish.qos.min(self.qos),
+            retain: publish.retain && self.retain_as_published,
+            packet_identifier: None,
+            topic_alias: None,
+            subscription_identifiers: self.subscription_identifiers.clone(),
+            ..publish.clone()
+        }
+    }
+}
+
+impl OverlapPolicy {
+    /// Resolves the deliveries to `client` of a message published to `topic`,
+    /// from its subscriptions in `store`. `own_message` is `true` if the
+    /// message was published by `client` itself, in which case the
+    /// subscriptions with the No Local option are left out.
+    ///
+    /// Shared subscriptions are left out, since their messages are
+    /// distributed among the clients of the share separately.
+    pub fn resolve<K: Hash + Eq>(
+        &self,
+        store: &SubscriptionStore<K>,
+        client: &K,
+        topic: &TopicName,
+        own_message: bool,
+    ) -> Vec<SubscriptionDelivery> {
+        let subscriptions = store
+            .matching(client, topic)
+            .into_iter()
+            .filter(|s| !(s.filter.is_shared() || own_message && s.options.no_local));
+
+        match self {
+            OverlapPolicy::PerSubscription => subscriptions
+                .map(|s| SubscriptionDelivery {
+                    qos: s.options.qos,
+                    retain_as_published: s.options.retain_as_published,
+                    subscription_identifiers: s.identifier.into_iter().collect(),
+                })
+                .collect(),
+            OverlapPolicy::Merge => {
+                let mut merged: Option<SubscriptionDelivery> = None;
+                for s in subscriptions {
+                    let delivery = merged.get_or_insert(SubscriptionDelivery {
+                        qos: QoS::AtMostOnce,
+                        retain_as_published: false,
+                        subscription_identifiers: Vec::new(),
+                    });
+                    delivery.qos = delivery.qos.max(s.options.qos);
+                    delivery.retain_as_published |= s.options.retain_as_published;
+                    if let Some(identifier) = s.identifier {
+                        if !delivery.subscription_identifiers.contains(&identifier) {
+                            delivery.subscription_identifiers.push(identifier);
+                        }
+                    }
+                }
+                merged.into_iter().collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{Subscription, SubscriptionOptions};
+
+    fn store() -> SubscriptionStore<&'static str> {
+        let mut store = SubscriptionStore::new();
+        for (filter, qos, no_local, identifier) in [
+            ("sport/#", QoS::AtMostOnce, false, 1),
+            ("sport/tennis/+", QoS::ExactlyOnce, true, 2),
+            ("sport/tennis/player1", QoS::AtLeastOnce, false, 0),
+            ("$share/group/sport/#", QoS::ExactlyOnce, false, 3),
+            ("finance", QoS::ExactlyOnce, false, 4),
+        ] {
+            store.insert(
+                "client",
+                Subscription {
+                    filter: filter.try_into().unwrap(),
+                    options: SubscriptionOptions {
+                        qos,
+                        no_local,
+                        retain_as_published: identifier == 1,
+                        ..Default::default()
+                    },
+                    identifier: SubscriptionId::new(identifier),
+                },
+            );
+        }
+        store
+    }
+
+    #[test]
+    fn merge() {
+        let store = store();
+        let topic = "sport/tennis/player1".try_into().unwrap();
+        let id = |id| SubscriptionId::new(id).unwrap();
+
+        let deliveries = OverlapPolicy::Merge.resolve(&store, &"client", &topic, false);
+        assert_eq!(
+            deliveries,
+            vec![SubscriptionDelivery {
+                qos: QoS::ExactlyOnce,
+                retain_as_published: true,
+                subscription_identifiers: vec![id(1), id(2)],
+            }]
+        );
+
+        let deliveries = OverlapPolicy::Merge.resolve(&store, &"client", &topic, true);
+        assert_eq!(deliveries[0].qos, QoS::AtLeastOnce);
+        assert_eq!(deliveries[0].subscription_identifiers, vec![id(1)]);
+
+        let topic = "weather".try_into().unwrap();
+        assert!(OverlapPolicy::Merge
+            .resolve(&store, &"client", &topic, false)
+            .is_empty());
+    }
+
+    #[test]
+    fn per_subscription() {
+        let store = store();
+        let topic = "sport/tennis/player1".try_into().unwrap();
+        let deliveries = OverlapPolicy::PerSubscription.resolve(&store, &"client", &topic, false);
+        let qos: Vec<QoS> = deliveries.iter().map(|d| d.qos).collect();
+        assert_eq!(
+            qos,
+            vec![QoS::AtMostOnce, QoS::ExactlyOnce, QoS::AtLeastOnce]
+        );
+        assert!(deliveries[2].subscription_identifiers.is_empty());
+
+        let publish = Publish {
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            packet_identifier: Some(42),
+            topic_name: topic,
+            ..Default::default()
+        };
+        let forwarded = deliveries[1].apply(&publish);
+        assert_eq!(forwarded.qos, QoS::AtLeastOnce);
+        assert!(!forwarded.retain);
+        assert_eq!(forwarded.packet_identifier, None);
+        assert_eq!(
+            forwarded.subscription_identifiers,
+            vec![SubscriptionId::new(2).unwrap()]
+        );
+        assert!(deliveries[0].apply(&publish).retain);
+    }
+}
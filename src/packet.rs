@@ -1,26 +1,91 @@
 use crate::{
-    codec, Auth, ConnAck, Connect, Disconnect, PacketType, PingReq, PingResp, PubAck, PubComp,
-    PubRec, PubRel, Publish, ReasonCode::ProtocolError, Result as SageResult, SubAck, Subscribe,
-    UnSubAck, UnSubscribe,
+    codec, immediate, Auth, ConnAck, Connect, DecodeOptions, Disconnect, PacketType, PingReq,
+    PingResp, PubAck, PubComp, PubRec, PubRel, Publish,
+    ReasonCode::{MalformedPacket, PacketTooLarge, ProtocolError},
+    Result as SageResult, SubAck, Subscribe, UnSubAck, UnSubscribe,
 };
-use std::{fmt, marker::Unpin};
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use std::{
+    fmt,
+    io::{Error as IOError, ErrorKind},
+    marker::Unpin,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The largest remaining length a fixed header can describe.
+const MAXIMUM_REMAINING_LENGTH: usize = 268_435_455;
 
-#[derive(Debug)]
-struct FixedHeader {
-    pub packet_type: PacketType,
-    pub remaining_size: usize,
+/// The fixed header present at the beginning of every MQTT packet. It
+/// describes the packet type along with its flags, and the number of bytes of
+/// the rest of the packet.
+/// Reading the fixed header alone allows routing or skipping a packet without
+/// decoding its content.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct FixedHeader {
+    packet_type: PacketType,
+    remaining_size: usize,
 }
 
 impl FixedHeader {
-    async fn encode<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
+    /// Creates a fixed header for a packet of type `packet_type` whose
+    /// variable header and payload are `remaining_length` bytes long.
+    pub fn new(packet_type: PacketType, remaining_length: usize) -> Self {
+        FixedHeader {
+            packet_type,
+            remaining_size: remaining_length,
+        }
+    }
+
+    /// The type of the packet, along with its flags.
+    pub fn packet_type(&self) -> PacketType {
+        self.packet_type
+    }
+
+    /// The number of bytes following the fixed header in the packet.
+    pub fn remaining_length(&self) -> usize {
+        self.remaining_size
+    }
+
+    /// The size of the whole packet, including the fixed header.
+    pub fn packet_size(&self) -> usize {
+        let length_size = match self.remaining_size {
+            0..=127 => 1,
+            128..=16_383 => 2,
+            16_384..=2_097_151 => 3,
+            _ => 4,
+        };
+        1 + length_size + self.remaining_size
+    }
+
+    /// Write the `FixedHeader` to `writer`, returning the number of bytes
+    /// written.
+    /// In case of failure, the operation will return any MQTT-related error, or
+    /// `std::io::Error`.
+    pub async fn write<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
+        if self.remaining_size > MAXIMUM_REMAINING_LENGTH {
+            return Err(MalformedPacket.into());
+        }
         let mut n = codec::write_control_packet_type(self.packet_type, writer).await?;
         n += codec::write_variable_byte_integer(self.remaining_size as u32, writer).await?;
         Ok(n)
     }
 
-    async fn decode<R: AsyncRead + Unpin>(reader: &mut R) -> SageResult<Self> {
-        let packet_type = codec::read_control_packet_type(reader).await?;
+    /// Read a `FixedHeader` from `reader`, leaving the rest of the packet
+    /// unread.
+    /// In case of failure, the operation will return any MQTT-related error, or
+    /// `std::io::Error`.
+    pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> SageResult<Self> {
+        FixedHeader::read_with(reader, &DecodeOptions::default()).await
+    }
+
+    /// Read a `FixedHeader` from `reader` according to the given decoding
+    /// `options`, leaving the rest of the packet unread.
+    /// In case of failure, the operation will return any MQTT-related error, or
+    /// `std::io::Error`.
+    pub async fn read_with<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        options: &DecodeOptions,
+    ) -> SageResult<Self> {
+        let packet_type = codec::read_control_packet_type_with(reader, options).await?;
         let remaining_size = codec::read_variable_byte_integer(reader).await? as usize;
         Ok(FixedHeader {
             packet_type,
@@ -29,9 +94,51 @@ impl FixedHeader {
     }
 }
 
+/// The number of bytes used by each section of an encoded packet, as returned
+/// by `Packet::encode_stats`. The sections do not overlap and add up to the
+/// size of the encoded packet.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct EncodeStats {
+    /// The size of the fixed header.
+    pub fixed_header: usize,
+
+    /// The size of the variable header, excluding the properties and the
+    /// topic name.
+    pub variable_header: usize,
+
+    /// The size of the properties, including their length. For a `Connect`
+    /// packet, the will properties are included.
+    pub properties: usize,
+
+    /// The size of the topic name of a `Publish` packet, of the will topic of a
+    /// `Connect` packet, or of the topic filters of a `Subscribe` or
+    /// `UnSubscribe` packet.
+    pub topic: usize,
+
+    /// The size of the payload, excluding the topic and properties already
+    /// accounted for.
+    pub payload: usize,
+}
+
+impl EncodeStats {
+    /// The size of the whole encoded packet.
+    pub fn total(&self) -> usize {
+        self.fixed_header + self.variable_header + self.properties + self.topic + self.payload
+    }
+}
+
+// Returns the size of the properties starting at the beginning of `data`,
+// including their length.
+fn properties_size(data: &[u8]) -> SageResult<usize> {
+    let mut reader = data;
+    let len = immediate::poll_once(codec::read_variable_byte_integer(&mut reader))
+        .unwrap_or_else(|| Err(IOError::from(ErrorKind::WouldBlock).into()))?;
+    Ok(data.len() - reader.len() + len as usize)
+}
+
 /// The standard type to manipulate a AsyncRead/AsyncWrite-able MQTT packet. Each packet
 /// is an enum value with its own type.
-#[derive(Debug, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Packet {
     /// CONNECT MQTT packet. Opens a connection request.
     Connect(Connect),
@@ -180,6 +287,32 @@ impl From<Auth> for Packet {
 }
 
 impl Packet {
+    /// Returns the type of the packet, along with the flags of its fixed
+    /// header for a `Publish` packet.
+    pub fn packet_type(&self) -> PacketType {
+        match self {
+            Packet::Connect(_) => PacketType::Connect,
+            Packet::ConnAck(_) => PacketType::ConnAck,
+            Packet::Publish(publish) => PacketType::Publish {
+                duplicate: publish.duplicate,
+                qos: publish.qos,
+                retain: publish.retain,
+            },
+            Packet::PubAck(_) => PacketType::PubAck,
+            Packet::PubRec(_) => PacketType::PubRec,
+            Packet::PubRel(_) => PacketType::PubRel,
+            Packet::PubComp(_) => PacketType::PubComp,
+            Packet::Subscribe(_) => PacketType::Subscribe,
+            Packet::SubAck(_) => PacketType::SubAck,
+            Packet::UnSubscribe(_) => PacketType::UnSubscribe,
+            Packet::UnSubAck(_) => PacketType::UnSubAck,
+            Packet::PingReq => PacketType::PingReq,
+            Packet::PingResp => PacketType::PingResp,
+            Packet::Disconnect(_) => PacketType::Disconnect,
+            Packet::Auth(_) => PacketType::Auth,
+        }
+    }
+
     /// Write the entire `Packet` to `writer`, returning the number of
     /// bytes written.
     /// In case of failure, the operation will return any MQTT-related error, or
@@ -249,57 +382,195 @@ impl Packet {
 
         let mut fixed_header_buffer = Vec::new();
 
-        let fixed_size = FixedHeader {
-            packet_type,
-            remaining_size,
-        }
-        .encode(&mut fixed_header_buffer)
-        .await?;
+        let fixed_size = FixedHeader::new(packet_type, remaining_size)
+            .write(&mut fixed_header_buffer)
+            .await?;
 
         writer.write_all(&fixed_header_buffer).await?;
         writer.write_all(&variable_and_payload).await?;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            ?packet_type,
+            size = fixed_size + remaining_size,
+            "encoded packet"
+        );
         Ok(fixed_size + remaining_size)
     }
 
+    /// Encodes the `Packet` into a new buffer.
+    /// In case of failure, the operation will return any MQTT-related error.
+    pub fn encode_vec(&self) -> SageResult<Vec<u8>> {
+        let mut buffer = Vec::new();
+        // Writing to a `Vec` never blocks, hence encoding completes within a
+        // single poll.
+        immediate::poll_once(self.clone().encode(&mut buffer))
+            .unwrap_or_else(|| Err(IOError::from(ErrorKind::WouldBlock).into()))?;
+        Ok(buffer)
+    }
+
+    /// Encodes the `Packet` into a new buffer, returning it along with the
+    /// number of bytes used by each of its sections.
+    /// In case of failure, the operation will return any MQTT-related error.
+    pub fn encode_stats(&self) -> SageResult<(Vec<u8>, EncodeStats)> {
+        let buffer = self.encode_vec()?;
+        let mut body = &buffer[..];
+        immediate::poll_once(FixedHeader::read(&mut body))
+            .unwrap_or_else(|| Err(IOError::from(ErrorKind::WouldBlock).into()))?;
+
+        let mut stats = EncodeStats {
+            fixed_header: buffer.len() - body.len(),
+            ..Default::default()
+        };
+
+        match self {
+            Packet::Connect(connect) => {
+                // Protocol name, version, flags and keep alive
+                stats.variable_header = 10;
+                stats.properties = properties_size(&body[10..])?;
+                if let Some(will) = &connect.will {
+                    let offset = 10 + stats.properties;
+                    let client_id =
+                        2 + u16::from_be_bytes([body[offset], body[offset + 1]]) as usize;
+                    stats.properties += properties_size(&body[offset + client_id..])?;
+                    stats.topic = 2 + will.topic.as_str().len();
+                }
+            }
+            Packet::Publish(publish) => {
+                stats.topic = 2 + publish.topic_name.as_str().len();
+                stats.variable_header = if publish.packet_identifier.is_some() {
+                    2
+                } else {
+                    0
+                };
+                stats.properties = properties_size(&body[stats.topic + stats.variable_header..])?;
+            }
+            Packet::PubAck(_) | Packet::PubRec(_) | Packet::PubRel(_) | Packet::PubComp(_) => {
+                // Packet identifier and reason code
+                stats.variable_header = body.len().min(3);
+                stats.properties = body.len() - stats.variable_header;
+            }
+            Packet::Disconnect(_) | Packet::Auth(_) => {
+                // Reason code
+                stats.variable_header = body.len().min(1);
+                stats.properties = body.len() - stats.variable_header;
+            }
+            Packet::ConnAck(_) | Packet::SubAck(_) | Packet::UnSubAck(_) => {
+                // Flags and reason code, or packet identifier
+                stats.variable_header = 2;
+                stats.properties = properties_size(&body[2..])?;
+            }
+            Packet::Subscribe(subscribe) => {
+                stats.variable_header = 2;
+                stats.properties = properties_size(&body[2..])?;
+                stats.topic = subscribe
+                    .subscriptions
+                    .iter()
+                    .map(|(filter, _)| 2 + filter.as_str().len())
+                    .sum();
+            }
+            Packet::UnSubscribe(unsubscribe) => {
+                stats.variable_header = 2;
+                stats.properties = properties_size(&body[2..])?;
+                stats.topic = unsubscribe
+                    .subscriptions
+                    .iter()
+                    .map(|filter| 2 + filter.as_str().len())
+                    .sum();
+            }
+            Packet::PingReq | Packet::PingResp => (),
+        }
+        stats.payload = body.len() - stats.variable_header - stats.properties - stats.topic;
+
+        Ok((buffer, stats))
+    }
+
+    /// Decodes a control packet from the beginning of `data`, returning the
+    /// packet along with the number of bytes it was encoded with.
+    /// In case of failure, the operation will return any MQTT-related error, or
+    /// `std::io::Error` if `data` is incomplete. A packet whose size does not
+    /// match its fixed header is rejected with `ProtocolError`.
+    pub fn decode_slice(data: &[u8]) -> SageResult<(Self, usize)> {
+        let mut reader = data;
+        let fixed_header = immediate::poll_once(FixedHeader::read(&mut reader))
+            .unwrap_or_else(|| Err(IOError::from(ErrorKind::WouldBlock).into()))?;
+
+        let mut reader = data;
+        let packet = immediate::poll_once(Packet::decode(&mut reader))
+            .unwrap_or_else(|| Err(IOError::from(ErrorKind::WouldBlock).into()))?;
+        let consumed = data.len() - reader.len();
+        if consumed != fixed_header.packet_size() {
+            return Err(ProtocolError.into());
+        }
+        Ok((packet, consumed))
+    }
+
     /// Read a control packet from `reader`, returning a new `Packet`.
     /// In case of failure, the operation will return any MQTT-related error, or
     /// `std::io::Error`.
     pub async fn decode<R: AsyncRead + Unpin>(reader: &mut R) -> SageResult<Self> {
-        let fixed_header = FixedHeader::decode(reader).await?;
+        Packet::decode_with(reader, &DecodeOptions::default()).await
+    }
+
+    /// Read a control packet from `reader` according to the given decoding
+    /// `options`, returning a new `Packet`.
+    /// In case of failure, the operation will return any MQTT-related error, or
+    /// `std::io::Error`.
+    pub async fn decode_with<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        options: &DecodeOptions,
+    ) -> SageResult<Self> {
+        let fixed_header = FixedHeader::read_with(reader, options).await?;
+        if let Some(maximum_packet_size) = options.maximum_packet_size {
+            if fixed_header.packet_size() > maximum_packet_size as usize {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    packet_type = ?fixed_header.packet_type,
+                    size = fixed_header.packet_size(),
+                    maximum_packet_size,
+                    "packet too large"
+                );
+                return Err(PacketTooLarge.into());
+            }
+        }
 
+        let mut body = reader.take(fixed_header.remaining_size as u64);
         let packet = match fixed_header.packet_type {
-            PacketType::Connect => Packet::Connect(Connect::read(reader).await?),
-            PacketType::ConnAck => Packet::ConnAck(ConnAck::read(reader).await?),
+            PacketType::Connect => Packet::Connect(Connect::read(&mut body, options).await?),
+            PacketType::ConnAck => Packet::ConnAck(ConnAck::read(&mut body, options).await?),
             PacketType::PubAck => {
-                Packet::PubAck(PubAck::read(reader, fixed_header.remaining_size == 2).await?)
+                Packet::PubAck(PubAck::read(&mut body, fixed_header.remaining_size, options).await?)
             }
             PacketType::PubRec => {
-                Packet::PubRec(PubRec::read(reader, fixed_header.remaining_size == 2).await?)
+                Packet::PubRec(PubRec::read(&mut body, fixed_header.remaining_size, options).await?)
             }
             PacketType::PingReq => Packet::PingReq,
             PacketType::PingResp => Packet::PingResp,
             PacketType::SubAck => {
-                Packet::SubAck(SubAck::read(reader, fixed_header.remaining_size).await?)
+                Packet::SubAck(SubAck::read(&mut body, fixed_header.remaining_size, options).await?)
             }
-            PacketType::UnSubscribe => {
-                Packet::UnSubscribe(UnSubscribe::read(reader, fixed_header.remaining_size).await?)
+            PacketType::UnSubscribe => Packet::UnSubscribe(
+                UnSubscribe::read(&mut body, fixed_header.remaining_size, options).await?,
+            ),
+            PacketType::Auth => {
+                Packet::Auth(Auth::read(&mut body, fixed_header.remaining_size, options).await?)
             }
-            PacketType::Auth => Packet::Auth(Auth::read(reader).await?),
             PacketType::PubRel => {
-                Packet::PubRel(PubRel::read(reader, fixed_header.remaining_size == 2).await?)
-            }
-            PacketType::Disconnect => Packet::Disconnect(Disconnect::read(reader).await?),
-            PacketType::PubComp => {
-                Packet::PubComp(PubComp::read(reader, fixed_header.remaining_size == 2).await?)
+                Packet::PubRel(PubRel::read(&mut body, fixed_header.remaining_size, options).await?)
             }
+            PacketType::Disconnect => Packet::Disconnect(
+                Disconnect::read(&mut body, fixed_header.remaining_size, options).await?,
+            ),
+            PacketType::PubComp => Packet::PubComp(
+                PubComp::read(&mut body, fixed_header.remaining_size, options).await?,
+            ),
 
-            PacketType::Subscribe => {
-                Packet::Subscribe(Subscribe::read(reader, fixed_header.remaining_size).await?)
-            }
+            PacketType::Subscribe => Packet::Subscribe(
+                Subscribe::read(&mut body, fixed_header.remaining_size, options).await?,
+            ),
 
-            PacketType::UnSubAck => {
-                Packet::UnSubAck(UnSubAck::read(reader, fixed_header.remaining_size).await?)
-            }
+            PacketType::UnSubAck => Packet::UnSubAck(
+                UnSubAck::read(&mut body, fixed_header.remaining_size, options).await?,
+            ),
 
             PacketType::Publish {
                 duplicate,
@@ -307,17 +578,194 @@ impl Packet {
                 retain,
             } => Packet::Publish(
                 Publish::read(
-                    reader,
+                    &mut body,
                     duplicate,
                     qos,
                     retain,
                     fixed_header.remaining_size as u64,
+                    options,
                 )
                 .await?,
             ),
             _ => return Err(ProtocolError.into()),
         };
 
+        if body.limit() > 0 {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                packet_type = ?fixed_header.packet_type,
+                unread = body.limit(),
+                "packet body not fully consumed"
+            );
+            return Err(MalformedPacket.into());
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            packet_type = ?fixed_header.packet_type,
+            size = fixed_header.packet_size(),
+            "decoded packet"
+        );
         Ok(packet)
     }
 }
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{Property, QoS, ReasonCode, Will};
+    use std::collections::HashSet;
+
+    #[test]
+    fn encode_vec_decode_slice() {
+        let packet: Packet = PubAck {
+            packet_identifier: 1337,
+            ..Default::default()
+        }
+        .into();
+        let mut data = packet.encode_vec().unwrap();
+        assert_eq!(data, vec![0x40, 0x02, 0x05, 0x39]);
+
+        data.extend_from_slice(&[0xC0, 0x00]);
+        let (decoded, n_bytes) = Packet::decode_slice(&data).unwrap();
+        assert!(matches!(decoded, Packet::PubAck(puback) if puback.packet_identifier == 1337));
+        assert_eq!(n_bytes, 4);
+        assert!(matches!(
+            Packet::decode_slice(&data[n_bytes..]).unwrap(),
+            (Packet::PingReq, 2)
+        ));
+    }
+
+    #[test]
+    fn decode_slice_shortened() {
+        let (packet, _) = Packet::decode_slice(&[0x40, 0x03, 0x05, 0x39, 0x10]).unwrap();
+        assert!(matches!(packet, Packet::PubAck(puback)
+            if puback.reason_code == ReasonCode::NoMatchingSubscribers));
+        let (packet, _) = Packet::decode_slice(&[0xE0, 0x00]).unwrap();
+        assert_eq!(packet, Disconnect::default().into());
+        let (packet, _) = Packet::decode_slice(&[0xF0, 0x00]).unwrap();
+        assert_eq!(packet, Auth::default().into());
+    }
+
+    #[test]
+    fn decode_slice_size_mismatch() {
+        // PINGREQ with a remaining length encoded on two bytes.
+        assert!(matches!(
+            Packet::decode_slice(&[0xC0, 0x80, 0x00]),
+            Err(crate::Error::Reason(ProtocolError))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fixed_header() {
+        let data = Packet::from(Publish::default()).encode_vec().unwrap();
+        let mut reader = &data[..];
+        let fixed_header = FixedHeader::read(&mut reader).await.unwrap();
+        assert!(matches!(
+            fixed_header.packet_type(),
+            PacketType::Publish { .. }
+        ));
+        assert_eq!(fixed_header.remaining_length(), reader.len());
+        assert_eq!(fixed_header.packet_size(), data.len());
+    }
+
+    #[tokio::test]
+    async fn maximum_packet_size() {
+        let data = Packet::from(Publish::default()).encode_vec().unwrap();
+        let mut options = DecodeOptions {
+            maximum_packet_size: Some(data.len() as u32),
+            ..Default::default()
+        };
+        assert!(Packet::decode_with(&mut &data[..], &options).await.is_ok());
+
+        options.maximum_packet_size = Some(data.len() as u32 - 1);
+        assert!(matches!(
+            Packet::decode_with(&mut &data[..], &options).await,
+            Err(crate::Error::Reason(PacketTooLarge))
+        ));
+    }
+
+    #[tokio::test]
+    async fn raw_properties() {
+        let publish = Publish {
+            topic_name: "Around the World".try_into().unwrap(),
+            user_properties: vec![
+                ("Daft".into(), "Punk".into()),
+                ("Daft".into(), "Punk".into()),
+            ],
+            content_type: "text/plain".into(),
+            ..Default::default()
+        };
+        let data = Packet::from(publish).encode_vec().unwrap();
+
+        let options = DecodeOptions {
+            retain_raw_properties: true,
+            ..Default::default()
+        };
+        let packet = Packet::decode_with(&mut &data[..], &options).await.unwrap();
+        let raw_properties = match packet {
+            Packet::Publish(publish) => publish.raw_properties.unwrap(),
+            _ => panic!("unexpected packet"),
+        };
+        assert_eq!(
+            Vec::from(raw_properties),
+            vec![
+                Property::UserProperty("Daft".into(), "Punk".into()),
+                Property::UserProperty("Daft".into(), "Punk".into()),
+                Property::ContentType("text/plain".into()),
+            ]
+        );
+
+        let packet = Packet::decode(&mut &data[..]).await.unwrap();
+        assert!(matches!(packet, Packet::Publish(publish) if publish.raw_properties.is_none()));
+    }
+
+    #[test]
+    fn encode_stats() {
+        let packet = Packet::from(Publish {
+            qos: QoS::AtLeastOnce,
+            topic_name: "Around the World".try_into().unwrap(),
+            packet_identifier: Some(1337),
+            content_type: "text/plain".into(),
+            message: "Harder, Better, Faster, Stronger".into(),
+            ..Default::default()
+        });
+        let (buffer, stats) = packet.encode_stats().unwrap();
+        assert_eq!(buffer, packet.encode_vec().unwrap());
+        assert_eq!(
+            stats,
+            EncodeStats {
+                fixed_header: 2,
+                variable_header: 2,
+                properties: 14,
+                topic: 18,
+                payload: 32,
+            }
+        );
+        assert_eq!(stats.total(), buffer.len());
+
+        let packet = Packet::from(Connect {
+            client_id: Some("Jaden".into()),
+            will: Some(Will {
+                content_type: "text/plain".into(),
+                ..Will::with_message("CloZee".try_into().unwrap(), "Oregon")
+            }),
+            ..Default::default()
+        });
+        let (buffer, stats) = packet.encode_stats().unwrap();
+        assert_eq!(stats.properties, 1 + 14);
+        assert_eq!(stats.topic, 8);
+        assert_eq!(stats.payload, 7 + 8);
+        assert_eq!(stats.total(), buffer.len());
+    }
+
+    #[test]
+    fn hash() {
+        let mut packets = HashSet::new();
+        packets.insert(Packet::from(Publish::default()));
+        packets.insert(Packet::from(Publish::default()));
+        packets.insert(Packet::PingReq);
+        assert_eq!(packets.len(), 2);
+    }
+}
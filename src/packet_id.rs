@@ -0,0 +1,172 @@
+use crate::{Error, Result as SageResult};
+use std::collections::HashSet;
+
+/// What a `PacketIdAllocator` does once all the packet identifiers are in
+/// use.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum PacketIdExhaustion {
+    /// The allocation fails with `Error::PacketIdentifiersExhausted`.
+    #[default]
+    Error,
+
+    /// The allocation returns no identifier. The exchange must wait until an
+    /// identifier is released.
+    Wait,
+}
+
+/// Hands out the packet identifiers used by `Publish` packets with a quality
+/// of service greater than `AtMostOnce`, as well as `Subscribe` and
+/// `UnSubscribe` packets.
+/// An identifier stays in use until it is released, once the exchange it was
+/// allocated for is acknowledged. Identifiers are allocated in a round robin
+/// fashion so that a released identifier is not reused immediately.
+#[derive(Debug, Clone)]
+pub struct PacketIdAllocator {
+    next: u16,
+    in_use: HashSet<u16>,
+    exhaustion: PacketIdExhaustion,
+}
+
+impl Default for PacketIdAllocator {
+    fn default() -> Self {
+        PacketIdAllocator {
+            next: 1,
+            in_use: HashSet::new(),
+            exhaustion: Default::default(),
+        }
+    }
+}
+
+impl PacketIdAllocator {
+    /// Creates an allocator with no identifier in use.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Creates an allocator with no identifier in use, whose next allocation
+    /// starts from `next`, such as when restoring a session.
+    pub fn starting_at(next: u16) -> Self {
+        PacketIdAllocator {
+            next: next.max(1),
+            ..Default::default()
+        }
+    }
+
+    /// Sets what `try_allocate` does once all the identifiers are in use.
+    pub fn with_exhaustion(self, exhaustion: PacketIdExhaustion) -> Self {
+        PacketIdAllocator { exhaustion, ..self }
+    }
+
+    /// Returns what `try_allocate` does once all the identifiers are in use.
+    pub fn exhaustion(&self) -> PacketIdExhaustion {
+        self.exhaustion
+    }
+
+    /// Returns the identifier the next allocation starts from.
+    pub fn next(&self) -> u16 {
+        self.next
+    }
+
+    /// Allocates a new non-zero packet identifier.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::PacketIdentifiersExhausted` if all the identifiers are
+    /// in use.
+    pub fn allocate(&mut self) -> SageResult<u16> {
+        if self.in_use.len() == u16::MAX as usize {
+            return Err(Error::PacketIdentifiersExhausted);
+        }
+        loop {
+            let id = self.next;
+            self.next = self.next.checked_add(1).unwrap_or(1);
+            if self.in_use.insert(id) {
+                return Ok(id);
+            }
+        }
+    }
+
+    /// Allocates a new non-zero packet identifier, following the exhaustion
+    /// policy of the allocator once all the identifiers are in use: returns
+    /// `None` with `PacketIdExhaustion::Wait`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::PacketIdentifiersExhausted` if all the identifiers are
+    /// in use with `PacketIdExhaustion::Error`.
+    pub fn try_allocate(&mut self) -> SageResult<Option<u16>> {
+        match self.allocate() {
+            Ok(id) => Ok(Some(id)),
+            Err(Error::PacketIdentifiersExhausted)
+                if self.exhaustion == PacketIdExhaustion::Wait =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Marks `id` as in use, such as when restoring a session. Returns `false`
+    /// if `id` is zero or is already in use.
+    pub fn reserve(&mut self, id: u16) -> bool {
+        id != 0 && self.in_use.insert(id)
+    }
+
+    /// Releases `id` so that it can be allocated again. Returns `false` if
+    /// `id` was not in use.
+    pub fn release(&mut self, id: u16) -> bool {
+        self.in_use.remove(&id)
+    }
+
+    /// Returns `true` if `id` is in use.
+    pub fn is_in_use(&self, id: u16) -> bool {
+        self.in_use.contains(&id)
+    }
+
+    /// Returns the number of identifiers in use.
+    pub fn in_use(&self) -> usize {
+        self.in_use.len()
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn allocate_release() {
+        let mut allocator = PacketIdAllocator::new();
+        assert_eq!(allocator.allocate().unwrap(), 1);
+        assert!(allocator.reserve(2));
+        assert!(!allocator.reserve(0));
+        assert_eq!(allocator.allocate().unwrap(), 3);
+        assert!(allocator.release(1));
+        assert!(!allocator.release(1));
+        assert_eq!(allocator.allocate().unwrap(), 4);
+        assert_eq!(allocator.in_use(), 3);
+    }
+
+    #[test]
+    fn exhausted() {
+        let mut allocator = PacketIdAllocator::new();
+        for _ in 0..u16::MAX {
+            allocator.allocate().unwrap();
+        }
+        assert!(matches!(
+            allocator.allocate(),
+            Err(Error::PacketIdentifiersExhausted)
+        ));
+        allocator.release(1337);
+        assert_eq!(allocator.allocate().unwrap(), 1337);
+
+        assert!(matches!(
+            allocator.try_allocate(),
+            Err(Error::PacketIdentifiersExhausted)
+        ));
+        let mut allocator = allocator.with_exhaustion(PacketIdExhaustion::Wait);
+        assert_eq!(allocator.try_allocate().unwrap(), None);
+        allocator.release(42);
+        assert_eq!(allocator.try_allocate().unwrap(), Some(42));
+    }
+}
@@ -4,27 +4,63 @@ use crate::QoS;
 /// in an MQTT paquet. It is encoded in a 8bit flag set where the 4 most
 /// significant bits represent the type of the paquet and the 4 least are flags
 /// where values depend on the type.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PacketType {
+    /// Reserved value. Never sent by a conforming client or server.
     Reserved,
+
+    /// CONNECT packet type.
     Connect,
+
+    /// CONNACK packet type.
     ConnAck,
+
+    /// PUBLISH packet type, along with the flags of the fixed header.
     Publish {
+        /// `true` if the packet is a new attempt to send an earlier one.
         duplicate: bool,
+
+        /// The quality of service of the message.
         qos: QoS,
+
+        /// `true` if the message is to be retained.
         retain: bool,
     },
+
+    /// PUBACK packet type.
     PubAck,
+
+    /// PUBREC packet type.
     PubRec,
+
+    /// PUBREL packet type.
     PubRel,
+
+    /// PUBCOMP packet type.
     PubComp,
+
+    /// SUBSCRIBE packet type.
     Subscribe,
+
+    /// SUBACK packet type.
     SubAck,
+
+    /// UNSUBSCRIBE packet type.
     UnSubscribe,
+
+    /// UNSUBACK packet type.
     UnSubAck,
+
+    /// PINGREQ packet type.
     PingReq,
+
+    /// PINGRESP packet type.
     PingResp,
+
+    /// DISCONNECT packet type.
     Disconnect,
+
+    /// AUTH packet type.
     Auth,
 }
 
@@ -0,0 +1,186 @@
+use crate::{Authenticator, Connect, ReasonCode::BadUserNameOrPassword, Result as SageResult};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Authenticates the clients of a broker with the user names and password
+/// hashes of a password file, in the format of mosquitto: one `user:hash`
+/// entry per line, where empty lines and lines starting with `#` are ignored.
+///
+/// The hashes are either Argon2 hashes in the PHC string format, such as
+/// `$argon2id$v=19$...`, or bcrypt hashes such as `$2b$...`. The users whose
+/// hash uses any other format cannot be authenticated.
+///
+/// The file is read by `load`, and read again by `reload` if it was modified
+/// in the meantime, so that a broker calling `reload` periodically picks up
+/// the changes without restarting.
+#[derive(Debug, Clone, Default)]
+pub struct PasswordFile {
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    hashes: HashMap<String, String>,
+}
+
+impl PasswordFile {
+    /// Parses the entries of a password file from `contents`. The result is
+    /// not backed by any file and is never reloaded.
+    pub fn parse(contents: &str) -> Self {
+        let hashes = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(user_name, hash)| (user_name.into(), hash.into()))
+            .collect();
+        PasswordFile {
+            hashes,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the password file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns any `std::io::Error` reading the file.
+    pub fn load<P: AsRef<Path>>(path: P) -> SageResult<Self> {
+        let path = path.as_ref();
+        let modified = fs::metadata(path)?.modified().ok();
+        Ok(PasswordFile {
+            path: Some(path.into()),
+            modified,
+            ..PasswordFile::parse(&fs::read_to_string(path)?)
+        })
+    }
+
+    /// Reads the password file again if it was modified since it was last
+    /// read, returning `true` if the entries were replaced. The entries are
+    /// kept as they are if the file cannot be read.
+    ///
+    /// # Errors
+    ///
+    /// Returns any `std::io::Error` reading the file.
+    pub fn reload(&mut self) -> SageResult<bool> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let modified = fs::metadata(path)?.modified().ok();
+        if modified.is_some() && modified == self.modified {
+            return Ok(false);
+        }
+        *self = PasswordFile::load(path)?;
+        Ok(true)
+    }
+
+    /// Returns the number of users of the file.
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Returns `true` if the file has no user.
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Returns `true` if `password` matches the hash of `user_name`.
+    pub fn verify(&self, user_name: &str, password: &[u8]) -> bool {
+        let hash = match self.hashes.get(user_name) {
+            Some(hash) => hash,
+            None => return false,
+        };
+        if hash.starts_with("$argon2") {
+            PasswordHash::new(hash)
+                .map(|hash| Argon2::default().verify_password(password, &hash).is_ok())
+                .unwrap_or(false)
+        } else if hash.starts_with("$2") {
+            bcrypt::verify(password, hash).unwrap_or(false)
+        } else {
+            false
+        }
+    }
+}
+
+impl Authenticator for PasswordFile {
+    /// Checks the user name and password of `connect`, refusing it with
+    /// `BadUserNameOrPassword` if any of them is missing, or if the password
+    /// does not match.
+    fn check(&self, connect: &Connect) -> SageResult<()> {
+        match (&connect.user_name, &connect.password) {
+            (Some(user_name), Some(password)) if self.verify(user_name, password) => Ok(()),
+            _ => Err(BadUserNameOrPassword.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use argon2::{password_hash::SaltString, PasswordHasher};
+
+    fn argon2(password: &str) -> String {
+        let salt = SaltString::encode_b64(b"Around the World").unwrap();
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    fn connect(user_name: &str, password: &str) -> Connect {
+        Connect {
+            user_name: Some(user_name.into()),
+            password: Some(password.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn verify() {
+        let contents = format!(
+            "# Daft Punk\n\nthomas:{}\nguy:{}\nmanuel:$6$unsupported\n",
+            argon2("Harder"),
+            bcrypt::hash("Better", 4).unwrap(),
+        );
+        let file = PasswordFile::parse(&contents);
+        assert_eq!(file.len(), 3);
+        assert!(file.verify("thomas", b"Harder"));
+        assert!(!file.verify("thomas", b"Better"));
+        assert!(file.verify("guy", b"Better"));
+        assert!(!file.verify("guy", b"Harder"));
+        assert!(!file.verify("manuel", b"unsupported"));
+        assert!(!file.verify("nobody", b"Harder"));
+
+        assert!(file.check(&connect("thomas", "Harder")).is_ok());
+        assert!(matches!(
+            file.check(&connect("thomas", "Faster")),
+            Err(crate::Error::Reason(BadUserNameOrPassword))
+        ));
+        assert!(file.check(&Connect::default()).is_err());
+    }
+
+    #[test]
+    fn reload() {
+        let path = std::env::temp_dir().join(format!("sage_mqtt_{}.passwd", std::process::id()));
+        fs::write(&path, format!("thomas:{}\n", argon2("Harder"))).unwrap();
+        let mut file = PasswordFile::load(&path).unwrap();
+        assert!(file.verify("thomas", b"Harder"));
+        assert!(!file.reload().unwrap());
+
+        fs::write(&path, format!("guy:{}\n", argon2("Better"))).unwrap();
+        let modified = SystemTime::now() + std::time::Duration::from_secs(1);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(modified))
+            .unwrap();
+        assert!(file.reload().unwrap());
+        assert!(!file.verify("thomas", b"Harder"));
+        assert!(file.verify("guy", b"Better"));
+        fs::remove_file(&path).unwrap();
+    }
+}
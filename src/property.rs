@@ -7,11 +7,12 @@ use crate::{
         DEFAULT_TOPIC_ALIAS_MAXIMUM, DEFAULT_WILCARD_SUBSCRIPTION_AVAILABLE,
         DEFAULT_WILL_DELAY_INTERVAL,
     },
-    QoS,
+    DecodeOptions, QoS,
     ReasonCode::{MalformedPacket, ProtocolError},
-    Result as SageResult, Topic,
+    Redacted, Result as SageResult, SubscriptionId, TopicName,
 };
 use std::collections::HashSet;
+use std::fmt;
 use std::marker::Unpin;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, Take};
 
@@ -86,41 +87,189 @@ async fn read_property_id<R: AsyncRead + Unpin>(reader: &mut R) -> SageResult<Pr
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// A property of a control packet, as read from or written to the wire.
+///
+/// Its `Debug` output redacts the authentication data.
+#[derive(PartialEq, Eq, Hash, Clone)]
 #[allow(clippy::enum_variant_names)]
 pub enum Property {
+    /// If true, the payload is a valid UTF-8 encoded string.
     PayloadFormatIndicator(bool),
+
+    /// The lifetime of the message, in seconds.
     MessageExpiryInterval(u32),
+
+    /// The type of content of the payload.
     ContentType(String),
-    ResponseTopic(Topic),
+
+    /// The topic the response to a request must be published to.
+    ResponseTopic(TopicName),
+
+    /// Data correlating a response with its request.
     CorrelationData(Vec<u8>),
-    SubscriptionIdentifier(u32),
+
+    /// The identifier of a subscription.
+    SubscriptionIdentifier(SubscriptionId),
+
+    /// The session expiry interval, in seconds.
     SessionExpiryInterval(u32),
+
+    /// The client identifier assigned by the server.
     AssignedClientIdentifier(String),
+
+    /// The keep alive imposed by the server, in seconds.
     ServerKeepAlive(u16),
+
+    /// The name of the enhanced authentication method.
     AuthenticationMethod(String),
+
+    /// The enhanced authentication data.
     AuthenticationData(Vec<u8>),
+
+    /// If true, the server may send reason strings and user properties on failures.
     RequestProblemInformation(bool),
+
+    /// The delay before publishing the will message, in seconds.
     WillDelayInterval(u32),
+
+    /// If true, the client requests response information from the server.
     RequestResponseInformation(bool),
+
+    /// The basis used to create response topics.
     ResponseInformation(String),
+
+    /// The other server(s) the client should use.
     ServerReference(String),
+
+    /// A human readable reason associated with the reason code.
     ReasonString(String),
+
+    /// The maximum number of unacknowledged QoS 1 and 2 publications.
     ReceiveMaximum(u16),
+
+    /// The highest topic alias value accepted.
     TopicAliasMaximum(u16),
+
+    /// The alias used in place of the topic name.
     TopicAlias(u16),
+
+    /// The maximum quality of service supported by the server.
     MaximumQoS(QoS),
+
+    /// If true, the server supports retained messages.
     RetainAvailable(bool),
+
+    /// A general purpose key-value pair.
     UserProperty(String, String),
+
+    /// The maximum packet size accepted, in bytes.
     MaximumPacketSize(u32),
+
+    /// If true, the server supports wildcard subscriptions.
     WildcardSubscriptionAvailable(bool),
+
+    /// If true, the server supports subscription identifiers.
     SubscriptionIdentifiersAvailable(bool),
+
+    /// If true, the server supports shared subscriptions.
     SharedSubscriptionAvailable(bool),
 }
 
+impl fmt::Debug for Property {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, value): (&str, &dyn fmt::Debug) = match self {
+            Property::PayloadFormatIndicator(value) => ("PayloadFormatIndicator", value),
+            Property::MessageExpiryInterval(value) => ("MessageExpiryInterval", value),
+            Property::ContentType(value) => ("ContentType", value),
+            Property::ResponseTopic(value) => ("ResponseTopic", value),
+            Property::CorrelationData(value) => ("CorrelationData", value),
+            Property::SubscriptionIdentifier(value) => ("SubscriptionIdentifier", value),
+            Property::SessionExpiryInterval(value) => ("SessionExpiryInterval", value),
+            Property::AssignedClientIdentifier(value) => ("AssignedClientIdentifier", value),
+            Property::ServerKeepAlive(value) => ("ServerKeepAlive", value),
+            Property::AuthenticationMethod(value) => ("AuthenticationMethod", value),
+            Property::AuthenticationData(_) => ("AuthenticationData", &Redacted),
+            Property::RequestProblemInformation(value) => ("RequestProblemInformation", value),
+            Property::WillDelayInterval(value) => ("WillDelayInterval", value),
+            Property::RequestResponseInformation(value) => ("RequestResponseInformation", value),
+            Property::ResponseInformation(value) => ("ResponseInformation", value),
+            Property::ServerReference(value) => ("ServerReference", value),
+            Property::ReasonString(value) => ("ReasonString", value),
+            Property::ReceiveMaximum(value) => ("ReceiveMaximum", value),
+            Property::TopicAliasMaximum(value) => ("TopicAliasMaximum", value),
+            Property::TopicAlias(value) => ("TopicAlias", value),
+            Property::MaximumQoS(value) => ("MaximumQoS", value),
+            Property::RetainAvailable(value) => ("RetainAvailable", value),
+            Property::UserProperty(key, value) => {
+                return f
+                    .debug_tuple("UserProperty")
+                    .field(key)
+                    .field(value)
+                    .finish();
+            }
+            Property::MaximumPacketSize(value) => ("MaximumPacketSize", value),
+            Property::WildcardSubscriptionAvailable(value) => {
+                ("WildcardSubscriptionAvailable", value)
+            }
+            Property::SubscriptionIdentifiersAvailable(value) => {
+                ("SubscriptionIdentifiersAvailable", value)
+            }
+            Property::SharedSubscriptionAvailable(value) => ("SharedSubscriptionAvailable", value),
+        };
+        f.debug_tuple(name).field(value).finish()
+    }
+}
+
+/// The sequence of properties of a packet, in the order they were read,
+/// including any duplicated `UserProperty` and `SubscriptionIdentifier`.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct Properties(Vec<Property>);
+
+impl Properties {
+    /// Returns an iterator over the properties.
+    pub fn iter(&self) -> std::slice::Iter<'_, Property> {
+        self.0.iter()
+    }
+
+    /// Returns the number of properties.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if there are no properties.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Properties> for Vec<Property> {
+    fn from(properties: Properties) -> Self {
+        properties.0
+    }
+}
+
+impl IntoIterator for Properties {
+    type Item = Property;
+    type IntoIter = std::vec::IntoIter<Property>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Properties {
+    type Item = &'a Property;
+    type IntoIter = std::slice::Iter<'a, Property>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
 pub struct PropertiesDecoder<R: AsyncRead + Unpin> {
     reader: Take<R>,
     marked: HashSet<PropertyId>,
+    raw: Option<Vec<Property>>,
 }
 
 impl<'a, R: AsyncRead + Unpin> PropertiesDecoder<R> {
@@ -130,9 +279,25 @@ impl<'a, R: AsyncRead + Unpin> PropertiesDecoder<R> {
         Ok(PropertiesDecoder {
             reader,
             marked: HashSet::new(),
+            raw: None,
         })
     }
 
+    /// Same as `take`, additionally recording the properties read if the
+    /// `options` require to retain them.
+    pub async fn take_with(stream: R, options: &DecodeOptions) -> SageResult<Self> {
+        let mut decoder = Self::take(stream).await?;
+        if options.retain_raw_properties {
+            decoder.raw = Some(Vec::new());
+        }
+        Ok(decoder)
+    }
+
+    /// Returns the properties read so far if they are recorded.
+    pub fn raw_properties(&mut self) -> Option<Properties> {
+        self.raw.take().map(Properties)
+    }
+
     pub fn into_inner(self) -> R {
         self.reader.into_inner()
     }
@@ -152,7 +317,11 @@ impl<'a, R: AsyncRead + Unpin> PropertiesDecoder<R> {
         {
             return Err(ProtocolError.into());
         }
-        self.read_property_value(property_id).await
+        let property = self.read_property_value(property_id).await?;
+        if let Some(raw) = &mut self.raw {
+            raw.push(property.clone());
+        }
+        Ok(property)
     }
 
     async fn read_property_value(&mut self, id: PropertyId) -> SageResult<Property> {
@@ -169,20 +338,17 @@ impl<'a, R: AsyncRead + Unpin> PropertiesDecoder<R> {
             PropertyId::ContentType => Ok(Property::ContentType(
                 codec::read_utf8_string(reader).await?,
             )),
-            PropertyId::ResponseTopic => Ok(Property::ResponseTopic(Topic::from(
-                codec::read_utf8_string(reader).await?,
-            ))),
+            PropertyId::ResponseTopic => Ok(Property::ResponseTopic(
+                codec::read_utf8_string(reader).await?.try_into()?,
+            )),
             PropertyId::CorrelationData => Ok(Property::CorrelationData(
                 codec::read_binary_data(reader).await?,
             )),
-            PropertyId::SubscriptionIdentifier => {
-                let v = codec::read_variable_byte_integer(reader).await?;
-                if v == 0 {
-                    Err(ProtocolError.into())
-                } else {
-                    Ok(Property::SubscriptionIdentifier(v))
-                }
-            }
+            PropertyId::SubscriptionIdentifier => Ok(Property::SubscriptionIdentifier(
+                codec::read_variable_byte_integer(reader)
+                    .await?
+                    .try_into()?,
+            )),
 
             PropertyId::SessionExpiryInterval => Ok(Property::SessionExpiryInterval(
                 codec::read_four_byte_integer(reader).await?,
@@ -263,7 +429,7 @@ impl<'a, R: AsyncRead + Unpin> PropertiesDecoder<R> {
 }
 
 impl Property {
-    pub async fn encode<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
+    pub(crate) async fn encode<W: AsyncWrite + Unpin>(self, writer: &mut W) -> SageResult<usize> {
         match self {
             Property::PayloadFormatIndicator(v) => {
                 if v != DEFAULT_PAYLOAD_FORMAT_INDICATOR {
@@ -284,29 +450,19 @@ impl Property {
             }
             Property::ResponseTopic(v) => {
                 let n_bytes = write_property_id(PropertyId::ResponseTopic, writer).await?;
-                Ok(n_bytes + codec::write_utf8_string(&v.to_string(), writer).await?)
+                Ok(n_bytes + codec::write_utf8_string(v.as_str(), writer).await?)
             }
             Property::CorrelationData(v) => {
                 let n_bytes = write_property_id(PropertyId::CorrelationData, writer).await?;
                 Ok(n_bytes + codec::write_binary_data(&v, writer).await?)
             }
             Property::SubscriptionIdentifier(v) => {
-                if v == 0 {
-                    Err(ProtocolError.into())
-                } else {
-                    let n_bytes =
-                        write_property_id(PropertyId::SubscriptionIdentifier, writer).await?;
-                    Ok(n_bytes + codec::write_variable_byte_integer(v, writer).await?)
-                }
+                let n_bytes = write_property_id(PropertyId::SubscriptionIdentifier, writer).await?;
+                Ok(n_bytes + codec::write_variable_byte_integer(v.get(), writer).await?)
             }
             Property::SessionExpiryInterval(v) => {
-                if v != 0 {
-                    let n_bytes =
-                        write_property_id(PropertyId::SessionExpiryInterval, writer).await?;
-                    Ok(n_bytes + codec::write_four_byte_integer(v, writer).await?)
-                } else {
-                    Ok(0)
-                }
+                let n_bytes = write_property_id(PropertyId::SessionExpiryInterval, writer).await?;
+                Ok(n_bytes + codec::write_four_byte_integer(v, writer).await?)
             }
             Property::AssignedClientIdentifier(v) => {
                 let n_bytes =
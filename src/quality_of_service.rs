@@ -1,8 +1,13 @@
 use crate::{Error as SageError, ReasonCode::MalformedPacket};
-use std::convert::TryFrom;
+use std::{
+    convert::TryFrom,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
 
 /// Description the quality of service used in message publishing.
-#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+/// Quality of service levels are ordered from `AtMostOnce` to `ExactlyOnce`.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum QoS {
     /// The message is delivered according to the capabilities of the
     /// underlying network. No response is sent by the receiver and no retry is
@@ -35,3 +40,55 @@ impl TryFrom<u8> for QoS {
         }
     }
 }
+
+impl QoS {
+    /// Returns the quality of service capped to `max`. This is the quality of
+    /// service to be granted when `self` is requested and `max` is the
+    /// maximum supported.
+    pub fn downgrade_to(self, max: QoS) -> QoS {
+        self.min(max)
+    }
+}
+
+impl Display for QoS {
+    /// Displays the quality of service name followed with its numeric value,
+    /// such as `At least once (QoS 1)`.
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let name = match self {
+            QoS::AtMostOnce => "At most once",
+            QoS::AtLeastOnce => "At least once",
+            QoS::ExactlyOnce => "Exactly once",
+        };
+        write!(f, "{} (QoS {})", name, *self as u8)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn try_from() {
+        assert_eq!(QoS::try_from(1).unwrap(), QoS::AtLeastOnce);
+        assert!(QoS::try_from(3).is_err());
+    }
+
+    #[test]
+    fn downgrade_to() {
+        assert!(QoS::AtMostOnce < QoS::ExactlyOnce);
+        assert_eq!(
+            QoS::ExactlyOnce.downgrade_to(QoS::AtLeastOnce),
+            QoS::AtLeastOnce
+        );
+        assert_eq!(
+            QoS::AtMostOnce.downgrade_to(QoS::ExactlyOnce),
+            QoS::AtMostOnce
+        );
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(QoS::AtLeastOnce.to_string(), "At least once (QoS 1)");
+    }
+}
@@ -0,0 +1,279 @@
+use crate::{
+    handshakes::Handshakes, tls::rustls::server_config, ClientCertificates, Listener, PeerInfo,
+    Result as SageResult,
+};
+use quinn::{Connecting, Connection, Endpoint, IdleTimeout, RecvStream, SendStream};
+use std::{
+    future::Future,
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::rustls::Certificate;
+
+/// Whether a `QuicListener` accepts 0-RTT data, sent by resuming clients
+/// before the end of the handshake.
+///
+/// 0-RTT data can be replayed by an attacker: the `Connect` packet and the
+/// packets sent along with it, such as `Publish` packets, may then be handled
+/// several times. It should only be enabled if this is harmless, such as when
+/// all the messages are idempotent.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum ZeroRtt {
+    /// The connections are only returned once their handshake completes, and
+    /// 0-RTT data is refused.
+    #[default]
+    Disabled,
+
+    /// 0-RTT data is accepted, and the connections are returned before the
+    /// end of their handshake.
+    Enabled,
+}
+
+/// The configuration of a `QuicListener`.
+///
+/// QUIC and MQTT each have their own keep-alive mechanism. QUIC keep-alive
+/// packets do not count as MQTT control packets, so they neither reset the
+/// MQTT keep alive of a client nor replace its `PingReq` packets. The QUIC
+/// idle timeout however closes the connections on which nothing is received
+/// for its duration, and must be longer than the time MQTT keeps idle
+/// connections open, which is one and a half times the keep alive. See
+/// `with_mqtt_keep_alive`.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct QuicConfig {
+    /// Whether 0-RTT data is accepted.
+    pub zero_rtt: ZeroRtt,
+
+    /// The interval of the QUIC keep-alive packets sent by the server, if
+    /// any, such as to keep NAT bindings open.
+    pub keep_alive_interval: Option<Duration>,
+
+    /// The QUIC idle timeout of the connections. `None` disables it, leaving
+    /// the detection of idle connections to MQTT keep alive.
+    pub idle_timeout: Option<Duration>,
+
+    /// Whether the clients are asked for a certificate.
+    pub client_certificates: ClientCertificates,
+}
+
+impl QuicConfig {
+    /// Sets the idle timeout slightly beyond one and a half times
+    /// `keep_alive`, so that the connections with a MQTT keep alive of up to
+    /// `keep_alive` are not closed by QUIC before MQTT would.
+    pub fn with_mqtt_keep_alive(self, keep_alive: Duration) -> Self {
+        QuicConfig {
+            idle_timeout: Some(keep_alive * 3 / 2 + Duration::from_secs(1)),
+            ..self
+        }
+    }
+}
+
+/// A `Listener` accepting MQTT over QUIC connections using `quinn`. This
+/// transport is experimental.
+///
+/// The MQTT byte stream of a connection is the first bidirectional stream
+/// opened by the client, the connection is returned once this stream is
+/// opened. Connections whose handshake fails are dropped.
+pub struct QuicListener {
+    endpoint: Endpoint,
+    zero_rtt: ZeroRtt,
+    accept: Option<Pin<Box<dyn Future<Output = Option<Connecting>> + Send>>>,
+    handshakes: Handshakes<QuicStream>,
+}
+
+impl QuicListener {
+    /// Creates a listener accepting the connections of `endpoint`. 0-RTT data
+    /// must also be enabled in the configuration of `endpoint` for
+    /// `ZeroRtt::Enabled` to take effect.
+    pub fn new(endpoint: Endpoint, zero_rtt: ZeroRtt) -> Self {
+        QuicListener {
+            endpoint,
+            zero_rtt,
+            accept: None,
+            handshakes: Default::default(),
+        }
+    }
+
+    /// Binds an endpoint to `address`, accepting connections with the PEM
+    /// encoded `certificates` chain, leaf first, and `private_key`, as
+    /// `TlsListener::from_pem` does. The `mqtt` ALPN protocol is advertised.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` IO error if the certificates, the key, the
+    /// certificate authorities or the idle timeout of `config` are not valid,
+    /// and the IO error of the creation of the socket.
+    pub fn bind(
+        address: SocketAddr,
+        certificates: &[u8],
+        private_key: &[u8],
+        config: QuicConfig,
+    ) -> SageResult<Self> {
+        let mut crypto = server_config(certificates, private_key, config.client_certificates)?;
+        if config.zero_rtt == ZeroRtt::Enabled {
+            crypto.max_early_data_size = u32::MAX;
+        }
+
+        let mut transport = quinn::TransportConfig::default();
+        transport.keep_alive_interval(config.keep_alive_interval);
+        let idle_timeout = match config.idle_timeout {
+            Some(timeout) => Some(
+                IdleTimeout::try_from(timeout)
+                    .map_err(|e| IoError::new(ErrorKind::InvalidInput, e.to_string()))?,
+            ),
+            None => None,
+        };
+        transport.max_idle_timeout(idle_timeout);
+
+        let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        server_config.transport_config(Arc::new(transport));
+        let endpoint = Endpoint::server(server_config, address)?;
+        Ok(Self::new(endpoint, config.zero_rtt))
+    }
+
+    /// Drops the connections whose handshake does not complete within
+    /// `timeout`, 10 seconds by default. `None` waits for the handshakes
+    /// indefinitely.
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshakes.set_timeout(timeout);
+        self
+    }
+
+    /// Stops accepting connections while `maximum` handshakes are in
+    /// progress, 256 by default.
+    pub fn with_maximum_handshakes(mut self, maximum: usize) -> Self {
+        self.handshakes.set_maximum(maximum);
+        self
+    }
+
+    /// Returns the endpoint accepting the connections.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+}
+
+impl Listener for QuicListener {
+    type Stream = QuicStream;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+        loop {
+            let full = self.handshakes.is_full();
+            if !full {
+                let endpoint = &self.endpoint;
+                let accept = self.accept.get_or_insert_with(|| {
+                    let endpoint = endpoint.clone();
+                    Box::pin(async move { endpoint.accept().await })
+                });
+                match accept.as_mut().poll(cx) {
+                    Poll::Ready(Some(connecting)) => {
+                        self.accept = None;
+                        self.handshakes.push(handshake(connecting, self.zero_rtt));
+                        continue;
+                    }
+                    Poll::Ready(None) => {
+                        self.accept = None;
+                        return Poll::Ready(Err(IoError::new(
+                            ErrorKind::NotConnected,
+                            "QUIC endpoint closed",
+                        )));
+                    }
+                    Poll::Pending => (),
+                }
+            }
+            match self.handshakes.poll_next(cx) {
+                Poll::Ready(connection) => return Poll::Ready(Ok(connection)),
+                // Failed handshakes made room while the endpoint was not polled.
+                Poll::Pending if full && !self.handshakes.is_full() => (),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+async fn handshake(connecting: Connecting, zero_rtt: ZeroRtt) -> IoResult<(QuicStream, PeerInfo)> {
+    let connection = match zero_rtt {
+        ZeroRtt::Enabled => match connecting.into_0rtt() {
+            Ok((connection, _)) => connection,
+            Err(connecting) => connecting.await?,
+        },
+        ZeroRtt::Disabled => connecting.await?,
+    };
+    let (send, recv) = connection.accept_bi().await?;
+    let certificates = connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<Certificate>>().ok())
+        .map(|chain| chain.into_iter().map(|c| c.0).collect())
+        .unwrap_or_default();
+    let peer = PeerInfo {
+        address: Some(connection.remote_address()),
+        certificates,
+        ..Default::default()
+    };
+    Ok((
+        QuicStream {
+            send,
+            recv,
+            connection,
+        },
+        peer,
+    ))
+}
+
+/// The stream of an MQTT over QUIC connection accepted by a `QuicListener`,
+/// which is the first bidirectional stream of the connection.
+#[derive(Debug)]
+pub struct QuicStream {
+    send: SendStream,
+    recv: RecvStream,
+    connection: Connection,
+}
+
+impl QuicStream {
+    /// Returns the QUIC connection of the stream.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.get_mut().send).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().send).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn keep_alive() {
+        let config = QuicConfig::default();
+        assert_eq!(config.zero_rtt, ZeroRtt::Disabled);
+        assert_eq!(config.idle_timeout, None);
+        let config = config.with_mqtt_keep_alive(Duration::from_secs(60));
+        assert_eq!(config.idle_timeout, Some(Duration::from_secs(91)));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{Packet, QoS};
+
+/// The send quota of the flow control of MQTT 5, limiting the number of
+/// `AtLeastOnce` and `ExactlyOnce` `Publish` packets sent and not yet
+/// acknowledged to the Receive Maximum advertised by the peer.
+///
+/// The quota starts at the Receive Maximum and:
+/// - is decremented each time a `Publish` packet with a quality of service
+///   greater than `AtMostOnce` is sent, which is not allowed once it reaches
+///   zero,
+/// - is incremented each time a `PubAck` or a `PubComp` packet is received,
+///   whatever its reason code, or a `PubRec` packet with an error reason code
+///   is received, without exceeding the Receive Maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    maximum: u16,
+    available: u16,
+}
+
+impl Quota {
+    /// Creates a quota for the given Receive Maximum. A value of zero is not
+    /// valid in MQTT and is treated as one.
+    pub fn new(receive_maximum: u16) -> Self {
+        let maximum = receive_maximum.max(1);
+        Quota {
+            maximum,
+            available: maximum,
+        }
+    }
+
+    /// The Receive Maximum the quota was created with.
+    pub fn maximum(&self) -> u16 {
+        self.maximum
+    }
+
+    /// The number of `Publish` packets which can be sent before the quota is
+    /// exhausted.
+    pub fn available(&self) -> u16 {
+        self.available
+    }
+
+    /// Returns `true` if no `Publish` packet with a quality of service
+    /// greater than `AtMostOnce` can be sent.
+    pub fn is_exhausted(&self) -> bool {
+        self.available == 0
+    }
+
+    /// Accounts for sending a `Publish` packet of quality of service `qos`.
+    /// Returns `false` if the quota is exhausted, in which case the packet
+    /// must not be sent. `AtMostOnce` packets are always allowed.
+    pub fn acquire(&mut self, qos: QoS) -> bool {
+        if qos == QoS::AtMostOnce {
+            true
+        } else if self.available > 0 {
+            self.available -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Gives back one unit of quota. Returns `false` if the quota was already
+    /// at its maximum, in which case it is left unchanged.
+    pub fn release(&mut self) -> bool {
+        if self.available < self.maximum {
+            self.available += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Accounts for a packet received from the peer, releasing one unit of
+    /// quota if it completes a delivery. Returns `true` if the quota was
+    /// incremented.
+    pub fn receive(&mut self, packet: &Packet) -> bool {
+        Quota::completed(packet).is_some() && self.release()
+    }
+
+    /// Returns the packet identifier of the delivery completed by `packet`,
+    /// if it is one of the packets incrementing the quota.
+    pub fn completed(packet: &Packet) -> Option<u16> {
+        match packet {
+            Packet::PubAck(puback) => Some(puback.packet_identifier),
+            Packet::PubRec(pubrec) if pubrec.reason_code.is_error() => {
+                Some(pubrec.packet_identifier)
+            }
+            Packet::PubComp(pubcomp) => Some(pubcomp.packet_identifier),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{test_rng::TestRng, PubAck, PubComp, PubRec, PubRel, ReasonCode};
+
+    fn completing(seed: u64) -> Packet {
+        match seed % 5 {
+            0 => PubAck::default().into(),
+            1 => PubAck {
+                reason_code: ReasonCode::UnspecifiedError,
+                ..Default::default()
+            }
+            .into(),
+            2 => PubComp::default().into(),
+            3 => PubRec {
+                reason_code: ReasonCode::QuotaExceeded,
+                ..Default::default()
+            }
+            .into(),
+            _ => PubComp {
+                reason_code: ReasonCode::PacketIdentifierNotFound,
+                ..Default::default()
+            }
+            .into(),
+        }
+    }
+
+    #[test]
+    fn rules() {
+        let mut quota = Quota::new(2);
+        assert!(quota.acquire(QoS::AtLeastOnce));
+        assert!(quota.acquire(QoS::ExactlyOnce));
+        assert!(quota.is_exhausted());
+        assert!(!quota.acquire(QoS::AtLeastOnce));
+        assert!(quota.acquire(QoS::AtMostOnce));
+
+        assert!(!quota.receive(&PubRec::default().into()));
+        assert!(!quota.receive(&PubRel::default().into()));
+        assert!(quota.receive(&completing(3)));
+        assert!(quota.receive(&completing(0)));
+        assert!(!quota.receive(&completing(2)));
+        assert_eq!(quota.available(), quota.maximum());
+        assert_eq!(Quota::new(0).maximum(), 1);
+    }
+
+    #[test]
+    fn random_sequences() {
+        let mut rng = TestRng::new(1337);
+        for _ in 0..100 {
+            let maximum = rng.draw() as u16 % 10 + 1;
+            let mut quota = Quota::new(maximum);
+            let mut outstanding: u16 = 0;
+            for _ in 0..200 {
+                let op = rng.draw();
+                match op % 6 {
+                    0 => assert!(quota.acquire(QoS::AtMostOnce)),
+                    1 | 2 => {
+                        let allowed = quota.acquire(QoS::ExactlyOnce);
+                        assert_eq!(allowed, outstanding < maximum);
+                        if allowed {
+                            outstanding += 1;
+                        }
+                    }
+                    _ => {
+                        let released = quota.receive(&completing(op >> 3));
+                        assert_eq!(released, outstanding > 0);
+                        outstanding = outstanding.saturating_sub(1);
+                    }
+                }
+                assert!(quota.available() <= quota.maximum());
+                assert_eq!(quota.available(), maximum - outstanding);
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+/// Fills `buffer` with random bytes from the random number generator of the
+/// operating system.
+///
+/// # Panics
+///
+/// Panics if the operating system fails to provide random bytes, which only
+/// happens on unsupported platforms or very early during boot.
+pub(crate) fn fill(buffer: &mut [u8]) {
+    getrandom::getrandom(buffer).expect("the operating system provides random bytes");
+}
+
+/// Returns a random number lower than `bound`, every value being equally
+/// likely. `bound` must not be zero.
+pub(crate) fn below(bound: usize) -> usize {
+    let bound = bound as u64;
+    // The values above the last multiple of `bound` are discarded, as keeping
+    // them would favour the lowest results.
+    let limit = u64::MAX - u64::MAX % bound;
+    loop {
+        let mut bytes = [0; 8];
+        fill(&mut bytes);
+        let value = u64::from_ne_bytes(bytes);
+        if value < limit {
+            return (value % bound) as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn below_bound() {
+        let mut seen = [false; 3];
+        for _ in 0..1000 {
+            seen[below(3)] = true;
+        }
+        assert_eq!(seen, [true; 3]);
+        assert_eq!(below(1), 0);
+    }
+}
@@ -1,9 +1,14 @@
-use crate::Error as SageError;
-use std::{convert::TryFrom, io::ErrorKind};
+use crate::{Error as SageError, PacketType};
+use std::{
+    convert::TryFrom,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::ErrorKind,
+};
 
 /// A `ReasonCode` is an identifier describing a response in any ackowledgement
 /// packet (such as `Connack` or `SubAck`)
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
 pub enum ReasonCode {
     /// Generic success reason code indicating an operation performed well.
     /// According to the emmiting packet, the following meanings are applied:
@@ -144,6 +149,159 @@ pub enum ReasonCode {
 
     /// The server does not support wildcard subcriptions.
     WildcardSubscriptionsNotSupported = 0xA2,
+
+    /// A reason code which is not part of the specification, such as a vendor
+    /// specific value. It is only produced when decoding with
+    /// `DecodeOptions::accept_unknown_reason_codes` and holds the raw value.
+    Unknown(u8),
+}
+
+impl ReasonCode {
+    /// Returns `true` if the reason code indicates a successful outcome, that
+    /// is any value lower than `0x80`.
+    pub fn is_success(&self) -> bool {
+        u8::from(*self) < 0x80
+    }
+
+    /// Returns `true` if the reason code indicates a failure, that is any
+    /// value greater or equal to `0x80`.
+    pub fn is_error(&self) -> bool {
+        !self.is_success()
+    }
+
+    /// Returns `true` if the reason code can be sent within a packet of type
+    /// `packet_type`, according to the MQTT 5 specifications.
+    pub fn allowed_in(&self, packet_type: PacketType) -> bool {
+        use PacketType::*;
+        match self {
+            ReasonCode::Success => matches!(
+                packet_type,
+                ConnAck
+                    | PubAck
+                    | PubRec
+                    | PubRel
+                    | PubComp
+                    | SubAck
+                    | UnSubAck
+                    | Disconnect
+                    | Auth
+            ),
+            ReasonCode::GrantedQoS1 | ReasonCode::GrantedQoS2 => matches!(packet_type, SubAck),
+            ReasonCode::DisconnectWithWillMessage
+            | ReasonCode::ServerShuttingDown
+            | ReasonCode::KeepAliveTimeout
+            | ReasonCode::SessionTakenOver
+            | ReasonCode::ReceiveMaximumExceeded
+            | ReasonCode::TopicAliasInvalid
+            | ReasonCode::MessageRateTooHigh
+            | ReasonCode::AdministrativeAction
+            | ReasonCode::MaximumConnectTime => matches!(packet_type, Disconnect),
+            ReasonCode::NoMatchingSubscribers => matches!(packet_type, PubAck | PubRec),
+            ReasonCode::NoSubscriptionExisted => matches!(packet_type, UnSubAck),
+            ReasonCode::ContinueAuthentication | ReasonCode::ReAuthenticate => {
+                matches!(packet_type, Auth)
+            }
+            ReasonCode::UnspecifiedError
+            | ReasonCode::ImplementationSpecificError
+            | ReasonCode::NotAuthorized => matches!(
+                packet_type,
+                ConnAck | PubAck | PubRec | SubAck | UnSubAck | Disconnect
+            ),
+            ReasonCode::MalformedPacket
+            | ReasonCode::ProtocolError
+            | ReasonCode::ServerBusy
+            | ReasonCode::BadAuthenticationMethod
+            | ReasonCode::PacketTooLarge
+            | ReasonCode::RetainNotSupported
+            | ReasonCode::QoSNotSupported
+            | ReasonCode::UseAnotherServer
+            | ReasonCode::ServerMoved
+            | ReasonCode::ConnectionRateExceeded => matches!(packet_type, ConnAck | Disconnect),
+            ReasonCode::UnsupportedProtocolVersion
+            | ReasonCode::ClientIdentifierNotValid
+            | ReasonCode::BadUserNameOrPassword
+            | ReasonCode::ServerUnavailable
+            | ReasonCode::Banned => matches!(packet_type, ConnAck),
+            ReasonCode::TopicFilterInvalid => matches!(packet_type, SubAck | UnSubAck | Disconnect),
+            ReasonCode::TopicNameInvalid | ReasonCode::PayloadFormatInvalid => {
+                matches!(packet_type, ConnAck | PubAck | PubRec | Disconnect)
+            }
+            ReasonCode::PacketIdentifierInUse => {
+                matches!(packet_type, PubAck | PubRec | SubAck | UnSubAck)
+            }
+            ReasonCode::PacketIdentifierNotFound => matches!(packet_type, PubRel | PubComp),
+            ReasonCode::QuotaExceeded => {
+                matches!(packet_type, ConnAck | PubAck | PubRec | SubAck | Disconnect)
+            }
+            ReasonCode::SharedSubscriptionsNotSupported
+            | ReasonCode::SubscriptionIdentifiersNotSupported
+            | ReasonCode::WildcardSubscriptionsNotSupported => {
+                matches!(packet_type, SubAck | Disconnect)
+            }
+            ReasonCode::Unknown(_) => false,
+        }
+    }
+
+    /// Returns the human readable name of the reason code, as worded in the
+    /// MQTT 5 specifications.
+    pub fn description(&self) -> &'static str {
+        match self {
+            ReasonCode::Success => "Success",
+            ReasonCode::GrantedQoS1 => "Granted QoS 1",
+            ReasonCode::GrantedQoS2 => "Granted QoS 2",
+            ReasonCode::DisconnectWithWillMessage => "Disconnect with Will Message",
+            ReasonCode::NoMatchingSubscribers => "No matching subscribers",
+            ReasonCode::NoSubscriptionExisted => "No subscription existed",
+            ReasonCode::ContinueAuthentication => "Continue authentication",
+            ReasonCode::ReAuthenticate => "Re-authenticate",
+            ReasonCode::UnspecifiedError => "Unspecified error",
+            ReasonCode::MalformedPacket => "Malformed Packet",
+            ReasonCode::ProtocolError => "Protocol Error",
+            ReasonCode::ImplementationSpecificError => "Implementation specific error",
+            ReasonCode::UnsupportedProtocolVersion => "Unsupported Protocol Version",
+            ReasonCode::ClientIdentifierNotValid => "Client Identifier not valid",
+            ReasonCode::BadUserNameOrPassword => "Bad User Name or Password",
+            ReasonCode::NotAuthorized => "Not authorized",
+            ReasonCode::ServerUnavailable => "Server unavailable",
+            ReasonCode::ServerBusy => "Server busy",
+            ReasonCode::Banned => "Banned",
+            ReasonCode::ServerShuttingDown => "Server shutting down",
+            ReasonCode::BadAuthenticationMethod => "Bad authentication method",
+            ReasonCode::KeepAliveTimeout => "Keep Alive timeout",
+            ReasonCode::SessionTakenOver => "Session taken over",
+            ReasonCode::TopicFilterInvalid => "Topic Filter invalid",
+            ReasonCode::TopicNameInvalid => "Topic Name invalid",
+            ReasonCode::PacketIdentifierInUse => "Packet Identifier in use",
+            ReasonCode::PacketIdentifierNotFound => "Packet Identifier not found",
+            ReasonCode::ReceiveMaximumExceeded => "Receive Maximum exceeded",
+            ReasonCode::TopicAliasInvalid => "Topic Alias invalid",
+            ReasonCode::PacketTooLarge => "Packet too large",
+            ReasonCode::MessageRateTooHigh => "Message rate too high",
+            ReasonCode::QuotaExceeded => "Quota exceeded",
+            ReasonCode::AdministrativeAction => "Administrative action",
+            ReasonCode::PayloadFormatInvalid => "Payload format invalid",
+            ReasonCode::RetainNotSupported => "Retain not supported",
+            ReasonCode::QoSNotSupported => "QoS not supported",
+            ReasonCode::UseAnotherServer => "Use another server",
+            ReasonCode::ServerMoved => "Server moved",
+            ReasonCode::SharedSubscriptionsNotSupported => "Shared Subscriptions not supported",
+            ReasonCode::ConnectionRateExceeded => "Connection rate exceeded",
+            ReasonCode::MaximumConnectTime => "Maximum connect time",
+            ReasonCode::SubscriptionIdentifiersNotSupported => {
+                "Subscription Identifiers not supported"
+            }
+            ReasonCode::WildcardSubscriptionsNotSupported => "Wildcard Subscriptions not supported",
+            ReasonCode::Unknown(_) => "Unknown reason code",
+        }
+    }
+}
+
+impl Display for ReasonCode {
+    /// Displays the reason code description followed with its numeric value,
+    /// such as `Quota exceeded (0x97)`.
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{} (0x{:02X})", self.description(), u8::from(*self))
+    }
 }
 
 impl From<SageError> for ReasonCode {
@@ -154,6 +312,60 @@ impl From<SageError> for ReasonCode {
                 ErrorKind::UnexpectedEof => ReasonCode::ProtocolError,
                 _ => ReasonCode::MalformedPacket,
             },
+            SageError::PacketIdentifiersExhausted | SageError::TopicAliasesExhausted => {
+                ReasonCode::QuotaExceeded
+            }
+        }
+    }
+}
+
+impl From<ReasonCode> for u8 {
+    fn from(code: ReasonCode) -> Self {
+        match code {
+            ReasonCode::Success => 0x00,
+            ReasonCode::GrantedQoS1 => 0x01,
+            ReasonCode::GrantedQoS2 => 0x02,
+            ReasonCode::DisconnectWithWillMessage => 0x04,
+            ReasonCode::NoMatchingSubscribers => 0x10,
+            ReasonCode::NoSubscriptionExisted => 0x11,
+            ReasonCode::ContinueAuthentication => 0x18,
+            ReasonCode::ReAuthenticate => 0x19,
+            ReasonCode::UnspecifiedError => 0x80,
+            ReasonCode::MalformedPacket => 0x81,
+            ReasonCode::ProtocolError => 0x82,
+            ReasonCode::ImplementationSpecificError => 0x83,
+            ReasonCode::UnsupportedProtocolVersion => 0x84,
+            ReasonCode::ClientIdentifierNotValid => 0x85,
+            ReasonCode::BadUserNameOrPassword => 0x86,
+            ReasonCode::NotAuthorized => 0x87,
+            ReasonCode::ServerUnavailable => 0x88,
+            ReasonCode::ServerBusy => 0x89,
+            ReasonCode::Banned => 0x8A,
+            ReasonCode::ServerShuttingDown => 0x8B,
+            ReasonCode::BadAuthenticationMethod => 0x8C,
+            ReasonCode::KeepAliveTimeout => 0x8D,
+            ReasonCode::SessionTakenOver => 0x8E,
+            ReasonCode::TopicFilterInvalid => 0x8F,
+            ReasonCode::TopicNameInvalid => 0x90,
+            ReasonCode::PacketIdentifierInUse => 0x91,
+            ReasonCode::PacketIdentifierNotFound => 0x92,
+            ReasonCode::ReceiveMaximumExceeded => 0x93,
+            ReasonCode::TopicAliasInvalid => 0x94,
+            ReasonCode::PacketTooLarge => 0x95,
+            ReasonCode::MessageRateTooHigh => 0x96,
+            ReasonCode::QuotaExceeded => 0x97,
+            ReasonCode::AdministrativeAction => 0x98,
+            ReasonCode::PayloadFormatInvalid => 0x99,
+            ReasonCode::RetainNotSupported => 0x9A,
+            ReasonCode::QoSNotSupported => 0x9B,
+            ReasonCode::UseAnotherServer => 0x9C,
+            ReasonCode::ServerMoved => 0x9D,
+            ReasonCode::SharedSubscriptionsNotSupported => 0x9E,
+            ReasonCode::ConnectionRateExceeded => 0x9F,
+            ReasonCode::MaximumConnectTime => 0xA0,
+            ReasonCode::SubscriptionIdentifiersNotSupported => 0xA1,
+            ReasonCode::WildcardSubscriptionsNotSupported => 0xA2,
+            ReasonCode::Unknown(value) => value,
         }
     }
 }
@@ -210,3 +422,43 @@ impl TryFrom<u8> for ReasonCode {
         }
     }
 }
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(ReasonCode::Success.to_string(), "Success (0x00)");
+        assert_eq!(
+            ReasonCode::QuotaExceeded.to_string(),
+            "Quota exceeded (0x97)"
+        );
+        assert_eq!(
+            ReasonCode::WildcardSubscriptionsNotSupported.to_string(),
+            "Wildcard Subscriptions not supported (0xA2)"
+        );
+    }
+
+    #[test]
+    fn classification() {
+        assert!(ReasonCode::Success.is_success());
+        assert!(ReasonCode::ReAuthenticate.is_success());
+        assert!(!ReasonCode::ReAuthenticate.is_error());
+        assert!(ReasonCode::UnspecifiedError.is_error());
+        assert!(ReasonCode::WildcardSubscriptionsNotSupported.is_error());
+    }
+
+    #[test]
+    fn allowed_in() {
+        assert!(ReasonCode::Success.allowed_in(PacketType::Disconnect));
+        assert!(!ReasonCode::Success.allowed_in(PacketType::Connect));
+        assert!(ReasonCode::GrantedQoS2.allowed_in(PacketType::SubAck));
+        assert!(!ReasonCode::GrantedQoS2.allowed_in(PacketType::PubAck));
+        assert!(ReasonCode::PacketIdentifierNotFound.allowed_in(PacketType::PubComp));
+        assert!(!ReasonCode::PacketIdentifierNotFound.allowed_in(PacketType::PubAck));
+        assert!(ReasonCode::Banned.allowed_in(PacketType::ConnAck));
+        assert!(!ReasonCode::Banned.allowed_in(PacketType::Disconnect));
+    }
+}
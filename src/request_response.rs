@@ -0,0 +1,226 @@
+use crate::{Connect, ExpiryQueue, Publish, Result as SageResult, TopicFilter, TopicName};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+/// Sends requests following the MQTT 5 Request/Response pattern, and matches
+/// the responses to them.
+///
+/// Each request is given a unique correlation data and the response topic of
+/// the requester, which must subscribe to it beforehand. Responses are matched
+/// with the pending requests using their correlation data, and requests which
+/// are not answered in time are timed out. The current time is always given
+/// by the caller.
+#[derive(Debug, Clone)]
+pub struct Requester {
+    response_topic: TopicName,
+    next_correlation: u64,
+    pending: HashSet<Vec<u8>>,
+    timeouts: ExpiryQueue<Vec<u8>>,
+}
+
+impl Requester {
+    /// Creates a requester receiving its responses on `response_topic`, where
+    /// `now` is the current time.
+    pub fn new(response_topic: TopicName, now: Instant) -> Self {
+        Requester {
+            response_topic,
+            next_correlation: 0,
+            pending: HashSet::new(),
+            timeouts: ExpiryQueue::new(now),
+        }
+    }
+
+    /// Creates a requester whose response topic is built from the response
+    /// information sent by the server in its `ConnAck` packet, followed with
+    /// `suffix`, such as `{response_information}/{suffix}`.
+    pub fn with_response_information(
+        response_information: &str,
+        suffix: &str,
+        now: Instant,
+    ) -> SageResult<Self> {
+        let response_topic = format!("{}/{}", response_information, suffix).try_into()?;
+        Ok(Requester::new(response_topic, now))
+    }
+
+    /// The topic the responses are received on.
+    pub fn response_topic(&self) -> &TopicName {
+        &self.response_topic
+    }
+
+    /// The topic filter to subscribe to in order to receive the responses.
+    pub fn filter(&self) -> SageResult<TopicFilter> {
+        self.response_topic.as_str().try_into()
+    }
+
+    /// Returns the number of requests waiting for a response.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Turns `publish` into a request, setting its response topic and a new
+    /// correlation data, and waits for its response until `timeout` elapses.
+    pub fn request(&mut self, mut publish: Publish, timeout: Duration, now: Instant) -> Publish {
+        let correlation_data = self.next_correlation.to_be_bytes().to_vec();
+        self.next_correlation = self.next_correlation.wrapping_add(1);
+        self.pending.insert(correlation_data.clone());
+        self.timeouts
+            .insert(correlation_data.clone(), now + timeout);
+        publish.response_topic = Some(self.response_topic.clone());
+        publish.correlation_data = Some(correlation_data);
+        publish
+    }
+
+    /// Checks whether `publish` is the response to a pending request. If so,
+    /// the request is no longer pending and its correlation data is returned.
+    pub fn response(&mut self, publish: &Publish) -> Option<Vec<u8>> {
+        if publish.topic_name != self.response_topic {
+            return None;
+        }
+        let correlation_data = publish.correlation_data.as_ref()?;
+        if self.pending.remove(correlation_data) {
+            self.timeouts.remove(correlation_data);
+            Some(correlation_data.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Stops waiting for the response to the request of `correlation_data`.
+    /// Returns `false` if it was not pending.
+    pub fn cancel(&mut self, correlation_data: &[u8]) -> bool {
+        let correlation_data = correlation_data.to_vec();
+        self.timeouts.remove(&correlation_data);
+        self.pending.remove(&correlation_data)
+    }
+
+    /// Removes and returns the correlation data of the requests which were not
+    /// answered in time at `now`.
+    pub fn timed_out(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let timed_out = self.timeouts.expired_before(now);
+        for correlation_data in &timed_out {
+            self.pending.remove(correlation_data);
+        }
+        timed_out
+    }
+
+    /// Returns the time until `timed_out` must be called next, if any request
+    /// is pending.
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        self.timeouts.next_wakeup(now)
+    }
+}
+
+/// Assigns the response information of the clients requesting it, for
+/// brokers. The response information of a client is a topic prefix reserved
+/// for its responses, of the form `{prefix}/{client_id}`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ResponseInformation {
+    prefix: String,
+}
+
+impl ResponseInformation {
+    /// Creates a response information with the given topic prefix.
+    pub fn new<S: Into<String>>(prefix: S) -> Self {
+        ResponseInformation {
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Returns the response information to send in the `ConnAck` packet to
+    /// the client of `client_id`, or `None` if it did not request it with
+    /// `Connect::request_response_information`.
+    pub fn for_client(&self, connect: &Connect, client_id: &str) -> Option<String> {
+        if connect.request_response_information {
+            Some(format!("{}/{}", self.prefix, client_id))
+        } else {
+            None
+        }
+    }
+
+    /// Checks whether `topic` is under the response information of the client
+    /// of `client_id`, such as to allow it to subscribe to it.
+    pub fn is_response_topic(&self, client_id: &str, topic: &TopicName) -> bool {
+        matches!(
+            topic
+                .as_str()
+                .strip_prefix(self.prefix.as_str())
+                .and_then(|t| t.strip_prefix('/'))
+                .and_then(|t| t.strip_prefix(client_id)),
+            Some(rest) if rest.is_empty() || rest.starts_with('/')
+        )
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn request_response() {
+        let start = Instant::now();
+        let mut requester =
+            Requester::with_response_information("responses/client1", "rpc", start).unwrap();
+        assert_eq!(
+            requester.filter().unwrap().as_str(),
+            "responses/client1/rpc"
+        );
+
+        let timeout = Duration::from_secs(5);
+        let publish = Publish {
+            topic_name: "services/time".try_into().unwrap(),
+            ..Default::default()
+        };
+        let first = requester.request(publish.clone(), timeout, start);
+        let second = requester.request(publish, timeout, start + Duration::from_secs(1));
+        assert_eq!(
+            first.response_topic,
+            Some(requester.response_topic().clone())
+        );
+        assert_ne!(first.correlation_data, second.correlation_data);
+        assert_eq!(requester.pending(), 2);
+
+        let response = second.response("noon".into()).unwrap();
+        assert_eq!(response.topic_name.as_str(), "responses/client1/rpc");
+        assert_eq!(requester.response(&response), second.correlation_data);
+        assert_eq!(requester.response(&response), None);
+
+        assert!(matches!(requester.next_timeout(start), Some(t) if t <= timeout));
+        assert!(requester
+            .timed_out(start + Duration::from_secs(4))
+            .is_empty());
+        assert_eq!(
+            requester.timed_out(start + timeout),
+            vec![first.correlation_data.clone().unwrap()]
+        );
+        assert_eq!(
+            requester.response(&first.response(Vec::new()).unwrap()),
+            None
+        );
+        assert_eq!(requester.pending(), 0);
+        assert!(Publish::default().response(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn response_information() {
+        let information = ResponseInformation::new("responses");
+        let connect = Connect {
+            request_response_information: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            information.for_client(&connect, "client1"),
+            Some("responses/client1".into())
+        );
+        assert_eq!(information.for_client(&Default::default(), "client1"), None);
+
+        let is_response_topic =
+            |topic: &str| information.is_response_topic("client1", &topic.try_into().unwrap());
+        assert!(is_response_topic("responses/client1"));
+        assert!(is_response_topic("responses/client1/rpc"));
+        assert!(!is_response_topic("responses/client10"));
+        assert!(!is_response_topic("responses/client2/rpc"));
+    }
+}
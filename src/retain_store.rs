@@ -0,0 +1,310 @@
+use crate::{Message, Publish, RetainHandling, Subscribe, Subscription, TopicFilter, TopicName};
+use std::collections::HashMap;
+
+const LEVEL_SEPARATOR: char = '/';
+
+/// A storage of retained messages, one per topic name.
+pub trait RetainStore {
+    /// Sets the retained message of its topic, replacing any previous one.
+    fn set(&mut self, message: Message);
+
+    /// Removes and returns the retained message of `topic`, if any.
+    fn clear(&mut self, topic: &TopicName) -> Option<Message>;
+
+    /// Returns the retained messages whose topic matches `filter`, following
+    /// the same rules as `TopicFilter::matches`. No message is returned for a
+    /// shared subscription, since retained messages are not sent to them.
+    fn matching(&self, filter: &TopicFilter) -> Vec<Message>;
+
+    /// Handles a message published with the retain flag: a message with an
+    /// empty payload clears the retained message of its topic, any other
+    /// message replaces it.
+    fn retain(&mut self, message: Message) {
+        if message.payload.is_empty() {
+            self.clear(&message.topic);
+        } else {
+            self.set(message);
+        }
+    }
+
+    /// Returns the `Publish` packets of the retained messages to send upon
+    /// `subscription`, according to its Retain Handling option. `new` tells
+    /// whether the subscription did not exist before, as returned by
+    /// `SubscriptionStore::insert`.
+    ///
+    /// The messages are sent with the maximum quality of service of the
+    /// subscription and its identifier. Their retain flag is always set,
+    /// whatever the Retain As Published option, which only applies to the
+    /// messages forwarded as they are published.
+    fn on_subscribe(&self, subscription: &Subscription, new: bool) -> Vec<Publish> {
+        let send = match subscription.options.retain_handling {
+            RetainHandling::OnSubscribe => true,
+            RetainHandling::OnFirstSubscribe => new,
+            RetainHandling::DontSend => false,
+        };
+        if !send {
+            return Vec::new();
+        }
+        self.matching(&subscription.filter)
+            .into_iter()
+            .map(|message| Publish {
+                qos: message.qos.min(subscription.options.qos),
+                retain: true,
+                subscription_identifiers: subscription.identifier.into_iter().collect(),
+                ..message.into_publish(None, None)
+            })
+            .collect()
+    }
+
+    /// Returns the `Publish` packets of the retained messages to send upon
+    /// `subscribe`, in the order of its subscriptions, as `on_subscribe`
+    /// does. `new` tells whether each subscription is new, as returned by
+    /// `SubscriptionStore::subscribe`.
+    fn on_subscribe_packet(&self, subscribe: &Subscribe, new: &[bool]) -> Vec<Publish> {
+        subscribe
+            .subscriptions
+            .iter()
+            .zip(new)
+            .flat_map(|((filter, options), new)| {
+                let subscription = Subscription {
+                    filter: filter.clone(),
+                    options: *options,
+                    identifier: subscribe.subscription_identifier,
+                };
+                self.on_subscribe(&subscription, *new)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    message: Option<Message>,
+    children: HashMap<String, Node>,
+}
+
+impl Node {
+    fn is_empty(&self) -> bool {
+        self.message.is_none() && self.children.is_empty()
+    }
+
+    fn collect_all(&self, messages: &mut Vec<Message>) {
+        messages.extend(self.message.iter().cloned());
+        for node in self.children.values() {
+            node.collect_all(messages);
+        }
+    }
+
+    fn collect(&self, levels: &[&str], root: bool, messages: &mut Vec<Message>) {
+        match levels.split_first() {
+            None => messages.extend(self.message.iter().cloned()),
+            Some((&"#", _)) => {
+                // `#` also matches the parent level, except for the root.
+                if !root {
+                    messages.extend(self.message.iter().cloned());
+                }
+                for (level, node) in &self.children {
+                    if !(root && level.starts_with('$')) {
+                        node.collect_all(messages);
+                    }
+                }
+            }
+            Some((&"+", levels)) => {
+                for (level, node) in &self.children {
+                    if !(root && level.starts_with('$')) {
+                        node.collect(levels, false, messages);
+                    }
+                }
+            }
+            Some((level, levels)) => {
+                if let Some(node) = self.children.get(*level) {
+                    node.collect(levels, false, messages);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, levels: &[&str]) -> Option<Message> {
+        match levels.split_first() {
+            None => self.message.take(),
+            Some((level, levels)) => {
+                let node = self.children.get_mut(*level)?;
+                let message = node.remove(levels);
+                if node.is_empty() {
+                    self.children.remove(*level);
+                }
+                message
+            }
+        }
+    }
+}
+
+/// A `RetainStore` keeping the retained messages in memory, in a tree of
+/// topic levels so that wildcard lookups only walk the matching levels.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryRetainStore {
+    root: Node,
+    len: usize,
+}
+
+impl InMemoryRetainStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the number of retained messages.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if there is no retained message.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl RetainStore for InMemoryRetainStore {
+    fn set(&mut self, message: Message) {
+        let node = message
+            .topic
+            .as_str()
+            .split(LEVEL_SEPARATOR)
+            .fold(&mut self.root, |node, level| {
+                node.children.entry(level.into()).or_default()
+            });
+        if node.message.replace(message).is_none() {
+            self.len += 1;
+        }
+    }
+
+    fn clear(&mut self, topic: &TopicName) -> Option<Message> {
+        let levels: Vec<&str> = topic.as_str().split(LEVEL_SEPARATOR).collect();
+        let message = self.root.remove(&levels);
+        if message.is_some() {
+            self.len -= 1;
+        }
+        message
+    }
+
+    fn matching(&self, filter: &TopicFilter) -> Vec<Message> {
+        if filter.is_shared() {
+            return Vec::new();
+        }
+        let levels: Vec<&str> = filter.as_str().split(LEVEL_SEPARATOR).collect();
+        let mut messages = Vec::new();
+        self.root.collect(&levels, true, &mut messages);
+        messages
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{QoS, SubscriptionOptions};
+
+    fn message(topic: &str) -> Message {
+        Message {
+            topic: topic.try_into().unwrap(),
+            payload: topic.into(),
+            ..Default::default()
+        }
+    }
+
+    fn matching(store: &InMemoryRetainStore, filter: &str) -> Vec<String> {
+        let mut topics: Vec<String> = store
+            .matching(&filter.try_into().unwrap())
+            .into_iter()
+            .map(|message| message.topic.into())
+            .collect();
+        topics.sort_unstable();
+        topics
+    }
+
+    #[test]
+    fn matching_filters() {
+        let mut store = InMemoryRetainStore::new();
+        for topic in [
+            "sport",
+            "sport/tennis",
+            "sport/tennis/player1",
+            "/finance",
+            "$SYS/monitor",
+        ] {
+            store.set(message(topic));
+        }
+        assert_eq!(store.len(), 5);
+
+        assert_eq!(
+            matching(&store, "sport/#"),
+            vec!["sport", "sport/tennis", "sport/tennis/player1"]
+        );
+        assert_eq!(matching(&store, "sport/+"), vec!["sport/tennis"]);
+        assert_eq!(matching(&store, "+/+"), vec!["/finance", "sport/tennis"]);
+        assert_eq!(matching(&store, "#").len(), 4);
+        assert_eq!(matching(&store, "$SYS/#"), vec!["$SYS/monitor"]);
+        assert!(matching(&store, "$share/group/sport/#").is_empty());
+
+        for filter in ["sport/#", "#", "+/tennis/#"] {
+            let filter: TopicFilter = filter.try_into().unwrap();
+            for message in store.matching(&filter) {
+                assert!(filter.matches(&message.topic));
+            }
+        }
+    }
+
+    #[test]
+    fn retain() {
+        let mut store = InMemoryRetainStore::new();
+        store.retain(message("sport/tennis"));
+        store.retain(Message {
+            payload: "updated".into(),
+            ..message("sport/tennis")
+        });
+        assert_eq!(store.len(), 1);
+        assert_eq!(
+            store.matching(&"sport/tennis".try_into().unwrap())[0].payload,
+            b"updated"
+        );
+
+        store.retain(Message {
+            payload: Vec::new(),
+            ..message("sport/tennis")
+        });
+        assert!(store.is_empty());
+        assert!(store.clear(&"sport/tennis".try_into().unwrap()).is_none());
+    }
+
+    #[test]
+    fn retain_handling() {
+        let mut store = InMemoryRetainStore::new();
+        store.retain(Message {
+            qos: QoS::ExactlyOnce,
+            ..message("sport/tennis")
+        });
+        let subscription = |retain_handling, qos| Subscription {
+            filter: "sport/#".try_into().unwrap(),
+            options: SubscriptionOptions {
+                qos,
+                retain_handling,
+                ..Default::default()
+            },
+            identifier: None,
+        };
+
+        let sent = store.on_subscribe(
+            &subscription(RetainHandling::OnSubscribe, QoS::AtLeastOnce),
+            false,
+        );
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].retain);
+        assert_eq!(sent[0].qos, QoS::AtLeastOnce);
+
+        let on_first = subscription(RetainHandling::OnFirstSubscribe, QoS::ExactlyOnce);
+        assert_eq!(store.on_subscribe(&on_first, true).len(), 1);
+        assert!(store.on_subscribe(&on_first, false).is_empty());
+        let dont_send = subscription(RetainHandling::DontSend, QoS::ExactlyOnce);
+        assert!(store.on_subscribe(&dont_send, true).is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{OutgoingDelivery, Packet, PubRel, Publish, QoS, SessionState};
+use std::collections::VecDeque;
+
+/// The packets to send again when a session is resumed, that is when a
+/// `ConnAck` packet is received with `session_present` set.
+/// The outgoing deliveries of the session are resent in the order they were
+/// started:
+/// - unacknowledged messages as `Publish` packets with the `duplicate` flag
+///   set and their original packet identifier,
+/// - released messages as `PubRel` packets.
+///
+/// Topic aliases are never used since they do not outlive a connection, and
+/// `AtMostOnce` messages are never resent.
+#[derive(Debug, Default, Clone)]
+pub struct RetransmitQueue {
+    packets: VecDeque<Packet>,
+}
+
+impl RetransmitQueue {
+    /// Builds the queue of the packets to resend from a restored session.
+    pub fn new(state: &SessionState) -> Self {
+        let packets = state
+            .outgoing
+            .iter()
+            .filter_map(|delivery| match delivery {
+                OutgoingDelivery::Unacknowledged {
+                    packet_identifier,
+                    message,
+                } if message.qos != QoS::AtMostOnce => Some(
+                    Publish {
+                        duplicate: true,
+                        ..message.clone().into_publish(Some(*packet_identifier), None)
+                    }
+                    .into(),
+                ),
+                OutgoingDelivery::Unacknowledged { .. } => None,
+                OutgoingDelivery::Released { packet_identifier } => Some(
+                    PubRel {
+                        packet_identifier: *packet_identifier,
+                        ..Default::default()
+                    }
+                    .into(),
+                ),
+            })
+            .collect();
+        RetransmitQueue { packets }
+    }
+
+    /// Returns the number of packets left to send.
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Returns `true` if there is no packet left to send.
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+}
+
+impl From<&SessionState> for RetransmitQueue {
+    fn from(state: &SessionState) -> Self {
+        RetransmitQueue::new(state)
+    }
+}
+
+impl Iterator for RetransmitQueue {
+    type Item = Packet;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.packets.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::Message;
+
+    fn message(qos: QoS) -> Message {
+        Message {
+            topic: "Around the World".try_into().unwrap(),
+            payload: "Harder, Better, Faster, Stronger".into(),
+            qos,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sequence() {
+        let state = SessionState {
+            outgoing: vec![
+                OutgoingDelivery::Released {
+                    packet_identifier: 3,
+                },
+                OutgoingDelivery::Unacknowledged {
+                    packet_identifier: 4,
+                    message: message(QoS::ExactlyOnce),
+                },
+                OutgoingDelivery::Unacknowledged {
+                    packet_identifier: 5,
+                    message: message(QoS::AtMostOnce),
+                },
+                OutgoingDelivery::Unacknowledged {
+                    packet_identifier: 6,
+                    message: message(QoS::AtLeastOnce),
+                },
+            ],
+            ..Default::default()
+        };
+
+        let queue = RetransmitQueue::new(&state);
+        assert_eq!(queue.len(), 3);
+        let packets: Vec<Packet> = queue.collect();
+        assert_eq!(
+            packets,
+            vec![
+                PubRel {
+                    packet_identifier: 3,
+                    ..Default::default()
+                }
+                .into(),
+                Publish {
+                    duplicate: true,
+                    ..message(QoS::ExactlyOnce).into_publish(Some(4), None)
+                }
+                .into(),
+                Publish {
+                    duplicate: true,
+                    ..message(QoS::AtLeastOnce).into_publish(Some(6), None)
+                }
+                .into(),
+            ]
+        );
+        assert!(RetransmitQueue::from(&SessionState::default()).is_empty());
+    }
+}
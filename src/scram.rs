@@ -0,0 +1,348 @@
+use crate::{
+    Authenticator,
+    ReasonCode::{NotAuthorized, ProtocolError},
+    Result as SageResult,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::io::Error as IOError;
+
+/// The name of the `SCRAM-SHA-256` authentication method.
+pub const SCRAM_SHA_256: &str = "SCRAM-SHA-256";
+
+/// The iteration count of the credentials made up for the unknown users.
+const DEFAULT_ITERATIONS: u32 = 4096;
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// The salted verifiers of a user, which a server stores instead of its
+/// password to authenticate it with `SCRAM-SHA-256`, as described by
+/// RFC 5802 and RFC 7677.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct ScramCredentials {
+    /// The salt the password is hashed with.
+    pub salt: Vec<u8>,
+
+    /// The number of iterations of the hash.
+    pub iterations: u32,
+
+    /// The hash of the client key, which verifies the proof of the client.
+    pub stored_key: [u8; 32],
+
+    /// The key the server proves it knows the password with.
+    pub server_key: [u8; 32],
+}
+
+impl ScramCredentials {
+    /// Computes the verifiers of `password`, hashed with `salt` over
+    /// `iterations` iterations.
+    pub fn new(password: &[u8], salt: Vec<u8>, iterations: u32) -> Self {
+        let mut salted_password = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password, &salt, iterations, &mut salted_password);
+        let client_key = hmac_sha256(&salted_password, b"Client Key");
+        ScramCredentials {
+            salt,
+            iterations,
+            stored_key: Sha256::digest(client_key).into(),
+            server_key: hmac_sha256(&salted_password, b"Server Key"),
+        }
+    }
+}
+
+/// The outcome of the authentication data of a client fed into a
+/// `ScramServer`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ScramStep {
+    /// The data to send to the client with `ServerConnection::challenge`.
+    Challenge(Vec<u8>),
+
+    /// The client is authenticated as `user_name`. `data` is to be sent with
+    /// `ServerConnection::connack`, or with
+    /// `ServerConnection::reauthenticated` for a re-authentication.
+    Authenticated {
+        /// The authenticated user name.
+        user_name: String,
+
+        /// The final message of the server.
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum State {
+    Initial,
+    Challenged {
+        user_name: String,
+        credentials: Option<ScramCredentials>,
+        gs2_header: String,
+        nonce: String,
+        auth_message: String,
+    },
+    Done,
+}
+
+/// The server side of the `SCRAM-SHA-256` enhanced authentication method,
+/// without any I/O.
+///
+/// The authentication data of the `Connect` packet, or of the `Auth` packet
+/// starting a re-authentication, is fed into `receive` along with the
+/// `Authenticator` storing the credentials of the users, and so are the data
+/// of each `ServerEvent::AuthChallenge` that follows. A new `ScramServer` is
+/// needed for each exchange.
+///
+/// Channel binding is not supported. An unknown user is only refused once
+/// its proof is received, as if its password was wrong, so that the
+/// existence of users is not disclosed.
+#[derive(Debug, Clone)]
+pub struct ScramServer {
+    state: State,
+}
+
+impl Default for ScramServer {
+    fn default() -> Self {
+        ScramServer {
+            state: State::Initial,
+        }
+    }
+}
+
+impl ScramServer {
+    /// Creates a server waiting for the first message of the client.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Handles the authentication data of the client.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the data is not the expected SCRAM message,
+    /// or `NotAuthorized` if the client does not prove it knows the password
+    /// of the user. The exchange is then to be ended with
+    /// `ServerConnection::fail`.
+    pub fn receive<A: Authenticator + ?Sized>(
+        &mut self,
+        authenticator: &A,
+        data: &[u8],
+    ) -> SageResult<ScramStep> {
+        let message = std::str::from_utf8(data).map_err(|_| ProtocolError)?;
+        match std::mem::replace(&mut self.state, State::Done) {
+            State::Initial => self.client_first(authenticator, message),
+            State::Challenged {
+                user_name,
+                credentials,
+                gs2_header,
+                nonce,
+                auth_message,
+            } => {
+                let (without_proof, proof) = message.rsplit_once(",p=").ok_or(ProtocolError)?;
+                let mut attributes = without_proof.split(',');
+                let channel_binding = attributes
+                    .next()
+                    .and_then(|c| c.strip_prefix("c="))
+                    .ok_or(ProtocolError)?;
+                let client_nonce = attributes
+                    .next()
+                    .and_then(|r| r.strip_prefix("r="))
+                    .ok_or(ProtocolError)?;
+                if channel_binding != BASE64.encode(&gs2_header) || client_nonce != nonce {
+                    return Err(ProtocolError.into());
+                }
+                let proof = BASE64.decode(proof).map_err(|_| ProtocolError)?;
+
+                let credentials = credentials.ok_or(NotAuthorized)?;
+                let auth_message = format!("{},{}", auth_message, without_proof);
+                let signature = hmac_sha256(&credentials.stored_key, auth_message.as_bytes());
+                if proof.len() != signature.len() {
+                    return Err(NotAuthorized.into());
+                }
+                let client_key: Vec<u8> = proof
+                    .iter()
+                    .zip(signature.iter())
+                    .map(|(p, s)| p ^ s)
+                    .collect();
+                if Sha256::digest(client_key)[..] != credentials.stored_key[..] {
+                    return Err(NotAuthorized.into());
+                }
+                let verifier = hmac_sha256(&credentials.server_key, auth_message.as_bytes());
+                Ok(ScramStep::Authenticated {
+                    user_name,
+                    data: format!("v={}", BASE64.encode(verifier)).into_bytes(),
+                })
+            }
+            State::Done => Err(ProtocolError.into()),
+        }
+    }
+
+    fn client_first<A: Authenticator + ?Sized>(
+        &mut self,
+        authenticator: &A,
+        message: &str,
+    ) -> SageResult<ScramStep> {
+        // The GS2 header is made of the channel binding flag and the optional
+        // authorization identity, each followed by a comma.
+        let mut parts = message.splitn(3, ',');
+        let (flag, authzid, bare) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(flag), Some(authzid), Some(bare)) => (flag, authzid, bare),
+            _ => return Err(ProtocolError.into()),
+        };
+        if !matches!(flag, "n" | "y") {
+            return Err(ProtocolError.into());
+        }
+        let gs2_header = format!("{},{},", flag, authzid);
+
+        let mut attributes = bare.split(',');
+        let user_name = attributes
+            .next()
+            .and_then(|n| n.strip_prefix("n="))
+            .ok_or(ProtocolError)?
+            .replace("=2C", ",")
+            .replace("=3D", "=");
+        let client_nonce = attributes
+            .next()
+            .and_then(|r| r.strip_prefix("r="))
+            .filter(|r| !r.is_empty())
+            .ok_or(ProtocolError)?;
+
+        let mut random = [0; 18];
+        getrandom::getrandom(&mut random).map_err(|e| IOError::other(e.to_string()))?;
+        let nonce = format!("{}{}", client_nonce, BASE64.encode(random));
+
+        let credentials = authenticator.scram_credentials(&user_name);
+        let (salt, iterations) = match &credentials {
+            Some(credentials) => (credentials.salt.clone(), credentials.iterations),
+            None => {
+                let salt = Sha256::digest(format!("{}:{}", SCRAM_SHA_256, user_name));
+                (salt[..16].to_vec(), DEFAULT_ITERATIONS)
+            }
+        };
+        let server_first = format!("r={},s={},i={}", nonce, BASE64.encode(salt), iterations);
+        self.state = State::Challenged {
+            user_name,
+            credentials,
+            gs2_header,
+            nonce,
+            auth_message: format!("{},{}", bare, server_first),
+        };
+        Ok(ScramStep::Challenge(server_first.into_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use std::collections::HashMap;
+
+    fn users() -> HashMap<String, ScramCredentials> {
+        let mut users = HashMap::new();
+        users.insert(
+            "thomas".into(),
+            ScramCredentials::new(b"Harder", b"Around the World".to_vec(), 4096),
+        );
+        users
+    }
+
+    // Runs the client side of the exchange, returning the final message of
+    // the client and the expected final message of the server.
+    fn client_final(
+        password: &[u8],
+        client_first_bare: &str,
+        server_first: &str,
+    ) -> (String, String) {
+        let mut attributes = server_first.split(',');
+        let nonce = attributes.next().unwrap().strip_prefix("r=").unwrap();
+        let salt = BASE64
+            .decode(attributes.next().unwrap().strip_prefix("s=").unwrap())
+            .unwrap();
+        let iterations = attributes
+            .next()
+            .unwrap()
+            .strip_prefix("i=")
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let mut salted_password = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password, &salt, iterations, &mut salted_password);
+        let client_key = hmac_sha256(&salted_password, b"Client Key");
+        let stored_key = Sha256::digest(client_key);
+        let without_proof = format!("c=biws,r={}", nonce);
+        let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+        let signature = hmac_sha256(&stored_key, auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(signature.iter())
+            .map(|(k, s)| k ^ s)
+            .collect();
+        let server_signature = hmac_sha256(
+            &hmac_sha256(&salted_password, b"Server Key"),
+            auth_message.as_bytes(),
+        );
+        (
+            format!("{},p={}", without_proof, BASE64.encode(proof)),
+            format!("v={}", BASE64.encode(server_signature)),
+        )
+    }
+
+    fn authenticate(user_name: &str, password: &[u8]) -> SageResult<ScramStep> {
+        let users = users();
+        let mut server = ScramServer::new();
+        let client_first_bare = format!("n={},r=fyko+d2lbbFgONRv9qkxdawL", user_name);
+        let server_first =
+            match server.receive(&users, format!("n,,{}", client_first_bare).as_bytes())? {
+                ScramStep::Challenge(data) => String::from_utf8(data).unwrap(),
+                step => panic!("unexpected step {:?}", step),
+            };
+        assert!(server_first.starts_with("r=fyko+d2lbbFgONRv9qkxdawL"));
+        let (client_final, server_final) =
+            client_final(password, &client_first_bare, &server_first);
+        let step = server.receive(&users, client_final.as_bytes())?;
+        assert_eq!(
+            step,
+            ScramStep::Authenticated {
+                user_name: user_name.into(),
+                data: server_final.into_bytes(),
+            }
+        );
+        Ok(step)
+    }
+
+    #[test]
+    fn exchange() {
+        assert!(authenticate("thomas", b"Harder").is_ok());
+        assert!(matches!(
+            authenticate("thomas", b"Better"),
+            Err(crate::Error::Reason(NotAuthorized))
+        ));
+        assert!(matches!(
+            authenticate("guy", b"Harder"),
+            Err(crate::Error::Reason(NotAuthorized))
+        ));
+    }
+
+    #[test]
+    fn malformed() {
+        let users = users();
+        for data in [
+            &b"n=thomas,r=abc"[..],
+            b"p=tls-unique,,n=thomas,r=abc",
+            b"n,,r=abc",
+        ] {
+            assert!(matches!(
+                ScramServer::new().receive(&users, data),
+                Err(crate::Error::Reason(ProtocolError))
+            ));
+        }
+        let mut server = ScramServer::new();
+        server.receive(&users, b"n,,n=thomas,r=abc").unwrap();
+        assert!(server.receive(&users, b"c=biws,r=abc,p=AAAA").is_err());
+        assert!(server.receive(&users, b"n,,n=thomas,r=abc").is_err());
+    }
+}
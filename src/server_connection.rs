@@ -0,0 +1,1585 @@
+use crate::{
+    deliveries::{Delivered, Deliveries},
+    AuthFlow, AuthStep, Authentication, ConnAck, Connect, Disconnect, InterceptorChain, KeepAlive,
+    Metrics, Packet, PubAck, PubRec, Publish, QoS,
+    ReasonCode::{
+        self, BadAuthenticationMethod, BadUserNameOrPassword, KeepAliveTimeout, NotAuthorized,
+        ProtocolError, QoSNotSupported, ReceiveMaximumExceeded, RetainNotSupported,
+        ServerShuttingDown, ServerUnavailable, SharedSubscriptionsNotSupported,
+        SubscriptionIdentifiersNotSupported, Success, UnspecifiedError,
+        WildcardSubscriptionsNotSupported,
+    },
+    Result as SageResult, SessionState, SubAck, Subscribe, UnSubAck, UnSubscribe, Will,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// An event of a `ServerConnection` to be handled by the embedding broker.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ServerEvent {
+    /// A client asks to connect. The broker must answer with
+    /// `ServerConnection::connack`, or with `ServerConnection::challenge` to
+    /// continue an enhanced authentication.
+    Connect(Connect),
+
+    /// The client sent authentication data, either to continue an enhanced
+    /// authentication or to start a re-authentication. The broker must answer
+    /// with `ServerConnection::challenge`, `ServerConnection::connack` upon
+    /// connection, `ServerConnection::reauthenticated` or
+    /// `ServerConnection::fail`.
+    AuthChallenge(Vec<u8>),
+
+    /// A message was received from the client. `ExactlyOnce` messages are
+    /// only delivered once, even if the client sends them again.
+    Message(Publish),
+
+    /// The delivery of an outgoing message ended, with the reason code given
+    /// by the client.
+    Acknowledged {
+        /// The packet identifier of the delivered `Publish` packet.
+        packet_identifier: u16,
+
+        /// The reason code of the acknowledgement.
+        reason_code: ReasonCode,
+    },
+
+    /// The client subscribes to topics. The broker must answer with
+    /// `ServerConnection::suback`.
+    Subscribe(Subscribe),
+
+    /// The client unsubscribes from topics. The broker must answer with
+    /// `ServerConnection::unsuback`.
+    UnSubscribe(UnSubscribe),
+
+    /// The connection is closed, either by the client or by the server upon
+    /// a protocol violation, a keep alive timeout, a refused `Connect` packet
+    /// or a call to `disconnect`. The `Disconnect` packet is the one sent by
+    /// either side, one with the reason code of the refusal, or one with
+    /// `UnspecifiedError` if the network connection was lost.
+    Disconnected(Disconnect),
+
+    /// The connection of the client was closed otherwise than by a
+    /// `Disconnect` packet with `Success` sent by the client, and its will
+    /// message must be published, once its Will Delay Interval elapses, such
+    /// as with `SessionStore::schedule_will`. It follows `Disconnected`.
+    Will(Will),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Initial,
+    Connecting,
+    Connected,
+    Closed,
+}
+
+/// The server side of an MQTT connection, without any I/O.
+///
+/// Like `ClientConnection`, it consumes the packets decoded from the network
+/// with `handle_packet` and the passing of time with `handle_timeout`, and
+/// produces the packets to send with `poll_transmit` and the events of the
+/// broker with `poll_event`. Decisions, such as accepting a connection or a
+/// subscription, are left to the broker which is notified with a
+/// `ServerEvent` and answers using the dedicated methods.
+///
+/// The connection ensures the first packet is a `Connect` packet, drives the
+/// enhanced authentication, enforces the Receive Maximum, `Capabilities` and
+/// topic aliases advertised in the `ConnAck` packet, and closes the
+/// connection if the client does not respect its own keep alive. Its
+/// packets may be rewritten or refused by an `InterceptorChain`.
+#[derive(Debug)]
+pub struct ServerConnection {
+    state: State,
+    connect: Connect,
+    connack: ConnAck,
+    auth: Option<AuthFlow>,
+    // Created when the connection is accepted.
+    keep_alive: Option<KeepAlive>,
+    deliveries: Deliveries,
+    transmit: VecDeque<Packet>,
+    events: VecDeque<ServerEvent>,
+    metrics: Option<Metrics>,
+    published_at: HashMap<u16, (QoS, Instant)>,
+    interceptors: InterceptorChain,
+    shutdown_at: Option<Instant>,
+}
+
+impl Default for ServerConnection {
+    fn default() -> Self {
+        ServerConnection {
+            state: State::Initial,
+            connect: Default::default(),
+            connack: Default::default(),
+            auth: None,
+            keep_alive: None,
+            deliveries: Default::default(),
+            transmit: VecDeque::new(),
+            events: VecDeque::new(),
+            metrics: None,
+            published_at: HashMap::new(),
+            interceptors: InterceptorChain::new(),
+            shutdown_at: None,
+        }
+    }
+}
+
+impl ServerConnection {
+    /// Creates a connection waiting for the `Connect` packet of a client.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Creates a connection to a session restored from `state`, such as
+    /// returned by `SessionStore::connect`. Its outgoing deliveries are
+    /// resumed if the connection is accepted with `session_present`, and
+    /// discarded otherwise.
+    pub fn with_session(state: &SessionState) -> Self {
+        let mut connection = Self::new();
+        connection.deliveries.restore(state);
+        connection
+    }
+
+    /// Records the activity of the connection into `metrics`: its packets,
+    /// its acceptance and closing, its authentication failures and the
+    /// latency of its outgoing deliveries.
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Runs the packets received from the client and the messages published
+    /// to it through `interceptors`.
+    pub fn set_interceptors(&mut self, interceptors: InterceptorChain) {
+        self.interceptors = interceptors;
+    }
+
+    // The client id of the connection, for tracing.
+    #[cfg(feature = "tracing")]
+    fn client_id(&self) -> &str {
+        match &self.connect.client_id {
+            Some(client_id) if !client_id.is_empty() => client_id,
+            _ => self
+                .connack
+                .assigned_client_id
+                .as_deref()
+                .unwrap_or_default(),
+        }
+    }
+
+    fn record<F: FnOnce(&Metrics)>(&self, f: F) {
+        if let Some(metrics) = &self.metrics {
+            f(metrics);
+        }
+    }
+
+    /// Returns the state of the deliveries of the connection, to save into
+    /// its session such as with `SessionStore::disconnect`. The
+    /// subscriptions are left empty, since they are recorded by the broker.
+    pub fn session_state(&self) -> SessionState {
+        self.deliveries.session_state()
+    }
+
+    /// Returns the number of `AtLeastOnce` and `ExactlyOnce` messages sent to
+    /// the client and not acknowledged yet, which never exceeds its Receive
+    /// Maximum.
+    pub fn in_flight(&self) -> usize {
+        self.deliveries.in_flight()
+    }
+
+    /// Returns the number of messages published to the client and held until
+    /// its Receive Maximum allows them to be sent.
+    pub fn queued(&self) -> usize {
+        self.deliveries.queued()
+    }
+
+    /// Returns `true` once the connection was accepted, until it is closed.
+    pub fn is_connected(&self) -> bool {
+        self.state == State::Connected
+    }
+
+    /// Returns `true` if the connection is closed.
+    pub fn is_closed(&self) -> bool {
+        self.state == State::Closed
+    }
+
+    /// Returns the next packet to send to the client.
+    pub fn poll_transmit(&mut self) -> Option<Packet> {
+        self.transmit.pop_front()
+    }
+
+    /// Returns the next event to handle by the broker.
+    pub fn poll_event(&mut self) -> Option<ServerEvent> {
+        self.events.pop_front()
+    }
+
+    /// Returns the keep alive of the connection once accepted: the Server Keep
+    /// Alive of the `ConnAck` packet if any, such as given by
+    /// `KeepAlive::server_keep_alive`, or the one of the client otherwise.
+    /// It is `None` if the keep alive is disabled.
+    pub fn keep_alive(&self) -> Option<Duration> {
+        self.keep_alive.as_ref().and_then(KeepAlive::keep_alive)
+    }
+
+    /// Returns the next time `handle_timeout` must be called at, if any.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        if self.state != State::Connected {
+            return None;
+        }
+        let timeout = self
+            .keep_alive
+            .as_ref()
+            .and_then(KeepAlive::timeout_deadline);
+        match (timeout, self.shutdown_at) {
+            (Some(timeout), Some(shutdown)) => Some(timeout.min(shutdown)),
+            (timeout, shutdown) => timeout.or(shutdown),
+        }
+    }
+
+    fn send<P: Into<Packet>>(&mut self, packet: P, now: Instant) {
+        let packet = packet.into();
+        #[cfg(feature = "tracing")]
+        tracing::trace!(client_id = self.client_id(), %packet, "sending packet");
+        self.record(|metrics| metrics.packet_sent(&packet));
+        if let Some(keep_alive) = &mut self.keep_alive {
+            keep_alive.sent(now);
+        }
+        self.transmit.push_back(packet);
+    }
+
+    fn close(&mut self, reason_code: ReasonCode, now: Instant) {
+        let disconnect = Disconnect {
+            reason_code,
+            ..Default::default()
+        };
+        match self.state {
+            State::Connecting => self.send(ConnAck::rejection(reason_code), now),
+            State::Connected => self.send(disconnect.clone(), now),
+            State::Initial | State::Closed => (),
+        }
+        self.closed(disconnect, true);
+    }
+
+    /// Closes the connection once `disconnect` was sent or received. The will
+    /// message is only published if the connection was accepted.
+    fn closed(&mut self, disconnect: Disconnect, publish_will: bool) {
+        if self.state == State::Closed {
+            return;
+        }
+        let connected = self.state == State::Connected;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            client_id = self.client_id(),
+            reason_code = ?disconnect.reason_code,
+            connected,
+            "connection closed"
+        );
+        if connected {
+            self.record(Metrics::connection_closed);
+        }
+        self.state = State::Closed;
+        self.events.push_back(ServerEvent::Disconnected(disconnect));
+        if connected && publish_will {
+            self.publish_will();
+        }
+    }
+
+    fn publish_will(&mut self) {
+        if let Some(will) = self.connect.will.take() {
+            self.events.push_back(ServerEvent::Will(will));
+        }
+    }
+
+    /// Answers the `Connect` packet of the client. If the reason code of
+    /// `connack` is an error, the connection is refused and closed.
+    /// Otherwise the connection is accepted, ending the enhanced
+    /// authentication if any. In that case, the authentication data of
+    /// `connack` are the last data sent to the client.
+    /// If `session_present` is set, the deliveries of the previous connection
+    /// are resumed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if no `Connect` packet is waiting for an
+    /// answer or if `connack` is not valid. Returns `QoSNotSupported` or
+    /// `RetainNotSupported` if the will message of the client exceeds the
+    /// capabilities advertised in `connack`, after refusing the connection
+    /// with that reason code.
+    pub fn connack(&mut self, mut connack: ConnAck, now: Instant) -> SageResult<()> {
+        if self.state != State::Connecting {
+            return Err(ProtocolError.into());
+        }
+        connack.validate()?;
+
+        if connack.reason_code.is_error() {
+            if matches!(
+                connack.reason_code,
+                BadUserNameOrPassword | NotAuthorized | BadAuthenticationMethod
+            ) {
+                self.record(Metrics::authentication_failed);
+            }
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                client_id = self.client_id(),
+                reason_code = ?connack.reason_code,
+                "connection refused"
+            );
+            let disconnect = Disconnect {
+                reason_code: connack.reason_code,
+                ..Default::default()
+            };
+            self.send(connack, now);
+            self.closed(disconnect, false);
+            return Ok(());
+        }
+
+        // The will message must not exceed the advertised capabilities.
+        let will_refusal = match &self.connect.will {
+            Some(will) if will.qos > connack.maximum_qos => Some(QoSNotSupported),
+            Some(will) if will.retain && !connack.retain_available => Some(RetainNotSupported),
+            _ => None,
+        };
+        if let Some(reason_code) = will_refusal {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                client_id = self.client_id(),
+                reason_code = ?reason_code,
+                "connection refused for a will message exceeding the capabilities"
+            );
+            self.close(reason_code, now);
+            return Err(reason_code.into());
+        }
+
+        if let Some(auth) = &mut self.auth {
+            let data = connack
+                .authentication
+                .take()
+                .map(|authentication| authentication.data)
+                .unwrap_or_default();
+            auth.succeed(data.clone())?;
+            connack.authentication = Some(Authentication {
+                method: auth.method().into(),
+                data,
+            });
+        }
+
+        self.state = State::Connected;
+        self.record(Metrics::connection_opened);
+        self.keep_alive = Some(KeepAlive::new(
+            connack.keep_alive.unwrap_or(self.connect.keep_alive),
+            now,
+        ));
+        let packets = self.deliveries.connected(
+            connack.session_present,
+            self.connect.receive_maximum,
+            self.connect.topic_alias_maximum,
+            connack.topic_alias_maximum,
+        )?;
+        self.connack = connack.clone();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            client_id = self.client_id(),
+            session_present = connack.session_present,
+            "connection accepted"
+        );
+        self.send(connack, now);
+        for packet in packets {
+            self.send(packet, now);
+        }
+        Ok(())
+    }
+
+    /// Sends authentication data to the client, to continue an enhanced
+    /// authentication or a re-authentication.
+    pub fn challenge(&mut self, data: Vec<u8>, now: Instant) -> SageResult<()> {
+        let packet = self.auth.as_mut().ok_or(ProtocolError)?.respond(data)?;
+        self.send(packet, now);
+        Ok(())
+    }
+
+    /// Ends a re-authentication successfully, sending the last
+    /// authentication data to the client.
+    pub fn reauthenticated(&mut self, data: Vec<u8>, now: Instant) -> SageResult<()> {
+        if self.state != State::Connected {
+            return Err(ProtocolError.into());
+        }
+        let packet = self.auth.as_mut().ok_or(ProtocolError)?.succeed(data)?;
+        self.send(packet, now);
+        Ok(())
+    }
+
+    /// Ends an enhanced authentication or a re-authentication with an error,
+    /// closing the connection.
+    pub fn fail(&mut self, reason_code: ReasonCode, now: Instant) -> SageResult<()> {
+        let packet = self.auth.as_mut().ok_or(ProtocolError)?.fail(reason_code)?;
+        let disconnect = match &packet {
+            Packet::Disconnect(disconnect) => disconnect.clone(),
+            _ => Disconnect {
+                reason_code,
+                ..Default::default()
+            },
+        };
+        self.record(Metrics::authentication_failed);
+        self.send(packet, now);
+        self.closed(disconnect, true);
+        Ok(())
+    }
+
+    /// Returns the maximum packet size advertised to the client in the
+    /// `ConnAck` packet, if any. It is to be set in the `DecodeOptions` of the
+    /// packets received from the client, closing the connection with a
+    /// `PacketTooLarge` `Disconnect` packet upon a larger one.
+    pub fn maximum_packet_size(&self) -> Option<u32> {
+        self.connack.maximum_packet_size
+    }
+
+    // Returns `true` if `publish` does not exceed the maximum packet size of
+    // the client, once given a packet identifier.
+    fn fits(&self, publish: &Publish) -> SageResult<bool> {
+        let maximum_packet_size = match self.connect.maximum_packet_size {
+            Some(maximum_packet_size) => maximum_packet_size as usize,
+            None => return Ok(true),
+        };
+        let packet_identifier = match publish.qos {
+            QoS::AtMostOnce => None,
+            _ => Some(u16::MAX),
+        };
+        let packet = Packet::from(Publish {
+            packet_identifier,
+            ..publish.clone()
+        });
+        Ok(packet.encode_vec()?.len() <= maximum_packet_size)
+    }
+
+    /// Publishes a message to the client. `AtLeastOnce` and `ExactlyOnce`
+    /// messages are given a packet identifier, returned if any, and may be
+    /// held until the Receive Maximum of the client allows them to be sent.
+    /// A message exceeding the maximum packet size of the client is
+    /// discarded, as if it was delivered, and `None` is returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the connection is not established,
+    /// `ServerShuttingDown` if it is being shut down, or the error of the
+    /// interceptor refusing the message.
+    pub fn publish(&mut self, publish: Publish, now: Instant) -> SageResult<Option<u16>> {
+        if self.state != State::Connected {
+            return Err(ProtocolError.into());
+        }
+        if self.shutdown_at.is_some() {
+            return Err(ServerShuttingDown.into());
+        }
+        let publish = self.interceptors.outgoing(publish)?;
+        if !self.fits(&publish)? {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                client_id = self.client_id(),
+                topic = %publish.topic_name,
+                "discarding a message exceeding the maximum packet size of the client"
+            );
+            return Ok(None);
+        }
+        let qos = publish.qos;
+        let (packet_identifier, packet) = self.deliveries.publish(publish)?;
+        if let (Some(packet_identifier), Some(_)) = (packet_identifier, &self.metrics) {
+            self.published_at.insert(packet_identifier, (qos, now));
+        }
+        if let Some(packet) = packet {
+            self.send(packet, now);
+        }
+        Ok(packet_identifier)
+    }
+
+    /// Resolves the topic alias of a `Publish` packet received from the
+    /// client, so that its topic is known before the packet is handed to
+    /// `handle_packet`, such as to authorize it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TopicAliasInvalid` or `ProtocolError` if the topic cannot be
+    /// resolved, after closing the connection.
+    pub fn resolve_topic(&mut self, publish: &mut Publish, now: Instant) -> SageResult<()> {
+        if self.state != State::Connected {
+            return Err(ProtocolError.into());
+        }
+        if let Err(error) = self.deliveries.resolve(publish) {
+            let reason_code = ReasonCode::from(error);
+            self.close(reason_code, now);
+            return Err(reason_code.into());
+        }
+        Ok(())
+    }
+
+    /// Refuses a `Publish` packet received from the client with
+    /// `reason_code`, such as `NotAuthorized`, instead of handing it to
+    /// `handle_packet`. `AtLeastOnce` and `ExactlyOnce` messages are answered
+    /// with a `PubAck` or `PubRec` packet carrying `reason_code`, which ends
+    /// their delivery, and `AtMostOnce` ones are silently dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProtocolError` if the connection is not established, or if
+    /// `reason_code` is not an error allowed in the acknowledgement.
+    pub fn refuse(
+        &mut self,
+        publish: &Publish,
+        reason_code: ReasonCode,
+        now: Instant,
+    ) -> SageResult<()> {
+        if self.state != State::Connected || !reason_code.is_error() {
+            return Err(ProtocolError.into());
+        }
+        if let Some(keep_alive) = &mut self.keep_alive {
+            keep_alive.received(now);
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            client_id = self.client_id(),
+            topic = %publish.topic_name,
+            reason_code = ?reason_code,
+            "refusing a message"
+        );
+        match publish.qos {
+            QoS::AtMostOnce => (),
+            QoS::AtLeastOnce => self.send(PubAck::for_publish(publish, reason_code)?, now),
+            QoS::ExactlyOnce => self.send(PubRec::for_publish(publish, reason_code)?, now),
+        }
+        Ok(())
+    }
+
+    /// Answers a `ServerEvent::Subscribe`.
+    pub fn suback(&mut self, suback: SubAck, now: Instant) -> SageResult<()> {
+        if self.state != State::Connected {
+            return Err(ProtocolError.into());
+        }
+        self.send(suback, now);
+        Ok(())
+    }
+
+    /// Answers a `ServerEvent::UnSubscribe`.
+    pub fn unsuback(&mut self, unsuback: UnSubAck, now: Instant) -> SageResult<()> {
+        if self.state != State::Connected {
+            return Err(ProtocolError.into());
+        }
+        self.send(unsuback, now);
+        Ok(())
+    }
+
+    /// Closes the connection, sending `disconnect`. The will message of the
+    /// client is published, as with any disconnection by the server.
+    pub fn disconnect(&mut self, disconnect: Disconnect, now: Instant) {
+        if self.state == State::Connected {
+            self.send(disconnect.clone(), now);
+        }
+        self.closed(disconnect, true);
+    }
+
+    /// Starts closing the connection because the server shuts down. An
+    /// established connection is closed with `ServerShuttingDown` once the
+    /// deliveries in flight in both directions completed, or at `deadline`
+    /// at the latest, and no new message can be published to the client
+    /// meanwhile. A connection which is not established yet is refused with
+    /// `ServerUnavailable` right away.
+    pub fn shutdown(&mut self, deadline: Instant, now: Instant) {
+        match self.state {
+            State::Connected => {
+                self.shutdown_at = Some(deadline);
+                self.check_shutdown(now);
+            }
+            State::Initial | State::Connecting => self.close(ServerUnavailable, now),
+            State::Closed => (),
+        }
+    }
+
+    // Closes a connection being shut down once it is drained or its deadline
+    // passed.
+    fn check_shutdown(&mut self, now: Instant) {
+        let deadline = match self.shutdown_at {
+            Some(deadline) if self.state == State::Connected => deadline,
+            _ => return,
+        };
+        let drained = self.deliveries.in_flight() == 0 && self.deliveries.incoming() == 0;
+        if drained || now >= deadline {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(client_id = self.client_id(), drained, "shutting down");
+            self.close(ServerShuttingDown, now);
+        }
+    }
+
+    /// Handles the loss of the network connection, closed without any
+    /// `Disconnect` packet. The will message of the client is published.
+    pub fn connection_lost(&mut self) {
+        if self.state != State::Closed {
+            let disconnect = Disconnect {
+                reason_code: UnspecifiedError,
+                ..Default::default()
+            };
+            self.closed(disconnect, true);
+        }
+    }
+
+    /// Handles the passing of time, closing the connection with
+    /// `KeepAliveTimeout` if nothing was received from the client for one and
+    /// a half times the keep alive, or with `ServerShuttingDown` once the
+    /// deadline of its shutdown passed.
+    pub fn handle_timeout(&mut self, now: Instant) {
+        let timed_out = self
+            .keep_alive
+            .as_ref()
+            .is_some_and(|keep_alive| keep_alive.is_timed_out(now));
+        if self.state == State::Connected && timed_out {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(client_id = self.client_id(), "keep alive timed out");
+            self.close(KeepAliveTimeout, now);
+        }
+        self.check_shutdown(now);
+    }
+
+    /// Handles a packet received from the client.
+    ///
+    /// # Errors
+    ///
+    /// If the packet is not legal at this point of the connection, the
+    /// connection is closed and the reason code of the violation is returned.
+    /// The reason code is sent to the client in a `ConnAck` packet before the
+    /// connection is accepted, or in a `Disconnect` packet afterwards.
+    pub fn handle_packet(&mut self, packet: Packet, now: Instant) -> SageResult<()> {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(client_id = self.client_id(), %packet, "received packet");
+        self.record(|metrics| metrics.packet_received(&packet));
+        if let Some(keep_alive) = &mut self.keep_alive {
+            keep_alive.received(now);
+        }
+        let result = match self.intercept(packet, now) {
+            Ok(Some(packet)) => match self.state {
+                State::Initial => self.handle_initial(packet),
+                State::Connecting => self.handle_connecting(packet),
+                State::Connected => self.handle_connected(packet, now),
+                State::Closed => Err(ProtocolError.into()),
+            },
+            Ok(None) => Ok(()),
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            let reason_code = ReasonCode::from(error);
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                client_id = self.client_id(),
+                reason_code = ?reason_code,
+                "invalid packet"
+            );
+            if self.state != State::Closed {
+                self.close(reason_code, now);
+            }
+            return Err(reason_code.into());
+        }
+        self.check_shutdown(now);
+        Ok(())
+    }
+
+    // Runs the interceptors on a packet received from the client, once the
+    // topic alias of a `Publish` packet is resolved. Returns `None` if the
+    // interceptors refused a message, which was answered accordingly.
+    fn intercept(&mut self, packet: Packet, now: Instant) -> SageResult<Option<Packet>> {
+        if self.interceptors.is_empty() || self.state == State::Closed {
+            return Ok(Some(packet));
+        }
+        match packet {
+            Packet::Publish(mut publish) if self.state == State::Connected => {
+                self.deliveries.resolve(&mut publish)?;
+                match self.interceptors.incoming(publish.clone().into()) {
+                    Ok(packet) => Ok(Some(packet)),
+                    Err(error) => {
+                        let reason_code = ReasonCode::from(error);
+                        self.refuse(&publish, reason_code, now)
+                            .map_err(|_| reason_code)?;
+                        Ok(None)
+                    }
+                }
+            }
+            packet => self.interceptors.incoming(packet).map(Some),
+        }
+    }
+
+    fn handle_initial(&mut self, packet: Packet) -> SageResult<()> {
+        let connect = match packet {
+            Packet::Connect(connect) => connect,
+            _ => return Err(ProtocolError.into()),
+        };
+        if let Some(authentication) = &connect.authentication {
+            let mut auth = AuthFlow::server(authentication.method.clone());
+            auth.accept(connect.clone().into())?;
+            self.auth = Some(auth);
+        }
+        self.state = State::Connecting;
+        self.connect = connect.clone();
+        self.events.push_back(ServerEvent::Connect(connect));
+        Ok(())
+    }
+
+    fn handle_connecting(&mut self, packet: Packet) -> SageResult<()> {
+        match (&mut self.auth, packet) {
+            (Some(auth), packet @ Packet::Auth(_)) => match auth.accept(packet)? {
+                AuthStep::Challenge(data) => {
+                    self.events.push_back(ServerEvent::AuthChallenge(data));
+                    Ok(())
+                }
+                _ => Err(ProtocolError.into()),
+            },
+            _ => Err(ProtocolError.into()),
+        }
+    }
+
+    fn check_publish(&self, publish: &Publish) -> SageResult<()> {
+        if publish.qos > self.connack.maximum_qos {
+            Err(QoSNotSupported.into())
+        } else if publish.retain && !self.connack.retain_available {
+            Err(RetainNotSupported.into())
+        } else if publish.qos == QoS::ExactlyOnce
+            && !publish.duplicate
+            && self.deliveries.incoming() >= self.connack.receive_maximum as usize
+        {
+            Err(ReceiveMaximumExceeded.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn handle_connected(&mut self, packet: Packet, now: Instant) -> SageResult<()> {
+        match packet {
+            Packet::Publish(_)
+            | Packet::PubAck(_)
+            | Packet::PubRec(_)
+            | Packet::PubRel(_)
+            | Packet::PubComp(_) => {
+                if let Packet::Publish(publish) = &packet {
+                    self.check_publish(publish)?;
+                }
+                let (packets, delivered) = self.deliveries.handle(packet)?;
+                for packet in packets {
+                    self.send(packet, now);
+                }
+                match delivered {
+                    Some(Delivered::Message(publish)) => {
+                        self.events.push_back(ServerEvent::Message(publish))
+                    }
+                    Some(Delivered::Acknowledged {
+                        packet_identifier,
+                        reason_code,
+                    }) => {
+                        if let Some((qos, published_at)) =
+                            self.published_at.remove(&packet_identifier)
+                        {
+                            self.record(|metrics| {
+                                metrics.delivery_completed(qos, now - published_at)
+                            });
+                        }
+                        self.events.push_back(ServerEvent::Acknowledged {
+                            packet_identifier,
+                            reason_code,
+                        })
+                    }
+                    None => (),
+                }
+            }
+            Packet::Subscribe(subscribe) => {
+                let filters = || subscribe.subscriptions.iter().map(|(filter, _)| filter);
+                if !self.connack.shared_subscription_available
+                    && filters().any(|filter| filter.is_shared())
+                {
+                    return Err(SharedSubscriptionsNotSupported.into());
+                }
+                if !self.connack.wildcard_subscription_available
+                    && filters().any(|filter| filter.filter().contains(['+', '#']))
+                {
+                    return Err(WildcardSubscriptionsNotSupported.into());
+                }
+                if !self.connack.subscription_identifiers_available
+                    && subscribe.subscription_identifier.is_some()
+                {
+                    return Err(SubscriptionIdentifiersNotSupported.into());
+                }
+                self.events.push_back(ServerEvent::Subscribe(subscribe))
+            }
+            Packet::UnSubscribe(unsubscribe) => {
+                self.events.push_back(ServerEvent::UnSubscribe(unsubscribe))
+            }
+            Packet::PingReq => self.send(Packet::PingResp, now),
+            Packet::Auth(auth) => {
+                match self
+                    .auth
+                    .as_mut()
+                    .ok_or(ProtocolError)?
+                    .accept(auth.into())?
+                {
+                    AuthStep::Challenge(data) => {
+                        self.events.push_back(ServerEvent::AuthChallenge(data))
+                    }
+                    _ => return Err(ProtocolError.into()),
+                }
+            }
+            Packet::Disconnect(disconnect) => {
+                let publish_will = disconnect.reason_code != Success;
+                self.closed(disconnect, publish_will);
+            }
+            _ => return Err(ProtocolError.into()),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{
+        Capabilities, ClientConnection, ClientEvent, Interceptor, PacketType,
+        ReasonCode::{QuotaExceeded, TopicAliasInvalid},
+    };
+
+    fn publish(qos: QoS) -> Publish {
+        Publish {
+            qos,
+            topic_name: "Around the World".try_into().unwrap(),
+            message: "Harder, Better, Faster, Stronger".into(),
+            ..Default::default()
+        }
+    }
+
+    // Forwards the packets of the client to the server and back.
+    fn exchange(client: &mut ClientConnection, server: &mut ServerConnection, now: Instant) {
+        loop {
+            let mut idle = true;
+            while let Some(packet) = client.poll_transmit() {
+                idle = false;
+                let _ = server.handle_packet(packet, now);
+            }
+            while let Some(packet) = server.poll_transmit() {
+                idle = false;
+                let _ = client.handle_packet(packet, now);
+            }
+            if idle {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn connection() {
+        let now = Instant::now();
+        let mut client = ClientConnection::new(Connect {
+            keep_alive: 10,
+            ..Default::default()
+        });
+        let mut server = ServerConnection::new();
+
+        client.connect(now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert!(matches!(server.poll_event(), Some(ServerEvent::Connect(_))));
+        assert!(client.publish(publish(QoS::AtMostOnce), now).is_err());
+
+        server.connack(Default::default(), now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert!(client.is_connected() && server.is_connected());
+        assert!(matches!(
+            client.poll_event(),
+            Some(ClientEvent::Connected(_))
+        ));
+
+        client.publish(publish(QoS::ExactlyOnce), now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert_eq!(
+            server.poll_event(),
+            Some(ServerEvent::Message(Publish {
+                packet_identifier: Some(1),
+                ..publish(QoS::ExactlyOnce)
+            }))
+        );
+        assert_eq!(server.poll_event(), None);
+        assert_eq!(
+            client.poll_event(),
+            Some(ClientEvent::Acknowledged {
+                packet_identifier: 1,
+                reason_code: ReasonCode::Success
+            })
+        );
+
+        server.publish(publish(QoS::AtLeastOnce), now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert!(matches!(client.poll_event(), Some(ClientEvent::Message(_))));
+        assert!(matches!(
+            server.poll_event(),
+            Some(ServerEvent::Acknowledged { .. })
+        ));
+
+        assert_eq!(server.poll_timeout(), Some(now + Duration::from_secs(15)));
+        server.handle_timeout(now + Duration::from_secs(15));
+        assert!(server.is_closed());
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::Disconnect(disconnect)) if disconnect.reason_code == KeepAliveTimeout
+        ));
+    }
+
+    #[test]
+    fn metrics() {
+        let now = Instant::now();
+        let metrics = Metrics::new();
+        let mut client = ClientConnection::new(Default::default());
+        let mut server = ServerConnection::new();
+        server.set_metrics(metrics.clone());
+
+        client.connect(now).unwrap();
+        exchange(&mut client, &mut server, now);
+        server.connack(Default::default(), now).unwrap();
+        server.publish(publish(QoS::AtLeastOnce), now).unwrap();
+        exchange(&mut client, &mut server, now + Duration::from_millis(3));
+        assert_eq!(metrics.connections(), 1);
+        assert_eq!(metrics.packets_received(PacketType::Connect), 1);
+        assert_eq!(metrics.packets_received(PacketType::PubAck), 1);
+        assert_eq!(metrics.packets_sent(PacketType::ConnAck), 1);
+        assert_eq!(metrics.payload_bytes_sent(), 32);
+        let latencies = metrics.delivery_latencies(QoS::AtLeastOnce);
+        assert_eq!(latencies.count, 1);
+        assert_eq!(latencies.sum, Duration::from_millis(3));
+
+        server.disconnect(Default::default(), now);
+        assert_eq!(metrics.connections(), 0);
+
+        let mut server = ServerConnection::new();
+        server.set_metrics(metrics.clone());
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        server
+            .connack(ConnAck::rejection(BadUserNameOrPassword), now)
+            .unwrap();
+        assert_eq!(metrics.authentication_failures(), 1);
+        assert_eq!(metrics.connections_total(), 1);
+    }
+
+    #[test]
+    fn server_keep_alive() {
+        let now = Instant::now();
+        let mut server = ServerConnection::new();
+        let connect = Connect {
+            keep_alive: 600,
+            ..Default::default()
+        };
+        let server_keep_alive = KeepAlive::server_keep_alive(connect.keep_alive, 60);
+        server.handle_packet(connect.into(), now).unwrap();
+        let connack = ConnAck {
+            keep_alive: server_keep_alive,
+            ..Default::default()
+        };
+        server.connack(connack, now).unwrap();
+        assert_eq!(server.keep_alive(), Some(Duration::from_secs(60)));
+
+        let later = now + Duration::from_secs(60);
+        server.handle_packet(Packet::PingReq, later).unwrap();
+        assert_eq!(server.poll_timeout(), Some(later + Duration::from_secs(90)));
+        server.handle_timeout(later + Duration::from_secs(89));
+        assert!(server.is_connected());
+        server.handle_timeout(later + Duration::from_secs(90));
+        assert!(server.is_closed());
+    }
+
+    #[test]
+    fn first_packet() {
+        let now = Instant::now();
+        let mut server = ServerConnection::new();
+        assert!(server.handle_packet(Packet::PingReq, now).is_err());
+        assert!(server.is_closed());
+        assert_eq!(server.poll_transmit(), None);
+    }
+
+    #[test]
+    fn quotas() {
+        let now = Instant::now();
+        let mut server = ServerConnection::new();
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        let connack = ConnAck::builder()
+            .receive_maximum(1)
+            .maximum_qos(QoS::ExactlyOnce)
+            .retain_available(false)
+            .build();
+        server.connack(connack, now).unwrap();
+        server.poll_transmit();
+
+        let first = Publish {
+            packet_identifier: Some(1),
+            ..publish(QoS::ExactlyOnce)
+        };
+        server.handle_packet(first.into(), now).unwrap();
+        let second = Publish {
+            packet_identifier: Some(2),
+            ..publish(QoS::ExactlyOnce)
+        };
+        assert!(matches!(
+            server.handle_packet(second.into(), now),
+            Err(crate::Error::Reason(ReceiveMaximumExceeded))
+        ));
+        server.poll_transmit();
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::Disconnect(disconnect)) if disconnect.reason_code == ReceiveMaximumExceeded
+        ));
+    }
+
+    #[test]
+    fn client_receive_maximum() {
+        let now = Instant::now();
+        let mut server = ServerConnection::new();
+        let connect = Connect {
+            receive_maximum: 1,
+            ..Default::default()
+        };
+        server.handle_packet(connect.into(), now).unwrap();
+        server.connack(Default::default(), now).unwrap();
+        server.poll_transmit();
+
+        let first = server.publish(publish(QoS::AtLeastOnce), now).unwrap();
+        let second = server.publish(publish(QoS::ExactlyOnce), now).unwrap();
+        server.publish(publish(QoS::AtMostOnce), now).unwrap();
+        assert_eq!((server.in_flight(), server.queued()), (1, 1));
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::Publish(p)) if p.packet_identifier == first
+        ));
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::Publish(p)) if p.qos == QoS::AtMostOnce
+        ));
+        assert_eq!(server.poll_transmit(), None);
+
+        let puback = PubAck {
+            packet_identifier: first.unwrap(),
+            ..Default::default()
+        };
+        server.handle_packet(puback.into(), now).unwrap();
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::Publish(p)) if p.packet_identifier == second
+        ));
+        assert_eq!((server.in_flight(), server.queued()), (1, 0));
+    }
+
+    #[test]
+    fn shutdown() {
+        let now = Instant::now();
+        let deadline = now + Duration::from_secs(10);
+        let mut server = ServerConnection::new();
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        server.connack(Default::default(), now).unwrap();
+        server.poll_transmit();
+
+        let packet_identifier = server.publish(publish(QoS::AtLeastOnce), now).unwrap();
+        server.poll_transmit();
+        server.shutdown(deadline, now);
+        assert!(server.is_connected());
+        assert_eq!(server.poll_timeout(), Some(deadline));
+        assert!(matches!(
+            server.publish(publish(QoS::AtMostOnce), now),
+            Err(crate::Error::Reason(ServerShuttingDown))
+        ));
+
+        let puback = PubAck {
+            packet_identifier: packet_identifier.unwrap(),
+            ..Default::default()
+        };
+        server.handle_packet(puback.into(), now).unwrap();
+        assert!(server.is_closed());
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::Disconnect(disconnect)) if disconnect.reason_code == ServerShuttingDown
+        ));
+
+        let mut server = ServerConnection::new();
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        server.connack(Default::default(), now).unwrap();
+        server.publish(publish(QoS::ExactlyOnce), now).unwrap();
+        server.shutdown(deadline, now);
+        server.handle_timeout(deadline - Duration::from_secs(1));
+        assert!(server.is_connected());
+        server.handle_timeout(deadline);
+        assert!(server.is_closed());
+
+        let mut server = ServerConnection::new();
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        server.shutdown(deadline, now);
+        assert!(server.is_closed());
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::ConnAck(connack)) if connack.reason_code == ServerUnavailable
+        ));
+    }
+
+    #[test]
+    fn disconnected() {
+        let now = Instant::now();
+        let mut server = ServerConnection::new();
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        server.connack(Default::default(), now).unwrap();
+        while server.poll_event().is_some() {}
+
+        let disconnect = Disconnect {
+            reason_code: ReasonCode::AdministrativeAction,
+            ..Default::default()
+        };
+        server.disconnect(disconnect.clone(), now);
+        assert!(server.is_closed());
+        assert_eq!(
+            server.poll_event(),
+            Some(ServerEvent::Disconnected(disconnect))
+        );
+        server.disconnect(Default::default(), now);
+        assert_eq!(server.poll_event(), None);
+
+        let mut server = ServerConnection::new();
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        while server.poll_event().is_some() {}
+        server
+            .connack(ConnAck::rejection(NotAuthorized), now)
+            .unwrap();
+        assert!(server.is_closed());
+        assert!(matches!(
+            server.poll_event(),
+            Some(ServerEvent::Disconnected(disconnect)) if disconnect.reason_code == NotAuthorized
+        ));
+        assert_eq!(server.poll_event(), None);
+    }
+
+    #[test]
+    fn refuse() {
+        let now = Instant::now();
+        let mut server = ServerConnection::new();
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        let connack = ConnAck {
+            topic_alias_maximum: 10,
+            ..Default::default()
+        };
+        server.connack(connack, now).unwrap();
+        server.poll_transmit();
+
+        let mut first = Publish {
+            packet_identifier: Some(1),
+            topic_alias: Some(1),
+            ..publish(QoS::AtLeastOnce)
+        };
+        server.resolve_topic(&mut first, now).unwrap();
+        assert_eq!(first.topic_alias, None);
+        let mut second = Publish {
+            packet_identifier: Some(2),
+            topic_name: Default::default(),
+            topic_alias: Some(1),
+            ..publish(QoS::ExactlyOnce)
+        };
+        server.resolve_topic(&mut second, now).unwrap();
+        assert_eq!(second.topic_name, publish(QoS::AtMostOnce).topic_name);
+
+        assert!(server.refuse(&first, Success, now).is_err());
+        server.refuse(&first, NotAuthorized, now).unwrap();
+        server.refuse(&second, NotAuthorized, now).unwrap();
+        server
+            .refuse(&publish(QoS::AtMostOnce), NotAuthorized, now)
+            .unwrap();
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::PubAck(puback)) if puback.reason_code == NotAuthorized
+        ));
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::PubRec(pubrec)) if pubrec.reason_code == NotAuthorized
+        ));
+        assert_eq!(server.poll_transmit(), None);
+        assert_eq!(server.deliveries.incoming(), 0);
+
+        let mut unknown = Publish {
+            topic_name: Default::default(),
+            topic_alias: Some(2),
+            ..publish(QoS::AtMostOnce)
+        };
+        assert!(matches!(
+            server.resolve_topic(&mut unknown, now),
+            Err(crate::Error::Reason(TopicAliasInvalid))
+        ));
+        assert!(server.is_closed());
+    }
+
+    // Rewrites the topic of the messages received from the client, and
+    // refuses the retained messages in both directions.
+    struct Rewrite;
+
+    impl Interceptor for Rewrite {
+        fn incoming(&mut self, packet: Packet) -> SageResult<Packet> {
+            match packet {
+                Packet::Publish(publish) if publish.retain => Err(QuotaExceeded.into()),
+                Packet::Publish(publish) => Ok(Publish {
+                    topic_name: "One More Time".try_into().unwrap(),
+                    ..publish
+                }
+                .into()),
+                packet => Ok(packet),
+            }
+        }
+
+        fn outgoing(&mut self, publish: Publish) -> SageResult<Publish> {
+            if publish.retain {
+                Err(NotAuthorized.into())
+            } else {
+                Ok(publish)
+            }
+        }
+    }
+
+    #[test]
+    fn interceptors() {
+        let now = Instant::now();
+        let mut server = ServerConnection::new();
+        let mut interceptors = InterceptorChain::new();
+        interceptors.push(Rewrite);
+        server.set_interceptors(interceptors);
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        let connack = ConnAck {
+            topic_alias_maximum: 10,
+            ..Default::default()
+        };
+        server.connack(connack, now).unwrap();
+        server.poll_transmit();
+        server.poll_event();
+
+        let aliased = Publish {
+            topic_alias: Some(1),
+            ..publish(QoS::AtMostOnce)
+        };
+        server.handle_packet(aliased.into(), now).unwrap();
+        match server.poll_event() {
+            Some(ServerEvent::Message(publish)) => {
+                assert_eq!(publish.topic_name.as_str(), "One More Time");
+                assert_eq!(publish.topic_alias, None);
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+
+        let retained = Publish {
+            retain: true,
+            packet_identifier: Some(1),
+            topic_name: Default::default(),
+            topic_alias: Some(1),
+            ..publish(QoS::AtLeastOnce)
+        };
+        server.handle_packet(retained.into(), now).unwrap();
+        assert_eq!(server.poll_event(), None);
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::PubAck(puback)) if puback.reason_code == QuotaExceeded
+        ));
+        assert!(!server.is_closed());
+
+        let retained = Publish {
+            retain: true,
+            ..publish(QoS::AtLeastOnce)
+        };
+        assert!(matches!(
+            server.publish(retained, now),
+            Err(crate::Error::Reason(NotAuthorized))
+        ));
+        assert_eq!(server.poll_transmit(), None);
+        assert_eq!(
+            server.publish(publish(QoS::AtLeastOnce), now).unwrap(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn client_maximum_packet_size() {
+        let now = Instant::now();
+        let mut server = ServerConnection::new();
+        let connect = Connect {
+            maximum_packet_size: Some(64),
+            ..Default::default()
+        };
+        server.handle_packet(connect.into(), now).unwrap();
+        let connack = ConnAck::builder().maximum_packet_size(128).build();
+        server.connack(connack, now).unwrap();
+        server.poll_transmit();
+        assert_eq!(server.maximum_packet_size(), Some(128));
+
+        let large = Publish {
+            message: vec![0; 64],
+            ..publish(QoS::AtLeastOnce)
+        };
+        assert_eq!(server.publish(large, now).unwrap(), None);
+        assert_eq!(server.poll_transmit(), None);
+        assert!(server
+            .publish(publish(QoS::AtLeastOnce), now)
+            .unwrap()
+            .is_some());
+        assert!(matches!(server.poll_transmit(), Some(Packet::Publish(_))));
+    }
+
+    #[test]
+    fn shared_subscriptions() {
+        let now = Instant::now();
+        let mut server = ServerConnection::new();
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        let connack = ConnAck::builder()
+            .shared_subscription_available(false)
+            .build();
+        server.connack(connack, now).unwrap();
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            subscriptions: vec![(
+                "$share/group/sport/#".try_into().unwrap(),
+                Default::default(),
+            )],
+            ..Default::default()
+        };
+        assert!(matches!(
+            server.handle_packet(subscribe.into(), now),
+            Err(crate::Error::Reason(SharedSubscriptionsNotSupported))
+        ));
+        assert!(server.is_closed());
+    }
+
+    #[test]
+    fn capabilities() {
+        let now = Instant::now();
+        let capabilities = Capabilities {
+            maximum_qos: QoS::AtLeastOnce,
+            retain_available: false,
+            wildcard_subscription_available: false,
+            subscription_identifiers_available: false,
+            shared_subscription_available: true,
+        };
+        let connected = || {
+            let mut server = ServerConnection::new();
+            server
+                .handle_packet(Connect::default().into(), now)
+                .unwrap();
+            let connack = ConnAck::builder().capabilities(capabilities).build();
+            server.connack(connack, now).unwrap();
+            server
+        };
+        let subscribe = |filter: &str, subscription_identifier: Option<u32>| Subscribe {
+            packet_identifier: 1,
+            subscription_identifier: subscription_identifier.map(|id| id.try_into().unwrap()),
+            subscriptions: vec![(filter.try_into().unwrap(), Default::default())],
+            ..Default::default()
+        };
+
+        let mut server = connected();
+        assert!(server
+            .handle_packet(subscribe("$share/group/sport/tennis", None).into(), now)
+            .is_ok());
+        assert!(matches!(
+            server.handle_packet(subscribe("sport/+", None).into(), now),
+            Err(crate::Error::Reason(WildcardSubscriptionsNotSupported))
+        ));
+        assert!(server.is_closed());
+
+        let mut server = connected();
+        assert!(matches!(
+            server.handle_packet(subscribe("sport/tennis", Some(1)).into(), now),
+            Err(crate::Error::Reason(SubscriptionIdentifiersNotSupported))
+        ));
+
+        let mut server = connected();
+        assert!(matches!(
+            server.handle_packet(publish(QoS::ExactlyOnce).into(), now),
+            Err(crate::Error::Reason(QoSNotSupported))
+        ));
+
+        let will = Will::with_message("Around the World".try_into().unwrap(), "Harder");
+        for (will, reason_code) in [
+            (
+                Will {
+                    qos: QoS::ExactlyOnce,
+                    ..will.clone()
+                },
+                QoSNotSupported,
+            ),
+            (
+                Will {
+                    retain: true,
+                    ..will
+                },
+                RetainNotSupported,
+            ),
+        ] {
+            let mut server = ServerConnection::new();
+            let connect = Connect {
+                will: Some(will),
+                ..Default::default()
+            };
+            server.handle_packet(connect.into(), now).unwrap();
+            let connack = ConnAck::builder().capabilities(capabilities).build();
+            assert!(matches!(
+                server.connack(connack, now),
+                Err(crate::Error::Reason(r)) if r == reason_code
+            ));
+            assert!(server.is_closed());
+            assert!(matches!(
+                server.poll_transmit(),
+                Some(Packet::ConnAck(connack)) if connack.reason_code == reason_code
+            ));
+        }
+    }
+
+    #[test]
+    fn authentication() {
+        let now = Instant::now();
+        let mut client = ClientConnection::new(Connect {
+            authentication: Some(Authentication {
+                method: "SCRAM-SHA-1".into(),
+                data: b"client-first".to_vec(),
+            }),
+            ..Default::default()
+        });
+        let mut server = ServerConnection::new();
+
+        client.connect(now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert!(matches!(server.poll_event(), Some(ServerEvent::Connect(_))));
+        server.challenge(b"server-first".to_vec(), now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert_eq!(
+            client.poll_event(),
+            Some(ClientEvent::AuthChallenge(b"server-first".to_vec()))
+        );
+
+        client.authenticate(b"client-final".to_vec(), now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert_eq!(
+            server.poll_event(),
+            Some(ServerEvent::AuthChallenge(b"client-final".to_vec()))
+        );
+        server.connack(Default::default(), now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert!(client.is_connected() && server.is_connected());
+
+        client
+            .reauthenticate(b"client-first".to_vec(), now)
+            .unwrap();
+        exchange(&mut client, &mut server, now);
+        assert_eq!(
+            server.poll_event(),
+            Some(ServerEvent::AuthChallenge(b"client-first".to_vec()))
+        );
+        server.fail(ReasonCode::NotAuthorized, now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert!(client.is_closed() && server.is_closed());
+    }
+
+    #[test]
+    fn will() {
+        let now = Instant::now();
+        let connected = || {
+            let mut server = ServerConnection::new();
+            let connect = Connect {
+                will: Some(Will::with_message(
+                    "Around the World".try_into().unwrap(),
+                    "Bye",
+                )),
+                ..Default::default()
+            };
+            server.handle_packet(connect.into(), now).unwrap();
+            server.connack(Default::default(), now).unwrap();
+            server.poll_event();
+            server.poll_transmit();
+            server
+        };
+
+        let mut server = connected();
+        server
+            .handle_packet(Disconnect::default().into(), now)
+            .unwrap();
+        assert!(matches!(
+            server.poll_event(),
+            Some(ServerEvent::Disconnected(_))
+        ));
+        assert_eq!(server.poll_event(), None);
+
+        let mut server = connected();
+        let disconnect = Disconnect {
+            reason_code: ReasonCode::DisconnectWithWillMessage,
+            ..Default::default()
+        };
+        server.handle_packet(disconnect.into(), now).unwrap();
+        server.poll_event();
+        assert!(matches!(server.poll_event(), Some(ServerEvent::Will(_))));
+
+        let mut server = connected();
+        server.connection_lost();
+        assert!(server.is_closed());
+        assert_eq!(server.poll_transmit(), None);
+        assert!(matches!(
+            server.poll_event(),
+            Some(ServerEvent::Disconnected(disconnect)) if disconnect.reason_code == UnspecifiedError
+        ));
+        assert!(matches!(server.poll_event(), Some(ServerEvent::Will(_))));
+        server.connection_lost();
+        assert_eq!(server.poll_event(), None);
+    }
+
+    #[test]
+    fn resume_session() {
+        let now = Instant::now();
+        let mut server = ServerConnection::new();
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        server.connack(Default::default(), now).unwrap();
+        server.poll_transmit();
+        let first = server.publish(publish(QoS::AtLeastOnce), now).unwrap();
+        let second = server.publish(publish(QoS::ExactlyOnce), now).unwrap();
+        server.poll_transmit();
+        server.poll_transmit();
+        let pubrec = PubRec {
+            packet_identifier: second.unwrap(),
+            ..Default::default()
+        };
+        server.handle_packet(pubrec.into(), now).unwrap();
+        server.connection_lost();
+
+        let state = server.session_state();
+        assert_eq!(state.outgoing.len(), 2);
+        let mut server = ServerConnection::with_session(&state);
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        let connack = ConnAck {
+            session_present: true,
+            ..Default::default()
+        };
+        server.connack(connack, now).unwrap();
+        server.poll_transmit();
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::Publish(p)) if p.duplicate && p.packet_identifier == first
+        ));
+        assert!(matches!(
+            server.poll_transmit(),
+            Some(Packet::PubRel(p)) if Some(p.packet_identifier) == second
+        ));
+        assert_eq!(server.session_state(), state);
+    }
+}
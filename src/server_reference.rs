@@ -0,0 +1,117 @@
+use crate::{ReasonCode::MalformedPacket, Result as SageResult};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    str::FromStr,
+};
+
+/// A server a client is redirected to, as found in the server reference
+/// property of `ConnAck` and `Disconnect` packets.
+/// The server reference is a space separated list of references, each one
+/// being a host name or address optionally followed by a port, such as
+/// `myserver.xyz.org:8883` or `[::1]:1883`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct ServerReference {
+    /// The host name or address of the server. IPv6 addresses are stored
+    /// without their enclosing brackets.
+    pub host: String,
+
+    /// The port of the server, if specified.
+    pub port: Option<u16>,
+}
+
+impl ServerReference {
+    /// Parses a server reference property into the list of references it
+    /// contains, in order of preference.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MalformedPacket` if any of the references is invalid.
+    pub fn parse_list(references: &str) -> SageResult<Vec<Self>> {
+        references.split_whitespace().map(str::parse).collect()
+    }
+}
+
+impl FromStr for ServerReference {
+    type Err = crate::Error;
+
+    fn from_str(reference: &str) -> SageResult<Self> {
+        let (host, port) = if let Some(bracketed) = reference.strip_prefix('[') {
+            let (host, rest) = bracketed.split_once(']').ok_or(MalformedPacket)?;
+            match rest {
+                "" => (host, None),
+                _ => (host, Some(rest.strip_prefix(':').ok_or(MalformedPacket)?)),
+            }
+        } else {
+            match reference.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (reference, None),
+            }
+        };
+
+        if host.is_empty() || host.contains(char::is_whitespace) {
+            return Err(MalformedPacket.into());
+        }
+
+        let port = match port {
+            Some(port) => Some(port.parse().map_err(|_| MalformedPacket)?),
+            None => None,
+        };
+
+        Ok(ServerReference {
+            host: host.into(),
+            port,
+        })
+    }
+}
+
+impl Display for ServerReference {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        if self.host.contains(':') {
+            write!(f, "[{}]", self.host)?;
+        } else {
+            write!(f, "{}", self.host)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn parse_list() {
+        let references =
+            ServerReference::parse_list("myserver.xyz.org:8883 [::1]:1883 backup").unwrap();
+        assert_eq!(
+            references,
+            vec![
+                ServerReference {
+                    host: "myserver.xyz.org".into(),
+                    port: Some(8883)
+                },
+                ServerReference {
+                    host: "::1".into(),
+                    port: Some(1883)
+                },
+                ServerReference {
+                    host: "backup".into(),
+                    port: None
+                },
+            ]
+        );
+        assert_eq!(references[1].to_string(), "[::1]:1883");
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!("host:port".parse::<ServerReference>().is_err());
+        assert!(":1883".parse::<ServerReference>().is_err());
+        assert!("[::1".parse::<ServerReference>().is_err());
+        assert!("host:1883:1883".parse::<ServerReference>().is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{Message, PacketIdAllocator, Subscription};
+
+/// An outgoing delivery which is not complete yet.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum OutgoingDelivery {
+    /// A message with an `AtLeastOnce` or `ExactlyOnce` quality of service
+    /// which was sent but not acknowledged yet with a `PubAck` or `PubRec`
+    /// packet.
+    Unacknowledged {
+        /// The packet identifier of the `Publish` packet.
+        packet_identifier: u16,
+
+        /// The message being delivered.
+        message: Message,
+    },
+
+    /// An `ExactlyOnce` message whose `PubRel` packet was sent but not
+    /// completed yet with a `PubComp` packet.
+    Released {
+        /// The packet identifier of the `PubRel` packet.
+        packet_identifier: u16,
+    },
+}
+
+impl OutgoingDelivery {
+    /// Returns the packet identifier of the delivery.
+    pub fn packet_identifier(&self) -> u16 {
+        match self {
+            OutgoingDelivery::Unacknowledged {
+                packet_identifier, ..
+            }
+            | OutgoingDelivery::Released { packet_identifier } => *packet_identifier,
+        }
+    }
+}
+
+/// The state of a persistent session, which must be kept by both the client
+/// and the server as long as the session does not expire.
+/// The state can be saved and restored using any storage backend, with the
+/// `Serialize` and `Deserialize` traits of `serde` if the `serde` feature is
+/// enabled.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SessionState {
+    /// The subscriptions of the client, along with their subscription
+    /// identifiers.
+    pub subscriptions: Vec<Subscription>,
+
+    /// The outgoing deliveries which are not complete, in the order they were
+    /// started.
+    pub outgoing: Vec<OutgoingDelivery>,
+
+    /// The packet identifiers of the incoming `ExactlyOnce` messages which were
+    /// received but not released yet with a `PubRel` packet.
+    pub incoming: Vec<u16>,
+
+    /// The packet identifier the next allocation starts from.
+    pub next_packet_identifier: u16,
+}
+
+impl Default for SessionState {
+    fn default() -> Self {
+        SessionState {
+            subscriptions: Default::default(),
+            outgoing: Default::default(),
+            incoming: Default::default(),
+            next_packet_identifier: 1,
+        }
+    }
+}
+
+impl SessionState {
+    /// Returns a packet identifier allocator restored from the session, where
+    /// the identifiers of the outgoing deliveries are in use.
+    pub fn packet_id_allocator(&self) -> PacketIdAllocator {
+        let mut allocator = PacketIdAllocator::starting_at(self.next_packet_identifier);
+        for delivery in &self.outgoing {
+            allocator.reserve(delivery.packet_identifier());
+        }
+        allocator
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn packet_id_allocator() {
+        let state = SessionState {
+            outgoing: vec![
+                OutgoingDelivery::Unacknowledged {
+                    packet_identifier: 7,
+                    message: Default::default(),
+                },
+                OutgoingDelivery::Released {
+                    packet_identifier: 8,
+                },
+            ],
+            next_packet_identifier: 7,
+            ..Default::default()
+        };
+        let mut allocator = state.packet_id_allocator();
+        assert!(allocator.is_in_use(7) && allocator.is_in_use(8));
+        assert_eq!(allocator.allocate().unwrap(), 9);
+        assert_eq!(allocator.next(), 10);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let state = SessionState {
+            subscriptions: vec![Subscription {
+                filter: "sensors/+/temperature".try_into().unwrap(),
+                options: Default::default(),
+                identifier: crate::SubscriptionId::new(42),
+            }],
+            incoming: vec![3],
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json.contains("\"identifier\":42"));
+        assert_eq!(serde_json::from_str::<SessionState>(&json).unwrap(), state);
+
+        let json = json.replace("\"identifier\":42", "\"identifier\":0");
+        assert!(serde_json::from_str::<SessionState>(&json).is_err());
+    }
+}
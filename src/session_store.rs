@@ -0,0 +1,595 @@
+use crate::{
+    duration, Connect, ConnectDecision, ExistingSession, Expiry, ExpiryQueue, Metrics,
+    OfflineQueue, Publish, Result as SageResult, ServerConnection, SessionState, Will,
+    WillScheduler,
+};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+struct Session<Q> {
+    state: SessionState,
+    queue: Q,
+    expiry: Expiry,
+    expires_at: Option<Instant>,
+    generation: u64,
+    connected: bool,
+}
+
+/// A session opened or resumed by `SessionStore::connect`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ConnectedSession {
+    /// How the `Connect` packet was handled. Its `session_present` flag must
+    /// be sent in the `ConnAck` packet, and the connection currently using
+    /// the session must be closed if `take_over` is set.
+    pub decision: ConnectDecision,
+
+    /// Identifies the network connection using the session, to give back to
+    /// `save` and `disconnect`.
+    pub generation: u64,
+
+    /// The state of the session: the subscriptions to restore and the
+    /// deliveries to resume, such as with a `RetransmitQueue`. Empty for a
+    /// new session.
+    pub state: SessionState,
+
+    /// The messages queued while the client was disconnected, to send once
+    /// the `ConnAck` packet is sent, in order.
+    pub queued: Vec<Publish>,
+
+    /// The will message of the previous connection still waiting for its
+    /// Will Delay Interval, to publish right away since the session it
+    /// belonged to was discarded by Clean Start.
+    pub will: Option<Will>,
+
+    /// Whether the resumed session dropped messages while the client was
+    /// disconnected, with `OverflowPolicy::DisconnectOnReconnect`. The
+    /// connection must then be closed with `QuotaExceeded` once accepted, so
+    /// that the client knows messages were lost.
+    pub overflowed: bool,
+}
+
+impl ConnectedSession {
+    /// Takes the session over from `connection`, the network connection still
+    /// using it if `take_over` is set. `connection` is closed with a
+    /// `SessionTakenOver` `Disconnect` packet, which publishes its will.
+    ///
+    /// If the session is resumed, the deliveries in flight on `connection`
+    /// replace the ones of `state`, so that the new connection created with
+    /// `ServerConnection::with_session` resumes them without loss, and the
+    /// new state is to be saved with `SessionStore::save`. Otherwise they are
+    /// dropped along with the session.
+    pub fn take_over(&mut self, connection: &mut ServerConnection, now: Instant) {
+        let disconnect = match self.decision.takeover_disconnect() {
+            Some(disconnect) => disconnect,
+            None => return,
+        };
+        connection.disconnect(disconnect, now);
+        if self.decision.session_present {
+            self.state = SessionState {
+                subscriptions: std::mem::take(&mut self.state.subscriptions),
+                ..connection.session_state()
+            };
+        }
+    }
+}
+
+/// Keeps the sessions of the clients of a broker, identified by a key such as
+/// their client id, across their network connections.
+///
+/// A session is opened or resumed by `connect`, according to the Clean Start
+/// flag and the Session Expiry Interval of the `Connect` packet. When the
+/// network connection closes, `disconnect` saves the state of the session,
+/// which is then kept until its Session Expiry Interval elapses. The messages
+/// for the client are queued in its `OfflineQueue` in the meantime.
+///
+/// The will messages of the closed connections are scheduled with
+/// `schedule_will`, to be published once their Will Delay Interval elapses
+/// or their session ends, whichever happens first. A will is cancelled if the
+/// client reconnects to its session in the meantime.
+///
+/// Each connection to a session has a generation, so that the connection
+/// being taken over by a new one cannot overwrite the session of the new
+/// one. Since the state saved by the connection being taken over is ignored,
+/// the state of a session should be saved with `save` as it changes, such as
+/// upon each subscription.
+///
+/// The store does not run any timer: the broker sweeps it with `expire` and
+/// `due_wills` each time the delay returned by `next_wakeup` elapses, such as
+/// from a background task. A session can also be ended early with
+/// `force_expire`.
+///
+/// The current time is always given by the caller.
+#[derive(Debug, Clone)]
+pub struct SessionStore<K, Q> {
+    sessions: HashMap<K, Session<Q>>,
+    queue: Q,
+    expiries: ExpiryQueue<K>,
+    wills: WillScheduler<K>,
+    generation: u64,
+    metrics: Option<Metrics>,
+}
+
+impl<K: Hash + Eq + Clone, Q: OfflineQueue + Clone> SessionStore<K, Q> {
+    /// Creates an empty store, where `now` is the current time. Each session
+    /// queues its messages in a clone of the empty `queue`.
+    pub fn new(now: Instant, queue: Q) -> Self {
+        SessionStore {
+            sessions: HashMap::new(),
+            queue,
+            expiries: ExpiryQueue::new(now),
+            wills: WillScheduler::new(now),
+            generation: 0,
+            metrics: None,
+        }
+    }
+
+    /// Counts the messages dropped by the offline queues into `metrics`.
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Returns the number of sessions.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Returns `true` if there is no session.
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Returns the state of the session of `client`.
+    pub fn existing(&self, client: &K) -> ExistingSession {
+        match self.sessions.get(client) {
+            None => ExistingSession::None,
+            Some(session) if session.connected => ExistingSession::Connected,
+            Some(_) => ExistingSession::Disconnected,
+        }
+    }
+
+    /// Returns the keys of all the sessions, in no particular order.
+    pub fn clients(&self) -> impl Iterator<Item = &K> {
+        self.sessions.keys()
+    }
+
+    /// Returns the instant the session of `client` expires at, if it is
+    /// disconnected and its Session Expiry Interval is not `Expiry::Never`.
+    pub fn expires_at(&self, client: &K) -> Option<Instant> {
+        self.sessions
+            .get(client)
+            .filter(|session| !session.connected)
+            .and_then(|session| session.expires_at)
+    }
+
+    /// Returns the last saved state of the session of `client`, if any.
+    pub fn state(&self, client: &K) -> Option<&SessionState> {
+        self.sessions.get(client).map(|session| &session.state)
+    }
+
+    /// Opens the session of `client` upon receiving `connect`, resuming its
+    /// existing session unless Clean Start is set. The session stays open
+    /// until `disconnect` is called with the returned generation.
+    ///
+    /// The will of the previous connection still waiting for its delay is
+    /// cancelled if the session is resumed, and returned to be published if
+    /// the session is discarded.
+    pub fn connect(&mut self, client: K, connect: &Connect) -> ConnectedSession {
+        let decision = ConnectDecision::new(connect, self.existing(&client));
+        if decision.discard_session {
+            self.sessions.remove(&client);
+        }
+        let will = self
+            .wills
+            .cancel(&client)
+            .filter(|_| !decision.session_present);
+        self.expiries.remove(&client);
+        self.generation += 1;
+        let generation = self.generation;
+        let queue = &self.queue;
+        let session = self.sessions.entry(client).or_insert_with(|| Session {
+            state: Default::default(),
+            queue: queue.clone(),
+            expiry: Expiry::Default,
+            expires_at: None,
+            generation,
+            connected: true,
+        });
+        session.expiry = connect.session_expiry_interval;
+        session.expires_at = None;
+        session.generation = generation;
+        session.connected = true;
+        let overflowed = decision.session_present && session.queue.overflowed();
+        ConnectedSession {
+            decision,
+            generation,
+            state: session.state.clone(),
+            queued: session.queue.drain(),
+            will,
+            overflowed,
+        }
+    }
+
+    /// Saves the current `state` of the session of `client`, if it is still
+    /// used by the connection of `generation`. Returns `true` if it was
+    /// saved.
+    pub fn save(&mut self, client: &K, generation: u64, state: SessionState) -> bool {
+        match self.sessions.get_mut(client) {
+            Some(session) if session.connected && session.generation == generation => {
+                session.state = state;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Closes the session of `client` when the network connection of
+    /// `generation` closes, saving its final `state`. `expiry` is the Session
+    /// Expiry Interval of the `Disconnect` packet if any, `Expiry::Default`
+    /// keeping the one of the `Connect` packet.
+    ///
+    /// The session ends right away if its Session Expiry Interval is absent or
+    /// zero, and otherwise expires once it elapses after `now`. Returns
+    /// `true` if the session is kept. Nothing is done if the session was
+    /// taken over by another connection.
+    pub fn disconnect(
+        &mut self,
+        client: &K,
+        generation: u64,
+        state: SessionState,
+        expiry: Expiry,
+        now: Instant,
+    ) -> bool {
+        let session = match self.sessions.get_mut(client) {
+            Some(session) if session.connected && session.generation == generation => session,
+            _ => return false,
+        };
+        if expiry != Expiry::Default {
+            session.expiry = expiry;
+        }
+        session.state = state;
+        session.connected = false;
+        match session.expiry {
+            Expiry::Default | Expiry::Seconds(0) => {
+                self.sessions.remove(client);
+                false
+            }
+            Expiry::Seconds(secs) => {
+                session.expires_at = duration::deadline(now, secs);
+                if let Some(deadline) = session.expires_at {
+                    self.expiries.insert(client.clone(), deadline);
+                }
+                true
+            }
+            Expiry::Never => true,
+        }
+    }
+
+    /// Queues `publish` for the client of a session, to be sent once it
+    /// reconnects. Returns the messages dropped to keep the queue within its
+    /// limits, which is `publish` itself if `client` has no session. The
+    /// dropped and rejected messages are counted in the metrics, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns `QuotaExceeded` if the queue of the session is full and
+    /// rejects new messages.
+    pub fn enqueue(&mut self, client: &K, publish: Publish) -> SageResult<Vec<Publish>> {
+        let result = match self.sessions.get_mut(client) {
+            Some(session) => session.queue.enqueue(publish),
+            None => return Ok(vec![publish]),
+        };
+        if let Some(metrics) = &self.metrics {
+            metrics.messages_dropped(result.as_ref().map_or(1, Vec::len));
+        }
+        result
+    }
+
+    /// Schedules the `will` of `client`, whose connection was closed at `now`
+    /// with `disconnect`. The will is due once its Will Delay Interval
+    /// elapses, or when the session ends if it is earlier, which is right
+    /// away if the session ended with the connection. Returns the instant it
+    /// is due at.
+    pub fn schedule_will(&mut self, client: K, will: Will, now: Instant) -> Instant {
+        let expiry = match self.sessions.get(&client) {
+            Some(session) if !session.connected => session.expiry,
+            _ => Expiry::Default,
+        };
+        self.wills.schedule(client, will, expiry, now)
+    }
+
+    /// Removes and returns the wills which are due at `now`, in the order of
+    /// their deadlines. They must be published by the broker.
+    pub fn due_wills(&mut self, now: Instant) -> Vec<(K, Will)> {
+        self.wills.due(now)
+    }
+
+    /// Removes the disconnected sessions which expired at `now`, returning
+    /// their keys.
+    pub fn expire(&mut self, now: Instant) -> Vec<K> {
+        self.expiries
+            .expired_before(now)
+            .into_iter()
+            .filter(|client| self.sessions.remove(client).is_some())
+            .collect()
+    }
+
+    /// Ends the session of `client` right away if it is disconnected, as if
+    /// its Session Expiry Interval elapsed at `now`: its subscriptions and
+    /// queued messages are dropped, and its will still waiting for its delay,
+    /// if any, is due at `now`. Returns `true` if the session was removed.
+    pub fn force_expire(&mut self, client: &K, now: Instant) -> bool {
+        match self.sessions.get(client) {
+            Some(session) if !session.connected => (),
+            _ => return false,
+        }
+        self.sessions.remove(client);
+        self.expiries.remove(client);
+        if let Some(will) = self.wills.cancel(client) {
+            self.wills
+                .schedule(client.clone(), will, Expiry::Default, now);
+        }
+        true
+    }
+
+    /// Returns the delay until the next session expires or the next will is
+    /// due, if any.
+    pub fn next_wakeup(&self, now: Instant) -> Option<Duration> {
+        match (self.expiries.next_wakeup(now), self.wills.next_wakeup(now)) {
+            (Some(expiry), Some(will)) => Some(expiry.min(will)),
+            (expiry, will) => expiry.or(will),
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{
+        ConnAck, InMemoryOfflineQueue, OverflowPolicy, Packet, QoS, ReasonCode, Subscription,
+        SubscriptionId, TopicFilter,
+    };
+
+    fn store(now: Instant) -> SessionStore<&'static str, InMemoryOfflineQueue> {
+        SessionStore::new(
+            now,
+            InMemoryOfflineQueue::new(8, OverflowPolicy::DropOldest),
+        )
+    }
+
+    fn connect(clean_start: bool, session_expiry_interval: Expiry) -> Connect {
+        Connect {
+            clean_start,
+            session_expiry_interval,
+            ..Default::default()
+        }
+    }
+
+    fn state() -> SessionState {
+        SessionState {
+            subscriptions: vec![Subscription {
+                filter: TopicFilter::try_from("sensors/#").unwrap(),
+                options: Default::default(),
+                identifier: SubscriptionId::new(1),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resume() {
+        let now = Instant::now();
+        let mut store = store(now);
+        let session = store.connect("client", &connect(false, Expiry::Seconds(10)));
+        assert!(!session.decision.session_present);
+        assert_eq!(store.existing(&"client"), ExistingSession::Connected);
+        assert!(store.disconnect(&"client", session.generation, state(), Expiry::Default, now));
+        assert_eq!(store.existing(&"client"), ExistingSession::Disconnected);
+
+        let publish = Publish {
+            qos: QoS::AtLeastOnce,
+            ..Default::default()
+        };
+        assert_eq!(store.enqueue(&"client", publish.clone()).unwrap(), vec![]);
+        assert_eq!(
+            store.enqueue(&"other", publish.clone()).unwrap(),
+            vec![publish.clone()]
+        );
+
+        let session = store.connect("client", &connect(false, Expiry::Seconds(10)));
+        assert!(session.decision.session_present);
+        assert_eq!(session.state, state());
+        assert_eq!(session.queued, vec![publish]);
+
+        let session = store.connect("client", &connect(true, Expiry::Seconds(10)));
+        assert!(!session.decision.session_present);
+        assert!(session.decision.take_over);
+        assert_eq!(session.state, SessionState::default());
+    }
+
+    #[test]
+    fn overflow() {
+        let now = Instant::now();
+        let queue = InMemoryOfflineQueue::new(1, OverflowPolicy::DisconnectOnReconnect);
+        let mut store = SessionStore::new(now, queue);
+        let metrics = Metrics::new();
+        store.set_metrics(metrics.clone());
+        let session = store.connect("client", &connect(false, Expiry::Never));
+        assert!(!session.overflowed);
+        store.disconnect(&"client", session.generation, state(), Expiry::Default, now);
+
+        let publish = Publish::default();
+        assert!(store
+            .enqueue(&"client", publish.clone())
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            store.enqueue(&"client", publish.clone()).unwrap(),
+            vec![publish.clone()]
+        );
+        assert_eq!(metrics.dropped_messages(), 1);
+
+        let session = store.connect("client", &connect(false, Expiry::Never));
+        assert!(session.overflowed);
+        assert_eq!(session.queued, vec![publish]);
+        store.disconnect(&"client", session.generation, state(), Expiry::Default, now);
+        let session = store.connect("client", &connect(false, Expiry::Never));
+        assert!(!session.overflowed);
+    }
+
+    #[test]
+    fn expiry() {
+        let now = Instant::now();
+        let mut store = store(now);
+        let session = store.connect("a", &connect(false, Expiry::Default));
+        assert!(!store.disconnect(&"a", session.generation, state(), Expiry::Default, now));
+        assert!(store.is_empty());
+
+        let session = store.connect("a", &connect(false, Expiry::Default));
+        assert!(store.disconnect(&"a", session.generation, state(), Expiry::Seconds(5), now));
+        let session = store.connect("b", &connect(false, Expiry::Never));
+        assert!(store.disconnect(&"b", session.generation, state(), Expiry::Default, now));
+        assert!(store.next_wakeup(now).unwrap() <= Duration::from_secs(5));
+        assert!(store.expire(now + Duration::from_secs(4)).is_empty());
+        assert_eq!(store.expire(now + Duration::from_secs(5)), vec!["a"]);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.existing(&"b"), ExistingSession::Disconnected);
+    }
+
+    #[test]
+    fn force_expire() {
+        let now = Instant::now();
+        let will = Will {
+            delay_interval: 30,
+            ..Will::with_message("Around the World".try_into().unwrap(), "Bye")
+        };
+        let mut store = store(now);
+        let session = store.connect("a", &connect(false, Expiry::Seconds(60)));
+        assert!(!store.force_expire(&"a", now));
+        assert_eq!(store.expires_at(&"a"), None);
+
+        store.disconnect(&"a", session.generation, state(), Expiry::Default, now);
+        assert_eq!(store.expires_at(&"a"), Some(now + Duration::from_secs(60)));
+        store.schedule_will("a", will.clone(), now);
+        let later = now + Duration::from_secs(1);
+        assert!(store.force_expire(&"a", later));
+        assert!(store.is_empty() && store.clients().next().is_none());
+        assert_eq!(store.due_wills(later), vec![("a", will)]);
+        assert_eq!(store.next_wakeup(later), None);
+    }
+
+    #[test]
+    fn take_over() {
+        let now = Instant::now();
+        let mut store = store(now);
+        let first = store.connect("client", &connect(false, Expiry::Never));
+        assert!(store.save(&"client", first.generation, state()));
+        let second = store.connect("client", &connect(false, Expiry::Never));
+        assert!(second.decision.take_over && second.decision.session_present);
+        assert_eq!(second.state, state());
+
+        // The connection taken over cannot overwrite the session.
+        assert!(!store.save(&"client", first.generation, Default::default()));
+        assert!(!store.disconnect(
+            &"client",
+            first.generation,
+            Default::default(),
+            Expiry::Default,
+            now
+        ));
+        assert_eq!(store.existing(&"client"), ExistingSession::Connected);
+        assert_eq!(store.state(&"client"), Some(&state()));
+    }
+
+    #[test]
+    fn take_over_connection() {
+        let now = Instant::now();
+        let mut store = store(now);
+        let first = store.connect("client", &connect(false, Expiry::Never));
+        store.save(&"client", first.generation, state());
+        let mut connection = ServerConnection::with_session(&first.state);
+        connection
+            .handle_packet(connect(false, Expiry::Never).into(), now)
+            .unwrap();
+        connection.connack(ConnAck::default(), now).unwrap();
+        let publish = Publish {
+            qos: QoS::AtLeastOnce,
+            topic_name: "sensors/temperature".try_into().unwrap(),
+            ..Default::default()
+        };
+        let packet_identifier = connection.publish(publish, now).unwrap();
+
+        let mut second = store.connect("client", &connect(false, Expiry::Never));
+        second.take_over(&mut connection, now);
+        assert!(connection.is_closed());
+        assert!(matches!(
+            std::iter::from_fn(|| connection.poll_transmit()).last(),
+            Some(Packet::Disconnect(disconnect))
+                if disconnect.reason_code == ReasonCode::SessionTakenOver
+        ));
+        assert_eq!(second.state.subscriptions, state().subscriptions);
+        assert_eq!(second.state.outgoing.len(), 1);
+        assert_eq!(
+            Some(second.state.outgoing[0].packet_identifier()),
+            packet_identifier
+        );
+
+        // The deliveries are dropped along with the session on Clean Start.
+        let mut third = store.connect("client", &connect(true, Expiry::Never));
+        let mut connection = ServerConnection::with_session(&second.state);
+        connection
+            .handle_packet(connect(false, Expiry::Never).into(), now)
+            .unwrap();
+        connection.connack(ConnAck::default(), now).unwrap();
+        third.take_over(&mut connection, now);
+        assert_eq!(third.state, SessionState::default());
+    }
+
+    #[test]
+    fn wills() {
+        let now = Instant::now();
+        let secs = |secs| now + Duration::from_secs(secs);
+        let will = |delay_interval| Will {
+            delay_interval,
+            ..Will::with_message("Around the World".try_into().unwrap(), "Bye")
+        };
+        let mut store = store(now);
+
+        // The will is due when the session ends, before its delay.
+        let session = store.connect("a", &connect(false, Expiry::Seconds(5)));
+        store.disconnect(&"a", session.generation, state(), Expiry::Default, now);
+        assert_eq!(store.schedule_will("a", will(10), now), secs(5));
+
+        // The will is cancelled by the reconnection.
+        let session = store.connect("b", &connect(false, Expiry::Never));
+        store.disconnect(&"b", session.generation, state(), Expiry::Default, now);
+        assert_eq!(store.schedule_will("b", will(10), now), secs(10));
+        assert_eq!(
+            store.connect("b", &connect(false, Expiry::Never)).will,
+            None
+        );
+
+        // The will is published when the session is discarded.
+        let session = store.connect("c", &connect(false, Expiry::Never));
+        store.disconnect(&"c", session.generation, state(), Expiry::Default, now);
+        store.schedule_will("c", will(10), now);
+        assert_eq!(
+            store.connect("c", &connect(true, Expiry::Never)).will,
+            Some(will(10))
+        );
+
+        // The will is due right away when the session ends with the
+        // connection.
+        let session = store.connect("d", &connect(false, Expiry::Default));
+        store.disconnect(&"d", session.generation, state(), Expiry::Default, now);
+        assert_eq!(store.schedule_will("d", will(10), now), now);
+
+        assert_eq!(store.due_wills(now), vec![("d", will(10))]);
+        assert_eq!(store.due_wills(secs(5)), vec![("a", will(10))]);
+        assert!(store.due_wills(secs(10)).is_empty());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::{random, Publish, SharedSubscription, TopicName};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+/// How a `ShareBalancer` picks the member of a share group a message is
+/// delivered to.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum BalancePolicy {
+    /// The members are picked in turn, in the order they joined.
+    #[default]
+    RoundRobin,
+
+    /// A member is picked at random, using the random number generator of
+    /// the operating system. Selecting a member panics if it fails.
+    Random,
+
+    /// The member with the fewest deliveries in flight is picked, the earliest
+    /// one to join in case of a tie.
+    LeastInflight,
+
+    /// All the messages of the same topic are delivered to the same member,
+    /// as long as the members of the group do not change.
+    StickyByTopic,
+}
+
+#[derive(Debug, Clone)]
+struct Group<K> {
+    members: Vec<K>,
+    next: usize,
+}
+
+/// Dispatches the messages matching shared subscriptions to exactly one member
+/// of each share group, for brokers.
+///
+/// A share group gathers the clients subscribing with the same shared
+/// subscription, that is the same share name and topic filter. Members are
+/// identified by a key such as their client id.
+///
+/// The deliveries recorded with `sent` which are not acknowledged when the
+/// session of their member ends are dispatched again to another member of
+/// their group by `session_ended`.
+#[derive(Debug, Clone)]
+pub struct ShareBalancer<K> {
+    policy: BalancePolicy,
+    groups: HashMap<SharedSubscription, Group<K>>,
+    inflight: HashMap<K, usize>,
+    unacknowledged: HashMap<K, Vec<(u16, SharedSubscription, Publish)>>,
+}
+
+impl<K: Hash + Eq + Clone> ShareBalancer<K> {
+    /// Creates a balancer with no group, using `policy`.
+    pub fn new(policy: BalancePolicy) -> Self {
+        ShareBalancer {
+            policy,
+            groups: HashMap::new(),
+            inflight: HashMap::new(),
+            unacknowledged: HashMap::new(),
+        }
+    }
+
+    /// The policy picking the members.
+    pub fn policy(&self) -> BalancePolicy {
+        self.policy
+    }
+
+    /// Adds `member` to the group of `subscription`. Returns `false` if it is
+    /// already a member.
+    pub fn join(&mut self, subscription: SharedSubscription, member: K) -> bool {
+        let group = self.groups.entry(subscription).or_insert(Group {
+            members: Vec::new(),
+            next: 0,
+        });
+        if group.members.contains(&member) {
+            false
+        } else {
+            group.members.push(member);
+            true
+        }
+    }
+
+    /// Removes `member` from the group of `subscription`, such as when it
+    /// unsubscribes. Returns `false` if it was not a member.
+    pub fn leave(&mut self, subscription: &SharedSubscription, member: &K) -> bool {
+        let group = match self.groups.get_mut(subscription) {
+            Some(group) => group,
+            None => return false,
+        };
+        let index = match group.members.iter().position(|m| m == member) {
+            Some(index) => index,
+            None => return false,
+        };
+        group.members.remove(index);
+        if index < group.next {
+            group.next -= 1;
+        }
+        if group.members.is_empty() {
+            self.groups.remove(subscription);
+        }
+        true
+    }
+
+    /// Removes `member` from all the groups, such as when its session ends.
+    pub fn leave_all(&mut self, member: &K) {
+        let subscriptions: Vec<SharedSubscription> = self.groups.keys().cloned().collect();
+        for subscription in subscriptions {
+            self.leave(&subscription, member);
+        }
+        self.inflight.remove(member);
+    }
+
+    /// Returns the members of the group of `subscription`, in the order they
+    /// joined.
+    pub fn members(&self, subscription: &SharedSubscription) -> &[K] {
+        self.groups
+            .get(subscription)
+            .map_or(&[], |group| group.members.as_slice())
+    }
+
+    /// Picks the member of the group of `subscription` to deliver a message
+    /// of `topic` to, and counts the delivery as in flight for it. Returns
+    /// `None` if the group has no member.
+    pub fn select(&mut self, subscription: &SharedSubscription, topic: &TopicName) -> Option<K> {
+        let group = self.groups.get_mut(subscription)?;
+        let len = group.members.len();
+        if len == 0 {
+            return None;
+        }
+        let index = match self.policy {
+            BalancePolicy::RoundRobin => {
+                let index = group.next % len;
+                group.next = (index + 1) % len;
+                index
+            }
+            BalancePolicy::Random => random::below(len),
+            BalancePolicy::LeastInflight => {
+                let inflight = &self.inflight;
+                (0..len)
+                    .min_by_key(|&i| inflight.get(&group.members[i]).copied().unwrap_or(0))
+                    .unwrap_or(0)
+            }
+            BalancePolicy::StickyByTopic => {
+                let mut hasher = DefaultHasher::new();
+                topic.hash(&mut hasher);
+                (hasher.finish() % len as u64) as usize
+            }
+        };
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            %subscription,
+            %topic,
+            policy = ?self.policy,
+            member = index,
+            members = len,
+            "selected a shared subscription member"
+        );
+        let member = group.members[index].clone();
+        *self.inflight.entry(member.clone()).or_default() += 1;
+        Some(member)
+    }
+
+    /// Picks a member in each group whose topic filter matches `topic`.
+    pub fn dispatch(&mut self, topic: &TopicName) -> Vec<(SharedSubscription, K)> {
+        let subscriptions: Vec<SharedSubscription> = self
+            .groups
+            .keys()
+            .filter(|subscription| subscription.filter().matches(topic))
+            .cloned()
+            .collect();
+        subscriptions
+            .into_iter()
+            .filter_map(|subscription| {
+                let member = self.select(&subscription, topic)?;
+                Some((subscription, member))
+            })
+            .collect()
+    }
+
+    /// Counts a delivery to `member` as complete, once it has been
+    /// acknowledged or the message has been dropped.
+    pub fn completed(&mut self, member: &K) {
+        if let Some(inflight) = self.inflight.get_mut(member) {
+            *inflight = inflight.saturating_sub(1);
+        }
+    }
+
+    /// Records that `publish`, dispatched to `member` for `subscription`, was
+    /// sent with `packet_identifier`, until it is acknowledged. Only the
+    /// `AtLeastOnce` and `ExactlyOnce` deliveries need to be recorded.
+    pub fn sent(
+        &mut self,
+        member: K,
+        subscription: SharedSubscription,
+        packet_identifier: u16,
+        publish: Publish,
+    ) {
+        self.unacknowledged.entry(member).or_default().push((
+            packet_identifier,
+            subscription,
+            publish,
+        ));
+    }
+
+    /// Counts the delivery of `packet_identifier` to `member` as complete,
+    /// once it has been acknowledged. Returns `false` if it was not
+    /// recorded with `sent`.
+    pub fn acknowledged(&mut self, member: &K, packet_identifier: u16) -> bool {
+        let deliveries = match self.unacknowledged.get_mut(member) {
+            Some(deliveries) => deliveries,
+            None => return false,
+        };
+        let index = match deliveries
+            .iter()
+            .position(|(id, _, _)| *id == packet_identifier)
+        {
+            Some(index) => index,
+            None => return false,
+        };
+        deliveries.remove(index);
+        if deliveries.is_empty() {
+            self.unacknowledged.remove(member);
+        }
+        self.completed(member);
+        true
+    }
+
+    /// Removes `member` from all the groups once its session ends, and picks
+    /// another member of their group for each of its unacknowledged
+    /// deliveries. Returns the messages to send again, without packet
+    /// identifier. The messages of the groups left without member are
+    /// dropped.
+    pub fn session_ended(&mut self, member: &K) -> Vec<(K, SharedSubscription, Publish)> {
+        self.leave_all(member);
+        let deliveries = self.unacknowledged.remove(member).unwrap_or_default();
+        deliveries
+            .into_iter()
+            .filter_map(|(_, subscription, publish)| {
+                let member = self.select(&subscription, &publish.topic_name)?;
+                let publish = Publish {
+                    duplicate: false,
+                    packet_identifier: None,
+                    ..publish
+                };
+                Some((member, subscription, publish))
+            })
+            .collect()
+    }
+
+    /// Returns the number of deliveries in flight for `member`.
+    pub fn inflight(&self, member: &K) -> usize {
+        self.inflight.get(member).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    fn group(policy: BalancePolicy) -> (ShareBalancer<&'static str>, SharedSubscription) {
+        let subscription: SharedSubscription = "$share/group/sport/#".parse().unwrap();
+        let mut balancer = ShareBalancer::new(policy);
+        for member in ["a", "b", "c"] {
+            assert!(balancer.join(subscription.clone(), member));
+        }
+        assert!(!balancer.join(subscription.clone(), "a"));
+        (balancer, subscription)
+    }
+
+    fn select(
+        balancer: &mut ShareBalancer<&'static str>,
+        topic: &str,
+        n: usize,
+    ) -> Vec<&'static str> {
+        let topic = topic.try_into().unwrap();
+        let subscription = "$share/group/sport/#".parse().unwrap();
+        (0..n)
+            .map(|_| balancer.select(&subscription, &topic).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn round_robin() {
+        let (mut balancer, subscription) = group(BalancePolicy::RoundRobin);
+        assert_eq!(select(&mut balancer, "sport", 4), ["a", "b", "c", "a"]);
+        assert!(balancer.leave(&subscription, &"a"));
+        assert!(!balancer.leave(&subscription, &"a"));
+        assert_eq!(select(&mut balancer, "sport", 3), ["b", "c", "b"]);
+        balancer.leave_all(&"b");
+        balancer.leave_all(&"c");
+        assert!(balancer.members(&subscription).is_empty());
+        assert!(balancer
+            .select(&subscription, &"sport".try_into().unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn policies() {
+        let (mut balancer, _) = group(BalancePolicy::Random);
+        for member in select(&mut balancer, "sport", 20) {
+            assert!(["a", "b", "c"].contains(&member));
+        }
+
+        let (mut balancer, _) = group(BalancePolicy::LeastInflight);
+        assert_eq!(select(&mut balancer, "sport", 3), ["a", "b", "c"]);
+        balancer.completed(&"b");
+        assert_eq!(balancer.inflight(&"b"), 0);
+        assert_eq!(select(&mut balancer, "sport", 2), ["b", "a"]);
+
+        let (mut balancer, _) = group(BalancePolicy::StickyByTopic);
+        let member = select(&mut balancer, "sport/tennis", 1)[0];
+        assert_eq!(select(&mut balancer, "sport/tennis", 5), [member; 5]);
+    }
+
+    #[test]
+    fn dispatch() {
+        let (mut balancer, subscription) = group(BalancePolicy::RoundRobin);
+        let other: SharedSubscription = "$share/other/sport/tennis".parse().unwrap();
+        balancer.join(other.clone(), "d");
+
+        let mut dispatched = balancer.dispatch(&"sport/tennis".try_into().unwrap());
+        dispatched.sort_by_key(|(_, member)| *member);
+        assert_eq!(dispatched, vec![(subscription, "a"), (other, "d")]);
+        assert_eq!(
+            balancer.dispatch(&"sport/golf".try_into().unwrap()).len(),
+            1
+        );
+        assert!(balancer.dispatch(&"finance".try_into().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn redelivery() {
+        let (mut balancer, subscription) = group(BalancePolicy::RoundRobin);
+        let publish = Publish {
+            topic_name: "sport/tennis".try_into().unwrap(),
+            packet_identifier: Some(7),
+            ..Default::default()
+        };
+        let member = select(&mut balancer, "sport/tennis", 1)[0];
+        balancer.sent(member, subscription.clone(), 7, publish.clone());
+        balancer.sent(member, subscription.clone(), 8, publish.clone());
+        assert!(balancer.acknowledged(&member, 8));
+        assert!(!balancer.acknowledged(&member, 8));
+
+        let redelivered = balancer.session_ended(&member);
+        assert_eq!(redelivered.len(), 1);
+        let (other, redelivered_subscription, redelivered_publish) = &redelivered[0];
+        assert_ne!(*other, member);
+        assert_eq!(*redelivered_subscription, subscription);
+        assert_eq!(redelivered_publish.packet_identifier, None);
+        assert_eq!(balancer.members(&subscription).len(), 2);
+    }
+}
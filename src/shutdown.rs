@@ -0,0 +1,236 @@
+use crate::{Result as SageResult, Storage};
+use std::{
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Instant,
+};
+use tokio::sync::Notify;
+
+#[derive(Debug, Default)]
+struct State {
+    deadline: Option<Instant>,
+    connections: usize,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    state: Mutex<State>,
+    // Notified when the shutdown starts and when the last connection ends.
+    changed: Notify,
+}
+
+/// Coordinates the graceful shutdown of a broker between its accept loop,
+/// its connection tasks and whoever shuts it down, such as a signal handler.
+///
+/// The coordinator owns neither the listeners nor the connections, which are
+/// run by the tasks of the broker. A graceful shutdown goes as follows:
+/// 1. The accept loop registers each connection with `track`, which returns
+///    `None` once the shutdown started. The accept loop then closes the
+///    connection it just accepted and stops, dropping its listeners. It may
+///    also stop as soon as `signalled` resolves.
+/// 2. Each connection task waits for `signalled` alongside its network, then
+///    calls `ServerConnection::shutdown` with the deadline it returned. The
+///    connection sends a `Disconnect` packet with `ServerShuttingDown` once
+///    its deliveries in flight completed, or at the deadline at the latest.
+/// 3. The connection task keeps running the connection, sending its packets,
+///    handling the ones received and calling `handle_timeout` at
+///    `poll_timeout`, until it is closed. It then drops its `ShutdownGuard`.
+/// 4. `shutdown` starts the shutdown and resolves once every tracked
+///    connection ended, after flushing the storage of the broker. Since the
+///    connections close themselves at the deadline at the latest, it does not
+///    need a timer.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+impl Shutdown {
+    /// Creates a coordinator for a running broker.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.inner
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the deadline of the shutdown, once it started.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.lock().deadline
+    }
+
+    /// Returns `true` once the shutdown started.
+    pub fn is_shutting_down(&self) -> bool {
+        self.deadline().is_some()
+    }
+
+    /// Returns the number of tracked connections which did not end yet.
+    pub fn connections(&self) -> usize {
+        self.lock().connections
+    }
+
+    /// Tracks a new connection until the returned guard is dropped. Returns
+    /// `None` if the shutdown started, in which case the connection must be
+    /// closed right away.
+    pub fn track(&self) -> Option<ShutdownGuard> {
+        let mut state = self.lock();
+        if state.deadline.is_some() {
+            return None;
+        }
+        state.connections += 1;
+        Some(ShutdownGuard {
+            shutdown: self.clone(),
+        })
+    }
+
+    /// Waits for the shutdown to start, returning its deadline.
+    pub fn signalled(&self) -> impl Future<Output = Instant> + Send + '_ {
+        async move {
+            loop {
+                let changed = self.inner.changed.notified();
+                if let Some(deadline) = self.deadline() {
+                    return deadline;
+                }
+                changed.await;
+            }
+        }
+    }
+
+    /// Starts the shutdown if it did not start yet, then waits for every
+    /// tracked connection to end and flushes `storage`. The deadline of a
+    /// shutdown already started is kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `Storage::flush`.
+    pub fn shutdown<'a, S: Storage>(
+        &'a self,
+        deadline: Instant,
+        storage: &'a mut S,
+    ) -> impl Future<Output = SageResult<()>> + 'a {
+        {
+            let mut state = self.lock();
+            if state.deadline.is_none() {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(connections = state.connections, "shutting down");
+                state.deadline = Some(deadline);
+                self.inner.changed.notify_waiters();
+            }
+        }
+        async move {
+            loop {
+                let changed = self.inner.changed.notified();
+                if self.connections() == 0 {
+                    break;
+                }
+                changed.await;
+            }
+            storage.flush()
+        }
+    }
+}
+
+/// A connection tracked by a `Shutdown`, until it is dropped.
+#[derive(Debug)]
+pub struct ShutdownGuard {
+    shutdown: Shutdown,
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        let mut state = self.shutdown.lock();
+        state.connections -= 1;
+        if state.connections == 0 {
+            self.shutdown.inner.changed.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{
+        Connect, Disconnect, InMemoryStorage, Packet, PubAck, Publish, QoS,
+        ReasonCode::ServerShuttingDown, ServerConnection,
+    };
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn drain() {
+        let shutdown = Shutdown::new();
+        let first = shutdown.track().unwrap();
+        let second = shutdown.track().unwrap();
+        assert_eq!(shutdown.connections(), 2);
+        drop(second);
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let handle = shutdown.clone();
+        let connection = async move {
+            assert_eq!(handle.signalled().await, deadline);
+            assert!(handle.track().is_none());
+            assert_eq!(handle.connections(), 1);
+            drop(first);
+        };
+        let mut storage = InMemoryStorage::new();
+        let (_, result) = tokio::join!(connection, shutdown.shutdown(deadline, &mut storage));
+        result.unwrap();
+        assert!(shutdown.is_shutting_down());
+        assert_eq!(shutdown.connections(), 0);
+
+        let later = deadline + Duration::from_secs(1);
+        shutdown.shutdown(later, &mut storage).await.unwrap();
+        assert_eq!(shutdown.deadline(), Some(deadline));
+    }
+
+    #[tokio::test]
+    async fn graceful() {
+        let now = Instant::now();
+        let deadline = now + Duration::from_secs(30);
+        let shutdown = Shutdown::new();
+
+        // Accepted before the shutdown, with a delivery in flight.
+        let guard = shutdown.track().unwrap();
+        let mut server = ServerConnection::new();
+        server
+            .handle_packet(Connect::default().into(), now)
+            .unwrap();
+        server.connack(Default::default(), now).unwrap();
+        let publish = Publish {
+            qos: QoS::AtLeastOnce,
+            topic_name: "Around the World".try_into().unwrap(),
+            ..Default::default()
+        };
+        let packet_identifier = server.publish(publish, now).unwrap().unwrap();
+        while server.poll_transmit().is_some() {}
+
+        let handle = shutdown.clone();
+        let connection = async move {
+            let deadline = handle.signalled().await;
+            // The accept loop refuses the connections from now on.
+            assert!(handle.track().is_none());
+
+            server.shutdown(deadline, now);
+            assert!(server.is_connected());
+            let puback = PubAck {
+                packet_identifier,
+                ..Default::default()
+            };
+            server.handle_packet(puback.into(), now).unwrap();
+            assert!(server.is_closed());
+            let disconnect = Disconnect {
+                reason_code: ServerShuttingDown,
+                ..Default::default()
+            };
+            assert_eq!(server.poll_transmit(), Some(Packet::Disconnect(disconnect)));
+            drop(guard);
+        };
+        let mut storage = InMemoryStorage::new();
+        let (_, result) = tokio::join!(connection, shutdown.shutdown(deadline, &mut storage));
+        result.unwrap();
+        assert_eq!(shutdown.connections(), 0);
+    }
+}
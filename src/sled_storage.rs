@@ -0,0 +1,284 @@
+use crate::{
+    Message, Publish, Result as SageResult, Storage, StoredMessage, StoredSession, SubscriptionId,
+    TopicName,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sled::{Db, IVec, Tree};
+use std::{
+    io::{Error as IOError, ErrorKind},
+    path::Path,
+};
+
+// A message queued for a client, which unlike `Message` keeps the
+// subscription identifiers of the `Publish` packet.
+#[derive(Serialize, Deserialize)]
+struct QueuedMessage {
+    message: Message,
+    subscription_identifiers: Vec<u32>,
+}
+
+impl From<&Publish> for QueuedMessage {
+    fn from(publish: &Publish) -> Self {
+        QueuedMessage {
+            message: publish.clone().into(),
+            subscription_identifiers: publish
+                .subscription_identifiers
+                .iter()
+                .map(|id| id.get())
+                .collect(),
+        }
+    }
+}
+
+impl From<QueuedMessage> for Publish {
+    fn from(queued: QueuedMessage) -> Self {
+        Publish {
+            subscription_identifiers: queued
+                .subscription_identifiers
+                .into_iter()
+                .filter_map(SubscriptionId::new)
+                .collect(),
+            ..queued.message.into_publish(None, None)
+        }
+    }
+}
+
+fn storage_error(error: sled::Error) -> IOError {
+    IOError::other(error)
+}
+
+fn encode<T: Serialize>(value: &T) -> SageResult<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|e| IOError::new(ErrorKind::InvalidData, e).into())
+}
+
+fn decode<T: DeserializeOwned>(value: &IVec) -> SageResult<T> {
+    serde_json::from_slice(value).map_err(|e| IOError::new(ErrorKind::InvalidData, e).into())
+}
+
+// The keys of the queued messages and incoming packet identifiers of a
+// client start with its client id, which cannot contain a null character.
+fn prefix(client_id: &str) -> Vec<u8> {
+    let mut prefix = client_id.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+/// A `Storage` backed by a `sled` embedded database, which survives the
+/// restarts of the broker.
+///
+/// Each kind of state is kept in its own tree of the database, the sessions
+/// and messages being serialized as JSON. Changes are written to disk
+/// periodically by `sled`, and right away by `flush`.
+#[derive(Debug, Clone)]
+pub struct SledStorage {
+    db: Db,
+    sessions: Tree,
+    retained: Tree,
+    queues: Tree,
+    incoming: Tree,
+}
+
+impl SledStorage {
+    /// Opens the database at `path`, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an IO error if the database cannot be opened.
+    pub fn open<P: AsRef<Path>>(path: P) -> SageResult<Self> {
+        Self::with_db(sled::open(path).map_err(storage_error)?)
+    }
+
+    /// Uses an already opened database, such as a temporary one or one shared
+    /// with the rest of the broker.
+    ///
+    /// # Errors
+    ///
+    /// Returns an IO error if the trees of the storage cannot be opened.
+    pub fn with_db(db: Db) -> SageResult<Self> {
+        let tree = |name: &str| db.open_tree(name).map_err(storage_error);
+        Ok(SledStorage {
+            sessions: tree("sessions")?,
+            retained: tree("retained")?,
+            queues: tree("queues")?,
+            incoming: tree("incoming")?,
+            db,
+        })
+    }
+
+    fn clear_prefix(tree: &Tree, client_id: &str) -> SageResult<()> {
+        for entry in tree.scan_prefix(prefix(client_id)) {
+            let (key, _) = entry.map_err(storage_error)?;
+            tree.remove(key).map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    fn incoming_key(client_id: &str, packet_identifier: u16) -> Vec<u8> {
+        let mut key = prefix(client_id);
+        key.extend_from_slice(&packet_identifier.to_be_bytes());
+        key
+    }
+}
+
+impl Storage for SledStorage {
+    fn save_session(&mut self, client_id: &str, session: &StoredSession) -> SageResult<()> {
+        self.sessions
+            .insert(client_id, encode(session)?)
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn remove_session(&mut self, client_id: &str) -> SageResult<()> {
+        self.sessions.remove(client_id).map_err(storage_error)?;
+        Self::clear_prefix(&self.queues, client_id)?;
+        Self::clear_prefix(&self.incoming, client_id)
+    }
+
+    fn sessions(&self) -> SageResult<Vec<(String, StoredSession)>> {
+        self.sessions
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry.map_err(storage_error)?;
+                let client_id = String::from_utf8(key.to_vec())
+                    .map_err(|e| IOError::new(ErrorKind::InvalidData, e))?;
+                Ok((client_id, decode(&value)?))
+            })
+            .collect()
+    }
+
+    fn set_retained(&mut self, message: &StoredMessage) -> SageResult<()> {
+        self.retained
+            .insert(message.message.topic.as_str(), encode(message)?)
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn clear_retained(&mut self, topic: &TopicName) -> SageResult<()> {
+        self.retained
+            .remove(topic.as_str())
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn retained(&self) -> SageResult<Vec<StoredMessage>> {
+        self.retained
+            .iter()
+            .map(|entry| decode(&entry.map_err(storage_error)?.1))
+            .collect()
+    }
+
+    fn enqueue(&mut self, client_id: &str, publish: &Publish) -> SageResult<()> {
+        let mut key = prefix(client_id);
+        let id = self.db.generate_id().map_err(storage_error)?;
+        key.extend_from_slice(&id.to_be_bytes());
+        self.queues
+            .insert(key, encode(&QueuedMessage::from(publish))?)
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn dequeue(&mut self, client_id: &str) -> SageResult<Option<Publish>> {
+        let (key, value) = match self.queues.scan_prefix(prefix(client_id)).next() {
+            Some(entry) => entry.map_err(storage_error)?,
+            None => return Ok(None),
+        };
+        self.queues.remove(key).map_err(storage_error)?;
+        Ok(Some(decode::<QueuedMessage>(&value)?.into()))
+    }
+
+    fn drain(&mut self, client_id: &str) -> SageResult<Vec<Publish>> {
+        let mut queued = Vec::new();
+        while let Some(publish) = self.dequeue(client_id)? {
+            queued.push(publish);
+        }
+        Ok(queued)
+    }
+
+    fn insert_incoming(&mut self, client_id: &str, packet_identifier: u16) -> SageResult<bool> {
+        let key = Self::incoming_key(client_id, packet_identifier);
+        let previous = self
+            .incoming
+            .insert(key, IVec::default())
+            .map_err(storage_error)?;
+        Ok(previous.is_none())
+    }
+
+    fn remove_incoming(&mut self, client_id: &str, packet_identifier: u16) -> SageResult<bool> {
+        let key = Self::incoming_key(client_id, packet_identifier);
+        let previous = self.incoming.remove(key).map_err(storage_error)?;
+        Ok(previous.is_some())
+    }
+
+    fn incoming(&self, client_id: &str) -> SageResult<Vec<u16>> {
+        let prefix = prefix(client_id);
+        self.incoming
+            .scan_prefix(&prefix)
+            .map(|entry| {
+                let (key, _) = entry.map_err(storage_error)?;
+                let bytes: [u8; 2] = key[prefix.len()..]
+                    .try_into()
+                    .map_err(|e| IOError::new(ErrorKind::InvalidData, e))?;
+                Ok(u16::from_be_bytes(bytes))
+            })
+            .collect()
+    }
+
+    fn flush(&mut self) -> SageResult<()> {
+        self.db.flush().map_err(storage_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::{storage::unit::exercise, Expiry, InMemoryRetainStore, RetainStore};
+    use std::time::SystemTime;
+
+    #[test]
+    fn storage() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        exercise(&mut SledStorage::with_db(db).unwrap());
+    }
+
+    #[test]
+    fn restart() {
+        let path = std::env::temp_dir().join(format!("sage_mqtt-{}", std::process::id()));
+        let session = StoredSession {
+            expiry: Expiry::Never,
+            ..Default::default()
+        };
+        let publish = Publish {
+            topic_name: "Around the World".try_into().unwrap(),
+            subscription_identifiers: vec![SubscriptionId::new(7).unwrap()],
+            ..Default::default()
+        };
+        let retained = Message {
+            topic: "config/version".try_into().unwrap(),
+            payload: "1.2".into(),
+            retain: true,
+            ..Default::default()
+        };
+        let now = SystemTime::now();
+        {
+            let mut storage = SledStorage::open(&path).unwrap();
+            storage.save_session("daft", &session).unwrap();
+            storage
+                .set_retained(&StoredMessage::new(retained.clone(), now))
+                .unwrap();
+            storage.enqueue("daft", &publish).unwrap();
+            storage.insert_incoming("daft", 42).unwrap();
+            storage.flush().unwrap();
+        }
+        let mut storage = SledStorage::open(&path).unwrap();
+        assert_eq!(storage.sessions().unwrap(), vec![("daft".into(), session)]);
+        assert_eq!(storage.drain("daft").unwrap(), vec![publish]);
+        assert_eq!(storage.incoming("daft").unwrap(), vec![42]);
+        let mut store = InMemoryRetainStore::new();
+        assert_eq!(storage.restore_retained(&mut store, now).unwrap(), 1);
+        assert_eq!(store.clear(&retained.topic), Some(retained));
+        drop(storage);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
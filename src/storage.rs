@@ -0,0 +1,374 @@
+use crate::{Expiry, Message, Publish, Result as SageResult, RetainStore, SessionState, TopicName};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    time::{Duration, SystemTime},
+};
+
+/// A persistent session, as saved into a `Storage`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct StoredSession {
+    /// The state of the session.
+    pub state: SessionState,
+
+    /// The Session Expiry Interval of the session.
+    pub expiry: Expiry,
+}
+
+/// A retained message, as saved into a `Storage`.
+///
+/// Since the broker may be stopped for a while, the Message Expiry Interval
+/// of the message is saved as the wall clock time it expires at, so that the
+/// remaining interval can be computed once the message is restored.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StoredMessage {
+    /// The retained message.
+    pub message: Message,
+
+    /// The time the message expires at, if it has a Message Expiry Interval.
+    pub expires_at: Option<SystemTime>,
+}
+
+impl StoredMessage {
+    /// Prepares `message`, retained at `now`, to be saved.
+    pub fn new(message: Message, now: SystemTime) -> Self {
+        let expires_at = message
+            .properties
+            .message_expiry_interval
+            .and_then(|secs| now.checked_add(Duration::from_secs(secs.into())));
+        StoredMessage {
+            message,
+            expires_at,
+        }
+    }
+
+    /// Restores the message at `now`, with its Message Expiry Interval set to
+    /// the remaining one, rounded up. Returns `None` if it expired.
+    pub fn restore(self, now: SystemTime) -> Option<Message> {
+        let mut message = self.message;
+        if let Some(expires_at) = self.expires_at {
+            let remaining = expires_at.duration_since(now).ok()?;
+            if remaining.is_zero() {
+                return None;
+            }
+            let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+            message.properties.message_expiry_interval =
+                Some(u32::try_from(secs).unwrap_or(u32::MAX));
+        }
+        Some(message)
+    }
+}
+
+/// A persistence backend for the state of a broker which must survive its
+/// restarts: the persistent sessions, the retained messages, the messages
+/// queued for the disconnected clients and the packet identifiers of the
+/// incoming `ExactlyOnce` messages not released yet.
+///
+/// The in-memory stores, such as `SessionStore` or `InMemoryRetainStore`,
+/// remain the source of truth while the broker runs: the broker writes each
+/// change through to the storage, and reloads the stores from it at startup.
+/// Sessions are identified by the client id.
+pub trait Storage {
+    /// Saves the session of `client_id`, replacing the previous one if any.
+    fn save_session(&mut self, client_id: &str, session: &StoredSession) -> SageResult<()>;
+
+    /// Removes the session of `client_id`, along with its queued messages and
+    /// incoming packet identifiers.
+    fn remove_session(&mut self, client_id: &str) -> SageResult<()>;
+
+    /// Returns all the saved sessions, in no particular order.
+    fn sessions(&self) -> SageResult<Vec<(String, StoredSession)>>;
+
+    /// Saves the retained message of its topic, replacing the previous one if
+    /// any.
+    fn set_retained(&mut self, message: &StoredMessage) -> SageResult<()>;
+
+    /// Removes the retained message of `topic`, if any.
+    fn clear_retained(&mut self, topic: &TopicName) -> SageResult<()>;
+
+    /// Returns all the retained messages, in no particular order.
+    fn retained(&self) -> SageResult<Vec<StoredMessage>>;
+
+    /// Appends `publish` to the messages queued for `client_id`.
+    fn enqueue(&mut self, client_id: &str, publish: &Publish) -> SageResult<()>;
+
+    /// Removes and returns the oldest message queued for `client_id`, such as
+    /// when its `OfflineQueue` drops it.
+    fn dequeue(&mut self, client_id: &str) -> SageResult<Option<Publish>>;
+
+    /// Removes and returns all the messages queued for `client_id`, in the
+    /// order they were queued.
+    fn drain(&mut self, client_id: &str) -> SageResult<Vec<Publish>>;
+
+    /// Records the packet identifier of an incoming `ExactlyOnce` message of
+    /// `client_id`. Returns `false` if it was already recorded.
+    fn insert_incoming(&mut self, client_id: &str, packet_identifier: u16) -> SageResult<bool>;
+
+    /// Removes the packet identifier released by a `PubRel` packet. Returns
+    /// `false` if it was not recorded.
+    fn remove_incoming(&mut self, client_id: &str, packet_identifier: u16) -> SageResult<bool>;
+
+    /// Returns the recorded packet identifiers of `client_id`, in ascending
+    /// order.
+    fn incoming(&self, client_id: &str) -> SageResult<Vec<u16>>;
+
+    /// Ensures all the changes are durably written, such as before the
+    /// broker shuts down. Does nothing by default.
+    fn flush(&mut self) -> SageResult<()> {
+        Ok(())
+    }
+
+    /// Restores the retained messages into `store` at `now`, such as when the
+    /// broker starts, with their remaining Message Expiry Interval. The
+    /// messages which expired while the broker was stopped are removed from
+    /// the storage instead. Returns the number of restored messages.
+    fn restore_retained<R: RetainStore>(
+        &mut self,
+        store: &mut R,
+        now: SystemTime,
+    ) -> SageResult<usize> {
+        let mut restored = 0;
+        for stored in self.retained()? {
+            let topic = stored.message.topic.clone();
+            match stored.restore(now) {
+                Some(message) => {
+                    store.set(message);
+                    restored += 1;
+                }
+                None => self.clear_retained(&topic)?,
+            }
+        }
+        Ok(restored)
+    }
+}
+
+/// A `Storage` keeping everything in memory, which does not survive the
+/// restarts of the broker, for tests and brokers without persistence.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryStorage {
+    sessions: HashMap<String, StoredSession>,
+    retained: HashMap<TopicName, StoredMessage>,
+    queues: HashMap<String, VecDeque<Publish>>,
+    incoming: HashMap<String, BTreeSet<u16>>,
+}
+
+impl InMemoryStorage {
+    /// Creates an empty storage.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl Storage for InMemoryStorage {
+    fn save_session(&mut self, client_id: &str, session: &StoredSession) -> SageResult<()> {
+        self.sessions.insert(client_id.into(), session.clone());
+        Ok(())
+    }
+
+    fn remove_session(&mut self, client_id: &str) -> SageResult<()> {
+        self.sessions.remove(client_id);
+        self.queues.remove(client_id);
+        self.incoming.remove(client_id);
+        Ok(())
+    }
+
+    fn sessions(&self) -> SageResult<Vec<(String, StoredSession)>> {
+        Ok(self
+            .sessions
+            .iter()
+            .map(|(client_id, session)| (client_id.clone(), session.clone()))
+            .collect())
+    }
+
+    fn set_retained(&mut self, message: &StoredMessage) -> SageResult<()> {
+        self.retained
+            .insert(message.message.topic.clone(), message.clone());
+        Ok(())
+    }
+
+    fn clear_retained(&mut self, topic: &TopicName) -> SageResult<()> {
+        self.retained.remove(topic);
+        Ok(())
+    }
+
+    fn retained(&self) -> SageResult<Vec<StoredMessage>> {
+        Ok(self.retained.values().cloned().collect())
+    }
+
+    fn enqueue(&mut self, client_id: &str, publish: &Publish) -> SageResult<()> {
+        self.queues
+            .entry(client_id.into())
+            .or_default()
+            .push_back(publish.clone());
+        Ok(())
+    }
+
+    fn dequeue(&mut self, client_id: &str) -> SageResult<Option<Publish>> {
+        Ok(self
+            .queues
+            .get_mut(client_id)
+            .and_then(|queue| queue.pop_front()))
+    }
+
+    fn drain(&mut self, client_id: &str) -> SageResult<Vec<Publish>> {
+        Ok(self
+            .queues
+            .remove(client_id)
+            .map(Vec::from)
+            .unwrap_or_default())
+    }
+
+    fn insert_incoming(&mut self, client_id: &str, packet_identifier: u16) -> SageResult<bool> {
+        Ok(self
+            .incoming
+            .entry(client_id.into())
+            .or_default()
+            .insert(packet_identifier))
+    }
+
+    fn remove_incoming(&mut self, client_id: &str, packet_identifier: u16) -> SageResult<bool> {
+        Ok(self
+            .incoming
+            .get_mut(client_id)
+            .is_some_and(|incoming| incoming.remove(&packet_identifier)))
+    }
+
+    fn incoming(&self, client_id: &str) -> SageResult<Vec<u16>> {
+        Ok(self
+            .incoming
+            .get(client_id)
+            .map(|incoming| incoming.iter().copied().collect())
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod unit {
+
+    use super::*;
+    use crate::{InMemoryRetainStore, MessageProperties, QoS, Subscription, SubscriptionId};
+
+    fn publish(payload: &str) -> Publish {
+        Publish {
+            qos: QoS::AtLeastOnce,
+            topic_name: "Around the World".try_into().unwrap(),
+            message: payload.into(),
+            ..Default::default()
+        }
+    }
+
+    fn now() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    fn retained(topic: &str, message_expiry_interval: Option<u32>) -> Message {
+        Message {
+            topic: topic.try_into().unwrap(),
+            payload: "21".into(),
+            retain: true,
+            properties: MessageProperties {
+                message_expiry_interval,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    // Exercises any storage, so that every backend behaves the same.
+    pub(crate) fn exercise<S: Storage>(storage: &mut S) {
+        let session = StoredSession {
+            state: SessionState {
+                subscriptions: vec![Subscription {
+                    filter: "sensors/#".try_into().unwrap(),
+                    options: Default::default(),
+                    identifier: SubscriptionId::new(5),
+                }],
+                incoming: vec![3],
+                next_packet_identifier: 7,
+                ..Default::default()
+            },
+            expiry: Expiry::Seconds(3600),
+        };
+        storage.save_session("daft", &session).unwrap();
+        storage.save_session("punk", &Default::default()).unwrap();
+        let mut sessions = storage.sessions().unwrap();
+        sessions.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            sessions,
+            vec![
+                ("daft".into(), session),
+                ("punk".into(), Default::default())
+            ]
+        );
+
+        let message = StoredMessage::new(retained("sensors/temperature", Some(60)), now());
+        storage.set_retained(&message).unwrap();
+        assert_eq!(storage.retained().unwrap(), vec![message.clone()]);
+        storage.clear_retained(&message.message.topic).unwrap();
+        assert_eq!(storage.retained().unwrap(), vec![]);
+
+        for payload in ["Harder", "Better", "Faster"] {
+            storage.enqueue("daft", &publish(payload)).unwrap();
+        }
+        assert_eq!(storage.dequeue("daft").unwrap(), Some(publish("Harder")));
+        assert_eq!(
+            storage.drain("daft").unwrap(),
+            vec![publish("Better"), publish("Faster")]
+        );
+        assert_eq!(storage.drain("daft").unwrap(), vec![]);
+        assert_eq!(storage.dequeue("punk").unwrap(), None);
+
+        assert!(storage.insert_incoming("daft", 9).unwrap());
+        assert!(storage.insert_incoming("daft", 3).unwrap());
+        assert!(!storage.insert_incoming("daft", 9).unwrap());
+        assert_eq!(storage.incoming("daft").unwrap(), vec![3, 9]);
+        assert!(storage.remove_incoming("daft", 9).unwrap());
+        assert!(!storage.remove_incoming("daft", 9).unwrap());
+        assert_eq!(storage.incoming("punk").unwrap(), vec![]);
+
+        storage.enqueue("daft", &publish("Stronger")).unwrap();
+        storage.remove_session("daft").unwrap();
+        assert_eq!(storage.sessions().unwrap().len(), 1);
+        assert_eq!(storage.drain("daft").unwrap(), vec![]);
+        assert_eq!(storage.incoming("daft").unwrap(), vec![]);
+        storage.flush().unwrap();
+    }
+
+    #[test]
+    fn in_memory() {
+        exercise(&mut InMemoryStorage::new());
+    }
+
+    #[test]
+    fn restore_retained() {
+        let mut storage = InMemoryStorage::new();
+        for (topic, message_expiry_interval) in [
+            ("config/version", None),
+            ("sensors/temperature", Some(60)),
+            ("sensors/humidity", Some(10)),
+        ] {
+            let message = retained(topic, message_expiry_interval);
+            storage
+                .set_retained(&StoredMessage::new(message, now()))
+                .unwrap();
+        }
+
+        // The broker restarts 15.5 seconds later.
+        let later = now() + Duration::from_millis(15_500);
+        let mut store = InMemoryRetainStore::new();
+        assert_eq!(storage.restore_retained(&mut store, later).unwrap(), 2);
+        assert_eq!(store.len(), 2);
+        let filter = "config/version".try_into().unwrap();
+        assert_eq!(
+            store.matching(&filter),
+            vec![retained("config/version", None)]
+        );
+        let filter = "sensors/#".try_into().unwrap();
+        assert_eq!(
+            store.matching(&filter),
+            vec![retained("sensors/temperature", Some(45))]
+        );
+        assert_eq!(storage.retained().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::{Error as SageError, ReasonCode::ProtocolError};
+use std::{
+    convert::TryFrom,
+    fmt::{Display, Formatter, Result as FmtResult},
+    num::NonZeroU32,
+};
+
+/// The identifier of a subscription, given in a `Subscribe` packet and
+/// attached to the `Publish` packets sent because of that subscription.
+/// A subscription identifier cannot be `0`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u32", into = "u32")
+)]
+pub struct SubscriptionId(NonZeroU32);
+
+impl SubscriptionId {
+    /// Creates a subscription identifier, or returns `None` if `id` is `0`.
+    pub fn new(id: u32) -> Option<Self> {
+        NonZeroU32::new(id).map(SubscriptionId)
+    }
+
+    /// Returns the value of the subscription identifier.
+    pub fn get(self) -> u32 {
+        self.0.get()
+    }
+}
+
+impl From<NonZeroU32> for SubscriptionId {
+    fn from(id: NonZeroU32) -> Self {
+        SubscriptionId(id)
+    }
+}
+
+impl TryFrom<u32> for SubscriptionId {
+    type Error = SageError;
+    fn try_from(id: u32) -> Result<Self, Self::Error> {
+        SubscriptionId::new(id).ok_or_else(|| ProtocolError.into())
+    }
+}
+
+impl From<SubscriptionId> for u32 {
+    fn from(id: SubscriptionId) -> Self {
+        id.get()
+    }
+}
+
+impl Display for SubscriptionId {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn try_from() {
+        assert_eq!(SubscriptionId::try_from(451).unwrap().get(), 451);
+        assert!(SubscriptionId::try_from(0).is_err());
+    }
+}
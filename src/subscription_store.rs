@@ -0,0 +1,262 @@
+use crate::{Subscribe, SubscriptionId, SubscriptionOptions, TopicFilter, TopicName, TopicTree};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+/// A subscription of a client, as given in a `Subscribe` packet.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Subscription {
+    /// The topic filter of the subscription.
+    pub filter: TopicFilter,
+
+    /// The options of the subscription.
+    pub options: SubscriptionOptions,
+
+    /// The subscription identifier, to attach to the `Publish` packets sent
+    /// because of the subscription.
+    pub identifier: Option<SubscriptionId>,
+}
+
+/// Records the subscriptions of each client, identified by a key such as its
+/// client id.
+/// The subscriptions of a client are kept in the order they were made. A
+/// subscription to a filter the client is already subscribed to replaces the
+/// existing one in place, with its new options and identifier.
+///
+/// The clients are also indexed in a `TopicTree` under the filters they are
+/// subscribed to, so that routing a message with `subscribers` only walks the
+/// levels of its topic rather than every subscription.
+#[derive(Debug, Clone)]
+pub struct SubscriptionStore<K> {
+    clients: HashMap<K, Vec<Subscription>>,
+    tree: TopicTree<K>,
+}
+
+impl<K> Default for SubscriptionStore<K> {
+    fn default() -> Self {
+        SubscriptionStore {
+            clients: HashMap::new(),
+            tree: TopicTree::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> SubscriptionStore<K> {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Records `subscription` for `client`. Returns `true` if it is a new
+    /// subscription, `false` if it replaced an existing one for the same
+    /// filter.
+    pub fn insert(&mut self, client: K, subscription: Subscription) -> bool
+    where
+        K: Clone,
+    {
+        let subscriptions = self.clients.entry(client.clone()).or_default();
+        match subscriptions
+            .iter_mut()
+            .find(|s| s.filter == subscription.filter)
+        {
+            Some(existing) => {
+                *existing = subscription;
+                false
+            }
+            None => {
+                self.tree.insert(&subscription.filter, client);
+                subscriptions.push(subscription);
+                true
+            }
+        }
+    }
+
+    /// Records all the subscriptions of a `Subscribe` packet for `client`.
+    /// Returns, for each of them, whether it is a new subscription, as needed
+    /// by `RetainHandling::OnFirstSubscribe`.
+    pub fn subscribe(&mut self, client: K, subscribe: &Subscribe) -> Vec<bool>
+    where
+        K: Clone,
+    {
+        subscribe
+            .subscriptions
+            .iter()
+            .map(|(filter, options)| {
+                self.insert(
+                    client.clone(),
+                    Subscription {
+                        filter: filter.clone(),
+                        options: *options,
+                        identifier: subscribe.subscription_identifier,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Removes the subscription of `client` to `filter`, returning it if any.
+    pub fn unsubscribe(&mut self, client: &K, filter: &TopicFilter) -> Option<Subscription> {
+        let subscriptions = self.clients.get_mut(client)?;
+        let index = subscriptions.iter().position(|s| &s.filter == filter)?;
+        let subscription = subscriptions.remove(index);
+        if subscriptions.is_empty() {
+            self.clients.remove(client);
+        }
+        self.unindex(client, filter);
+        Some(subscription)
+    }
+
+    /// Removes all the subscriptions of `client`, such as when its session
+    /// ends, and returns them.
+    pub fn remove_client(&mut self, client: &K) -> Vec<Subscription> {
+        let subscriptions = self.clients.remove(client).unwrap_or_default();
+        for subscription in &subscriptions {
+            self.unindex(client, &subscription.filter);
+        }
+        subscriptions
+    }
+
+    // Removes a single entry of `client`, since the filters which only differ
+    // by their share name are stored under the same node of the tree.
+    fn unindex(&mut self, client: &K, filter: &TopicFilter) {
+        let mut removed = false;
+        self.tree.remove(filter, |c| {
+            let matches = !removed && c == client;
+            removed |= matches;
+            matches
+        });
+    }
+
+    /// Returns the clients having at least one subscription whose filter
+    /// matches `topic`, including shared ones, without duplicates. The
+    /// deliveries to each of them can then be resolved with an
+    /// `OverlapPolicy`.
+    pub fn subscribers(&self, topic: &TopicName) -> Vec<&K> {
+        let mut seen = HashSet::new();
+        let subscribers: Vec<&K> = self
+            .tree
+            .matches(topic)
+            .into_iter()
+            .filter(|client| seen.insert(*client))
+            .collect();
+        #[cfg(feature = "tracing")]
+        tracing::trace!(%topic, subscribers = subscribers.len(), "matched subscribers");
+        subscribers
+    }
+
+    /// Returns the subscriptions of `client`, in the order they were made.
+    pub fn subscriptions(&self, client: &K) -> &[Subscription] {
+        self.clients.get(client).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the subscriptions of `client` whose filter matches `topic`, in
+    /// the order they were made.
+    pub fn matching(&self, client: &K, topic: &TopicName) -> Vec<&Subscription> {
+        self.subscriptions(client)
+            .iter()
+            .filter(|s| s.filter.matches(topic))
+            .collect()
+    }
+
+    /// Returns the subscription identifiers to attach to a `Publish` packet
+    /// of `topic` sent to `client`: the identifiers of all the matching
+    /// subscriptions, without duplicates.
+    pub fn subscription_identifiers(&self, client: &K, topic: &TopicName) -> Vec<SubscriptionId> {
+        let mut identifiers = Vec::new();
+        for identifier in self
+            .matching(client, topic)
+            .into_iter()
+            .filter_map(|s| s.identifier)
+        {
+            if !identifiers.contains(&identifier) {
+                identifiers.push(identifier);
+            }
+        }
+        identifiers
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use crate::QoS;
+
+    fn subscription(filter: &str, identifier: u32) -> Subscription {
+        Subscription {
+            filter: filter.try_into().unwrap(),
+            options: Default::default(),
+            identifier: SubscriptionId::new(identifier),
+        }
+    }
+
+    #[test]
+    fn resubscribe() {
+        let mut store = SubscriptionStore::new();
+        assert!(store.insert("client", subscription("sport/#", 1)));
+        assert!(store.insert("client", subscription("sport/tennis/+", 2)));
+        let replacement = Subscription {
+            options: SubscriptionOptions {
+                qos: QoS::AtMostOnce,
+                ..Default::default()
+            },
+            ..subscription("sport/#", 3)
+        };
+        assert!(!store.insert("client", replacement.clone()));
+        assert_eq!(
+            store.subscriptions(&"client"),
+            &[replacement, subscription("sport/tennis/+", 2)]
+        );
+
+        let subscribe = Subscribe {
+            subscription_identifier: SubscriptionId::new(4),
+            subscriptions: vec![
+                ("sport/tennis/+".try_into().unwrap(), Default::default()),
+                ("finance".try_into().unwrap(), Default::default()),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(store.subscribe("client", &subscribe), vec![false, true]);
+        assert_eq!(store.subscriptions(&"client").len(), 3);
+    }
+
+    #[test]
+    fn matching() {
+        let mut store = SubscriptionStore::new();
+        store.insert("client", subscription("sport/#", 1));
+        store.insert("client", subscription("sport/tennis/+", 2));
+        store.insert("client", subscription("+/tennis/#", 1));
+        store.insert("client", subscription("finance", 0));
+        store.insert("other", subscription("#", 5));
+
+        let topic = "sport/tennis/player1".try_into().unwrap();
+        assert_eq!(store.matching(&"client", &topic).len(), 3);
+        assert_eq!(
+            store.subscription_identifiers(&"client", &topic),
+            vec![
+                SubscriptionId::new(1).unwrap(),
+                SubscriptionId::new(2).unwrap()
+            ]
+        );
+
+        let mut subscribers = store.subscribers(&topic);
+        subscribers.sort_unstable();
+        assert_eq!(subscribers, vec![&"client", &"other"]);
+        assert_eq!(
+            store.subscribers(&"$SYS/monitor".try_into().unwrap()).len(),
+            0
+        );
+
+        let finance = "finance".try_into().unwrap();
+        assert_eq!(
+            store.unsubscribe(&"client", &finance),
+            Some(subscription("finance", 0))
+        );
+        assert_eq!(store.unsubscribe(&"client", &finance), None);
+        assert_eq!(store.remove_client(&"client").len(), 3);
+        assert!(store.subscriptions(&"client").is_empty());
+        assert_eq!(store.subscribers(&topic), vec![&"other"]);
+    }
+}
@@ -0,0 +1,16 @@
+/// A deterministic generator of pseudo-random numbers, for the tests running
+/// random sequences of operations which must be reproducible.
+pub(crate) struct TestRng(u64);
+
+impl TestRng {
+    /// Creates a generator starting from `seed`.
+    pub(crate) fn new(seed: u64) -> Self {
+        TestRng(seed)
+    }
+
+    /// Returns the next number, of 31 bits.
+    pub(crate) fn draw(&mut self) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1);
+        self.0 >> 33
+    }
+}
@@ -0,0 +1,33 @@
+#[cfg(feature = "native-tls")]
+mod native;
+#[cfg(feature = "tls")]
+pub(crate) mod rustls;
+
+#[cfg(feature = "tls")]
+pub use self::rustls::TlsListener;
+#[cfg(feature = "native-tls")]
+pub use native::NativeTlsListener;
+
+use std::io::{Error as IoError, ErrorKind};
+
+/// Whether a TLS listener requests a certificate from its clients. The
+/// certificates presented by the clients are given in
+/// `PeerInfo::certificates`.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub enum ClientCertificates {
+    /// The clients are not asked for a certificate.
+    #[default]
+    NotRequested,
+
+    /// The clients may present a certificate, issued by one of the given PEM
+    /// encoded certificate authorities.
+    Optional(Vec<u8>),
+
+    /// The clients must present a certificate, issued by one of the given PEM
+    /// encoded certificate authorities.
+    Required(Vec<u8>),
+}
+
+fn invalid_input<E: std::fmt::Display>(e: E) -> crate::Error {
+    IoError::new(ErrorKind::InvalidInput, e.to_string()).into()
+}
@@ -0,0 +1,161 @@
+use super::{invalid_input, ClientCertificates};
+use crate::{handshakes::Handshakes, Listener, PeerInfo, Result as SageResult};
+use std::{
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio_native_tls::{native_tls, TlsAcceptor, TlsStream};
+
+/// A `Listener` accepting TLS connections over the connections of another
+/// listener, such as a `TcpListener`, using the TLS stack of the platform
+/// through `native-tls`. It is used the same way as `TlsListener`.
+///
+/// The TLS handshakes run as the listener is polled, a connection is only
+/// returned once its handshake completes. Connections whose handshake fails
+/// are dropped. `native-tls` can neither advertise ALPN protocols nor request
+/// client certificates, `PeerInfo::certificates` only holds the certificate
+/// the peer may have presented anyway.
+pub struct NativeTlsListener<L: Listener> {
+    listener: L,
+    acceptor: TlsAcceptor,
+    handshakes: Handshakes<TlsStream<L::Stream>>,
+}
+
+impl<L> NativeTlsListener<L>
+where
+    L: Listener,
+    L::Stream: Send + 'static,
+{
+    /// Creates a listener accepting TLS connections with `acceptor`.
+    pub fn new(listener: L, acceptor: native_tls::TlsAcceptor) -> Self {
+        NativeTlsListener {
+            listener,
+            acceptor: acceptor.into(),
+            handshakes: Default::default(),
+        }
+    }
+
+    /// Creates a listener accepting TLS connections with the PEM encoded
+    /// `certificates` chain, leaf first, and PKCS #8 `private_key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` IO error if the certificates or the key
+    /// cannot be loaded, and an `Unsupported` IO error if
+    /// `client_certificates` requests client certificates.
+    pub fn from_pem(
+        listener: L,
+        certificates: &[u8],
+        private_key: &[u8],
+        client_certificates: ClientCertificates,
+    ) -> SageResult<Self> {
+        if client_certificates != ClientCertificates::NotRequested {
+            return Err(IoError::new(
+                ErrorKind::Unsupported,
+                "client certificates are not supported by native-tls",
+            )
+            .into());
+        }
+        let identity =
+            native_tls::Identity::from_pkcs8(certificates, private_key).map_err(invalid_input)?;
+        let acceptor = native_tls::TlsAcceptor::new(identity).map_err(invalid_input)?;
+        Ok(Self::new(listener, acceptor))
+    }
+
+    /// Drops the connections whose handshake does not complete within
+    /// `timeout`, 10 seconds by default. `None` waits for the handshakes
+    /// indefinitely.
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshakes.set_timeout(timeout);
+        self
+    }
+
+    /// Stops accepting connections while `maximum` handshakes are in
+    /// progress, 256 by default.
+    pub fn with_maximum_handshakes(mut self, maximum: usize) -> Self {
+        self.handshakes.set_maximum(maximum);
+        self
+    }
+
+    /// Returns the wrapped listener.
+    pub fn get_ref(&self) -> &L {
+        &self.listener
+    }
+}
+
+impl<L> Listener for NativeTlsListener<L>
+where
+    L: Listener,
+    L::Stream: Send + 'static,
+{
+    type Stream = TlsStream<L::Stream>;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+        let acceptor = &self.acceptor;
+        self.handshakes
+            .poll_accept(&mut self.listener, cx, |stream, peer| {
+                let acceptor = acceptor.clone();
+                async move {
+                    let stream = acceptor
+                        .accept(stream)
+                        .await
+                        .map_err(|e| IoError::new(ErrorKind::ConnectionAborted, e))?;
+                    let certificates = match stream.get_ref().peer_certificate() {
+                        Ok(Some(certificate)) => certificate.to_der().into_iter().collect(),
+                        _ => Vec::new(),
+                    };
+                    Ok((
+                        stream,
+                        PeerInfo {
+                            certificates,
+                            ..peer
+                        },
+                    ))
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use std::io::Cursor;
+
+    struct Closed;
+
+    impl Listener for Closed {
+        type Stream = Cursor<Vec<u8>>;
+
+        fn poll_accept(&mut self, _: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn from_pem() {
+        let kind = |result: SageResult<NativeTlsListener<Closed>>| match result {
+            Err(crate::Error::Io(e)) => Some(e.kind()),
+            _ => None,
+        };
+        assert_eq!(
+            kind(NativeTlsListener::from_pem(
+                Closed,
+                b"",
+                b"",
+                Default::default()
+            )),
+            Some(ErrorKind::InvalidInput)
+        );
+        assert_eq!(
+            kind(NativeTlsListener::from_pem(
+                Closed,
+                b"",
+                b"",
+                ClientCertificates::Optional(Vec::new())
+            )),
+            Some(ErrorKind::Unsupported)
+        );
+    }
+}
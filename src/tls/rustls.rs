@@ -0,0 +1,269 @@
+use super::{invalid_input, ClientCertificates};
+use crate::{handshakes::Handshakes, Listener, PeerInfo, Result as SageResult};
+use std::{
+    io::Result as IoResult,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio_rustls::{
+    rustls::{
+        server::{
+            AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient,
+            ResolvesServerCertUsingSni, WantsServerCert,
+        },
+        sign::{self, CertifiedKey},
+        Certificate, ConfigBuilder, PrivateKey, RootCertStore, ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+
+/// The ALPN protocol identifier of MQTT.
+const ALPN_MQTT: &[u8] = b"mqtt";
+
+/// A `Listener` accepting TLS connections over the connections of another
+/// listener, such as a `TcpListener`, using `rustls`.
+///
+/// The TLS handshakes run as the listener is polled, a connection is only
+/// returned once its handshake completes. Connections whose handshake fails
+/// are dropped.
+///
+/// Secure WebSocket (`wss://`) connections are accepted by a
+/// `WebSocketListener` wrapping a `TlsListener`, see `SecureWebSocketListener`.
+pub struct TlsListener<L: Listener> {
+    listener: L,
+    acceptor: TlsAcceptor,
+    handshakes: Handshakes<TlsStream<L::Stream>>,
+}
+
+impl<L> TlsListener<L>
+where
+    L: Listener,
+    L::Stream: Send + 'static,
+{
+    /// Creates a listener accepting TLS connections with `config`.
+    pub fn new(listener: L, config: Arc<ServerConfig>) -> Self {
+        TlsListener {
+            listener,
+            acceptor: config.into(),
+            handshakes: Default::default(),
+        }
+    }
+
+    /// Creates a listener accepting TLS connections with the PEM encoded
+    /// `certificates` chain, leaf first, and `private_key`, which is either a
+    /// PKCS #8, a PKCS #1 or a SEC1 key. The `mqtt` ALPN protocol is
+    /// advertised.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` IO error if the certificates, the key or the
+    /// certificate authorities of `client_certificates` cannot be loaded.
+    pub fn from_pem(
+        listener: L,
+        certificates: &[u8],
+        private_key: &[u8],
+        client_certificates: ClientCertificates,
+    ) -> SageResult<Self> {
+        let config = server_config(certificates, private_key, client_certificates)?;
+        Ok(Self::new(listener, Arc::new(config)))
+    }
+
+    /// Creates a listener accepting TLS connections with a certificate
+    /// selected upon the server name the client requests with SNI, as
+    /// `from_pem` does with a single certificate. `certificates` lists each
+    /// server name along with its PEM encoded certificate chain and private
+    /// key. The handshakes of the clients requesting no server name or an
+    /// unknown one fail.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` IO error if `certificates` is empty, if a
+    /// certificate or a key cannot be loaded, if a certificate is not valid
+    /// for its server name, or if the certificate authorities of
+    /// `client_certificates` cannot be loaded.
+    pub fn from_pem_by_server_name(
+        listener: L,
+        certificates: &[(&str, &[u8], &[u8])],
+        client_certificates: ClientCertificates,
+    ) -> SageResult<Self> {
+        if certificates.is_empty() {
+            return Err(invalid_input("no server name certificate"));
+        }
+        let mut resolver = ResolvesServerCertUsingSni::new();
+        for (server_name, certificates, private_key) in certificates {
+            let private_key = load_private_key(private_key)?;
+            let key = sign::any_supported_type(&private_key).map_err(invalid_input)?;
+            let certified_key = CertifiedKey::new(load_certificates(certificates)?, key);
+            resolver
+                .add(server_name, certified_key)
+                .map_err(invalid_input)?;
+        }
+        let mut config =
+            config_builder(client_certificates)?.with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = vec![ALPN_MQTT.to_vec()];
+        Ok(Self::new(listener, Arc::new(config)))
+    }
+
+    /// Drops the connections whose handshake does not complete within
+    /// `timeout`, 10 seconds by default. `None` waits for the handshakes
+    /// indefinitely.
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshakes.set_timeout(timeout);
+        self
+    }
+
+    /// Stops accepting connections while `maximum` handshakes are in
+    /// progress, 256 by default.
+    pub fn with_maximum_handshakes(mut self, maximum: usize) -> Self {
+        self.handshakes.set_maximum(maximum);
+        self
+    }
+
+    /// Returns the wrapped listener.
+    pub fn get_ref(&self) -> &L {
+        &self.listener
+    }
+}
+
+impl<L> Listener for TlsListener<L>
+where
+    L: Listener,
+    L::Stream: Send + 'static,
+{
+    type Stream = TlsStream<L::Stream>;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+        let acceptor = &self.acceptor;
+        self.handshakes
+            .poll_accept(&mut self.listener, cx, |stream, peer| {
+                let accept = acceptor.accept(stream);
+                async move {
+                    let stream = accept.await?;
+                    let certificates = stream
+                        .get_ref()
+                        .1
+                        .peer_certificates()
+                        .map(|chain| chain.iter().map(|c| c.0.clone()).collect())
+                        .unwrap_or_default();
+                    Ok((
+                        stream,
+                        PeerInfo {
+                            certificates,
+                            ..peer
+                        },
+                    ))
+                }
+            })
+    }
+}
+
+/// Builds the configuration of a server with the PEM encoded `certificates`
+/// chain and `private_key`, advertising the `mqtt` ALPN protocol.
+pub(crate) fn server_config(
+    certificates: &[u8],
+    private_key: &[u8],
+    client_certificates: ClientCertificates,
+) -> SageResult<ServerConfig> {
+    let mut config = config_builder(client_certificates)?
+        .with_single_cert(
+            load_certificates(certificates)?,
+            load_private_key(private_key)?,
+        )
+        .map_err(invalid_input)?;
+    config.alpn_protocols = vec![ALPN_MQTT.to_vec()];
+    Ok(config)
+}
+
+fn config_builder(
+    client_certificates: ClientCertificates,
+) -> SageResult<ConfigBuilder<ServerConfig, WantsServerCert>> {
+    let builder = ServerConfig::builder().with_safe_defaults();
+    Ok(match client_certificates {
+        ClientCertificates::NotRequested => builder.with_no_client_auth(),
+        ClientCertificates::Optional(authorities) => builder.with_client_cert_verifier(
+            AllowAnyAnonymousOrAuthenticatedClient::new(root_store(&authorities)?).boxed(),
+        ),
+        ClientCertificates::Required(authorities) => builder.with_client_cert_verifier(
+            AllowAnyAuthenticatedClient::new(root_store(&authorities)?).boxed(),
+        ),
+    })
+}
+
+fn load_certificates(pem: &[u8]) -> SageResult<Vec<Certificate>> {
+    let certificates = rustls_pemfile::certs(&mut &pem[..])?;
+    if certificates.is_empty() {
+        Err(invalid_input("no PEM certificate"))
+    } else {
+        Ok(certificates.into_iter().map(Certificate).collect())
+    }
+}
+
+fn load_private_key(pem: &[u8]) -> SageResult<PrivateKey> {
+    let mut reader = pem;
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => (),
+        }
+    }
+    Err(invalid_input("no PEM private key"))
+}
+
+fn root_store(pem: &[u8]) -> SageResult<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(pem)? {
+        roots.add(&certificate).map_err(invalid_input)?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use std::io::{Cursor, ErrorKind};
+
+    struct Closed;
+
+    impl Listener for Closed {
+        type Stream = Cursor<Vec<u8>>;
+
+        fn poll_accept(&mut self, _: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn invalid_pem() {
+        let invalid = |result: SageResult<TlsListener<Closed>>| match result {
+            Err(crate::Error::Io(e)) => e.kind() == ErrorKind::InvalidInput,
+            _ => false,
+        };
+        assert!(invalid(TlsListener::from_pem(
+            Closed,
+            b"",
+            b"",
+            Default::default()
+        )));
+        assert!(invalid(TlsListener::from_pem(
+            Closed,
+            b"not a certificate",
+            b"not a key",
+            ClientCertificates::Required(Vec::new())
+        )));
+        assert!(invalid(TlsListener::from_pem_by_server_name(
+            Closed,
+            &[],
+            Default::default()
+        )));
+        assert!(invalid(TlsListener::from_pem_by_server_name(
+            Closed,
+            &[("broker.example.com", b"", b"")],
+            Default::default()
+        )));
+    }
+}
@@ -1,6 +1,11 @@
-use std::fmt;
+use crate::{
+    Error as SageError,
+    ReasonCode::{TopicFilterInvalid, TopicNameInvalid},
+};
+use std::{convert::TryFrom, fmt, str::FromStr};
 
 const LEVEL_SEPARATOR: char = '/';
+const SHARE_PREFIX: &str = "$share/";
 
 #[derive(Hash, Debug, Eq, PartialEq, Clone)]
 enum TopicLevel {
@@ -33,13 +38,13 @@ impl fmt::Display for Topic {
             self.spec
                 .iter()
                 .map(|l| match l {
-                    TopicLevel::Empty => "",
-                    TopicLevel::Name(s) => s.as_ref(),
-                    TopicLevel::Share(_) => "Shareuh",
-                    TopicLevel::Any => "+",
-                    TopicLevel::MultipleAny => "#",
+                    TopicLevel::Empty => "".into(),
+                    TopicLevel::Name(s) => s.clone(),
+                    TopicLevel::Share(s) => format!("{}{}", SHARE_PREFIX, s),
+                    TopicLevel::Any => "+".into(),
+                    TopicLevel::MultipleAny => "#".into(),
                 })
-                .collect::<Vec<&str>>()
+                .collect::<Vec<String>>()
                 .join("/")
         )
     }
@@ -55,7 +60,7 @@ impl From<&str> for Topic {
     /// Builds a new topic has a name
     fn from(s: &str) -> Self {
         let (mut shared, topic) = {
-            let stripped = s.strip_prefix("$share/");
+            let stripped = s.strip_prefix(SHARE_PREFIX);
             (stripped.is_some(), stripped.unwrap_or(s))
         };
 
@@ -89,7 +94,10 @@ impl From<&str> for Topic {
 impl Topic {
     /// Returns the name of the share if any
     pub fn share(&self) -> Option<String> {
-        None
+        match self.spec.first() {
+            Some(TopicLevel::Share(share)) => Some(share.clone()),
+            _ => None,
+        }
     }
 
     /// Checks whether the topic contains any wildcard
@@ -98,6 +106,330 @@ impl Topic {
             .iter()
             .any(|l| matches!(l, TopicLevel::Any | TopicLevel::MultipleAny))
     }
+
+    /// Checks whether the topic is a valid topic filter:
+    /// - It is at least one character long.
+    /// - The `+` wildcard only occupies entire levels.
+    /// - The `#` wildcard only occupies an entire level and is the last one.
+    /// - The share name of a shared subscription is not empty, does not
+    ///   contain wildcards and is followed with a non-empty topic filter.
+    pub fn is_valid_filter(&self) -> bool {
+        let has_wildcard = |s: &str| s.contains(['+', '#']);
+        let last = self.spec.len() - 1;
+        self.spec != [TopicLevel::Empty]
+            && self.spec.iter().enumerate().all(|(i, l)| match l {
+                TopicLevel::Empty | TopicLevel::Any => true,
+                TopicLevel::Name(s) => !has_wildcard(s),
+                TopicLevel::Share(s) => {
+                    !s.is_empty()
+                        && !has_wildcard(s)
+                        && i < last
+                        && self.spec[i + 1..] != [TopicLevel::Empty]
+                }
+                TopicLevel::MultipleAny => i == last,
+            })
+    }
+}
+
+/// A topic name, as used to publish messages. A topic name cannot contain
+/// wildcards. It can only be empty in a `Publish` packet using a topic alias.
+#[derive(Hash, Debug, Eq, PartialEq, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct TopicName(String);
+
+impl TopicName {
+    /// Returns the topic name as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the topic name split into its levels.
+    pub fn topic(&self) -> Topic {
+        Topic::from(self.as_str())
+    }
+
+    /// Returns `true` if the topic name starts with `$`. Such topics are
+    /// reserved for the server, such as `$SYS/` for server statistics, and
+    /// must not be used by applications to publish their own messages. Topic
+    /// filters starting with a wildcard do not match them.
+    pub fn is_system(&self) -> bool {
+        self.0.starts_with('$')
+    }
+}
+
+impl TryFrom<String> for TopicName {
+    type Error = SageError;
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        if name.contains(['+', '#']) {
+            Err(TopicNameInvalid.into())
+        } else {
+            Ok(TopicName(name))
+        }
+    }
+}
+
+impl TryFrom<&str> for TopicName {
+    type Error = SageError;
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        TopicName::try_from(name.to_string())
+    }
+}
+
+impl From<TopicName> for String {
+    fn from(name: TopicName) -> Self {
+        name.0
+    }
+}
+
+impl fmt::Display for TopicName {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(&self.0)
+    }
+}
+
+/// A topic filter, as used to subscribe to topics. A topic filter can contain
+/// wildcards and is always valid according to `Topic::is_valid_filter`.
+#[derive(Hash, Debug, Eq, PartialEq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct TopicFilter(String);
+
+impl TopicFilter {
+    /// Returns the topic filter as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the topic filter split into its levels.
+    pub fn topic(&self) -> Topic {
+        Topic::from(self.as_str())
+    }
+
+    // The topic filter without the share name of a shared subscription.
+    pub(crate) fn filter(&self) -> &str {
+        match self.0.strip_prefix(SHARE_PREFIX) {
+            Some(shared) => shared.split_once(LEVEL_SEPARATOR).map_or("", |(_, f)| f),
+            None => self.as_str(),
+        }
+    }
+
+    /// Returns `true` if the topic filter is a shared subscription.
+    pub fn is_shared(&self) -> bool {
+        self.0.starts_with(SHARE_PREFIX)
+    }
+
+    /// Splits a shared subscription into its share name and topic filter.
+    /// Returns `None` if the topic filter is not a shared subscription.
+    pub fn shared_subscription(&self) -> Option<SharedSubscription> {
+        self.as_str().parse().ok()
+    }
+
+    /// Checks whether the topic filter matches the topic name `name`:
+    /// - `+` matches exactly one level, which can be empty.
+    /// - `#` matches any number of levels, including the parent level, so that
+    ///   `sport/#` matches `sport`.
+    /// - Topic names starting with `$` are not matched by a filter starting
+    ///   with a wildcard.
+    ///
+    /// The share name of a shared subscription is ignored.
+    pub fn matches(&self, name: &TopicName) -> bool {
+        let filter = self.filter();
+        if name.is_system() && filter.starts_with(['+', '#']) {
+            return false;
+        }
+
+        let mut levels = name.as_str().split(LEVEL_SEPARATOR);
+        for level in filter.split(LEVEL_SEPARATOR) {
+            match level {
+                "#" => return true,
+                "+" => {
+                    if levels.next().is_none() {
+                        return false;
+                    }
+                }
+                _ => {
+                    if levels.next() != Some(level) {
+                        return false;
+                    }
+                }
+            }
+        }
+        levels.next().is_none()
+    }
+
+    /// Returns the canonical form of the topic filter, which is the same for
+    /// all the filters matching the same topic names, and can be used to key
+    /// subscriptions. The only such filters are `#` and `+/#`, both matching
+    /// any topic name not starting with `$`, whose canonical form is `#`.
+    /// The share name of a shared subscription is kept.
+    pub fn canonical(&self) -> TopicFilter {
+        let filter = self.filter();
+        if filter == "+/#" {
+            let share = &self.0[..self.0.len() - filter.len()];
+            TopicFilter(format!("{}#", share))
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Checks whether the topic filter matches the same topic names as
+    /// `other`. The share names of shared subscriptions are ignored.
+    pub fn is_equivalent(&self, other: &TopicFilter) -> bool {
+        self.canonical().filter() == other.canonical().filter()
+    }
+
+    /// Checks whether the topic filter is subsumed by `other`, that is whether
+    /// all the topic names it matches are also matched by `other`. The share
+    /// names of shared subscriptions are ignored.
+    pub fn is_subsumed_by(&self, other: &TopicFilter) -> bool {
+        let (canonical, other) = (self.canonical(), other.canonical());
+        let (filter, other) = (canonical.filter(), other.filter());
+        if other.starts_with(['+', '#']) && filter.starts_with('$') {
+            return false;
+        }
+
+        let mut levels = filter.split(LEVEL_SEPARATOR);
+        for other_level in other.split(LEVEL_SEPARATOR) {
+            match (levels.next(), other_level) {
+                (_, "#") => return true,
+                (None, _) | (Some("#"), _) => return false,
+                (Some(_), "+") => (),
+                (Some(level), other_level) => {
+                    if level == "+" || level != other_level {
+                        return false;
+                    }
+                }
+            }
+        }
+        levels.next().is_none()
+    }
+
+    /// Checks whether the topic filter overlaps with `other`, that is whether
+    /// some topic names are matched by both. The share names of shared
+    /// subscriptions are ignored.
+    pub fn overlaps(&self, other: &TopicFilter) -> bool {
+        let (filter, other) = (self.filter(), other.filter());
+        if (filter.starts_with(['+', '#']) && other.starts_with('$'))
+            || (other.starts_with(['+', '#']) && filter.starts_with('$'))
+        {
+            return false;
+        }
+
+        let mut levels = filter.split(LEVEL_SEPARATOR);
+        for other_level in other.split(LEVEL_SEPARATOR) {
+            match (levels.next(), other_level) {
+                (Some("#"), _) | (_, "#") => return true,
+                (None, _) => return false,
+                (Some("+"), _) | (_, "+") => (),
+                (Some(level), other_level) => {
+                    if level != other_level {
+                        return false;
+                    }
+                }
+            }
+        }
+        matches!(levels.next(), None | Some("#"))
+    }
+}
+
+impl TryFrom<String> for TopicFilter {
+    type Error = SageError;
+    fn try_from(filter: String) -> Result<Self, Self::Error> {
+        if Topic::from(filter.as_str()).is_valid_filter() {
+            Ok(TopicFilter(filter))
+        } else {
+            Err(TopicFilterInvalid.into())
+        }
+    }
+}
+
+impl TryFrom<&str> for TopicFilter {
+    type Error = SageError;
+    fn try_from(filter: &str) -> Result<Self, Self::Error> {
+        TopicFilter::try_from(filter.to_string())
+    }
+}
+
+impl From<TopicFilter> for String {
+    fn from(filter: TopicFilter) -> Self {
+        filter.0
+    }
+}
+
+impl fmt::Display for TopicFilter {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(&self.0)
+    }
+}
+
+/// A shared subscription, with a topic filter of the form
+/// `$share/{share_name}/{filter}`. The messages matching a shared subscription
+/// are delivered to only one of the clients subscribing with the same share
+/// name.
+#[derive(Hash, Debug, Eq, PartialEq, Clone)]
+pub struct SharedSubscription {
+    share_name: String,
+    filter: TopicFilter,
+}
+
+impl SharedSubscription {
+    /// Creates a shared subscription.
+    /// The share name must not be empty nor contain `/`, `+` or `#`, and the
+    /// topic filter must not be itself a shared subscription, otherwise
+    /// `TopicFilterInvalid` is returned.
+    pub fn new<S: Into<String>>(share_name: S, filter: TopicFilter) -> Result<Self, SageError> {
+        let share_name = share_name.into();
+        if share_name.is_empty() || share_name.contains(['/', '+', '#']) || filter.is_shared() {
+            Err(TopicFilterInvalid.into())
+        } else {
+            Ok(SharedSubscription { share_name, filter })
+        }
+    }
+
+    /// The name of the share, grouping the subscriptions.
+    pub fn share_name(&self) -> &str {
+        &self.share_name
+    }
+
+    /// The topic filter the subscription is made to.
+    pub fn filter(&self) -> &TopicFilter {
+        &self.filter
+    }
+}
+
+impl FromStr for SharedSubscription {
+    type Err = SageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (share_name, filter) = s
+            .strip_prefix(SHARE_PREFIX)
+            .and_then(|s| s.split_once(LEVEL_SEPARATOR))
+            .ok_or(SageError::from(TopicFilterInvalid))?;
+        SharedSubscription::new(share_name, filter.try_into()?)
+    }
+}
+
+impl fmt::Display for SharedSubscription {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "{}{}/{}",
+            SHARE_PREFIX, self.share_name, self.filter
+        )
+    }
+}
+
+impl From<SharedSubscription> for TopicFilter {
+    fn from(shared: SharedSubscription) -> Self {
+        TopicFilter(shared.to_string())
+    }
 }
 
 #[cfg(test)]
@@ -162,6 +494,148 @@ mod unit {
         share_wildcard_pound_2: ("$share/#/#",             vec![Share("#".into()), MultipleAny], ),
     }
 
+    #[test]
+    fn valid_filters() {
+        for filter in &[
+            "sport/tennis/#",
+            "+",
+            "+/+",
+            "/+",
+            "sport/+/player1",
+            "$share/g/#",
+        ] {
+            assert!(Topic::from(*filter).is_valid_filter(), "{}", filter);
+        }
+        for filter in &[
+            "",
+            "sport/#/tennis",
+            "a/b+",
+            "sport#",
+            "$share/g",
+            "$share/g/",
+            "$share/",
+            "$share//a",
+            "$share/+/a",
+            "$share/#/a",
+            "$share/g+/a",
+            "$share/g#/a",
+        ] {
+            assert!(!Topic::from(*filter).is_valid_filter(), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn topic_name() {
+        assert_eq!(
+            TopicName::try_from("sport/tennis").unwrap().as_str(),
+            "sport/tennis"
+        );
+        assert!(TopicName::try_from("sport/+").is_err());
+        assert!(TopicFilter::try_from("sport/+").is_ok());
+        assert!(TopicFilter::try_from("sport/#/tennis").is_err());
+    }
+
+    #[test]
+    fn matches() {
+        let matches = |filter: &str, name: &str| {
+            TopicFilter::try_from(filter)
+                .unwrap()
+                .matches(&TopicName::try_from(name).unwrap())
+        };
+        assert!(matches("sport/tennis/player1/#", "sport/tennis/player1"));
+        assert!(matches(
+            "sport/tennis/player1/#",
+            "sport/tennis/player1/ranking"
+        ));
+        assert!(matches("sport/#", "sport"));
+        assert!(matches("#", "sport/tennis"));
+        assert!(matches("sport/tennis/+", "sport/tennis/player1"));
+        assert!(!matches("sport/tennis/+", "sport/tennis/player1/ranking"));
+        assert!(!matches("sport/+", "sport"));
+        assert!(matches("sport/+", "sport/"));
+        assert!(matches("+/+", "/finance"));
+        assert!(matches("/+", "/finance"));
+        assert!(!matches("+", "/finance"));
+        assert!(!matches("#", "$SYS/monitor/Clients"));
+        assert!(!matches("+/monitor/Clients", "$SYS/monitor/Clients"));
+        assert!(matches("$SYS/#", "$SYS/monitor/Clients"));
+        assert!(matches("$SYS/monitor/+", "$SYS/monitor/Clients"));
+        assert!(matches("$share/group/sport/+", "sport/tennis"));
+        assert!(!matches("sport/tennis", "sport/Tennis"));
+    }
+
+    #[test]
+    fn compare_filters() {
+        let filter = |filter: &str| TopicFilter::try_from(filter).unwrap();
+        assert_eq!(filter("+/#").canonical(), filter("#"));
+        assert_eq!(filter("$share/g/+/#").canonical(), filter("$share/g/#"));
+        assert_eq!(filter("a/+/#").canonical(), filter("a/+/#"));
+        assert!(filter("#").is_equivalent(&filter("$share/g/+/#")));
+        assert!(!filter("a/#").is_equivalent(&filter("a/+/#")));
+
+        let subsumed = |a: &str, b: &str| filter(a).is_subsumed_by(&filter(b));
+        assert!(subsumed("sport/tennis/+", "sport/#"));
+        assert!(subsumed("sport/tennis/player1", "sport/+/player1"));
+        assert!(subsumed("sport", "sport/#"));
+        assert!(subsumed("sport/+/#", "sport/#"));
+        assert!(subsumed("#", "+/#"));
+        assert!(subsumed("sport/+", "sport/+"));
+        assert!(!subsumed("sport/#", "sport/+/#"));
+        assert!(!subsumed("sport/+", "sport/tennis"));
+        assert!(!subsumed("sport/tennis", "sport/tennis/+"));
+        assert!(!subsumed("sport/tennis/+", "sport/tennis"));
+        assert!(!subsumed("$SYS/monitor", "#"));
+        assert!(subsumed("$SYS/monitor", "$SYS/#"));
+
+        let overlaps = |a: &str, b: &str| {
+            let (a, b) = (filter(a), filter(b));
+            assert_eq!(a.overlaps(&b), b.overlaps(&a));
+            a.overlaps(&b)
+        };
+        assert!(overlaps("sport/+/player1", "sport/tennis/+"));
+        assert!(overlaps("sport/#", "sport"));
+        assert!(overlaps("sport", "sport/#"));
+        assert!(overlaps("+/+", "/finance"));
+        assert!(!overlaps("sport/+", "sport"));
+        assert!(!overlaps("sport/tennis", "sport/golf/#"));
+        assert!(!overlaps("+/monitor", "$SYS/+"));
+        assert!(overlaps("$SYS/+", "$SYS/monitor/#"));
+    }
+
+    #[test]
+    fn is_system() {
+        assert!(TopicName::try_from("$SYS/monitor").unwrap().is_system());
+        assert!(!TopicName::try_from("SYS/monitor").unwrap().is_system());
+        assert!(!TopicName::try_from("/$SYS").unwrap().is_system());
+    }
+
+    #[test]
+    fn shared_subscription() {
+        let filter = TopicFilter::try_from("$share/consumer1/sport/tennis/+").unwrap();
+        assert!(filter.is_shared());
+        assert_eq!(filter.topic().share(), Some("consumer1".into()));
+        assert_eq!(filter.topic().to_string(), filter.as_str());
+
+        let shared = filter.shared_subscription().unwrap();
+        assert_eq!(shared.share_name(), "consumer1");
+        assert_eq!(shared.filter().as_str(), "sport/tennis/+");
+        assert_eq!(TopicFilter::from(shared), filter);
+
+        let filter = TopicFilter::try_from("sport/tennis/+").unwrap();
+        assert!(!filter.is_shared());
+        assert_eq!(filter.shared_subscription(), None);
+
+        assert!("$share/consumer1".parse::<SharedSubscription>().is_err());
+        assert!("$share//sport".parse::<SharedSubscription>().is_err());
+        assert!("$share/consumer1/".parse::<SharedSubscription>().is_err());
+        assert!("$share/c+/sport".parse::<SharedSubscription>().is_err());
+        assert!(TopicFilter::try_from("$share/consumer1").is_err());
+        assert!(TopicFilter::try_from("$share/consumer1/").is_err());
+        assert!(TopicFilter::try_from("$share/consumer#/sport").is_err());
+        assert!(SharedSubscription::new("a+b", filter.clone()).is_err());
+        assert!(SharedSubscription::new("consumer1", filter).is_ok());
+    }
+
     #[test]
     fn default_is_empty() {
         assert_eq!(
@@ -0,0 +1,267 @@
+use crate::{
+    Error, Publish,
+    ReasonCode::{ProtocolError, TopicAliasInvalid},
+    Result as SageResult, TopicName,
+};
+use std::collections::HashMap;
+
+/// Resolves the topic aliases of the `Publish` packets received on a
+/// connection.
+/// Topic aliases are only valid for the lifetime of a network connection, a
+/// new registry must be used upon each connection.
+#[derive(Debug, Default, Clone)]
+pub struct TopicAliasRegistry {
+    maximum: u16,
+    aliases: HashMap<u16, TopicName>,
+}
+
+impl TopicAliasRegistry {
+    /// Creates a registry accepting aliases up to `maximum`, the topic alias
+    /// maximum advertised to the peer.
+    pub fn new(maximum: u16) -> Self {
+        TopicAliasRegistry {
+            maximum,
+            aliases: HashMap::new(),
+        }
+    }
+
+    /// Resolves the topic name of a received `Publish` packet.
+    /// If the packet has a topic alias and a topic name, the alias is bound to
+    /// the topic name. If the topic name is empty, it is replaced with the one
+    /// bound to the alias. In both cases the topic alias is removed from the
+    /// packet, since it is meaningless outside of the connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TopicAliasInvalid` if the alias is zero, greater than the
+    /// maximum or not bound to any topic name, and `ProtocolError` if the
+    /// packet has neither a topic name nor an alias.
+    pub fn resolve(&mut self, publish: &mut Publish) -> SageResult<()> {
+        match publish.topic_alias.take() {
+            Some(alias) if alias == 0 || alias > self.maximum => Err(TopicAliasInvalid.into()),
+            Some(alias) => {
+                if publish.topic_name.as_str().is_empty() {
+                    match self.aliases.get(&alias) {
+                        Some(topic_name) => {
+                            publish.topic_name = topic_name.clone();
+                            Ok(())
+                        }
+                        None => Err(TopicAliasInvalid.into()),
+                    }
+                } else {
+                    self.aliases.insert(alias, publish.topic_name.clone());
+                    Ok(())
+                }
+            }
+            None if publish.topic_name.as_str().is_empty() => Err(ProtocolError.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What a `TopicAliasAllocator` does with a new topic once all the aliases
+/// allowed by the peer are in use.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum TopicAliasExhaustion {
+    /// The least recently used alias is bound to the new topic.
+    #[default]
+    EvictLeastRecentlyUsed,
+
+    /// The packet is sent with its topic name and no alias.
+    SendTopicName,
+
+    /// The assignment fails with `Error::TopicAliasesExhausted`.
+    Error,
+}
+
+/// Assigns topic aliases to the `Publish` packets sent on a connection.
+/// Aliases are bound to topics as they are published. Once all the aliases
+/// allowed by the peer are in use, the allocator follows its
+/// `TopicAliasExhaustion` policy, which rebinds the least recently used alias
+/// to the new topic by default.
+/// Topic aliases are only valid for the lifetime of a network connection, a
+/// new allocator must be used upon each connection.
+#[derive(Debug, Default, Clone)]
+pub struct TopicAliasAllocator {
+    maximum: u16,
+    exhaustion: TopicAliasExhaustion,
+    clock: u64,
+    aliases: HashMap<TopicName, u16>,
+    topics: HashMap<u16, (TopicName, u64)>,
+}
+
+impl TopicAliasAllocator {
+    /// Creates an allocator using aliases up to `maximum`, the topic alias
+    /// maximum advertised by the peer. If `maximum` is zero, no alias is ever
+    /// used.
+    pub fn new(maximum: u16) -> Self {
+        TopicAliasAllocator {
+            maximum,
+            ..Default::default()
+        }
+    }
+
+    /// Sets what the allocator does with a new topic once all the aliases are
+    /// in use.
+    pub fn with_exhaustion(self, exhaustion: TopicAliasExhaustion) -> Self {
+        TopicAliasAllocator { exhaustion, ..self }
+    }
+
+    /// Sets the topic alias of a `Publish` packet to send:
+    /// - If the topic is already bound to an alias, the topic name is replaced
+    ///   with the alias.
+    /// - Otherwise, an alias is bound to the topic and sent along with the
+    ///   topic name. If all of them are in use, the exhaustion policy of the
+    ///   allocator applies.
+    ///
+    /// Packets which already have a topic alias or no topic name are left
+    /// untouched, as well as all packets if the peer does not accept aliases.
+    /// Failures of `TopicAliasExhaustion::Error` leave the packet untouched
+    /// too, use `try_assign` to catch them.
+    pub fn assign(&mut self, publish: &mut Publish) {
+        let _ = self.try_assign(publish);
+    }
+
+    /// Sets the topic alias of a `Publish` packet to send, as `assign` does.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::TopicAliasesExhausted` if the topic is not bound to an
+    /// alias, all of them are in use and the exhaustion policy is
+    /// `TopicAliasExhaustion::Error`. The packet is left untouched.
+    pub fn try_assign(&mut self, publish: &mut Publish) -> SageResult<()> {
+        if self.maximum == 0
+            || publish.topic_alias.is_some()
+            || publish.topic_name.as_str().is_empty()
+        {
+            return Ok(());
+        }
+
+        self.clock += 1;
+        if let Some(&alias) = self.aliases.get(&publish.topic_name) {
+            if let Some((_, last_used)) = self.topics.get_mut(&alias) {
+                *last_used = self.clock;
+            }
+            publish.topic_name = Default::default();
+            publish.topic_alias = Some(alias);
+            return Ok(());
+        }
+
+        let alias = if self.topics.len() < self.maximum as usize {
+            self.topics.len() as u16 + 1
+        } else if self.exhaustion == TopicAliasExhaustion::SendTopicName {
+            return Ok(());
+        } else if self.exhaustion == TopicAliasExhaustion::Error {
+            return Err(Error::TopicAliasesExhausted);
+        } else {
+            let (&alias, (topic_name, _)) = self
+                .topics
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .expect("at least one alias is in use");
+            self.aliases.remove(topic_name);
+            alias
+        };
+        self.aliases.insert(publish.topic_name.clone(), alias);
+        self.topics
+            .insert(alias, (publish.topic_name.clone(), self.clock));
+        publish.topic_alias = Some(alias);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    fn publish(topic_name: &str, topic_alias: Option<u16>) -> Publish {
+        Publish {
+            topic_name: topic_name.try_into().unwrap(),
+            topic_alias,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn assign() {
+        let mut allocator = TopicAliasAllocator::new(2);
+        let mut registry = TopicAliasRegistry::new(2);
+        let mut send = |topic_name: &str| {
+            let mut tested_result = publish(topic_name, None);
+            allocator.assign(&mut tested_result);
+            let sent = tested_result.clone();
+            registry.resolve(&mut tested_result).unwrap();
+            assert_eq!(tested_result, publish(topic_name, None));
+            sent
+        };
+
+        assert_eq!(send("a"), publish("a", Some(1)));
+        assert_eq!(send("b"), publish("b", Some(2)));
+        assert_eq!(send("a"), publish("", Some(1)));
+        assert_eq!(send("c"), publish("c", Some(2)));
+        assert_eq!(send("a"), publish("", Some(1)));
+        assert_eq!(send("b"), publish("b", Some(2)));
+        assert_eq!(send("c"), publish("c", Some(1)));
+
+        let mut allocator = TopicAliasAllocator::new(0);
+        let mut tested_result = publish("a", None);
+        allocator.assign(&mut tested_result);
+        assert_eq!(tested_result, publish("a", None));
+    }
+
+    #[test]
+    fn exhaustion() {
+        let mut allocator =
+            TopicAliasAllocator::new(1).with_exhaustion(TopicAliasExhaustion::SendTopicName);
+        let mut tested_result = publish("a", None);
+        allocator.try_assign(&mut tested_result).unwrap();
+        assert_eq!(tested_result, publish("a", Some(1)));
+        let mut tested_result = publish("b", None);
+        allocator.try_assign(&mut tested_result).unwrap();
+        assert_eq!(tested_result, publish("b", None));
+
+        let mut allocator =
+            TopicAliasAllocator::new(1).with_exhaustion(TopicAliasExhaustion::Error);
+        allocator.assign(&mut publish("a", None));
+        let mut tested_result = publish("b", None);
+        assert!(matches!(
+            allocator.try_assign(&mut tested_result),
+            Err(Error::TopicAliasesExhausted)
+        ));
+        assert_eq!(tested_result, publish("b", None));
+        let mut tested_result = publish("a", None);
+        allocator.try_assign(&mut tested_result).unwrap();
+        assert_eq!(tested_result, publish("", Some(1)));
+    }
+
+    #[test]
+    fn resolve() {
+        let mut registry = TopicAliasRegistry::new(10);
+
+        let mut tested_result = publish("sport/tennis", Some(1));
+        registry.resolve(&mut tested_result).unwrap();
+        assert_eq!(tested_result, publish("sport/tennis", None));
+
+        let mut tested_result = publish("", Some(1));
+        registry.resolve(&mut tested_result).unwrap();
+        assert_eq!(tested_result, publish("sport/tennis", None));
+
+        let mut tested_result = publish("sport/golf", Some(1));
+        registry.resolve(&mut tested_result).unwrap();
+        let mut tested_result = publish("", Some(1));
+        registry.resolve(&mut tested_result).unwrap();
+        assert_eq!(tested_result, publish("sport/golf", None));
+
+        for (topic_name, alias) in [("", Some(2)), ("sport", Some(0)), ("sport", Some(11))] {
+            assert!(matches!(
+                registry.resolve(&mut publish(topic_name, alias)),
+                Err(Error::Reason(TopicAliasInvalid))
+            ));
+        }
+        assert!(matches!(
+            registry.resolve(&mut publish("", None)),
+            Err(Error::Reason(ProtocolError))
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::{Error as SageError, ReasonCode::TopicFilterInvalid, TopicFilter, TopicName};
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+const LEVEL_SEPARATOR: char = '/';
+
+/// A topic filter whose wildcards capture the levels of the topic names it
+/// matches, such as `devices/{device}/telemetry/+`.
+///
+/// A level written `{name}` is a `+` wildcard whose value can be retrieved by
+/// name as well as by position. Braces are not allowed in the other levels.
+/// The values of `+` and `#` wildcards can only be retrieved by position.
+/// Matching follows the same rules as `TopicFilter::matches`.
+#[derive(Hash, Debug, Eq, PartialEq, Clone)]
+pub struct TopicTemplate {
+    filter: TopicFilter,
+    names: Vec<Option<String>>,
+}
+
+impl TopicTemplate {
+    /// The topic filter of the template, with the named levels replaced with
+    /// `+`, to subscribe with.
+    pub fn filter(&self) -> &TopicFilter {
+        &self.filter
+    }
+
+    /// Returns the levels captured by the wildcards of the template if it
+    /// matches `topic`.
+    pub fn captures<'a>(&'a self, topic: &'a TopicName) -> Option<Captures<'a>> {
+        if !self.filter.matches(topic) {
+            return None;
+        }
+
+        let mut values = Vec::with_capacity(self.names.len());
+        let mut rest = Some(topic.as_str());
+        for level in self.filter.filter().split(LEVEL_SEPARATOR) {
+            let (head, tail) = match rest {
+                Some(rest) => match rest.split_once(LEVEL_SEPARATOR) {
+                    Some((head, tail)) => (head, Some(tail)),
+                    None => (rest, None),
+                },
+                // Only `#` can match the parent level.
+                None => ("", None),
+            };
+            match level {
+                "+" => values.push(head),
+                // `#` captures all the remaining levels.
+                "#" => values.push(rest.unwrap_or("")),
+                _ => (),
+            }
+            rest = tail;
+        }
+        Some(Captures {
+            names: &self.names,
+            values,
+        })
+    }
+}
+
+impl FromStr for TopicTemplate {
+    type Err = SageError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut names = Vec::new();
+        let mut levels = Vec::new();
+        for level in template.split(LEVEL_SEPARATOR) {
+            match level.strip_prefix('{').and_then(|l| l.strip_suffix('}')) {
+                Some(name) => {
+                    if name.is_empty() || name.contains(['{', '}', '+', '#']) {
+                        return Err(TopicFilterInvalid.into());
+                    }
+                    names.push(Some(name.to_string()));
+                    levels.push("+");
+                }
+                None => {
+                    if level.contains(['{', '}']) {
+                        return Err(TopicFilterInvalid.into());
+                    }
+                    if level == "+" || level == "#" {
+                        names.push(None);
+                    }
+                    levels.push(level);
+                }
+            }
+        }
+        let filter = TopicFilter::try_from(levels.join("/"))?;
+        if filter.is_shared() {
+            return Err(TopicFilterInvalid.into());
+        }
+        Ok(TopicTemplate { filter, names })
+    }
+}
+
+impl TryFrom<&str> for TopicTemplate {
+    type Error = SageError;
+    fn try_from(template: &str) -> Result<Self, Self::Error> {
+        template.parse()
+    }
+}
+
+impl fmt::Display for TopicTemplate {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = self.names.iter();
+        let levels: Vec<String> = self
+            .filter
+            .as_str()
+            .split(LEVEL_SEPARATOR)
+            .map(|level| match level {
+                "+" | "#" => match names.next() {
+                    Some(Some(name)) => format!("{{{}}}", name),
+                    _ => level.into(),
+                },
+                _ => level.into(),
+            })
+            .collect();
+        formatter.write_str(&levels.join("/"))
+    }
+}
+
+/// The levels of a topic name captured by the wildcards of a `TopicTemplate`,
+/// in the order of the wildcards.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Captures<'a> {
+    names: &'a [Option<String>],
+    values: Vec<&'a str>,
+}
+
+impl<'a> Captures<'a> {
+    /// Returns the number of captured levels.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns `true` if the template has no wildcard.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the value captured by the wildcard at `index`. The value of a
+    /// `#` wildcard is all the levels it matched, which is empty if it only
+    /// matched the parent level.
+    pub fn get(&self, index: usize) -> Option<&'a str> {
+        self.values.get(index).copied()
+    }
+
+    /// Returns the value captured by the level named `name`.
+    pub fn name(&self, name: &str) -> Option<&'a str> {
+        let index = self
+            .names
+            .iter()
+            .position(|n| matches!(n, Some(n) if n == name))?;
+        self.get(index)
+    }
+
+    /// Returns an iterator over the captured values, in order.
+    pub fn iter(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.values.iter().copied()
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let template: TopicTemplate = "devices/{device}/telemetry/+".parse().unwrap();
+        assert_eq!(template.filter().as_str(), "devices/+/telemetry/+");
+        assert_eq!(template.to_string(), "devices/{device}/telemetry/+");
+
+        for template in [
+            "devices/{}/telemetry",
+            "devices/{a+}",
+            "devices/{device}x",
+            "devices/#/telemetry",
+            "$share/group/devices/+",
+        ] {
+            assert!(TopicTemplate::try_from(template).is_err(), "{}", template);
+        }
+        assert!(matches!(
+            TopicTemplate::try_from("devices/{device}x"),
+            Err(SageError::Reason(TopicFilterInvalid))
+        ));
+    }
+
+    #[test]
+    fn captures() {
+        let template: TopicTemplate = "devices/{device}/telemetry/+".parse().unwrap();
+        let topic = "devices/sensor1/telemetry/temperature".try_into().unwrap();
+        let captures = template.captures(&topic).unwrap();
+        assert_eq!(captures.len(), 2);
+        assert_eq!(captures.name("device"), Some("sensor1"));
+        assert_eq!(captures.get(1), Some("temperature"));
+        assert_eq!(captures.name("metric"), None);
+        assert!(template
+            .captures(&"devices/sensor1/status/on".try_into().unwrap())
+            .is_none());
+
+        let template: TopicTemplate = "{site}/devices/#".parse().unwrap();
+        let topic = "paris/devices/sensor1/status".try_into().unwrap();
+        let captures = template.captures(&topic).unwrap();
+        assert_eq!(
+            captures.iter().collect::<Vec<_>>(),
+            ["paris", "sensor1/status"]
+        );
+        let topic = "paris/devices".try_into().unwrap();
+        assert_eq!(template.captures(&topic).unwrap().get(1), Some(""));
+        assert!(template
+            .captures(&"$SYS/devices".try_into().unwrap())
+            .is_none());
+
+        let template: TopicTemplate = "devices/status".parse().unwrap();
+        let topic = "devices/status".try_into().unwrap();
+        assert!(template.captures(&topic).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,313 @@
+use crate::{TopicFilter, TopicName};
+use std::collections::HashMap;
+
+const LEVEL_SEPARATOR: char = '/';
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+    values: Vec<T>,
+    children: HashMap<String, Node<T>>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            values: Vec::new(),
+            children: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.children.is_empty()
+    }
+
+    fn collect<'a>(&'a self, levels: &[&str], system: bool, values: &mut Vec<&'a T>) {
+        // Wildcards at the first level do not match topic names starting with
+        // `$`.
+        if !system {
+            if let Some(node) = self.children.get("#") {
+                values.extend(node.values.iter());
+            }
+        }
+
+        match levels.split_first() {
+            None => values.extend(self.values.iter()),
+            Some((level, levels)) => {
+                if let Some(node) = self.children.get(*level) {
+                    node.collect(levels, false, values);
+                }
+                if !system {
+                    if let Some(node) = self.children.get("+") {
+                        node.collect(levels, false, values);
+                    }
+                }
+            }
+        }
+    }
+
+    fn collect_all<'a>(&'a self, values: &mut Vec<&'a T>) {
+        values.extend(self.values.iter());
+        for node in self.children.values() {
+            node.collect_all(values);
+        }
+    }
+
+    fn collect_intersecting<'a>(
+        &'a self,
+        levels: &[&str],
+        first: bool,
+        system: bool,
+        values: &mut Vec<&'a T>,
+    ) {
+        // Wildcards at the first level, of the stored filters as well as of
+        // the one intersected, do not match topic names starting with `$`.
+        let visible = |level: &str| !first || !level.starts_with('$');
+        if !system {
+            if let Some(node) = self.children.get("#") {
+                values.extend(node.values.iter());
+            }
+        }
+
+        match levels.split_first() {
+            None => values.extend(self.values.iter()),
+            Some((&"#", _)) => {
+                values.extend(self.values.iter());
+                for (level, node) in &self.children {
+                    if level != "#" && visible(level) {
+                        node.collect_all(values);
+                    }
+                }
+            }
+            Some((&"+", levels)) => {
+                for (level, node) in &self.children {
+                    if level != "#" && visible(level) {
+                        node.collect_intersecting(levels, false, false, values);
+                    }
+                }
+            }
+            Some((level, levels)) => {
+                if let Some(node) = self.children.get(*level) {
+                    node.collect_intersecting(levels, false, false, values);
+                }
+                if !system {
+                    if let Some(node) = self.children.get("+") {
+                        node.collect_intersecting(levels, false, false, values);
+                    }
+                }
+            }
+        }
+    }
+
+    fn remove<F>(&mut self, levels: &[&str], predicate: &mut F) -> Vec<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        match levels.split_first() {
+            None => {
+                let (removed, kept) = self.values.drain(..).partition(|v| predicate(v));
+                self.values = kept;
+                removed
+            }
+            Some((level, levels)) => match self.children.get_mut(*level) {
+                Some(node) => {
+                    let removed = node.remove(levels, predicate);
+                    if node.is_empty() {
+                        self.children.remove(*level);
+                    }
+                    removed
+                }
+                None => Vec::new(),
+            },
+        }
+    }
+}
+
+/// A tree storing values under topic filters, such as the subscriptions of a
+/// broker. Finding the values whose filter matches a topic name only walks the
+/// levels of the topic name instead of testing every filter.
+/// The share name of shared subscriptions is ignored, so that a value stored
+/// under `$share/group/sport/#` is matched by `sport/tennis` and stored along
+/// the ones under `sport/#`.
+#[derive(Debug, Clone)]
+pub struct TopicTree<T> {
+    root: Node<T>,
+}
+
+impl<T> Default for TopicTree<T> {
+    fn default() -> Self {
+        TopicTree {
+            root: Default::default(),
+        }
+    }
+}
+
+impl<T> TopicTree<T> {
+    /// Creates an empty tree.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns `true` if the tree contains no value.
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    /// Stores `value` under `filter`. Several values can be stored under the
+    /// same filter.
+    pub fn insert(&mut self, filter: &TopicFilter, value: T) {
+        let node = filter
+            .filter()
+            .split(LEVEL_SEPARATOR)
+            .fold(&mut self.root, |node, level| {
+                node.children.entry(level.into()).or_default()
+            });
+        node.values.push(value);
+    }
+
+    /// Removes and returns the values stored under `filter` for which
+    /// `predicate` returns `true`.
+    pub fn remove<F>(&mut self, filter: &TopicFilter, mut predicate: F) -> Vec<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let levels: Vec<&str> = filter.filter().split(LEVEL_SEPARATOR).collect();
+        self.root.remove(&levels, &mut predicate)
+    }
+
+    /// Returns all the values whose filter matches the topic name `name`,
+    /// following the same rules as `TopicFilter::matches`.
+    pub fn matches(&self, name: &TopicName) -> Vec<&T> {
+        let levels: Vec<&str> = name.as_str().split(LEVEL_SEPARATOR).collect();
+        let mut values = Vec::new();
+        self.root.collect(&levels, name.is_system(), &mut values);
+        values
+    }
+
+    /// Returns all the values whose filter matches at least one of the topic
+    /// names matched by `filter`. The share name of `filter` is ignored.
+    pub fn intersecting(&self, filter: &TopicFilter) -> Vec<&T> {
+        let filter = filter.filter();
+        let levels: Vec<&str> = filter.split(LEVEL_SEPARATOR).collect();
+        let mut values = Vec::new();
+        self.root
+            .collect_intersecting(&levels, true, filter.starts_with('$'), &mut values);
+        values
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    fn tree() -> TopicTree<&'static str> {
+        let mut tree = TopicTree::new();
+        for filter in [
+            "sport/tennis/player1/#",
+            "sport/tennis/+",
+            "sport/#",
+            "+/+",
+            "#",
+            "$SYS/#",
+            "$share/group/sport/tennis/+",
+        ] {
+            tree.insert(&filter.try_into().unwrap(), filter);
+        }
+        tree
+    }
+
+    fn matches(tree: &TopicTree<&'static str>, name: &str) -> Vec<&'static str> {
+        let mut values: Vec<&str> = tree
+            .matches(&name.try_into().unwrap())
+            .into_iter()
+            .copied()
+            .collect();
+        values.sort_unstable();
+        values
+    }
+
+    #[test]
+    fn matches_filters() {
+        let tree = tree();
+        for filter in tree.matches(&"sport/tennis/player1".try_into().unwrap()) {
+            assert!(TopicFilter::try_from(*filter)
+                .unwrap()
+                .matches(&"sport/tennis/player1".try_into().unwrap()));
+        }
+        assert_eq!(
+            matches(&tree, "sport/tennis/player1"),
+            vec![
+                "#",
+                "$share/group/sport/tennis/+",
+                "sport/#",
+                "sport/tennis/+",
+                "sport/tennis/player1/#",
+            ]
+        );
+        assert_eq!(matches(&tree, "sport"), vec!["#", "sport/#"]);
+        assert_eq!(matches(&tree, "/finance"), vec!["#", "+/+"]);
+        assert_eq!(matches(&tree, "$SYS/monitor"), vec!["$SYS/#"]);
+    }
+
+    #[test]
+    fn intersecting_filters() {
+        let tree = tree();
+        let intersecting = |filter: &str| {
+            let mut values: Vec<&str> = tree
+                .intersecting(&filter.try_into().unwrap())
+                .into_iter()
+                .copied()
+                .collect();
+            values.sort_unstable();
+            values
+        };
+        assert_eq!(
+            intersecting("sport/tennis/player1"),
+            matches(&tree, "sport/tennis/player1")
+        );
+        assert_eq!(
+            intersecting("sport/+/player1/#"),
+            vec![
+                "#",
+                "$share/group/sport/tennis/+",
+                "sport/#",
+                "sport/tennis/+",
+                "sport/tennis/player1/#",
+            ]
+        );
+        assert_eq!(
+            intersecting("+/+/+"),
+            vec![
+                "#",
+                "$share/group/sport/tennis/+",
+                "sport/#",
+                "sport/tennis/+",
+                "sport/tennis/player1/#",
+            ]
+        );
+        assert_eq!(intersecting("+"), vec!["#", "sport/#"]);
+        assert_eq!(intersecting("finance/#"), vec!["#", "+/+"]);
+        assert_eq!(intersecting("$SYS/+"), vec!["$SYS/#"]);
+        assert_eq!(intersecting("#").len(), 6);
+    }
+
+    #[test]
+    fn remove() {
+        let mut tree = tree();
+        let filter = "sport/tennis/+".try_into().unwrap();
+        tree.insert(&filter, "other");
+        assert_eq!(tree.remove(&filter, |v| *v == "other"), vec!["other"]);
+        assert_eq!(
+            tree.remove(&filter, |_| true),
+            vec!["sport/tennis/+", "$share/group/sport/tennis/+"]
+        );
+        assert!(tree.remove(&filter, |_| true).is_empty());
+
+        for filter in ["sport/tennis/player1/#", "sport/#", "+/+", "#", "$SYS/#"] {
+            tree.remove(&filter.try_into().unwrap(), |_| true);
+        }
+        assert!(tree.is_empty());
+    }
+}
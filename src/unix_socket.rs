@@ -0,0 +1,82 @@
+use crate::{Listener, PeerInfo};
+use std::{
+    fs::{self, Permissions},
+    io::Result as IoResult,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    task::{Context, Poll},
+};
+use tokio::net::{UnixListener, UnixStream};
+
+/// A `Listener` accepting connections on a Unix domain socket, such as from
+/// the co-located services of a sidecar.
+///
+/// Access to the socket is controlled by the permissions of its file, and the
+/// credentials of the process of each peer are given in
+/// `PeerInfo::credentials`. The socket file is removed when the listener is
+/// dropped.
+#[derive(Debug)]
+pub struct UnixSocketListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl UnixSocketListener {
+    /// Binds a socket at `path`, whose file gets the permissions `mode`, such
+    /// as `0o660` to only accept the connections of the processes of the
+    /// owner and the group of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns the IO error of the creation of the socket, such as if `path`
+    /// already exists, or of the change of its permissions.
+    pub fn bind<P: AsRef<Path>>(path: P, mode: u32) -> IoResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        let listener = UnixSocketListener { listener, path };
+        fs::set_permissions(&listener.path, Permissions::from_mode(mode))?;
+        Ok(listener)
+    }
+
+    /// The path of the socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Listener for UnixSocketListener {
+    type Stream = UnixStream;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+        Listener::poll_accept(&mut self.listener, cx)
+    }
+}
+
+impl Drop for UnixSocketListener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    #[tokio::test]
+    async fn accept() {
+        let path = std::env::temp_dir().join(format!("sage_mqtt_{}.sock", std::process::id()));
+        let mut listener = UnixSocketListener::bind(&path, 0o600).unwrap();
+        let metadata = fs::metadata(listener.path()).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o600);
+
+        let _client = UnixStream::connect(&path).await.unwrap();
+        let (_, peer) = listener.accept().await.unwrap();
+        let credentials = peer.credentials.unwrap();
+        assert_eq!(credentials.uid, metadata.uid());
+
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,110 @@
+//! Helpers to manipulate user properties, which are represented as a list of
+//! UTF-8 string pairs. The specification allows the same key to appear
+//! several times and requires the order of the pairs to be preserved when
+//! forwarding a message.
+
+use std::collections::HashSet;
+
+/// How to handle several user properties sharing the same key.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum DuplicatePolicy {
+    /// All the pairs are kept, as the specification allows.
+    #[default]
+    KeepAll,
+
+    /// Only the first pair of each key is kept.
+    KeepFirst,
+
+    /// Only the last pair of each key is kept.
+    KeepLast,
+}
+
+/// Removes the pairs sharing the same key according to `policy`. The order of
+/// the remaining pairs is preserved.
+pub fn dedup(properties: &mut Vec<(String, String)>, policy: DuplicatePolicy) {
+    match policy {
+        DuplicatePolicy::KeepAll => (),
+        DuplicatePolicy::KeepFirst => {
+            let mut seen = HashSet::new();
+            properties.retain(|(k, _)| seen.insert(k.clone()));
+        }
+        DuplicatePolicy::KeepLast => {
+            let mut seen = HashSet::new();
+            properties.reverse();
+            properties.retain(|(k, _)| seen.insert(k.clone()));
+            properties.reverse();
+        }
+    }
+}
+
+/// Appends `other` to `properties` then removes the duplicated keys according
+/// to `policy`. Using `KeepFirst`, the pairs already in `properties` take
+/// precedence, while using `KeepLast` the pairs of `other` do.
+pub fn merge<I>(properties: &mut Vec<(String, String)>, other: I, policy: DuplicatePolicy)
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    properties.extend(other);
+    dedup(properties, policy);
+}
+
+/// Sorts the pairs by key into a canonical ordering. The sort is stable, so
+/// that the relative order of the pairs sharing the same key is preserved.
+pub fn sort(properties: &mut [(String, String)]) {
+    properties.sort_by(|(a, _), (b, _)| a.cmp(b));
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    fn properties() -> Vec<(String, String)> {
+        vec![
+            ("Daft".into(), "Punk".into()),
+            ("Mogwaï".into(), "Cat".into()),
+            ("Daft".into(), "Bodies".into()),
+        ]
+    }
+
+    #[test]
+    fn dedup_policies() {
+        let mut tested_result = properties();
+        dedup(&mut tested_result, DuplicatePolicy::KeepAll);
+        assert_eq!(tested_result, properties());
+
+        let mut tested_result = properties();
+        dedup(&mut tested_result, DuplicatePolicy::KeepFirst);
+        assert_eq!(
+            tested_result,
+            vec![
+                ("Daft".into(), "Punk".into()),
+                ("Mogwaï".into(), "Cat".into()),
+            ]
+        );
+
+        let mut tested_result = properties();
+        dedup(&mut tested_result, DuplicatePolicy::KeepLast);
+        assert_eq!(
+            tested_result,
+            vec![
+                ("Mogwaï".into(), "Cat".into()),
+                ("Daft".into(), "Bodies".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_and_sort() {
+        let mut tested_result = vec![("Mogwaï".into(), "Dog".into())];
+        merge(&mut tested_result, properties(), DuplicatePolicy::KeepFirst);
+        sort(&mut tested_result);
+        assert_eq!(
+            tested_result,
+            vec![
+                ("Daft".into(), "Punk".into()),
+                ("Mogwaï".into(), "Dog".into()),
+            ]
+        );
+    }
+}
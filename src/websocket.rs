@@ -0,0 +1,692 @@
+use crate::{handshakes::Handshakes, Listener, PeerInfo};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use sha1::{Digest, Sha1};
+use std::{
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+/// The GUID appended to the key of a WebSocket handshake, as specified by
+/// RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The WebSocket subprotocol of MQTT.
+const SUBPROTOCOL_MQTT: &str = "mqtt";
+
+/// The maximum size of an HTTP upgrade request.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+const BAD_REQUEST: &str = "400 Bad Request";
+const NOT_FOUND: &str = "404 Not Found";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// The status code of a normal closure of a WebSocket connection.
+const CLOSE_NORMAL: u16 = 1000;
+
+/// A `Listener` accepting MQTT over WebSocket connections, as specified by
+/// RFC 6455 with the `mqtt` subprotocol, over the connections of another
+/// listener, such as a `TcpListener` or a `TlsListener`.
+///
+/// The HTTP upgrade handshakes run as the listener is polled, a connection is
+/// only returned once its upgrade completes, with the requested path in
+/// `PeerInfo::path`. Requests which are not valid upgrades are answered with
+/// `400 Bad Request`, requests for an unknown path with `404 Not Found`, and
+/// the connections are dropped.
+pub struct WebSocketListener<L: Listener> {
+    listener: L,
+    paths: Arc<Vec<String>>,
+    handshakes: Handshakes<WebSocketStream<L::Stream>>,
+}
+
+impl<L> WebSocketListener<L>
+where
+    L: Listener,
+    L::Stream: Send + 'static,
+{
+    /// Creates a listener accepting WebSocket connections on any path.
+    pub fn new(listener: L) -> Self {
+        WebSocketListener {
+            listener,
+            paths: Default::default(),
+            handshakes: Default::default(),
+        }
+    }
+
+    /// Only accepts the connections requesting one of `paths`, such as
+    /// `/mqtt`. The query of the requested URI is not taken into account.
+    pub fn with_paths<P: Into<String>>(self, paths: impl IntoIterator<Item = P>) -> Self {
+        WebSocketListener {
+            paths: Arc::new(paths.into_iter().map(Into::into).collect()),
+            ..self
+        }
+    }
+
+    /// The paths the listener accepts connections on. Empty if any path is
+    /// accepted.
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// Drops the connections whose handshake does not complete within
+    /// `timeout`, 10 seconds by default. `None` waits for the handshakes
+    /// indefinitely.
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshakes.set_timeout(timeout);
+        self
+    }
+
+    /// Stops accepting connections while `maximum` handshakes are in
+    /// progress, 256 by default.
+    pub fn with_maximum_handshakes(mut self, maximum: usize) -> Self {
+        self.handshakes.set_maximum(maximum);
+        self
+    }
+
+    /// Returns the wrapped listener.
+    pub fn get_ref(&self) -> &L {
+        &self.listener
+    }
+}
+
+impl<L> Listener for WebSocketListener<L>
+where
+    L: Listener,
+    L::Stream: Send + 'static,
+{
+    type Stream = WebSocketStream<L::Stream>;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+        let paths = &self.paths;
+        self.handshakes
+            .poll_accept(&mut self.listener, cx, |stream, peer| {
+                upgrade(stream, peer, paths.clone())
+            })
+    }
+}
+
+/// A `Listener` accepting MQTT over secure WebSocket (`wss://`) connections,
+/// terminating TLS before handling the WebSocket upgrade. The peer
+/// information holds both the certificates presented by the client and the
+/// requested path. It is created with `WebSocketListener::new` from a
+/// `TlsListener`, which may select its certificate upon SNI with
+/// `TlsListener::from_pem_by_server_name`.
+#[cfg(feature = "tls")]
+pub type SecureWebSocketListener<L> = WebSocketListener<crate::TlsListener<L>>;
+
+/// The stream of an MQTT over WebSocket connection accepted by a
+/// `WebSocketListener`.
+///
+/// The payloads of the binary frames received are read as a continuous byte
+/// stream, regardless of how MQTT packets are split into frames. Each write
+/// is sent as a single binary frame, which may be buffered until the stream is
+/// flushed. Pings are answered, and a close frame ends the stream.
+#[derive(Debug)]
+pub struct WebSocketStream<S> {
+    inner: S,
+    read_buffer: Vec<u8>,
+    remaining: u64,
+    mask: [u8; 4],
+    mask_offset: usize,
+    // Whether a fragmented message is in progress.
+    in_message: bool,
+    write_buffer: Vec<u8>,
+    closed: bool,
+    close_sent: bool,
+}
+
+struct FrameHeader {
+    fin: bool,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    length: u64,
+    size: usize,
+}
+
+impl<S> WebSocketStream<S> {
+    fn new(inner: S, read_buffer: Vec<u8>) -> Self {
+        WebSocketStream {
+            inner,
+            read_buffer,
+            remaining: 0,
+            mask: [0; 4],
+            mask_offset: 0,
+            in_message: false,
+            write_buffer: Vec::new(),
+            closed: false,
+            close_sent: false,
+        }
+    }
+
+    /// Returns the wrapped stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Handles the frame starting the read buffer. Returns `false` if the
+    /// frame is not complete yet.
+    fn process(&mut self, header: FrameHeader) -> IoResult<bool> {
+        let mask = header
+            .mask
+            .ok_or_else(|| invalid_data("unmasked client frame"))?;
+        match header.opcode {
+            OPCODE_CONTINUATION | OPCODE_BINARY => {
+                if header.opcode == OPCODE_CONTINUATION && !self.in_message {
+                    return Err(invalid_data("continuation frame outside of a message"));
+                }
+                if header.opcode == OPCODE_BINARY && self.in_message {
+                    return Err(invalid_data(
+                        "new message before the end of the previous one",
+                    ));
+                }
+                self.in_message = !header.fin;
+                self.read_buffer.drain(..header.size);
+                self.remaining = header.length;
+                self.mask = mask;
+                self.mask_offset = 0;
+                Ok(true)
+            }
+            OPCODE_CLOSE | OPCODE_PING | OPCODE_PONG => {
+                if !header.fin || header.length > 125 {
+                    return Err(invalid_data("invalid control frame"));
+                }
+                let end = header.size + header.length as usize;
+                if self.read_buffer.len() < end {
+                    return Ok(false);
+                }
+                let payload: Vec<u8> = self
+                    .read_buffer
+                    .drain(..end)
+                    .skip(header.size)
+                    .enumerate()
+                    .map(|(i, byte)| byte ^ mask[i % 4])
+                    .collect();
+                match header.opcode {
+                    OPCODE_PING => encode_frame(OPCODE_PONG, &payload, &mut self.write_buffer),
+                    OPCODE_CLOSE => {
+                        self.closed = true;
+                        if !self.close_sent {
+                            self.close_sent = true;
+                            let status = &payload[..payload.len().min(2)];
+                            encode_frame(OPCODE_CLOSE, status, &mut self.write_buffer);
+                        }
+                    }
+                    _ => (),
+                }
+                Ok(true)
+            }
+            OPCODE_TEXT => Err(invalid_data("MQTT packets must be sent in binary frames")),
+            _ => Err(invalid_data("unknown frame opcode")),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> WebSocketStream<S> {
+    fn poll_write_buffer(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        while !self.write_buffer.is_empty() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.write_buffer) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => drop(self.write_buffer.drain(..n)),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            if this.remaining > 0 && !this.read_buffer.is_empty() {
+                let n = (this.remaining.min(this.read_buffer.len() as u64) as usize)
+                    .min(buf.remaining());
+                let mut payload: Vec<u8> = this.read_buffer.drain(..n).collect();
+                for byte in payload.iter_mut() {
+                    *byte ^= this.mask[this.mask_offset % 4];
+                    this.mask_offset += 1;
+                }
+                buf.put_slice(&payload);
+                this.remaining -= n as u64;
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.remaining == 0 && !this.closed {
+                if let Some(header) = decode_header(&this.read_buffer)? {
+                    if this.process(header)? {
+                        if let Poll::Ready(Err(e)) = this.poll_write_buffer(cx) {
+                            return Poll::Ready(Err(e));
+                        }
+                        continue;
+                    }
+                }
+            }
+            if this.closed {
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut chunk = [0; 4096];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+            if chunk.filled().is_empty() {
+                return if this.remaining == 0 && this.read_buffer.is_empty() {
+                    Poll::Ready(Ok(()))
+                } else {
+                    Poll::Ready(Err(ErrorKind::UnexpectedEof.into()))
+                };
+            }
+            this.read_buffer.extend_from_slice(chunk.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WebSocketStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        let this = self.get_mut();
+        if this.poll_write_buffer(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        if this.close_sent {
+            return Poll::Ready(Err(ErrorKind::BrokenPipe.into()));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        encode_frame(OPCODE_BINARY, buf, &mut this.write_buffer);
+        if let Poll::Ready(Err(e)) = this.poll_write_buffer(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let this = self.get_mut();
+        if this.poll_write_buffer(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let this = self.get_mut();
+        if !this.close_sent {
+            this.close_sent = true;
+            let status = CLOSE_NORMAL.to_be_bytes();
+            encode_frame(OPCODE_CLOSE, &status, &mut this.write_buffer);
+        }
+        if this.poll_write_buffer(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+fn invalid_data(message: &str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads the HTTP upgrade request from `stream` and answers it.
+async fn upgrade<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    peer: PeerInfo,
+    paths: Arc<Vec<String>>,
+) -> IoResult<(WebSocketStream<S>, PeerInfo)> {
+    let mut buffer = Vec::new();
+    let end = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if buffer.len() >= MAX_REQUEST_SIZE {
+            return refuse(stream, BAD_REQUEST).await;
+        }
+        let mut chunk = [0; 1024];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    };
+
+    let (path, key) = match parse_request(&buffer[..end]) {
+        Ok((path, _)) if !paths.is_empty() && !paths.contains(&path) => {
+            return refuse(stream, NOT_FOUND).await
+        }
+        Ok(request) => request,
+        Err(status) => return refuse(stream, status).await,
+    };
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\
+         Sec-WebSocket-Protocol: {}\r\n\r\n",
+        accept_key(&key),
+        SUBPROTOCOL_MQTT
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+
+    buffer.drain(..end);
+    let peer = PeerInfo {
+        path: Some(path),
+        ..peer
+    };
+    Ok((WebSocketStream::new(stream, buffer), peer))
+}
+
+/// Answers an HTTP upgrade request with an error `status`.
+async fn refuse<S: AsyncWrite + Unpin, T>(mut stream: S, status: &str) -> IoResult<T> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+        status
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Err(invalid_data(&format!(
+        "WebSocket upgrade refused: {}",
+        status
+    )))
+}
+
+/// Parses the head of an HTTP upgrade request into the requested path and the
+/// WebSocket key, or the status of the error response.
+fn parse_request(head: &[u8]) -> Result<(String, String), &'static str> {
+    let head = std::str::from_utf8(head).map_err(|_| BAD_REQUEST)?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let target = match (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) {
+        (Some("GET"), Some(target), Some("HTTP/1.1")) => target,
+        _ => return Err(BAD_REQUEST),
+    };
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let (mut upgrade, mut connection, mut version, mut protocol) = (false, false, false, false);
+    let mut key = None;
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or(BAD_REQUEST)?;
+        let value = value.trim();
+        let mut tokens = value.split(',').map(str::trim);
+        match name.trim().to_ascii_lowercase().as_str() {
+            "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+            "connection" => connection = tokens.any(|t| t.eq_ignore_ascii_case("upgrade")),
+            "sec-websocket-version" => version = value == "13",
+            "sec-websocket-protocol" => protocol |= tokens.any(|t| t == SUBPROTOCOL_MQTT),
+            "sec-websocket-key" => key = Some(value.to_string()),
+            _ => (),
+        }
+    }
+    match key {
+        Some(key) if upgrade && connection && version && protocol => Ok((path, key)),
+        _ => Err(BAD_REQUEST),
+    }
+}
+
+/// Decodes the header of the frame starting `buffer`. Returns `None` if the
+/// header is not complete yet.
+fn decode_header(buffer: &[u8]) -> IoResult<Option<FrameHeader>> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    if buffer[0] & 0x70 != 0 {
+        return Err(invalid_data("reserved frame bits set"));
+    }
+    let (length, mut size) = match buffer[1] & 0x7F {
+        126 if buffer.len() >= 4 => (u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4),
+        127 if buffer.len() >= 10 => {
+            let mut length = [0; 8];
+            length.copy_from_slice(&buffer[2..10]);
+            (u64::from_be_bytes(length), 10)
+        }
+        126 | 127 => return Ok(None),
+        length => (length as u64, 2),
+    };
+    let mask = if buffer[1] & 0x80 != 0 {
+        if buffer.len() < size + 4 {
+            return Ok(None);
+        }
+        let mut mask = [0; 4];
+        mask.copy_from_slice(&buffer[size..size + 4]);
+        size += 4;
+        Some(mask)
+    } else {
+        None
+    };
+    Ok(Some(FrameHeader {
+        fin: buffer[0] & 0x80 != 0,
+        opcode: buffer[0] & 0x0F,
+        mask,
+        length,
+        size,
+    }))
+}
+
+/// Encodes a final unmasked frame, as sent by servers.
+fn encode_frame(opcode: u8, payload: &[u8], buffer: &mut Vec<u8>) {
+    buffer.push(0x80 | opcode);
+    match payload.len() {
+        length if length < 126 => buffer.push(length as u8),
+        length if length <= u16::MAX as usize => {
+            buffer.push(126);
+            buffer.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            buffer.push(127);
+            buffer.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    buffer.extend_from_slice(payload);
+}
+
+/// Computes the `Sec-WebSocket-Accept` value answering a WebSocket key.
+fn accept_key(key: &str) -> String {
+    BASE64.encode(Sha1::digest(format!("{}{}", key, WEBSOCKET_GUID)))
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+    use std::{
+        collections::VecDeque,
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    struct Duplex {
+        input: Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Duplex {
+        fn new(input: Vec<u8>) -> (Self, Arc<Mutex<Vec<u8>>>) {
+            let output = Arc::new(Mutex::new(Vec::new()));
+            let duplex = Duplex {
+                input: Cursor::new(input),
+                output: output.clone(),
+            };
+            (duplex, output)
+        }
+    }
+
+    impl AsyncRead for Duplex {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<IoResult<()>> {
+            Pin::new(&mut self.get_mut().input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Duplex {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<IoResult<usize>> {
+            self.output.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<IoResult<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<IoResult<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    struct Queued(VecDeque<Duplex>);
+
+    impl Listener for Queued {
+        type Stream = Duplex;
+
+        fn poll_accept(&mut self, _: &mut Context<'_>) -> Poll<IoResult<(Self::Stream, PeerInfo)>> {
+            match self.0.pop_front() {
+                Some(duplex) => Poll::Ready(Ok((duplex, Default::default()))),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+        let mut frame = vec![(fin as u8) << 7 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    const REQUEST: &str = "GET /mqtt?client=1 HTTP/1.1\r\n\
+                           Host: localhost\r\n\
+                           Upgrade: websocket\r\n\
+                           Connection: keep-alive, Upgrade\r\n\
+                           Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                           Sec-WebSocket-Protocol: mqtt\r\n\
+                           Sec-WebSocket-Version: 13\r\n\r\n";
+
+    #[test]
+    fn handshake() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(
+            parse_request(REQUEST.as_bytes()).unwrap(),
+            ("/mqtt".into(), "dGhlIHNhbXBsZSBub25jZQ==".into())
+        );
+        let request = REQUEST.replace("Sec-WebSocket-Protocol: mqtt\r\n", "");
+        assert_eq!(parse_request(request.as_bytes()), Err(BAD_REQUEST));
+        let request = REQUEST.replace("GET", "POST");
+        assert_eq!(parse_request(request.as_bytes()), Err(BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn accept() {
+        let mut input = REQUEST.as_bytes().to_vec();
+        input.extend(client_frame(true, OPCODE_BINARY, &[0xC0, 0x00]));
+        let (duplex, output) = Duplex::new(input);
+        let mut listener =
+            WebSocketListener::new(Queued(vec![duplex].into())).with_paths(["/mqtt"]);
+
+        let (mut stream, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer.path.as_deref(), Some("/mqtt"));
+        let response = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(response.contains("Sec-WebSocket-Protocol: mqtt\r\n"));
+
+        let mut packet = [0; 2];
+        stream.read_exact(&mut packet).await.unwrap();
+        assert_eq!(packet, [0xC0, 0x00]);
+
+        let (duplex, output) = Duplex::new(REQUEST.replace("/mqtt", "/ws").into_bytes());
+        let paths = Arc::new(vec!["/mqtt".to_string()]);
+        assert!(upgrade(duplex, Default::default(), paths).await.is_err());
+        assert!(output
+            .lock()
+            .unwrap()
+            .starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test]
+    async fn frames() {
+        let mut input = client_frame(false, OPCODE_BINARY, &[0x10, 0x03]);
+        input.extend(client_frame(true, OPCODE_PING, b"ping"));
+        input.extend(client_frame(true, OPCODE_CONTINUATION, &[0x00, 0x01, 0x02]));
+        input.extend(client_frame(
+            true,
+            OPCODE_CLOSE,
+            &CLOSE_NORMAL.to_be_bytes(),
+        ));
+        let (duplex, output) = Duplex::new(input);
+        let mut stream = WebSocketStream::new(duplex, Vec::new());
+
+        let mut packet = Vec::new();
+        stream.read_to_end(&mut packet).await.unwrap();
+        assert_eq!(packet, [0x10, 0x03, 0x00, 0x01, 0x02]);
+        let mut expected = vec![0x8A, 0x04];
+        expected.extend_from_slice(b"ping");
+        expected.extend_from_slice(&[0x88, 0x02, 0x03, 0xE8]);
+        assert_eq!(*output.lock().unwrap(), expected);
+
+        let (duplex, output) = Duplex::new(Vec::new());
+        let mut stream = WebSocketStream::new(duplex, Vec::new());
+        stream.write_all(&[0xE0, 0x00]).await.unwrap();
+        stream.flush().await.unwrap();
+        assert_eq!(*output.lock().unwrap(), [0x82, 0x02, 0xE0, 0x00]);
+
+        let (duplex, _) = Duplex::new(client_frame(true, OPCODE_TEXT, b"text"));
+        let mut stream = WebSocketStream::new(duplex, Vec::new());
+        assert!(stream.read_to_end(&mut packet).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fragments() {
+        let (duplex, _) = Duplex::new(client_frame(true, OPCODE_CONTINUATION, &[0xC0, 0x00]));
+        let mut stream = WebSocketStream::new(duplex, Vec::new());
+        assert!(stream.read_to_end(&mut Vec::new()).await.is_err());
+
+        let mut input = client_frame(false, OPCODE_BINARY, &[0xC0]);
+        input.extend(client_frame(true, OPCODE_BINARY, &[0x00]));
+        let (duplex, _) = Duplex::new(input);
+        let mut stream = WebSocketStream::new(duplex, Vec::new());
+        assert!(stream.read_to_end(&mut Vec::new()).await.is_err());
+
+        let mut input = client_frame(false, OPCODE_BINARY, &[0xC0]);
+        input.extend(client_frame(true, OPCODE_CONTINUATION, &[0x00]));
+        input.extend(client_frame(true, OPCODE_BINARY, &[0xD0, 0x00]));
+        input.extend(client_frame(true, OPCODE_CLOSE, &[]));
+        let (duplex, _) = Duplex::new(input);
+        let mut stream = WebSocketStream::new(duplex, Vec::new());
+        let mut packets = Vec::new();
+        stream.read_to_end(&mut packets).await.unwrap();
+        assert_eq!(packets, [0xC0, 0x00, 0xD0, 0x00]);
+    }
+}
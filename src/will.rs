@@ -1,7 +1,8 @@
 use crate::{
     defaults::{DEFAULT_PAYLOAD_FORMAT_INDICATOR, DEFAULT_WILL_DELAY_INTERVAL},
-    QoS, Topic,
+    duration, Publish, QoS, Saturation, TopicName,
 };
+use std::time::{Duration, Instant};
 
 /// Due to the unstable nature of a connexion, the client can loose its
 /// connection to the server. This ungraceful disconnect can be notified
@@ -10,7 +11,7 @@ use crate::{
 /// When a client ungracefully disconnect from a server (when the keep alive
 /// is reached), the server will publish the Last Will message to anyone
 /// subscribed to its topic.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Will {
     /// The quality of service for the will message.
     pub qos: QoS,
@@ -36,7 +37,7 @@ pub struct Will {
     pub content_type: String,
 
     /// Optional topic used as response if the Will message is a request.
-    pub response_topic: Option<Topic>,
+    pub response_topic: Option<TopicName>,
 
     /// Optional correlation optionaly used if the Will message is a request.
     pub correlation_data: Option<Vec<u8>>,
@@ -45,7 +46,7 @@ pub struct Will {
     pub user_properties: Vec<(String, String)>,
 
     /// The Last Will Topic. Cannot be empty.
-    pub topic: Topic,
+    pub topic: TopicName,
 
     /// The last will payload.
     pub message: Vec<u8>,
@@ -53,7 +54,7 @@ pub struct Will {
 
 impl Will {
     /// Builds a default Will with specified topic and message
-    pub fn with_message(topic: Topic, message: &str) -> Self {
+    pub fn with_message(topic: TopicName, message: &str) -> Self {
         Will {
             qos: QoS::AtMostOnce,
             retain: false,
@@ -68,4 +69,101 @@ impl Will {
             message: message.as_bytes().to_vec(),
         }
     }
+
+    /// Returns the will delay interval as a `Duration`.
+    pub fn delay_interval_duration(&self) -> Duration {
+        Duration::from_secs(self.delay_interval as u64)
+    }
+
+    /// Sets the will delay interval from a `Duration`.
+    pub fn set_delay_interval_duration(&mut self, delay_interval: Duration) -> Saturation {
+        let (secs, saturation) = duration::to_u32_seconds(delay_interval);
+        self.delay_interval = secs;
+        saturation
+    }
+
+    /// Returns the message expiry interval as a `Duration`, if any.
+    pub fn message_expiry_interval_duration(&self) -> Option<Duration> {
+        self.message_expiry_interval
+            .map(|secs| Duration::from_secs(secs as u64))
+    }
+
+    /// Sets the message expiry interval from a `Duration`.
+    pub fn set_message_expiry_interval_duration(&mut self, interval: Duration) -> Saturation {
+        let (secs, saturation) = duration::to_u32_seconds(interval);
+        self.message_expiry_interval = Some(secs);
+        saturation
+    }
+
+    /// Returns the instant the Last Will message is to be published, given
+    /// the instant the network connection was closed at. Returns `None` if
+    /// the instant cannot be represented.
+    pub fn delay_deadline(&self, disconnected_at: Instant) -> Option<Instant> {
+        duration::deadline(disconnected_at, self.delay_interval)
+    }
+
+    /// Returns the instant the Last Will message expires, given the instant
+    /// it was published at. Returns `None` if the message never expires.
+    pub fn message_expiry_deadline(&self, published_at: Instant) -> Option<Instant> {
+        self.message_expiry_interval
+            .and_then(|secs| duration::deadline(published_at, secs))
+    }
+
+    /// Builds the `Publish` packet a server emits when publishing the Last
+    /// Will message. All the will properties are propagated. The packet
+    /// identifier is left unset and must be assigned by the server if the
+    /// quality of service requires one.
+    pub fn to_publish(&self) -> Publish {
+        Publish {
+            qos: self.qos,
+            retain: self.retain,
+            topic_name: self.topic.clone(),
+            payload_format_indicator: self.payload_format_indicator,
+            message_expiry_interval: self.message_expiry_interval,
+            response_topic: self.response_topic.clone(),
+            correlation_data: self.correlation_data.clone(),
+            user_properties: self.user_properties.clone(),
+            content_type: self.content_type.clone(),
+            message: self.message.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    #[test]
+    fn to_publish() {
+        let will = Will {
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            delay_interval: 10,
+            payload_format_indicator: true,
+            message_expiry_interval: Some(1337),
+            content_type: "text/plain".into(),
+            response_topic: Some("Sky".try_into().unwrap()),
+            correlation_data: Some(vec![0x0D, 0x15, 0xEA, 0x5E]),
+            user_properties: vec![("Mogwaï".into(), "Cat".into())],
+            ..Will::with_message("CloZee".try_into().unwrap(), "Oregon")
+        };
+        assert_eq!(
+            will.to_publish(),
+            Publish {
+                qos: QoS::AtLeastOnce,
+                retain: true,
+                topic_name: "CloZee".try_into().unwrap(),
+                payload_format_indicator: true,
+                message_expiry_interval: Some(1337),
+                content_type: "text/plain".into(),
+                response_topic: Some("Sky".try_into().unwrap()),
+                correlation_data: Some(vec![0x0D, 0x15, 0xEA, 0x5E]),
+                user_properties: vec![("Mogwaï".into(), "Cat".into())],
+                message: "Oregon".into(),
+                ..Default::default()
+            }
+        );
+    }
 }
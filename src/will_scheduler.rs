@@ -0,0 +1,147 @@
+use crate::{duration, Expiry, ExpiryQueue, Will};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// Schedules the publication of the Last Will messages of disconnected
+/// clients, for brokers.
+///
+/// A will is published once its Will Delay Interval has elapsed after the
+/// network connection was closed, or when the session ends if it happens
+/// first. It is not published if the client reconnects in the meantime, in
+/// which case it must be cancelled.
+/// Wills are identified by a key such as the client id of the session.
+#[derive(Debug, Clone)]
+pub struct WillScheduler<K> {
+    wills: HashMap<K, (Will, Instant)>,
+    queue: ExpiryQueue<K>,
+}
+
+impl<K: Hash + Eq + Clone> WillScheduler<K> {
+    /// Creates an empty scheduler, where `now` is the current time.
+    pub fn new(now: Instant) -> Self {
+        WillScheduler {
+            wills: HashMap::new(),
+            queue: ExpiryQueue::new(now),
+        }
+    }
+
+    /// Returns the number of scheduled wills.
+    pub fn len(&self) -> usize {
+        self.wills.len()
+    }
+
+    /// Returns `true` if no will is scheduled.
+    pub fn is_empty(&self) -> bool {
+        self.wills.is_empty()
+    }
+
+    /// Schedules `will`, whose network connection was closed at
+    /// `disconnected_at`, returning the instant it is due at.
+    /// `session_expiry` is the Session Expiry Interval in effect at the time
+    /// of the disconnection: the will is due when the session ends if it is
+    /// earlier than its delay. A will which is already scheduled for `key` is
+    /// replaced.
+    pub fn schedule(
+        &mut self,
+        key: K,
+        will: Will,
+        session_expiry: Expiry,
+        disconnected_at: Instant,
+    ) -> Instant {
+        let session_end = match session_expiry {
+            Expiry::Default => Some(disconnected_at),
+            Expiry::Seconds(secs) => duration::deadline(disconnected_at, secs),
+            Expiry::Never => None,
+        };
+        let delay_end = will.delay_deadline(disconnected_at);
+        let deadline = match (delay_end, session_end) {
+            (Some(delay_end), Some(session_end)) => delay_end.min(session_end),
+            (delay_end, session_end) => delay_end.or(session_end).unwrap_or(disconnected_at),
+        };
+        self.queue.insert(key.clone(), deadline);
+        self.wills.insert(key, (will, deadline));
+        deadline
+    }
+
+    /// Cancels the will scheduled for `key`, such as when the client
+    /// reconnects to its session, returning it if any.
+    pub fn cancel(&mut self, key: &K) -> Option<Will> {
+        self.queue.remove(key);
+        self.wills.remove(key).map(|(will, _)| will)
+    }
+
+    /// Returns the instant the will scheduled for `key` is due at, if any.
+    pub fn deadline(&self, key: &K) -> Option<Instant> {
+        self.wills.get(key).map(|(_, deadline)| *deadline)
+    }
+
+    /// Removes and returns the wills which are due at `now`, in the order of
+    /// their deadlines. They must be published by the broker.
+    pub fn due(&mut self, now: Instant) -> Vec<(K, Will)> {
+        self.queue
+            .expired_before(now)
+            .into_iter()
+            .filter_map(|key| {
+                let (will, _) = self.wills.remove(&key)?;
+                Some((key, will))
+            })
+            .collect()
+    }
+
+    /// Returns the delay until the next will is due, if any.
+    pub fn next_wakeup(&self, now: Instant) -> Option<Duration> {
+        self.queue.next_wakeup(now)
+    }
+}
+
+#[cfg(test)]
+mod unit {
+
+    use super::*;
+
+    fn will(delay_interval: u32) -> Will {
+        Will {
+            delay_interval,
+            ..Will::with_message("Around the World".try_into().unwrap(), "Bye")
+        }
+    }
+
+    #[test]
+    fn schedule() {
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let mut scheduler = WillScheduler::new(start);
+
+        assert_eq!(
+            scheduler.schedule("delayed", will(10), Expiry::Never, start),
+            secs(10)
+        );
+        assert_eq!(
+            scheduler.schedule("session end", will(10), Expiry::Seconds(5), start),
+            secs(5)
+        );
+        assert_eq!(
+            scheduler.schedule("immediate", will(10), Expiry::Default, start),
+            start
+        );
+        assert_eq!(
+            scheduler.schedule("reconnected", will(3), Expiry::Never, start),
+            secs(3)
+        );
+        assert_eq!(scheduler.len(), 4);
+
+        assert_eq!(scheduler.due(start), vec![("immediate", will(10))]);
+        assert_eq!(scheduler.cancel(&"reconnected"), Some(will(3)));
+        assert_eq!(scheduler.cancel(&"reconnected"), None);
+        assert!(scheduler.due(secs(4)).is_empty());
+        assert_eq!(scheduler.deadline(&"session end"), Some(secs(5)));
+        assert_eq!(
+            scheduler.due(secs(10)),
+            vec![("session end", will(10)), ("delayed", will(10))]
+        );
+        assert!(scheduler.is_empty());
+    }
+}